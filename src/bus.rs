@@ -1,278 +1,416 @@
+mod address_map;
 mod clint;
 mod device_tree;
+mod dma;
 pub mod dram;
+mod irq;
 mod mrom;
 mod plic;
+mod reservation;
 mod uart;
+mod virtio;
 
+use crate::cpu::{AddrTransMode, InstCache};
+use crate::machine::{VirtioKind, PLIC_SIZE, UART_SIZE, VIRTIO_SIZE};
+use crate::snapshot::{self, Reader, Writer};
 use crate::{elfload, Arguments, Isa, TrapCause};
+use address_map::AddressMap;
 use clint::Clint;
+use device_tree::DeviceTree;
+pub use dma::Dma;
 use dram::Dram;
+pub use irq::IrqLine;
 use mrom::Mrom;
 use plic::Plic;
+pub use plic::MIP_EIP;
+use reservation::Reservations;
+use std::io;
+pub use uart::term;
 use uart::Uart;
+use virtio::{Block, Console, Net, Rng, VirtioMmio};
+
+type StoreFn = fn(&mut dyn Device, u64, u64) -> Result<(), (Option<u64>, TrapCause, String)>;
+type LoadFn = fn(&dyn Device, u64) -> Result<u64, (Option<u64>, TrapCause, String)>;
+type LoadMutFn = fn(&mut dyn Device, u64) -> Result<u64, (Option<u64>, TrapCause, String)>;
+
+// what an address in the memory map is routed to
+#[derive(Clone, Copy)]
+enum Target {
+    Mrom,
+    Clint,
+    Plic,
+    Mmio(usize),
+}
 
 pub struct Bus {
     pub mrom: mrom::Mrom,
     pub clint: clint::Clint,
     pub dram: dram::Dram,
-    pub uart: uart::Uart,
     pub plic: plic::Plic,
+    pub icache: InstCache,
+    pub reservations: Reservations,
+    dt: DeviceTree,
+    // devices shadow DRAM where they overlap it
+    map: AddressMap<Target>,
+    devices: Vec<Box<dyn Device>>,
+    irq_lines: Vec<IrqLine>,
 }
 
 impl Bus {
+    // the bus of a machine running the ELF of args, for the tests
+    #[cfg(test)]
+    pub fn for_test(args: &Arguments, isa: Isa) -> Self {
+        crate::log::LOG_LEVEL.get_or_init(|| crate::log::LogLv::NoLog);
+        let loader =
+            elfload::ElfLoader::try_new(&args.filename).expect("loading the test ELF failed");
+        Bus::new(loader, args, isa)
+    }
+
     pub fn new(loader: elfload::ElfLoader, args: &Arguments, isa: Isa) -> Self {
+        let machine = &args.machine;
+        let entry_point = loader.get_entry_point().expect("entry point not found.");
+
         // load proxy kernel before user program when it's given
         let dram = Dram::new(loader, args, isa);
-        let mut mrom = Mrom::new(dram.base_addr, isa);
+        let mut mrom = Mrom::new(machine.mrom_base, entry_point, isa);
+
+        // create and load DTB; it's made again when a device adds a node
+        let dt = DeviceTree::new(
+            machine,
+            isa,
+            AddrTransMode::widest(isa, args.trans_mode),
+            &args.riscv_isa(isa),
+        );
+        mrom.load_dtb(&dt, &dram).expect("invalid device tree");
 
-        // create and load DTB
-        mrom.load_dtb(dram.base_addr, dram.initrd_start, dram.initrd_end, isa);
+        // the mrom and the clint only decode the part of their window they back
+        let clint = Clint::new(machine.clint_base, machine.harts);
+        let mut map = AddressMap::new();
+        [
+            (
+                "mrom",
+                machine.mrom_base,
+                mrom.mrom.len() as u64,
+                Target::Mrom,
+            ),
+            (
+                "clint",
+                machine.clint_base,
+                clint.clint.len() as u64,
+                Target::Clint,
+            ),
+            ("plic", machine.plic_base, PLIC_SIZE, Target::Plic),
+        ]
+        .into_iter()
+        .try_for_each(|(name, base, size, target)| map.insert(name, base, size, target))
+        .expect("invalid memory map");
 
-        Bus {
+        let mut bus = Bus {
             mrom,
-            clint: Clint::new(),
+            clint,
             dram,
-            uart: Uart::new(),
-            plic: Plic::new(),
+            plic: Plic::new(machine.plic_base, machine.harts),
+            icache: InstCache::new(),
+            reservations: Reservations::new(machine.harts, args.sc_fail_every),
+            dt,
+            map,
+            devices: Vec::new(),
+            irq_lines: Vec::new(),
+        };
+
+        let uart_irq = bus.irq_line(machine.uart_irq).expect("invalid uart irq");
+        let uart_backend = uart::Backend::open(
+            &machine.uart_backend,
+            machine.uart_input.as_deref(),
+            machine.uart_tee,
+            args.raw_console,
+        )
+        .unwrap_or_else(|e| {
+            panic!(
+                "opening the uart backend {} failed: {e}",
+                machine.uart_backend
+            )
+        });
+        bus.attach(
+            "uart",
+            machine.uart_base,
+            UART_SIZE,
+            Box::new(Uart::new(machine.uart_base, uart_irq, uart_backend)),
+        )
+        .expect("invalid memory map");
+
+        for (kind, base, irq) in machine.virtio_slots() {
+            let irq = bus.irq_line(irq).expect("invalid virtio irq");
+            let device: Box<dyn Device> = match kind {
+                VirtioKind::Block => {
+                    let path = machine.virtio_blk_image.as_ref().unwrap();
+                    let block = Block::new(path, machine.virtio_blk_readonly)
+                        .unwrap_or_else(|e| panic!("opening the disk image {path} failed: {e}"));
+                    Box::new(VirtioMmio::new(base, irq, block))
+                }
+                VirtioKind::Console => {
+                    let console = Console::new(&machine.virtio_console)
+                        .unwrap_or_else(|e| panic!("opening the console ports failed: {e}"));
+                    Box::new(VirtioMmio::new(base, irq, console))
+                }
+                VirtioKind::Rng => {
+                    let rng = Rng::new(machine.virtio_rng_seed.unwrap());
+                    Box::new(VirtioMmio::new(base, irq, rng))
+                }
+                VirtioKind::Net => {
+                    let spec = machine.virtio_net.as_ref().unwrap();
+                    let net = Net::new(spec, machine.virtio_net_mac).unwrap_or_else(|e| {
+                        panic!("opening the network backend {spec} failed: {e}")
+                    });
+                    Box::new(VirtioMmio::new(base, irq, net))
+                }
+            };
+            bus.attach(kind.name(), base, VIRTIO_SIZE, device)
+                .expect("invalid memory map");
         }
+
+        bus
     }
 
-    // store
-    pub fn store8(&mut self, addr: u64, data: u64) -> Result<(), (Option<u64>, TrapCause, String)> {
-        if self.mrom.in_range(addr) {
-            self.mrom.store8(addr, data)
-        } else if self.clint.in_range(addr) {
-            self.clint.store8(addr, data)
-        } else if self.dram.in_range(addr) {
-            self.dram.store8(addr, data)
-        } else if self.uart.in_range(addr) {
-            self.uart.store8_with_plic(addr, data, &mut self.plic)
-        } else if self.plic.in_range(addr) {
-            self.plic.store8(addr, data)
-        } else {
-            Err((
-                Some(addr),
-                TrapCause::StoreAMOAccessFault,
-                format!("addr out of range at store8: {addr:#x}"),
-            ))
+    // map an MMIO device to [base, base + size); the device receives absolute addresses
+    pub fn attach(
+        &mut self,
+        name: &str,
+        base: u64,
+        size: u64,
+        device: Box<dyn Device>,
+    ) -> Result<(), String> {
+        self.map
+            .insert(name, base, size, Target::Mmio(self.devices.len()))?;
+        if let Some(node) = device.dt_node(name, base, size) {
+            self.dt.add_node(node);
+            if let Err(e) = self.mrom.load_dtb(&self.dt, &self.dram) {
+                self.dt.remove_last_node();
+                self.map.remove(base);
+                return Err(e);
+            }
         }
+        self.devices.push(device);
+        Ok(())
     }
 
-    pub fn store16(
+    // a PLIC interrupt source for a device to drive, which must not be shared
+    pub fn irq_line(&mut self, id: u32) -> Result<IrqLine, String> {
+        if id == 0 || plic::NUM_IDS as u32 <= id {
+            return Err(format!("irq {id} is out of range"));
+        }
+        if self.irq_lines.iter().any(|line| line.id() == id) {
+            return Err(format!("irq {id} is already in use"));
+        }
+
+        let line = IrqLine::new(id);
+        self.irq_lines.push(line.clone());
+        Ok(line)
+    }
+
+    // (name, base, size) of the devices in address order
+    pub fn regions(&self) -> impl Iterator<Item = (&str, u64, u64)> {
+        self.map.regions()
+    }
+
+    // called once every harts have run their quantum
+    pub fn tick(&mut self) {
+        for index in 0..self.devices.len() {
+            self.devices[index].tick();
+            self.dma(index);
+        }
+        self.sync_irqs();
+    }
+
+    // DRAM for the page table walker
+    pub fn memory(&mut self) -> Dma<'_> {
+        Dma::new(&mut self.dram, &mut self.icache, &mut self.reservations)
+    }
+
+    fn dma(&mut self, index: usize) {
+        let mut mem = Dma::new(&mut self.dram, &mut self.icache, &mut self.reservations);
+        self.devices[index].dma(&mut mem);
+    }
+
+    fn sync_irqs(&mut self) {
+        for line in self.irq_lines.iter() {
+            if line.is_raised() != self.plic.interrupt_level(line.id()) {
+                self.plic
+                    .set_interrupt_level(line.id(), line.is_raised() as u32);
+            }
+        }
+    }
+
+    fn device(&self, target: Target) -> &dyn Device {
+        match target {
+            Target::Mrom => &self.mrom,
+            Target::Clint => &self.clint,
+            Target::Plic => &self.plic,
+            Target::Mmio(index) => self.devices[index].as_ref(),
+        }
+    }
+
+    fn device_mut(&mut self, target: Target) -> &mut dyn Device {
+        match target {
+            Target::Mrom => &mut self.mrom,
+            Target::Clint => &mut self.clint,
+            Target::Plic => &mut self.plic,
+            Target::Mmio(index) => self.devices[index].as_mut(),
+        }
+    }
+
+    fn store(
         &mut self,
         addr: u64,
         data: u64,
+        size: u64,
+        store: StoreFn,
     ) -> Result<(), (Option<u64>, TrapCause, String)> {
-        if self.mrom.in_range(addr) {
-            self.mrom.store16(addr, data)
-        } else if self.clint.in_range(addr) {
-            self.clint.store16(addr, data)
+        if let Some(target) = self.map.lookup(addr) {
+            let result = store(self.device_mut(target), addr, data);
+            if let Target::Mmio(index) = target {
+                self.dma(index);
+            }
+            self.sync_irqs();
+            result
         } else if self.dram.in_range(addr) {
-            self.dram.store16(addr, data)
-        } else if self.uart.in_range(addr) {
-            self.uart.store16(addr, data)
-        } else if self.plic.in_range(addr) {
-            self.plic.store16(addr, data)
+            self.icache.invalidate(addr, size);
+            self.reservations.invalidate(addr, size);
+            store(&mut self.dram, addr, data)
         } else {
             Err((
                 Some(addr),
                 TrapCause::StoreAMOAccessFault,
-                format!("addr out of range at store16: {addr:#x}"),
+                format!("addr out of range at store{}: {addr:#x}", size * 8),
             ))
         }
     }
 
-    pub fn store32(
-        &mut self,
+    fn load(
+        &self,
         addr: u64,
-        data: u64,
-    ) -> Result<(), (Option<u64>, TrapCause, String)> {
-        if self.mrom.in_range(addr) {
-            self.mrom.store32(addr, data)
-        } else if self.clint.in_range(addr) {
-            self.clint.store32(addr, data)
+        name: &str,
+        load: LoadFn,
+    ) -> Result<u64, (Option<u64>, TrapCause, String)> {
+        if let Some(target) = self.map.lookup(addr) {
+            load(self.device(target), addr)
         } else if self.dram.in_range(addr) {
-            self.dram.store32(addr, data)
-        } else if self.uart.in_range(addr) {
-            self.uart.store32(addr, data)
-        } else if self.plic.in_range(addr) {
-            self.plic.store32(addr, data)
+            load(&self.dram, addr)
         } else {
             Err((
                 Some(addr),
-                TrapCause::StoreAMOAccessFault,
-                format!("addr out of range at store32: {addr:#x}"),
+                TrapCause::LoadAccessFault,
+                format!("addr out of range at {name}: {addr:#x}"),
             ))
         }
     }
 
-    pub fn store64(
+    // loads that have side effects on the device, such as popping a FIFO
+    fn load_mut(
         &mut self,
         addr: u64,
-        data: u64,
-    ) -> Result<(), (Option<u64>, TrapCause, String)> {
-        if self.mrom.in_range(addr) {
-            self.mrom.store64(addr, data)
-        } else if self.clint.in_range(addr) {
-            self.clint.store64(addr, data)
+        name: &str,
+        load: LoadMutFn,
+    ) -> Result<u64, (Option<u64>, TrapCause, String)> {
+        if let Some(target) = self.map.lookup(addr) {
+            let result = load(self.device_mut(target), addr);
+            self.sync_irqs();
+            result
         } else if self.dram.in_range(addr) {
-            self.dram.store64(addr, data)
-        } else if self.uart.in_range(addr) {
-            self.uart.store64(addr, data)
-        } else if self.plic.in_range(addr) {
-            self.plic.store64(addr, data)
+            load(&mut self.dram, addr)
         } else {
             Err((
                 Some(addr),
-                TrapCause::StoreAMOAccessFault,
-                format!("addr out of range at store64: {addr:#x}"),
+                TrapCause::LoadAccessFault,
+                format!("addr out of range at {name}: {addr:#x}"),
             ))
         }
     }
 
+    // state of the MMIO devices in the order they were attached
+    pub fn save_devices(&self, w: &mut Writer) -> io::Result<()> {
+        w.tag(b"MMIO")?;
+        w.u64(self.devices.len() as u64)?;
+        self.devices
+            .iter()
+            .try_for_each(|device| device.save_state(w))
+    }
+
+    pub fn restore_devices(&mut self, r: &mut Reader) -> io::Result<()> {
+        r.tag(b"MMIO")?;
+        let num = r.u64()? as usize;
+        if num != self.devices.len() {
+            return Err(snapshot::invalid_data(format!(
+                "expected {} MMIO devices but found {num}",
+                self.devices.len()
+            )));
+        }
+        self.devices
+            .iter_mut()
+            .try_for_each(|device| device.restore_state(r))?;
+        self.sync_irqs();
+
+        Ok(())
+    }
+
+    // store
+    pub fn store8(&mut self, addr: u64, data: u64) -> Result<(), (Option<u64>, TrapCause, String)> {
+        self.store(addr, data, 1, |dev, addr, data| dev.store8(addr, data))
+    }
+
+    pub fn store16(
+        &mut self,
+        addr: u64,
+        data: u64,
+    ) -> Result<(), (Option<u64>, TrapCause, String)> {
+        self.store(addr, data, 2, |dev, addr, data| dev.store16(addr, data))
+    }
+
+    pub fn store32(
+        &mut self,
+        addr: u64,
+        data: u64,
+    ) -> Result<(), (Option<u64>, TrapCause, String)> {
+        self.store(addr, data, 4, |dev, addr, data| dev.store32(addr, data))
+    }
+
+    pub fn store64(
+        &mut self,
+        addr: u64,
+        data: u64,
+    ) -> Result<(), (Option<u64>, TrapCause, String)> {
+        self.store(addr, data, 8, |dev, addr, data| dev.store64(addr, data))
+    }
+
     // load
     pub fn load8(&mut self, addr: u64) -> Result<u64, (Option<u64>, TrapCause, String)> {
-        if self.mrom.in_range(addr) {
-            self.mrom.load8(addr)
-        } else if self.clint.in_range(addr) {
-            self.clint.load8(addr)
-        } else if self.dram.in_range(addr) {
-            self.dram.load8(addr)
-        } else if self.uart.in_range(addr) {
-            self.uart.load8_with_plic(addr, &mut self.plic)
-        } else if self.plic.in_range(addr) {
-            self.plic.load8(addr)
-        } else {
-            Err((
-                Some(addr),
-                TrapCause::LoadAccessFault,
-                format!("addr out of range at load8: {addr:#x}"),
-            ))
-        }
+        self.load_mut(addr, "load8", |dev, addr| dev.load8(addr))
     }
 
     pub fn load16(&self, addr: u64) -> Result<u64, (Option<u64>, TrapCause, String)> {
-        if self.mrom.in_range(addr) {
-            self.mrom.load16(addr)
-        } else if self.clint.in_range(addr) {
-            self.clint.load16(addr)
-        } else if self.dram.in_range(addr) {
-            self.dram.load16(addr)
-        } else if self.uart.in_range(addr) {
-            self.uart.load16(addr)
-        } else if self.plic.in_range(addr) {
-            self.plic.load16(addr)
-        } else {
-            Err((
-                Some(addr),
-                TrapCause::LoadAccessFault,
-                format!("addr out of range at load16: {addr:#x}"),
-            ))
-        }
+        self.load(addr, "load16", |dev, addr| dev.load16(addr))
     }
 
     pub fn load32(&mut self, addr: u64) -> Result<u64, (Option<u64>, TrapCause, String)> {
-        if self.mrom.in_range(addr) {
-            self.mrom.load32(addr)
-        } else if self.clint.in_range(addr) {
-            self.clint.load32(addr)
-        } else if self.dram.in_range(addr) {
-            self.dram.load32(addr)
-        } else if self.uart.in_range(addr) {
-            self.uart.load32(addr)
-        } else if self.plic.in_range(addr) {
-            self.plic.load32(addr)
-        } else {
-            Err((
-                Some(addr),
-                TrapCause::LoadAccessFault,
-                format!("addr out of range at load32: {addr:#x}"),
-            ))
-        }
+        self.load_mut(addr, "load32", |dev, addr| dev.load32(addr))
     }
 
     pub fn load64(&mut self, addr: u64) -> Result<u64, (Option<u64>, TrapCause, String)> {
-        if self.mrom.in_range(addr) {
-            self.mrom.load64(addr)
-        } else if self.clint.in_range(addr) {
-            self.clint.load64(addr)
-        } else if self.dram.in_range(addr) {
-            self.dram.load64(addr)
-        } else if self.uart.in_range(addr) {
-            self.uart.load64(addr)
-        } else if self.plic.in_range(addr) {
-            self.plic.load64(addr)
-        } else {
-            Err((
-                Some(addr),
-                TrapCause::LoadAccessFault,
-                format!("addr out of range at load64: {addr:#x}"),
-            ))
-        }
+        self.load_mut(addr, "load64", |dev, addr| dev.load64(addr))
     }
 
     pub fn load_u8(&self, addr: u64) -> Result<u64, (Option<u64>, TrapCause, String)> {
-        if self.mrom.in_range(addr) {
-            self.mrom.load_u8(addr)
-        } else if self.clint.in_range(addr) {
-            self.clint.load_u8(addr)
-        } else if self.dram.in_range(addr) {
-            self.dram.load_u8(addr)
-        } else if self.uart.in_range(addr) {
-            self.uart.load_u8(addr)
-        } else if self.plic.in_range(addr) {
-            self.plic.load_u8(addr)
-        } else {
-            Err((
-                Some(addr),
-                TrapCause::LoadAccessFault,
-                format!("addr out of range at load_u8: {addr:#x}"),
-            ))
-        }
+        self.load(addr, "load_u8", |dev, addr| dev.load_u8(addr))
     }
 
     pub fn load_u16(&self, addr: u64) -> Result<u64, (Option<u64>, TrapCause, String)> {
-        if self.mrom.in_range(addr) {
-            self.mrom.load_u16(addr)
-        } else if self.clint.in_range(addr) {
-            self.clint.load_u16(addr)
-        } else if self.dram.in_range(addr) {
-            self.dram.load_u16(addr)
-        } else if self.uart.in_range(addr) {
-            self.uart.load_u16(addr)
-        } else if self.plic.in_range(addr) {
-            self.plic.load_u16(addr)
-        } else {
-            Err((
-                Some(addr),
-                TrapCause::LoadAccessFault,
-                format!("addr out of range at load_u16: {addr:#x}"),
-            ))
-        }
+        self.load(addr, "load_u16", |dev, addr| dev.load_u16(addr))
     }
 
     pub fn load_u32(&self, addr: u64) -> Result<u64, (Option<u64>, TrapCause, String)> {
-        if self.mrom.in_range(addr) {
-            self.mrom.load_u32(addr)
-        } else if self.clint.in_range(addr) {
-            self.clint.load_u32(addr)
-        } else if self.dram.in_range(addr) {
-            self.dram.load_u32(addr)
-        } else if self.uart.in_range(addr) {
-            self.uart.load_u32(addr)
-        } else if self.plic.in_range(addr) {
-            self.plic.load_u32(addr)
-        } else {
-            Err((
-                Some(addr),
-                TrapCause::LoadAccessFault,
-                format!("addr out of range at load_u32: {addr:#x}"),
-            ))
-        }
+        self.load(addr, "load_u32", |dev, addr| dev.load_u32(addr))
     }
 }
-
 pub trait Device {
     fn in_range(&self, addr: u64) -> bool;
     fn addr2index(&self, addr: u64) -> usize;
@@ -287,4 +425,215 @@ pub trait Device {
     fn load_u8(&self, addr: u64) -> Result<u64, (Option<u64>, TrapCause, String)>;
     fn load_u16(&self, addr: u64) -> Result<u64, (Option<u64>, TrapCause, String)>;
     fn load_u32(&self, addr: u64) -> Result<u64, (Option<u64>, TrapCause, String)>;
+
+    // the node under /soc for a device the machine config doesn't describe
+    fn dt_node(&self, _name: &str, _base: u64, _size: u64) -> Option<String> {
+        None
+    }
+
+    // advance the device state outside of bus accesses
+    fn tick(&mut self) {}
+
+    // access guest memory, called after each store to the device and each tick
+    fn dma(&mut self, _mem: &mut Dma) {}
+
+    // devices without state worth keeping are left out of snapshots
+    fn save_state(&self, _w: &mut Writer) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn restore_state(&mut self, _r: &mut Reader) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmdline::Arguments;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    // an accelerator that raises its interrupt when the doorbell is rung
+    // and lowers it when the completion is acknowledged
+    struct Accelerator {
+        base_addr: u64,
+        doorbell: Rc<Cell<u32>>,
+        irq: IrqLine,
+    }
+
+    impl Device for Accelerator {
+        fn in_range(&self, addr: u64) -> bool {
+            (self.base_addr..self.base_addr + 0x1000).contains(&addr)
+        }
+
+        fn dt_node(&self, name: &str, base: u64, size: u64) -> Option<String> {
+            Some(format!(
+                "{name}@{base:x} {{
+                  compatible = \"carron,accel\";
+                  interrupt-parent = <&PLIC>;
+                  interrupts = <{}>;
+                  reg = <0x0 {base:#x} 0x0 {size:#x}>;
+                }};",
+                self.irq.id()
+            ))
+        }
+
+        fn addr2index(&self, addr: u64) -> usize {
+            (addr - self.base_addr) as usize
+        }
+
+        fn store8(
+            &mut self,
+            addr: u64,
+            _data: u64,
+        ) -> Result<(), (Option<u64>, TrapCause, String)> {
+            Err((
+                Some(addr),
+                TrapCause::StoreAMOAccessFault,
+                "store8".to_string(),
+            ))
+        }
+
+        fn store16(
+            &mut self,
+            addr: u64,
+            _data: u64,
+        ) -> Result<(), (Option<u64>, TrapCause, String)> {
+            Err((
+                Some(addr),
+                TrapCause::StoreAMOAccessFault,
+                "store16".to_string(),
+            ))
+        }
+
+        fn store32(
+            &mut self,
+            addr: u64,
+            data: u64,
+        ) -> Result<(), (Option<u64>, TrapCause, String)> {
+            match self.addr2index(addr) {
+                0x0 => {
+                    self.doorbell.set(data as u32);
+                    self.irq.raise();
+                }
+                0x4 => self.irq.lower(),
+                _ => (),
+            }
+            Ok(())
+        }
+
+        fn store64(
+            &mut self,
+            addr: u64,
+            _data: u64,
+        ) -> Result<(), (Option<u64>, TrapCause, String)> {
+            Err((
+                Some(addr),
+                TrapCause::StoreAMOAccessFault,
+                "store64".to_string(),
+            ))
+        }
+
+        fn load8(&mut self, addr: u64) -> Result<u64, (Option<u64>, TrapCause, String)> {
+            Err((Some(addr), TrapCause::LoadAccessFault, "load8".to_string()))
+        }
+
+        fn load16(&self, addr: u64) -> Result<u64, (Option<u64>, TrapCause, String)> {
+            Err((Some(addr), TrapCause::LoadAccessFault, "load16".to_string()))
+        }
+
+        fn load32(&mut self, addr: u64) -> Result<u64, (Option<u64>, TrapCause, String)> {
+            self.load_u32(addr)
+        }
+
+        fn load64(&mut self, addr: u64) -> Result<u64, (Option<u64>, TrapCause, String)> {
+            Err((Some(addr), TrapCause::LoadAccessFault, "load64".to_string()))
+        }
+
+        fn load_u8(&self, addr: u64) -> Result<u64, (Option<u64>, TrapCause, String)> {
+            Err((
+                Some(addr),
+                TrapCause::LoadAccessFault,
+                "load_u8".to_string(),
+            ))
+        }
+
+        fn load_u16(&self, addr: u64) -> Result<u64, (Option<u64>, TrapCause, String)> {
+            Err((
+                Some(addr),
+                TrapCause::LoadAccessFault,
+                "load_u16".to_string(),
+            ))
+        }
+
+        fn load_u32(&self, _addr: u64) -> Result<u64, (Option<u64>, TrapCause, String)> {
+            Ok(self.doorbell.get() as u64)
+        }
+    }
+
+    #[test]
+    fn attach_device_test() {
+        const ACCEL_BASE: u64 = 0x2000_0000;
+        const ACCEL_IRQ: u32 = 5;
+        const PLIC_BASE: u64 = 0x0c00_0000;
+        const MIP_MEIP: u64 = 1 << 11;
+        const MIP_SEIP: u64 = 1 << 9;
+        let mut bus = Bus::for_test(&Arguments::for_elf("./HelloWorld"), Isa::Rv32);
+        let doorbell = Rc::new(Cell::new(0));
+        let irq = bus.irq_line(ACCEL_IRQ).unwrap();
+        let accel = Accelerator {
+            base_addr: ACCEL_BASE,
+            doorbell: doorbell.clone(),
+            irq,
+        };
+        bus.attach("accel", ACCEL_BASE, 0x1000, Box::new(accel))
+            .unwrap();
+
+        // the DTB is made again with the node of the device
+        let dtb = &bus.mrom.mrom[Mrom::DTB_OFFSET..];
+        let has = |dtb: &[u8], name: &[u8]| dtb.windows(name.len()).any(|w| w == name);
+        assert_eq!(dtb[..4], [0xd0, 0x0d, 0xfe, 0xed]);
+        assert!(has(dtb, b"accel@20000000\0"));
+
+        // the line is taken and the window must not overlap the UART
+        assert!(bus.irq_line(ACCEL_IRQ).is_err());
+        assert!(bus.irq_line(1).is_err());
+        let dup = Accelerator {
+            base_addr: 0x1000_0080,
+            doorbell: doorbell.clone(),
+            irq: bus.irq_line(ACCEL_IRQ + 1).unwrap(),
+        };
+        assert!(bus
+            .attach("dup", 0x1000_0080, 0x100, Box::new(dup))
+            .is_err());
+        assert!(!has(&bus.mrom.mrom, b"dup@"));
+
+        // enable the source for the machine context of hart 0
+        bus.store32(PLIC_BASE + 4 * ACCEL_IRQ as u64, 1).unwrap();
+        bus.store32(PLIC_BASE + 0x2000, 1 << ACCEL_IRQ).unwrap();
+
+        bus.store32(ACCEL_BASE, 0xcafe).unwrap();
+        assert_eq!(doorbell.get(), 0xcafe);
+        assert_eq!(bus.load32(ACCEL_BASE).unwrap(), 0xcafe);
+        assert_eq!(bus.plic.mip[0], MIP_MEIP);
+
+        // the supervisor context leaves MEIP alone
+        bus.store32(PLIC_BASE + 0x2080, 1 << ACCEL_IRQ).unwrap();
+        assert_eq!(bus.plic.mip[0], MIP_MEIP | MIP_SEIP);
+        bus.store32(PLIC_BASE + 0x2080, 0).unwrap();
+        assert_eq!(bus.plic.mip[0], MIP_MEIP);
+        assert_eq!(bus.load32(PLIC_BASE + 0x20_0004).unwrap(), ACCEL_IRQ as u64);
+
+        bus.store32(ACCEL_BASE + 4, 1).unwrap();
+        bus.store32(PLIC_BASE + 0x20_0004, ACCEL_IRQ as u64)
+            .unwrap();
+        assert_eq!(bus.plic.mip[0], 0);
+
+        assert_eq!(
+            bus.regions().map(|(name, _, _)| name).collect::<Vec<_>>(),
+            vec!["mrom", "clint", "plic", "uart", "accel"]
+        );
+        assert!(bus.load32(0x9000_0000).is_err());
+    }
 }
@@ -0,0 +1,99 @@
+// non-overlapping [base, end) regions sorted by base address
+pub struct AddressMap<T> {
+    regions: Vec<Region<T>>,
+}
+
+struct Region<T> {
+    name: String,
+    base: u64,
+    end: u64,
+    target: T,
+}
+
+impl<T: Copy> AddressMap<T> {
+    pub fn new() -> Self {
+        AddressMap {
+            regions: Vec::new(),
+        }
+    }
+
+    pub fn insert(&mut self, name: &str, base: u64, size: u64, target: T) -> Result<(), String> {
+        let end = base
+            .checked_add(size)
+            .filter(|_| size > 0)
+            .ok_or(format!("{name} has an invalid size {size:#x} at {base:#x}"))?;
+
+        let pos = self.regions.partition_point(|region| region.base < base);
+        let overlapped = [pos.checked_sub(1), Some(pos)]
+            .into_iter()
+            .flatten()
+            .filter_map(|i| self.regions.get(i))
+            .find(|region| region.base < end && base < region.end);
+        if let Some(region) = overlapped {
+            return Err(format!(
+                "{name} ({base:#x}-) overlaps {} ({:#x}-)",
+                region.name, region.base
+            ));
+        }
+
+        self.regions.insert(
+            pos,
+            Region {
+                name: name.to_string(),
+                base,
+                end,
+                target,
+            },
+        );
+        Ok(())
+    }
+
+    pub fn remove(&mut self, base: u64) {
+        self.regions.retain(|region| region.base != base);
+    }
+
+    pub fn lookup(&self, addr: u64) -> Option<T> {
+        let pos = self.regions.partition_point(|region| region.base <= addr);
+        self.regions[..pos]
+            .last()
+            .filter(|region| addr < region.end)
+            .map(|region| region.target)
+    }
+
+    // (name, base, size) in address order
+    pub fn regions(&self) -> impl Iterator<Item = (&str, u64, u64)> {
+        self.regions
+            .iter()
+            .map(|region| (region.name.as_str(), region.base, region.end - region.base))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn address_map_test() {
+        let mut map = AddressMap::new();
+        map.insert("b", 0x2000, 0x1000, 'b').unwrap();
+        map.insert("a", 0x1000, 0x100, 'a').unwrap();
+        map.insert("c", 0x3000, 0x10, 'c').unwrap();
+        assert_eq!(map.lookup(0x0fff), None);
+        assert_eq!(map.lookup(0x1000), Some('a'));
+        assert_eq!(map.lookup(0x10ff), Some('a'));
+        assert_eq!(map.lookup(0x1100), None);
+        assert_eq!(map.lookup(0x2fff), Some('b'));
+        assert_eq!(map.lookup(0x3000), Some('c'));
+        assert_eq!(map.lookup(0x3010), None);
+
+        assert!(map.insert("d", 0x1f00, 0x200, 'd').is_err());
+        assert!(map.insert("e", 0x0, 0x1001, 'e').is_err());
+        assert!(map.insert("f", 0x100, 0, 'f').is_err());
+        assert!(map.insert("g", u64::MAX, 2, 'g').is_err());
+        map.insert("h", 0x1100, 0xf00, 'h').unwrap();
+        assert_eq!(
+            map.regions().map(|(name, _, _)| name).collect::<Vec<_>>(),
+            vec!["a", "h", "b", "c"]
+        );
+    }
+}
@@ -1,31 +1,84 @@
 use super::Device;
+use crate::snapshot::{Reader, Snapshot, Writer};
 use crate::TrapCause;
+use std::io;
+
+const MSIP_BASE: usize = 0x0;
+const MTIMECMP_BASE: usize = 0x4000;
+const MTIME: usize = 0xbff8;
 
 pub struct Clint {
     pub clint: Vec<u8>,
     pub base_addr: u64,
     size: usize,
-}
-
-impl Default for Clint {
-    fn default() -> Self {
-        Self::new()
-    }
+    // mtime >= mtimecmp, only compared when either of them changes
+    mtip: Vec<bool>,
 }
 
 impl Clint {
     #[allow(arithmetic_overflow)]
-    pub fn new() -> Self {
+    pub fn new(base_addr: u64, harts: usize) -> Self {
         const CLINT_SIZE: usize = 0xFFFF;
 
-        Clint {
+        let mut clint = Clint {
             clint: vec![0; CLINT_SIZE],
-            base_addr: 0x0200_0000,
+            base_addr,
             size: CLINT_SIZE,
+            mtip: vec![false; harts],
+        };
+        clint.update_mtip();
+        clint
+    }
+
+    fn read_u64(&self, index: usize) -> u64 {
+        u64::from_le_bytes(self.clint[index..index + 8].try_into().unwrap())
+    }
+
+    // msip and mtimecmp are banked per hart
+    pub fn msip(&self, hartid: usize) -> bool {
+        self.clint[MSIP_BASE + 4 * hartid] & 0x1 != 0
+    }
+
+    pub fn mtimecmp(&self, hartid: usize) -> u64 {
+        self.read_u64(MTIMECMP_BASE + 8 * hartid)
+    }
+
+    pub fn mtime(&self) -> u64 {
+        self.read_u64(MTIME)
+    }
+
+    pub fn set_mtime(&mut self, mtime: u64) {
+        self.clint[MTIME..MTIME + 8].copy_from_slice(&mtime.to_le_bytes());
+        self.update_mtip();
+    }
+
+    // the timer interrupt stays pending until mtimecmp is moved past mtime
+    pub fn mtip(&self, hartid: usize) -> bool {
+        self.mtip[hartid]
+    }
+
+    fn update_mtip(&mut self) {
+        let mtime = self.mtime();
+        for hartid in 0..self.mtip.len() {
+            self.mtip[hartid] = mtime >= self.mtimecmp(hartid);
         }
     }
 }
 
+impl Snapshot for Clint {
+    fn save(&self, w: &mut Writer) -> io::Result<()> {
+        w.tag(b"CLNT")?;
+        w.bytes(&self.clint)
+    }
+
+    fn restore(&mut self, r: &mut Reader) -> io::Result<()> {
+        r.tag(b"CLNT")?;
+        r.bytes_into(&mut self.clint)?;
+        self.update_mtip();
+        Ok(())
+    }
+}
+
 #[allow(clippy::identity_op)]
 impl Device for Clint {
     // is addr in device address space
@@ -61,6 +114,9 @@ impl Device for Clint {
         self.clint[addr + 2] = ((data >> 16) & 0xFF) as u8;
         self.clint[addr + 1] = ((data >> 8) & 0xFF) as u8;
         self.clint[addr + 0] = ((data >> 0) & 0xFF) as u8;
+        if addr >= MTIMECMP_BASE {
+            self.update_mtip();
+        }
         Ok(())
     }
 
@@ -74,6 +130,9 @@ impl Device for Clint {
         self.clint[addr + 2] = ((data >> 16) & 0xFF) as u8;
         self.clint[addr + 1] = ((data >> 8) & 0xFF) as u8;
         self.clint[addr + 0] = ((data >> 0) & 0xFF) as u8;
+        if addr >= MTIMECMP_BASE {
+            self.update_mtip();
+        }
         Ok(())
     }
 
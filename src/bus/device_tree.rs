@@ -1,27 +1,79 @@
 mod dtb;
 mod dts;
 
+use super::dram::Dram;
 use super::mrom::Mrom;
+use crate::cpu::AddrTransMode;
+use crate::machine::{MachineConfig, MROM_SIZE};
 use crate::Isa;
 
-impl Mrom {
-    pub fn load_dtb(
-        &mut self,
-        dram_addr: u64,
-        initrd_start: Option<usize>,
-        initrd_end: Option<usize>,
+// what the DTB is made of, kept to remake it when a device is attached
+pub struct DeviceTree {
+    machine: MachineConfig,
+    isa: Isa,
+    trans_mode: AddrTransMode,
+    riscv_isa: String,
+    // nodes of the devices attached besides the ones in the machine config
+    nodes: Vec<String>,
+}
+
+impl DeviceTree {
+    pub fn new(
+        machine: &MachineConfig,
         isa: Isa,
-    ) {
-        let dts: String = dts::make_dts(dram_addr, initrd_start, initrd_end, isa).replace("  ", "");
-        let dtb: Vec<u8> = dtb::make_dtb(dts);
+        trans_mode: AddrTransMode,
+        riscv_isa: &str,
+    ) -> Self {
+        DeviceTree {
+            machine: machine.clone(),
+            isa,
+            trans_mode,
+            riscv_isa: riscv_isa.to_string(),
+            nodes: Vec::new(),
+        }
+    }
+
+    pub fn add_node(&mut self, node: String) {
+        self.nodes.push(node);
+    }
+
+    pub fn remove_last_node(&mut self) {
+        self.nodes.pop();
+    }
+
+    fn make_dtb(&self, dram: &Dram) -> Vec<u8> {
+        let dts: String = dts::make_dts(
+            &self.machine,
+            dram.base_addr,
+            dram.initrd_start,
+            dram.initrd_end,
+            self.isa,
+            self.trans_mode,
+            &self.riscv_isa,
+            &self.nodes.concat(),
+        )
+        .replace("  ", "");
+        dtb::make_dtb(dts)
+    }
+}
+
+impl Mrom {
+    // the DTB follows the reset vector, which passes its address in a1
+    pub fn load_dtb(&mut self, dt: &DeviceTree, dram: &Dram) -> Result<(), String> {
+        let dtb = dt.make_dtb(dram);
+        if Mrom::DTB_OFFSET + dtb.len() > MROM_SIZE as usize {
+            return Err(format!(
+                "the DTB ({:#x} bytes) doesn't fit in the mrom",
+                dtb.len()
+            ));
+        }
+        self.mrom.truncate(Mrom::DTB_OFFSET);
         self.mrom.extend(dtb);
 
-        const MROM_ALIGN: usize = 0x1000;
-        self.mrom.resize(
-            (self.mrom.len() + MROM_ALIGN - 1) / MROM_ALIGN * MROM_ALIGN,
-            0,
-        );
+        // the window is backed as a whole, so the DTB can grow in place
+        self.mrom.resize(MROM_SIZE as usize, 0);
 
         self.set_size();
+        Ok(())
     }
 }
@@ -37,7 +37,7 @@ impl LabelManager {
         self.labels
             .iter()
             .find_map(|(l, n)| {
-                if n.split('/').last() == Some(node_name) {
+                if n.split('/').next_back() == Some(node_name) {
                     Some(l)
                 } else {
                     None
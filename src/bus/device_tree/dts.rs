@@ -1,8 +1,110 @@
+use crate::cpu::AddrTransMode;
+use crate::machine::{MachineConfig, CLINT_SIZE, PLIC_SIZE, UART_SIZE, VIRTIO_SIZE};
 use crate::Isa;
 
-fn dts_32(dram_addr: u64, initrd_start: Option<usize>, initrd_end: Option<usize>) -> String {
+fn cpu_nodes(harts: usize, isa: &str, mmu_type: &str) -> String {
+    (0..harts)
+        .map(|id| {
+            format!(
+                "CPU{id}: cpu@{id} {{
+                  device_type = \"cpu\";
+                  reg = <{id}>;
+                  status = \"okay\";
+                  compatible = \"riscv\";
+                  riscv,isa = \"{isa}\";
+                  mmu-type = \"riscv,{mmu_type}\";
+                  riscv,pmpregions = <16>;
+                  riscv,pmpgranularity = <4>;
+                  clock-frequency = <1000000000>;
+                  CPU{id}_intc: interrupt-controller {{
+                    #address-cells = <2>;
+                    #interrupt-cells = <1>;
+                    interrupt-controller;
+                    compatible = \"riscv,cpu-intc\";
+                  }};
+                }};
+                "
+            )
+        })
+        .collect()
+}
+
+// how the SBI PMU events map onto the hpm counters and their event selectors,
+// for the firmware to set them up; raw events are passed through as selectors
+const PMU_NODE: &str = "pmu {
+                  compatible = \"riscv,pmu\";
+                  riscv,event-to-mhpmevent = <0x1 0x0 0x1 0x2 0x0 0x2 0x5 0x0 0x6 0x10019 0x0 0xb 0x1001b 0x0 0xb 0x10021 0x0 0xa>;
+                  riscv,event-to-mhpmcounters = <0x1 0x1 0xfffffff9 0x2 0x2 0xfffffffc 0x5 0x5 0xfffffff8 0x10019 0x10019 0xfffffff8 0x1001b 0x1001b 0xfffffff8 0x10021 0x10021 0xfffffff8>;
+                  riscv,raw-event-to-mhpmcounters = <0x0 0x0 0xffffffff 0xffffff00 0xfffffff8>;
+                };";
+
+// a 64-bit value as two cells
+fn cells(val: u64) -> String {
+    format!("{:#x} {:#x}", val >> 32, val & 0xffff_ffff)
+}
+
+// the interrupt sources of each hart, listed in hart order
+fn interrupts_extended(harts: usize, irqs: &[u32]) -> String {
+    (0..harts)
+        .flat_map(|id| irqs.iter().map(move |irq| format!("&CPU{id}_intc {irq} ")))
+        .collect()
+}
+
+fn virtio_nodes(machine: &MachineConfig) -> String {
+    machine
+        .virtio_slots()
+        .iter()
+        .map(|(_, base, irq)| {
+            let reg = cells(*base);
+            format!(
+                "virtio_mmio@{base:x} {{
+                  compatible = \"virtio,mmio\";
+                  interrupt-parent = <&PLIC>;
+                  interrupts = <{irq}>;
+                  reg = <{reg} 0x0 {VIRTIO_SIZE:#x}>;
+                }};
+                "
+            )
+        })
+        .collect()
+}
+
+// the root filesystem is on the virtio disk unless an initrd is given
+fn bootargs(machine: &MachineConfig, initrd_start: Option<usize>) -> String {
+    match (&machine.virtio_blk_image, initrd_start) {
+        (Some(_), None) => {
+            let mode = if machine.virtio_blk_readonly {
+                "ro"
+            } else {
+                "rw"
+            };
+            format!("root=/dev/vda {mode} console=ttyS0 earlycon")
+        }
+        _ => "root=/dev/ram console=ttyS0 earlycon".to_string(),
+    }
+}
+
+fn dts_32(
+    machine: &MachineConfig,
+    dram_addr: u64,
+    initrd_start: Option<usize>,
+    initrd_end: Option<usize>,
+    riscv_isa: &str,
+    nodes: &str,
+) -> String {
+    let harts = machine.harts;
+    let bootargs = bootargs(machine, initrd_start);
     let initrd_start = initrd_start.unwrap_or(0);
     let initrd_end = initrd_end.unwrap_or(0);
+    let cpus = cpu_nodes(harts, riscv_isa, "sv32");
+    let clint_irqs = interrupts_extended(harts, &[3, 7]);
+    let plic_irqs = interrupts_extended(harts, &[11, 9]);
+    let (dram_reg, dram_size) = (cells(dram_addr), cells(machine.dram_size));
+    let (clint_base, clint_reg) = (machine.clint_base, cells(machine.clint_base));
+    let (plic_base, plic_reg) = (machine.plic_base, cells(machine.plic_base));
+    let (uart_base, uart_reg) = (machine.uart_base, cells(machine.uart_base));
+    let uart_irq = machine.uart_irq;
+    let virtio = virtio_nodes(machine);
     format!(
         "/dts-v1/;
             / {{
@@ -14,63 +116,50 @@ fn dts_32(dram_addr: u64, initrd_start: Option<usize>, initrd_end: Option<usize>
                 stdout-path = &SERIAL0;
                 linux,initrd-start = <{initrd_start}>;
                 linux,initrd-end = <{initrd_end}>;
-                bootargs = \"root=/dev/ram console=ttyS0 earlycon\";
+                bootargs = \"{bootargs}\";
               }};
               cpus {{
                 #address-cells = <1>;
                 #size-cells = <0>;
                 timebase-frequency = <10000000>;
-                CPU0: cpu@0 {{
-                  device_type = \"cpu\";
-                  reg = <0>;
-                  status = \"okay\";
-                  compatible = \"riscv\";
-                  riscv,isa = \"rv32imac\";
-                  mmu-type = \"riscv,sv32\";
-                  riscv,pmpregions = <16>;
-                  riscv,pmpgranularity = <4>;
-                  clock-frequency = <1000000000>;
-                  CPU0_intc: interrupt-controller {{
-                    #address-cells = <2>;
-                    #interrupt-cells = <1>;
-                    interrupt-controller;
-                    compatible = \"riscv,cpu-intc\";
-                  }};
-                }};
+                {cpus}
               }};
+              {PMU_NODE}
               memory@{dram_addr:x} {{
                 device_type = \"memory\";
-                reg = <0x0 {dram_addr:#x} 0x0 0x80000000>;
+                reg = <{dram_reg} {dram_size}>;
               }};
               soc {{
                 #address-cells = <2>;
                 #size-cells = <2>;
                 compatible = \"ucbbar,spike-bare-soc\", \"simple-bus\";
                 ranges;
-                clint@2000000 {{
+                clint@{clint_base:x} {{
                   compatible = \"riscv,clint0\";
-                  interrupts-extended = <&CPU0_intc 3 &CPU0_intc 7 >;
-                  reg = <0x0 0x2000000 0x0 0xc0000>;
+                  interrupts-extended = <{clint_irqs}>;
+                  reg = <{clint_reg} 0x0 {CLINT_SIZE:#x}>;
                 }};
-                PLIC: plic@c000000 {{
+                PLIC: plic@{plic_base:x} {{
                   compatible = \"riscv,plic0\";
                   #address-cells = <2>;
-                  interrupts-extended = <&CPU0_intc 11 &CPU0_intc 9 >;
-                  reg = <0x0 0xc000000 0x0 0x1000000>;
+                  interrupts-extended = <{plic_irqs}>;
+                  reg = <{plic_reg} 0x0 {PLIC_SIZE:#x}>;
                   riscv,ndev = <0x1f>;
                   riscv,max-priority = <0xf>;
                   #interrupt-cells = <1>;
                   interrupt-controller;
                 }};
-                SERIAL0: ns16550@10000000 {{
+                SERIAL0: ns16550@{uart_base:x} {{
                   compatible = \"ns16550a\";
                   clock-frequency = <10000000>;
                   interrupt-parent = <&PLIC>;
-                  interrupts = <1>;
-                  reg = <0x0 0x10000000 0x0 0x100>;
+                  interrupts = <{uart_irq}>;
+                  reg = <{uart_reg} 0x0 {UART_SIZE:#x}>;
                   reg-shift = <0x0>;
                   reg-io-width = <0x1>;
                 }};
+                {virtio}
+                {nodes}
               }};
               htif {{
                 compatible = \"ucb,htif0\";
@@ -79,9 +168,28 @@ fn dts_32(dram_addr: u64, initrd_start: Option<usize>, initrd_end: Option<usize>
     )
 }
 
-fn dts_64(dram_addr: u64, initrd_start: Option<usize>, initrd_end: Option<usize>) -> String {
+fn dts_64(
+    machine: &MachineConfig,
+    dram_addr: u64,
+    initrd_start: Option<usize>,
+    initrd_end: Option<usize>,
+    trans_mode: AddrTransMode,
+    riscv_isa: &str,
+    nodes: &str,
+) -> String {
+    let harts = machine.harts;
+    let bootargs = bootargs(machine, initrd_start);
     let initrd_start = initrd_start.unwrap_or(0);
     let initrd_end = initrd_end.unwrap_or(0);
+    let cpus = cpu_nodes(harts, riscv_isa, trans_mode.name());
+    let clint_irqs = interrupts_extended(harts, &[3, 7]);
+    let plic_irqs = interrupts_extended(harts, &[11, 9]);
+    let (dram_reg, dram_size) = (cells(dram_addr), cells(machine.dram_size));
+    let (clint_base, clint_reg) = (machine.clint_base, cells(machine.clint_base));
+    let (plic_base, plic_reg) = (machine.plic_base, cells(machine.plic_base));
+    let (uart_base, uart_reg) = (machine.uart_base, cells(machine.uart_base));
+    let uart_irq = machine.uart_irq;
+    let virtio = virtio_nodes(machine);
     format!(
         "/dts-v1/;
 
@@ -94,63 +202,50 @@ fn dts_64(dram_addr: u64, initrd_start: Option<usize>, initrd_end: Option<usize>
             stdout-path = &SERIAL0;
             linux,initrd-start = <{initrd_start}>;
             linux,initrd-end = <{initrd_end}>;
-            bootargs = \"root=/dev/ram console=ttyS0 earlycon\";
+            bootargs = \"{bootargs}\";
           }};
           cpus {{
             #address-cells = <1>;
             #size-cells = <0>;
             timebase-frequency = <10000000>;
-            CPU0: cpu@0 {{
-              device_type = \"cpu\";
-              reg = <0>;
-              status = \"okay\";
-              compatible = \"riscv\";
-              riscv,isa = \"rv64imac\";
-              mmu-type = \"riscv,sv57\";
-              riscv,pmpregions = <16>;
-              riscv,pmpgranularity = <4>;
-              clock-frequency = <1000000000>;
-              CPU0_intc: interrupt-controller {{
-                #address-cells = <2>;
-                #interrupt-cells = <1>;
-                interrupt-controller;
-                compatible = \"riscv,cpu-intc\";
-              }};
-            }};
+            {cpus}
           }};
+          {PMU_NODE}
           memory@{dram_addr:x} {{
             device_type = \"memory\";
-            reg = <0x0 {dram_addr:#x} 0x0 0x80000000>;
+            reg = <{dram_reg} {dram_size}>;
           }};
           soc {{
             #address-cells = <2>;
             #size-cells = <2>;
             compatible = \"ucbbar,spike-bare-soc\", \"simple-bus\";
             ranges;
-            clint@2000000 {{
+            clint@{clint_base:x} {{
               compatible = \"riscv,clint0\";
-              interrupts-extended = <&CPU0_intc 3 &CPU0_intc 7 >;
-              reg = <0x0 0x2000000 0x0 0xc0000>;
+              interrupts-extended = <{clint_irqs}>;
+              reg = <{clint_reg} 0x0 {CLINT_SIZE:#x}>;
             }};
-            PLIC: plic@c000000 {{
+            PLIC: plic@{plic_base:x} {{
               compatible = \"riscv,plic0\";
               #address-cells = <2>;
-              interrupts-extended = <&CPU0_intc 11 &CPU0_intc 9 >;
-              reg = <0x0 0xc000000 0x0 0x1000000>;
+              interrupts-extended = <{plic_irqs}>;
+              reg = <{plic_reg} 0x0 {PLIC_SIZE:#x}>;
               riscv,ndev = <0x1f>;
               riscv,max-priority = <0xf>;
               #interrupt-cells = <1>;
               interrupt-controller;
             }};
-            SERIAL0: ns16550@10000000 {{
+            SERIAL0: ns16550@{uart_base:x} {{
               compatible = \"ns16550a\";
               clock-frequency = <10000000>;
               interrupt-parent = <&PLIC>;
-              interrupts = <1>;
-              reg = <0x0 0x10000000 0x0 0x100>;
+              interrupts = <{uart_irq}>;
+              reg = <{uart_reg} 0x0 {UART_SIZE:#x}>;
               reg-shift = <0x0>;
               reg-io-width = <0x1>;
             }};
+            {virtio}
+            {nodes}
           }};
           htif {{
             compatible = \"ucb,htif0\";
@@ -159,14 +254,34 @@ fn dts_64(dram_addr: u64, initrd_start: Option<usize>, initrd_end: Option<usize>
     )
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn make_dts(
+    machine: &MachineConfig,
     dram_addr: u64,
     initrd_start: Option<usize>,
     initrd_end: Option<usize>,
     isa: Isa,
+    trans_mode: AddrTransMode,
+    riscv_isa: &str,
+    nodes: &str,
 ) -> String {
     match isa {
-        Isa::Rv32 => dts_32(dram_addr, initrd_start, initrd_end),
-        Isa::Rv64 => dts_64(dram_addr, initrd_start, initrd_end),
+        Isa::Rv32 => dts_32(
+            machine,
+            dram_addr,
+            initrd_start,
+            initrd_end,
+            riscv_isa,
+            nodes,
+        ),
+        Isa::Rv64 => dts_64(
+            machine,
+            dram_addr,
+            initrd_start,
+            initrd_end,
+            trans_mode,
+            riscv_isa,
+            nodes,
+        ),
     }
 }
@@ -0,0 +1,73 @@
+use super::dram::Dram;
+use super::reservation::Reservations;
+use crate::cpu::InstCache;
+
+// guest memory as seen by a bus-mastering device or the page table walker;
+// writes have the same side effects on cached instructions and LR/SC
+// reservations as a store from a hart
+pub struct Dma<'a> {
+    dram: &'a mut Dram,
+    icache: &'a mut InstCache,
+    reservations: &'a mut Reservations,
+}
+
+impl<'a> Dma<'a> {
+    pub(super) fn new(
+        dram: &'a mut Dram,
+        icache: &'a mut InstCache,
+        reservations: &'a mut Reservations,
+    ) -> Self {
+        Dma {
+            dram,
+            icache,
+            reservations,
+        }
+    }
+
+    pub fn contains(&self, addr: u64, len: usize) -> bool {
+        self.dram.contains_range(addr, len)
+    }
+
+    pub fn read(&self, addr: u64, buf: &mut [u8]) -> Result<(), String> {
+        self.dram.read_bytes(addr, buf)
+    }
+
+    pub fn write(&mut self, addr: u64, data: &[u8]) -> Result<(), String> {
+        self.dram.write_bytes(addr, data)?;
+        if !data.is_empty() {
+            self.icache.invalidate(addr, data.len() as u64);
+            self.reservations.invalidate(addr, data.len() as u64);
+        }
+        Ok(())
+    }
+
+    pub fn read_u16(&self, addr: u64) -> Result<u16, String> {
+        let mut buf = [0; 2];
+        self.read(addr, &mut buf)?;
+        Ok(u16::from_le_bytes(buf))
+    }
+
+    pub fn read_u32(&self, addr: u64) -> Result<u32, String> {
+        let mut buf = [0; 4];
+        self.read(addr, &mut buf)?;
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    pub fn read_u64(&self, addr: u64) -> Result<u64, String> {
+        let mut buf = [0; 8];
+        self.read(addr, &mut buf)?;
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    pub fn write_u16(&mut self, addr: u64, data: u16) -> Result<(), String> {
+        self.write(addr, &data.to_le_bytes())
+    }
+
+    pub fn write_u32(&mut self, addr: u64, data: u32) -> Result<(), String> {
+        self.write(addr, &data.to_le_bytes())
+    }
+
+    pub fn write_u64(&mut self, addr: u64, data: u64) -> Result<(), String> {
+        self.write(addr, &data.to_le_bytes())
+    }
+}
@@ -1,11 +1,16 @@
+mod sparse;
+
 use memmap::Mmap;
 use std::fs::File;
 
 use super::Device;
+use crate::snapshot::{self, Reader, Snapshot, Writer};
 use crate::{elfload, Arguments, Isa, TrapCause};
+use sparse::SparseMemory;
+use std::io;
 
 pub struct Dram {
-    dram: Vec<u8>,
+    dram: SparseMemory,
     pub base_addr: u64,
     size: usize,
     pub initrd_start: Option<usize>,
@@ -14,28 +19,36 @@ pub struct Dram {
 
 impl Dram {
     pub fn new(loader: elfload::ElfLoader, args: &Arguments, isa: Isa) -> Self {
-        const DRAM_SIZE: usize = 1024 * 1024 * 1024 * 2; // 2^27
-        let virt_entry = loader.get_entry_point().expect("entry point not found.");
+        let dram_size = args.machine.dram_size as usize;
+        let base_addr = args
+            .machine
+            .dram_base
+            .unwrap_or_else(|| loader.get_entry_point().expect("entry point not found."));
 
         // create new dram
-        let mut new_dram = vec![0; DRAM_SIZE];
+        let mut new_dram = match &args.machine.dram_image {
+            Some(path) => SparseMemory::with_image(dram_size, path)
+                .unwrap_or_else(|e| panic!("mapping the DRAM image failed: {e}")),
+            None => SparseMemory::new(dram_size),
+        };
 
         // load elf memory mapping
         for segment in loader.prog_headers.iter() {
             if segment.is_loadable() {
                 let (offset, paddr) = segment.offset_and_addr();
-                let dram_start = (paddr - virt_entry) as usize;
+                let dram_start = paddr
+                    .checked_sub(base_addr)
+                    .filter(|&start| start + segment.p_filesz() <= dram_size as u64)
+                    .unwrap_or_else(|| panic!("segment at {paddr:#x} is outside of DRAM"))
+                    as usize;
                 let mmap_start = (offset) as usize;
-                let dram_end = dram_start + segment.p_filesz() as usize;
                 let mmap_end = (offset + segment.p_filesz()) as usize;
 
-                new_dram.splice(
-                    dram_start..dram_end,
-                    loader.mem_data[mmap_start..mmap_end].iter().cloned(),
-                );
+                new_dram.write(dram_start, &loader.mem_data[mmap_start..mmap_end]);
             }
         }
 
+        let mut kernel_end = 0;
         if let Some(path) = &args.kernel_path {
             let file = File::open(path).unwrap();
             let mapped_kernel = unsafe { Mmap::map(&file).unwrap() };
@@ -43,10 +56,14 @@ impl Dram {
                 Isa::Rv32 => 0x400000,
                 Isa::Rv64 => 0x200000,
             };
-            new_dram.splice(
-                kernel_offset..kernel_offset + mapped_kernel.len(),
-                mapped_kernel.iter().cloned(),
-            );
+            kernel_end = kernel_offset + mapped_kernel.len();
+            if kernel_end > dram_size {
+                panic!(
+                    "the kernel ({:#x} bytes) doesn't fit in DRAM above {kernel_offset:#x}",
+                    mapped_kernel.len()
+                );
+            }
+            new_dram.write(kernel_offset, &mapped_kernel);
         }
 
         let mut initrd_start: Option<usize> = None;
@@ -55,33 +72,137 @@ impl Dram {
             let file = File::open(path).unwrap();
             let mapped_initrd = unsafe { Mmap::map(&file).unwrap() };
 
-            const INITRD_END: usize = 0x8000_0000 + DRAM_SIZE - 0x1000;
-            let initrd_head = INITRD_END - mapped_initrd.len();
-            let initrd_offset = initrd_head - virt_entry as usize;
-            initrd_start = Some(INITRD_END - mapped_initrd.len());
-            initrd_end = Some(INITRD_END);
-            new_dram.splice(
-                initrd_offset..initrd_offset + mapped_initrd.len(),
-                mapped_initrd.iter().cloned(),
-            );
+            // at the top of DRAM, below the last page
+            let initrd_offset = dram_size
+                .checked_sub(0x1000 + mapped_initrd.len())
+                .filter(|&offset| offset >= kernel_end)
+                .unwrap_or_else(|| {
+                    panic!(
+                        "the initrd ({:#x} bytes) doesn't fit in DRAM above the kernel",
+                        mapped_initrd.len()
+                    )
+                });
+            let initrd_head = base_addr as usize + initrd_offset;
+            initrd_start = Some(initrd_head);
+            initrd_end = Some(initrd_head + mapped_initrd.len());
+            new_dram.write(initrd_offset, &mapped_initrd);
         }
 
-        let dram_size = new_dram.len();
         Dram {
             dram: new_dram,
-            base_addr: virt_entry,
+            base_addr,
             size: dram_size,
             initrd_start,
             initrd_end,
         }
     }
+
+    fn offset_of(&self, addr: u64, len: usize) -> Option<usize> {
+        addr.checked_sub(self.base_addr)
+            .map(|offset| offset as usize)
+            .filter(|offset| offset.checked_add(len).is_some_and(|end| end <= self.size))
+    }
+
+    // every byte of an access has to be in DRAM
+    fn load(&self, addr: u64, buf: &mut [u8]) -> Result<(), (Option<u64>, TrapCause, String)> {
+        let offset = self.offset_of(addr, buf.len()).ok_or((
+            Some(addr),
+            TrapCause::LoadAccessFault,
+            format!("load{} runs past the end of DRAM: {addr:#x}", buf.len() * 8),
+        ))?;
+        self.dram.read(offset, buf);
+        Ok(())
+    }
+
+    fn store(&mut self, addr: u64, data: &[u8]) -> Result<(), (Option<u64>, TrapCause, String)> {
+        let offset = self.offset_of(addr, data.len()).ok_or((
+            Some(addr),
+            TrapCause::StoreAMOAccessFault,
+            format!(
+                "store{} runs past the end of DRAM: {addr:#x}",
+                data.len() * 8
+            ),
+        ))?;
+        self.dram.write(offset, data);
+        Ok(())
+    }
+
+    pub fn contains_range(&self, addr: u64, len: usize) -> bool {
+        self.offset_of(addr, len).is_some()
+    }
+
+    // bulk access for bus-mastering devices; fails if any byte is outside of DRAM
+    pub fn read_bytes(&self, addr: u64, buf: &mut [u8]) -> Result<(), String> {
+        let offset = self
+            .offset_of(addr, buf.len())
+            .ok_or(format!("DMA read from {addr:#x} is outside of DRAM"))?;
+        self.dram.read(offset, buf);
+        Ok(())
+    }
+
+    pub fn write_bytes(&mut self, addr: u64, data: &[u8]) -> Result<(), String> {
+        let offset = self
+            .offset_of(addr, data.len())
+            .ok_or(format!("DMA write to {addr:#x} is outside of DRAM"))?;
+        self.dram.write(offset, data);
+        Ok(())
+    }
+}
+
+// only the pages written since boot are saved; the others come from the zero fill or the image
+impl Snapshot for Dram {
+    fn save(&self, w: &mut Writer) -> io::Result<()> {
+        w.tag(b"DRAM")?;
+        w.u64(self.base_addr)?;
+        w.u64(self.size as u64)?;
+        w.u64(self.dram.image_len() as u64)?;
+        for (index, page) in self.dram.dirty_pages() {
+            w.u64(index as u64)?;
+            w.bytes(page)?;
+        }
+        w.u64(u64::MAX)
+    }
+
+    fn restore(&mut self, r: &mut Reader) -> io::Result<()> {
+        r.tag(b"DRAM")?;
+        let (base_addr, size) = (r.u64()?, r.u64()? as usize);
+        if base_addr != self.base_addr || size != self.size {
+            return Err(snapshot::invalid_data(format!(
+                "the snapshot has DRAM at {base_addr:#x} ({size:#x} bytes) \
+                 but the machine has it at {:#x} ({:#x} bytes)",
+                self.base_addr, self.size
+            )));
+        }
+        let image_len = r.u64()? as usize;
+        if image_len != self.dram.image_len() {
+            return Err(snapshot::invalid_data(format!(
+                "the snapshot was taken with a {image_len:#x} bytes DRAM image \
+                 but the machine has {:#x} bytes",
+                self.dram.image_len()
+            )));
+        }
+
+        self.dram.discard_writes();
+        loop {
+            match r.u64()? {
+                u64::MAX => return Ok(()),
+                index if (index as usize) < self.dram.page_num() => {
+                    r.bytes_into(self.dram.dirty_page_mut(index as usize))?
+                }
+                index => {
+                    return Err(snapshot::invalid_data(format!(
+                        "DRAM page {index} is out of range"
+                    )))
+                }
+            }
+        }
+    }
 }
 
-#[allow(clippy::identity_op)]
 impl Device for Dram {
     // is addr in device address space
     fn in_range(&self, addr: u64) -> bool {
-        (self.base_addr..=self.base_addr + self.size as u64).contains(&addr)
+        (self.base_addr..self.base_addr + self.size as u64).contains(&addr)
     }
 
     // address to raw index
@@ -91,87 +212,56 @@ impl Device for Dram {
 
     // store
     fn store8(&mut self, addr: u64, data: u64) -> Result<(), (Option<u64>, TrapCause, String)> {
-        let index = self.addr2index(addr);
-        self.dram[index] = (data & 0xFF) as u8;
-        Ok(())
+        self.store(addr, &(data as u8).to_le_bytes())
     }
 
     fn store16(&mut self, addr: u64, data: u64) -> Result<(), (Option<u64>, TrapCause, String)> {
-        let index = self.addr2index(addr);
-        self.dram[index + 1] = ((data >> 8) & 0xFF) as u8;
-        self.dram[index + 0] = ((data >> 0) & 0xFF) as u8;
-        Ok(())
+        self.store(addr, &(data as u16).to_le_bytes())
     }
 
     fn store32(&mut self, addr: u64, data: u64) -> Result<(), (Option<u64>, TrapCause, String)> {
-        let index = self.addr2index(addr);
-        self.dram[index + 3] = ((data >> 24) & 0xFF) as u8;
-        self.dram[index + 2] = ((data >> 16) & 0xFF) as u8;
-        self.dram[index + 1] = ((data >> 8) & 0xFF) as u8;
-        self.dram[index + 0] = ((data >> 0) & 0xFF) as u8;
-        Ok(())
+        self.store(addr, &(data as u32).to_le_bytes())
     }
 
     fn store64(&mut self, addr: u64, data: u64) -> Result<(), (Option<u64>, TrapCause, String)> {
-        let index = self.addr2index(addr);
-        self.dram[index + 7] = ((data >> 56) & 0xFF) as u8;
-        self.dram[index + 6] = ((data >> 48) & 0xFF) as u8;
-        self.dram[index + 5] = ((data >> 40) & 0xFF) as u8;
-        self.dram[index + 4] = ((data >> 32) & 0xFF) as u8;
-        self.dram[index + 3] = ((data >> 24) & 0xFF) as u8;
-        self.dram[index + 2] = ((data >> 16) & 0xFF) as u8;
-        self.dram[index + 1] = ((data >> 8) & 0xFF) as u8;
-        self.dram[index + 0] = ((data >> 0) & 0xFF) as u8;
-        Ok(())
+        self.store(addr, &data.to_le_bytes())
     }
 
     // load
     fn load8(&mut self, addr: u64) -> Result<u64, (Option<u64>, TrapCause, String)> {
-        let index = self.addr2index(addr);
-        Ok(self.dram[index] as i8 as i64 as u64)
+        Ok(self.load_u8(addr)? as i8 as i64 as u64)
     }
 
     fn load16(&self, addr: u64) -> Result<u64, (Option<u64>, TrapCause, String)> {
-        let index = self.addr2index(addr);
-        Ok(((self.dram[index + 1] as i16) << 8 | (self.dram[index + 0] as i16)) as i64 as u64)
+        Ok(self.load_u16(addr)? as i16 as i64 as u64)
     }
 
     fn load32(&mut self, addr: u64) -> Result<u64, (Option<u64>, TrapCause, String)> {
-        let index = self.addr2index(addr);
-        Ok(((self.dram[index + 3] as i32) << 24
-            | (self.dram[index + 2] as i32) << 16
-            | (self.dram[index + 1] as i32) << 8
-            | (self.dram[index + 0] as i32)) as i64 as u64)
+        Ok(self.load_u32(addr)? as i32 as i64 as u64)
     }
 
     fn load64(&mut self, addr: u64) -> Result<u64, (Option<u64>, TrapCause, String)> {
-        let index = self.addr2index(addr);
-        Ok((self.dram[index + 7] as u64) << 56
-            | (self.dram[index + 6] as u64) << 48
-            | (self.dram[index + 5] as u64) << 40
-            | (self.dram[index + 4] as u64) << 32
-            | (self.dram[index + 3] as u64) << 24
-            | (self.dram[index + 2] as u64) << 16
-            | (self.dram[index + 1] as u64) << 8
-            | (self.dram[index + 0] as u64))
+        let mut buf = [0; 8];
+        self.load(addr, &mut buf)?;
+        Ok(u64::from_le_bytes(buf))
     }
 
     fn load_u8(&self, addr: u64) -> Result<u64, (Option<u64>, TrapCause, String)> {
-        let index = self.addr2index(addr);
-        Ok(self.dram[index] as u64)
+        let mut buf = [0; 1];
+        self.load(addr, &mut buf)?;
+        Ok(buf[0] as u64)
     }
 
     fn load_u16(&self, addr: u64) -> Result<u64, (Option<u64>, TrapCause, String)> {
-        let index = self.addr2index(addr);
-        Ok(((self.dram[index + 1] as u16) << 8 | (self.dram[index + 0] as u16)) as u64)
+        let mut buf = [0; 2];
+        self.load(addr, &mut buf)?;
+        Ok(u16::from_le_bytes(buf) as u64)
     }
 
     fn load_u32(&self, addr: u64) -> Result<u64, (Option<u64>, TrapCause, String)> {
-        let index = self.addr2index(addr);
-        Ok(((self.dram[index + 3] as u32) << 24
-            | (self.dram[index + 2] as u32) << 16
-            | (self.dram[index + 1] as u32) << 8
-            | (self.dram[index + 0] as u32)) as u64)
+        let mut buf = [0; 4];
+        self.load(addr, &mut buf)?;
+        Ok(u32::from_le_bytes(buf) as u64)
     }
 }
 
@@ -183,7 +273,7 @@ mod tests {
     #[test]
     fn load_store_u8_test() {
         let dram = &mut Dram {
-            dram: vec![0; DRAM_SIZE],
+            dram: SparseMemory::new(DRAM_SIZE),
             base_addr: 0,
             size: DRAM_SIZE,
             initrd_start: None,
@@ -210,7 +300,7 @@ mod tests {
     #[test]
     fn load_store_8_test() {
         let dram = &mut Dram {
-            dram: vec![0; DRAM_SIZE],
+            dram: SparseMemory::new(DRAM_SIZE),
             base_addr: 0,
             size: DRAM_SIZE,
             initrd_start: None,
@@ -235,7 +325,7 @@ mod tests {
     #[test]
     fn load_store_16_test() {
         let dram = &mut Dram {
-            dram: vec![0; DRAM_SIZE],
+            dram: SparseMemory::new(DRAM_SIZE),
             base_addr: 0,
             size: DRAM_SIZE,
             initrd_start: None,
@@ -261,7 +351,7 @@ mod tests {
     #[test]
     fn load_store_u16_test() {
         let dram = &mut Dram {
-            dram: vec![0; DRAM_SIZE],
+            dram: SparseMemory::new(DRAM_SIZE),
             base_addr: 0,
             size: DRAM_SIZE,
             initrd_start: None,
@@ -290,7 +380,7 @@ mod tests {
     #[allow(overflowing_literals)]
     fn load_store_32_test() {
         let dram = &mut Dram {
-            dram: vec![0; DRAM_SIZE],
+            dram: SparseMemory::new(DRAM_SIZE),
             base_addr: 0,
             size: DRAM_SIZE,
             initrd_start: None,
@@ -310,4 +400,30 @@ mod tests {
         test_32(0b1000000010000000);
         test_32(0b10000000100000001000000010000000);
     }
+
+    #[test]
+    fn end_of_dram_test() {
+        let dram = &mut Dram {
+            dram: SparseMemory::new(DRAM_SIZE),
+            base_addr: 0x8000_0000,
+            size: DRAM_SIZE,
+            initrd_start: None,
+            initrd_end: None,
+        };
+        let end = 0x8000_0000 + DRAM_SIZE as u64;
+        assert!(dram.in_range(end - 1));
+        assert!(!dram.in_range(end));
+
+        // an access that runs past the end faults instead of indexing a missing page
+        Dram::store32(dram, end - 4, 0x1234).unwrap();
+        assert_eq!(Dram::load_u32(dram, end - 4).unwrap(), 0x1234);
+        assert!(matches!(
+            Dram::store64(dram, end - 4, 0),
+            Err((_, TrapCause::StoreAMOAccessFault, _))
+        ));
+        assert!(matches!(
+            Dram::load64(dram, end - 4),
+            Err((_, TrapCause::LoadAccessFault, _))
+        ));
+    }
 }
@@ -0,0 +1,158 @@
+use memmap::Mmap;
+use std::fs::File;
+use std::io;
+
+pub const PAGE_SIZE: usize = 4096;
+const ZERO_PAGE: [u8; PAGE_SIZE] = [0; PAGE_SIZE];
+
+type Page = Box<[u8; PAGE_SIZE]>;
+
+// pages are allocated on the first write; untouched pages read as zero or
+// from the backing image, which is never written (copy-on-write)
+pub struct SparseMemory {
+    pages: Vec<Option<Page>>,
+    image: Option<Mmap>,
+    size: usize,
+}
+
+impl SparseMemory {
+    pub fn new(size: usize) -> Self {
+        SparseMemory {
+            pages: (0..size.div_ceil(PAGE_SIZE)).map(|_| None).collect(),
+            image: None,
+            size,
+        }
+    }
+
+    pub fn with_image(size: usize, path: &str) -> io::Result<Self> {
+        let image = unsafe { Mmap::map(&File::open(path)?)? };
+        if image.len() > size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{path} is larger than DRAM ({size:#x} bytes)"),
+            ));
+        }
+
+        Ok(SparseMemory {
+            image: Some(image),
+            ..SparseMemory::new(size)
+        })
+    }
+
+    pub fn image_len(&self) -> usize {
+        self.image.as_ref().map_or(0, |image| image.len())
+    }
+
+    pub fn page_num(&self) -> usize {
+        self.pages.len()
+    }
+
+    // the last page is cut short when the size is not page aligned
+    fn page_len(&self, index: usize) -> usize {
+        PAGE_SIZE.min(self.size - index * PAGE_SIZE)
+    }
+
+    // the current contents of a page (shorter than PAGE_SIZE at the end of an image)
+    fn page(&self, index: usize) -> &[u8] {
+        match (&self.pages[index], &self.image) {
+            (Some(page), _) => &page[..],
+            (None, Some(image)) if index * PAGE_SIZE < image.len() => {
+                &image[index * PAGE_SIZE..image.len().min((index + 1) * PAGE_SIZE)]
+            }
+            _ => &ZERO_PAGE,
+        }
+    }
+
+    fn page_mut(&mut self, index: usize) -> &mut [u8; PAGE_SIZE] {
+        if self.pages[index].is_none() {
+            let mut page = Box::new(ZERO_PAGE);
+            let orig = self.page(index);
+            page[..orig.len()].copy_from_slice(orig);
+            self.pages[index] = Some(page);
+        }
+        self.pages[index].as_mut().unwrap()
+    }
+
+    pub fn read(&self, offset: usize, buf: &mut [u8]) {
+        let mut done = 0;
+        while done < buf.len() {
+            let (index, off) = ((offset + done) / PAGE_SIZE, (offset + done) % PAGE_SIZE);
+            let len = (buf.len() - done).min(PAGE_SIZE - off);
+            let page = self.page(index);
+            let avail = page.len().saturating_sub(off).min(len);
+            buf[done..done + avail].copy_from_slice(&page[off..off + avail]);
+            buf[done + avail..done + len].fill(0);
+            done += len;
+        }
+    }
+
+    pub fn write(&mut self, offset: usize, data: &[u8]) {
+        let mut done = 0;
+        while done < data.len() {
+            let (index, off) = ((offset + done) / PAGE_SIZE, (offset + done) % PAGE_SIZE);
+            let len = (data.len() - done).min(PAGE_SIZE - off);
+            self.page_mut(index)[off..off + len].copy_from_slice(&data[done..done + len]);
+            done += len;
+        }
+    }
+
+    // pages that differ from the zero fill or the image
+    pub fn dirty_pages(&self) -> impl Iterator<Item = (usize, &[u8])> {
+        self.pages.iter().enumerate().filter_map(|(index, page)| {
+            page.as_ref()
+                .map(|page| (index, &page[..self.page_len(index)]))
+        })
+    }
+
+    pub fn dirty_page_mut(&mut self, index: usize) -> &mut [u8] {
+        let len = self.page_len(index);
+        &mut self.page_mut(index)[..len]
+    }
+
+    // forget every write so that all pages read from the zero fill or the image again
+    pub fn discard_writes(&mut self) {
+        self.pages.iter_mut().for_each(|page| *page = None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn copy_on_write_test() {
+        let path = std::env::temp_dir().join(format!("carron-image-{}", std::process::id()));
+        File::create(&path)
+            .unwrap()
+            .write_all(&[0xaa; PAGE_SIZE + 2])
+            .unwrap();
+        let path = path.to_str().unwrap().to_string();
+
+        let mut mem = SparseMemory::with_image(PAGE_SIZE * 4, &path).unwrap();
+        let mut buf = [0; 4];
+        mem.read(PAGE_SIZE - 2, &mut buf);
+        assert_eq!(buf, [0xaa; 4]);
+        mem.read(PAGE_SIZE, &mut buf);
+        assert_eq!(buf, [0xaa, 0xaa, 0, 0]);
+        assert_eq!(mem.dirty_pages().count(), 0);
+
+        // a write across the page boundary copies both pages
+        mem.write(PAGE_SIZE - 1, &[1, 2]);
+        mem.read(PAGE_SIZE - 2, &mut buf);
+        assert_eq!(buf, [0xaa, 1, 2, 0xaa]);
+        assert_eq!(
+            mem.dirty_pages()
+                .map(|(index, _)| index)
+                .collect::<Vec<_>>(),
+            vec![0, 1]
+        );
+
+        // the image itself is left untouched
+        mem.discard_writes();
+        mem.read(PAGE_SIZE - 2, &mut buf);
+        assert_eq!(buf, [0xaa; 4]);
+        assert_eq!(std::fs::read(&path).unwrap()[PAGE_SIZE], 0xaa);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,39 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+// an interrupt source wired into the PLIC; the device drives the level and
+// the bus forwards changes to the PLIC after each access and tick
+#[derive(Clone)]
+pub struct IrqLine {
+    id: u32,
+    level: Rc<Cell<bool>>,
+}
+
+impl IrqLine {
+    pub(super) fn new(id: u32) -> Self {
+        IrqLine {
+            id,
+            level: Rc::new(Cell::new(false)),
+        }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn is_raised(&self) -> bool {
+        self.level.get()
+    }
+
+    pub fn set_level(&self, level: bool) {
+        self.level.set(level);
+    }
+
+    pub fn raise(&self) {
+        self.set_level(true);
+    }
+
+    pub fn lower(&self) {
+        self.set_level(false);
+    }
+}
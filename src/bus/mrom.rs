@@ -8,8 +8,10 @@ pub struct Mrom {
 }
 
 impl Mrom {
+    pub const DTB_OFFSET: usize = 32;
+
     #[allow(arithmetic_overflow)]
-    pub fn new(entry_point: u64, isa: Isa) -> Self {
+    pub fn new(base_addr: u64, entry_point: u64, isa: Isa) -> Self {
         let entry_upper = (entry_point >> 32) as u32;
         let entry_lower = (entry_point & 0xffffffff) as u32;
         let reset_vector: Vec<u32> = vec![
@@ -35,7 +37,7 @@ impl Mrom {
         let mrom_size = mrom.len();
         Mrom {
             mrom,
-            base_addr: 0x1000,
+            base_addr,
             size: mrom_size,
         }
     }
@@ -1,6 +1,9 @@
 use super::Device;
 use crate::cpu::PrivilegedLevel;
+use crate::machine::PLIC_SIZE;
+use crate::snapshot::{self, Reader, Snapshot, Writer};
 use crate::TrapCause;
+use std::io;
 
 const PRIORITY_BASE: usize = 0x0;
 const ENABLE_BASE: usize = 0x2000;
@@ -10,12 +13,16 @@ const CONTEXT_PER_HART: usize = 0x1000;
 const CONTEXT_THRESHOLD: usize = 0x0;
 const CONTEXT_CLAIM: usize = 0x4;
 
-const PLIC_SIZE: usize = 0x100_0000;
 const PLIC_MAX_DEVICES: usize = 1024;
 const NDEV: usize = 0x1f;
-const NUM_IDS: usize = NDEV + 1;
-const NUM_IDS_WORD: usize = ((NDEV + 1) + (32 - 1)) / 32;
-const CONTEXT_NUM: usize = 2;
+pub const NUM_IDS: usize = NDEV + 1;
+const NUM_IDS_WORD: usize = (NDEV + 1).div_ceil(32);
+const CONTEXTS_PER_HART: usize = 2;
+
+const MIP_SEIP: u64 = 1 << 9;
+const MIP_MEIP: u64 = 1 << 11;
+// the bits of mip a hart's contexts drive
+pub const MIP_EIP: u64 = MIP_MEIP | MIP_SEIP;
 
 pub struct PlicContext {
     priority_thresould: u8,
@@ -43,32 +50,32 @@ pub struct Plic {
     priority: Vec<u8>,
     level: Vec<u32>,
     contexts: Vec<PlicContext>,
-    pub mip_mask: u64,
-    pub mip_value: u64,
+    // MEIP and SEIP of each hart, indexed by hart id
+    pub mip: Vec<u64>,
     pub base_addr: u64,
     size: usize,
 }
 
-impl Default for Plic {
-    fn default() -> Self {
-        Self::new()
-    }
-}
-
 impl Plic {
     #[allow(arithmetic_overflow)]
-    pub fn new() -> Self {
+    pub fn new(base_addr: u64, harts: usize) -> Self {
+        // each hart has a machine and a supervisor context
+        let contexts = (0..harts)
+            .flat_map(|_| {
+                [
+                    PlicContext::new(PrivilegedLevel::Machine),
+                    PlicContext::new(PrivilegedLevel::Supervisor),
+                ]
+            })
+            .collect();
+
         Plic {
             priority: vec![0; PLIC_MAX_DEVICES],
             level: vec![0; PLIC_MAX_DEVICES],
-            contexts: vec![
-                PlicContext::new(PrivilegedLevel::Machine),
-                PlicContext::new(PrivilegedLevel::Supervisor),
-            ],
-            mip_mask: 0,
-            mip_value: 0,
-            base_addr: 0x0c00_0000,
-            size: PLIC_SIZE,
+            contexts,
+            mip: vec![0; harts],
+            base_addr,
+            size: PLIC_SIZE as usize,
         }
     }
 
@@ -100,24 +107,20 @@ impl Plic {
         best_id
     }
 
+    // each context drives its own bit, so the other context's is kept
     fn context_update(&mut self, context_id: usize) {
-        const MIP_MEIP_MASK: u64 = 1 << 11;
-        const MIP_SEIP_MASK: u64 = 1 << 9;
         let best_id = self.context_best_pending(context_id);
-        self.mip_mask = match self.contexts[context_id].context_priv {
-            PrivilegedLevel::Machine => MIP_MEIP_MASK,
-            PrivilegedLevel::Supervisor => MIP_SEIP_MASK,
+        let hartid = context_id / CONTEXTS_PER_HART;
+        let bit = match self.contexts[context_id].context_priv {
+            PrivilegedLevel::Machine => MIP_MEIP,
+            PrivilegedLevel::Supervisor => MIP_SEIP,
             _ => unreachable!(),
         };
-        self.mip_value = if best_id == 0 {
-            0
+        if best_id == 0 {
+            self.mip[hartid] &= !bit;
         } else {
-            match self.contexts[context_id].context_priv {
-                PrivilegedLevel::Machine => MIP_MEIP_MASK,
-                PrivilegedLevel::Supervisor => MIP_SEIP_MASK,
-                _ => unreachable!(),
-            }
-        };
+            self.mip[hartid] |= bit;
+        }
     }
 
     fn context_claim(&mut self, context_id: usize) -> u32 {
@@ -224,6 +227,10 @@ impl Plic {
         }
     }
 
+    pub fn interrupt_level(&self, id: u32) -> bool {
+        self.level[(id / 32) as usize] & (1 << (id % 32)) != 0
+    }
+
     pub fn set_interrupt_level(&mut self, id: u32, level: u32) {
         if id == 0 || NUM_IDS as u32 <= id {
             return;
@@ -239,7 +246,7 @@ impl Plic {
             self.level[id_word] &= !id_mask;
         }
 
-        for c in 0..CONTEXT_NUM {
+        for c in 0..self.contexts.len() {
             if self.contexts[c].enable[id_word] & id_mask != 0 {
                 if level != 0 {
                     self.contexts[c].pending[id_word] |= id_mask;
@@ -251,7 +258,6 @@ impl Plic {
                 }
 
                 self.context_update(c);
-                break;
             }
         }
     }
@@ -289,7 +295,7 @@ impl Device for Plic {
     fn store32(&mut self, addr: u64, data: u64) -> Result<(), (Option<u64>, TrapCause, String)> {
         const ENABLE_BASE_MINUS_ONE: usize = ENABLE_BASE - 1;
         const CONTEXT_BASE_MINUS_ONE: usize = CONTEXT_BASE - 1;
-        const PLIC_SIZE_MINUS_ONE: usize = PLIC_SIZE - 1;
+        const PLIC_SIZE_MINUS_ONE: usize = PLIC_SIZE as usize - 1;
         let addr = self.addr2index(addr);
         match addr {
             PRIORITY_BASE..=ENABLE_BASE_MINUS_ONE => {
@@ -299,7 +305,7 @@ impl Device for Plic {
             ENABLE_BASE..=CONTEXT_BASE_MINUS_ONE => {
                 let cntx = (addr - ENABLE_BASE) / ENABLE_PER_HART;
                 let addr = addr - (cntx * ENABLE_PER_HART + ENABLE_BASE);
-                if cntx < CONTEXT_NUM {
+                if cntx < self.contexts.len() {
                     self.context_enable_write(cntx, addr, data as u32);
                     Ok(())
                 } else {
@@ -313,7 +319,7 @@ impl Device for Plic {
             CONTEXT_BASE..=PLIC_SIZE_MINUS_ONE => {
                 let cntx = (addr - CONTEXT_BASE) / CONTEXT_PER_HART;
                 let addr = addr - (cntx * CONTEXT_PER_HART + CONTEXT_BASE);
-                if cntx < CONTEXT_NUM {
+                if cntx < self.contexts.len() {
                     self.context_write(cntx, addr, data as u32);
                     Ok(())
                 } else {
@@ -353,14 +359,14 @@ impl Device for Plic {
     fn load32(&mut self, addr: u64) -> Result<u64, (Option<u64>, TrapCause, String)> {
         const ENABLE_BASE_MINUS_ONE: usize = ENABLE_BASE - 1;
         const CONTEXT_BASE_MINUS_ONE: usize = CONTEXT_BASE - 1;
-        const PLIC_SIZE_MINUS_ONE: usize = PLIC_SIZE - 1;
+        const PLIC_SIZE_MINUS_ONE: usize = PLIC_SIZE as usize - 1;
         let addr = self.addr2index(addr);
         match addr {
             PRIORITY_BASE..=ENABLE_BASE_MINUS_ONE => Ok(self.priority_read(addr) as u64),
             ENABLE_BASE..=CONTEXT_BASE_MINUS_ONE => {
                 let cntx = (addr - ENABLE_BASE) / ENABLE_PER_HART;
                 let addr = addr - (cntx * ENABLE_PER_HART + ENABLE_BASE);
-                if cntx < CONTEXT_NUM {
+                if cntx < self.contexts.len() {
                     Ok(self.context_enable_read(cntx, addr) as u64)
                 } else {
                     Err((
@@ -373,7 +379,7 @@ impl Device for Plic {
             CONTEXT_BASE..=PLIC_SIZE_MINUS_ONE => {
                 let cntx = (addr - CONTEXT_BASE) / CONTEXT_PER_HART;
                 let addr = addr - (cntx * CONTEXT_PER_HART + CONTEXT_BASE);
-                if cntx < CONTEXT_NUM {
+                if cntx < self.contexts.len() {
                     Ok(self.context_read(cntx, addr) as u64)
                 } else {
                     Err((
@@ -416,3 +422,44 @@ impl Device for Plic {
         ))
     }
 }
+
+// the privilege of each context is fixed by the configuration
+impl Snapshot for Plic {
+    fn save(&self, w: &mut Writer) -> io::Result<()> {
+        w.tag(b"PLIC")?;
+        w.bytes(&self.priority)?;
+        w.u32s(&self.level)?;
+        w.u64(self.contexts.len() as u64)?;
+        for context in self.contexts.iter() {
+            w.u8(context.priority_thresould)?;
+            w.u32s(&context.enable)?;
+            w.u32s(&context.pending)?;
+            w.bytes(&context.pending_priority)?;
+            w.u32s(&context.claimed)?;
+        }
+        w.u64s(&self.mip)
+    }
+
+    fn restore(&mut self, r: &mut Reader) -> io::Result<()> {
+        r.tag(b"PLIC")?;
+        r.bytes_into(&mut self.priority)?;
+        r.u32s_into(&mut self.level)?;
+        let context_num = r.u64()? as usize;
+        if context_num != self.contexts.len() {
+            return Err(snapshot::invalid_data(format!(
+                "expected {} PLIC contexts but found {context_num}",
+                self.contexts.len()
+            )));
+        }
+        for context in self.contexts.iter_mut() {
+            context.priority_thresould = r.u8()?;
+            r.u32s_into(&mut context.enable)?;
+            r.u32s_into(&mut context.pending)?;
+            r.bytes_into(&mut context.pending_priority)?;
+            r.u32s_into(&mut context.claimed)?;
+        }
+        r.u64s_into(&mut self.mip)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,132 @@
+use crate::snapshot::{self, Reader, Snapshot, Writer};
+use std::io;
+
+// LR reserves the naturally aligned block that contains the address
+const RESERVATION_SET_SIZE: u64 = 64;
+
+// LR/SC reservations of every hart, indexed by hart id
+pub struct Reservations {
+    sets: Vec<Option<u64>>,
+    // every n-th SC that would succeed fails spuriously
+    sc_fail_every: Option<u64>,
+    sc_count: u64,
+}
+
+impl Reservations {
+    pub fn new(harts: usize, sc_fail_every: Option<u64>) -> Self {
+        Reservations {
+            sets: vec![None; harts],
+            sc_fail_every,
+            sc_count: 0,
+        }
+    }
+
+    fn set_of(paddr: u64) -> u64 {
+        paddr & !(RESERVATION_SET_SIZE - 1)
+    }
+
+    pub fn get(&self, hartid: usize) -> Option<u64> {
+        self.sets[hartid]
+    }
+
+    pub fn reserve(&mut self, hartid: usize, paddr: u64) {
+        self.sets[hartid] = Some(Self::set_of(paddr));
+    }
+
+    // an SC consumes the reservation whether it succeeds or not
+    pub fn store_conditional(&mut self, hartid: usize, paddr: u64) -> bool {
+        if self.sets[hartid].take() != Some(Self::set_of(paddr)) {
+            return false;
+        }
+
+        self.sc_count += 1;
+        !self
+            .sc_fail_every
+            .is_some_and(|n| self.sc_count.is_multiple_of(n))
+    }
+
+    pub fn yield_reservation(&mut self, hartid: usize) {
+        self.sets[hartid] = None;
+    }
+
+    // a store from any agent breaks every reservation on the blocks it touches
+    pub fn invalidate(&mut self, paddr: u64, size: u64) {
+        let first = Self::set_of(paddr);
+        let last = Self::set_of(paddr + size - 1);
+        for set in self.sets.iter_mut() {
+            if set.is_some_and(|set| first <= set && set <= last) {
+                *set = None;
+            }
+        }
+    }
+}
+
+impl Snapshot for Reservations {
+    fn save(&self, w: &mut Writer) -> io::Result<()> {
+        w.tag(b"RSRV")?;
+        w.u64(self.sets.len() as u64)?;
+        self.sets.iter().try_for_each(|set| w.opt_u64(*set))?;
+        w.u64(self.sc_count)
+    }
+
+    fn restore(&mut self, r: &mut Reader) -> io::Result<()> {
+        r.tag(b"RSRV")?;
+        let harts = r.u64()? as usize;
+        if harts != self.sets.len() {
+            return Err(snapshot::invalid_data(format!(
+                "expected {} reservations but found {harts}",
+                self.sets.len()
+            )));
+        }
+        for set in self.sets.iter_mut() {
+            *set = r.opt_u64()?;
+        }
+        self.sc_count = r.u64()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reservation_test() {
+        let mut rsv = Reservations::new(2, None);
+
+        // any address in the reservation set satisfies the SC, but only once
+        rsv.reserve(0, 0x8000_0048);
+        assert!(rsv.store_conditional(0, 0x8000_0040));
+        assert!(!rsv.store_conditional(0, 0x8000_0040));
+
+        // a store by another agent to the same block breaks the reservation
+        rsv.reserve(0, 0x8000_0040);
+        rsv.reserve(1, 0x8000_0080);
+        rsv.invalidate(0x8000_007e, 4);
+        assert_eq!(rsv.get(0), None);
+        assert_eq!(rsv.get(1), None);
+
+        rsv.reserve(1, 0x8000_0080);
+        rsv.invalidate(0x8000_0100, 8);
+        assert!(!rsv.store_conditional(0, 0x8000_0080));
+        assert!(rsv.store_conditional(1, 0x8000_0080));
+
+        // a trap drops the reservation
+        rsv.reserve(1, 0x8000_0080);
+        rsv.yield_reservation(1);
+        assert!(!rsv.store_conditional(1, 0x8000_0080));
+    }
+
+    #[test]
+    fn spurious_failure_test() {
+        let mut rsv = Reservations::new(1, Some(3));
+        let results: Vec<bool> = (0..6)
+            .map(|_| {
+                rsv.reserve(0, 0x8000_0000);
+                rsv.store_conditional(0, 0x8000_0000)
+            })
+            .collect();
+        assert_eq!(results, vec![true, true, false, true, true, false]);
+    }
+}
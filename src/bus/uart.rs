@@ -1,14 +1,22 @@
+mod backend;
 mod io;
+pub mod term;
 
-use super::Device;
-use crate::bus::Plic;
+use super::{Device, IrqLine};
+use crate::machine::UART_SIZE;
+use crate::snapshot::{Reader, Writer};
 use crate::TrapCause;
+pub use backend::Backend;
 use std::collections::VecDeque;
-use std::sync::mpsc;
 
-const UART_QUEUE_SIZE: usize = 64;
+// the 16550 has 16-byte FIFOs; without them the receiver holds one character
+const FIFO_SIZE: usize = 16;
 const MAX_BACKOFF: u64 = 16;
-const UART_IIR_TYPE_BITS: u8 = 0xc0;
+// a character time is much shorter than a tick, so the timeout of four of them
+// is stretched to four ticks
+const RX_TIMEOUT_TICKS: u64 = 4;
+// MSR inputs while no loopback: DCD, DSR and CTS
+const MSR_CONNECTED: u8 = 0xb0;
 
 #[allow(dead_code)]
 #[allow(non_camel_case_types)]
@@ -42,7 +50,9 @@ enum IirMask {
     THRI = 0x02,
     RDI = 0x04,
     RLSI = 0x06,
+    RX_TIMEOUT = 0x0c,
     ID = 0x0e,
+    FIFO_ENABLED = 0xc0,
 }
 
 #[allow(dead_code)]
@@ -53,6 +63,7 @@ enum FcrMask {
     CLEAR_RCVR = 0x02,
     CLEAR_XMIT = 0x04,
     DMA_SELECT = 0x08,
+    TRIGGER = 0xc0,
 }
 
 #[allow(dead_code)]
@@ -64,6 +75,7 @@ enum LcrMask {
     EPAR = 0x10,
     PARITY = 0x08,
     STOP = 0x04,
+    WLEN = 0x03,
 }
 
 #[allow(dead_code)]
@@ -91,160 +103,68 @@ enum LsrMask {
     BRK_ERROR_BITS = 0x1E,
 }
 
+#[allow(dead_code)]
+#[allow(non_camel_case_types)]
+#[allow(clippy::upper_case_acronyms)]
+enum MsrMask {
+    DCD = 0x80,
+    RI = 0x40,
+    DSR = 0x20,
+    CTS = 0x10,
+    DDCD = 0x08,
+    TERI = 0x04,
+    DDSR = 0x02,
+    DCTS = 0x01,
+    ANY_DELTA = 0x0f,
+}
+
+// ns16550a; characters leave as soon as they are written, so the transmitter is always empty
 pub struct Uart {
-    pub uart: Vec<u8>,
+    pub base_addr: u64,
+    size: usize,
+    irq: IrqLine,
+    backend: Backend,
+    ier: u8,
+    lcr: u8,
+    mcr: u8,
+    scr: u8,
     dll: u8,
     dlm: u8,
+    // FIFO enable, DMA mode and RX trigger level as last written
+    fcr: u8,
+    // OE, PE, FE and BI latched until LSR is read
+    lsr: u8,
+    msr: u8,
+    // received characters with the line status errors they came with
+    rx_fifo: VecDeque<(u8, u8)>,
+    thr_ipending: bool,
+    timeout_ipending: bool,
+    rx_idle_ticks: u64,
     backoff_counter: u64,
-    pub base_addr: u64,
-    size: usize,
-    rx_queue: VecDeque<u8>,
-    stdin_channel: mpsc::Receiver<String>,
-}
-
-impl Default for Uart {
-    fn default() -> Self {
-        Self::new()
-    }
 }
 
 impl Uart {
-    pub fn new() -> Self {
-        const UART_SIZE: usize = 0x100;
-        let mut uart = vec![0; UART_SIZE];
-        uart[UartRegister::IIR_FCR as usize] = 0x1; // IIR_NO_INT
-        uart[UartRegister::LSR as usize] = 0x60; // LSR_TEMT | LSR_THRE
-        uart[UartRegister::MSR as usize] = 0xb0; // UART_MSR_DCD | UART_MSR_DSR | UART_MSR_CTS
-        uart[UartRegister::MCR as usize] = 0x08; // MCR_OUT2
-
-        let (tx, rx) = mpsc::channel::<String>();
-        std::thread::spawn(move || loop {
-            let mut buffer = String::new();
-            std::io::stdin().read_line(&mut buffer).unwrap();
-            tx.send(buffer).unwrap();
-        });
-
+    pub fn new(base_addr: u64, irq: IrqLine, backend: Backend) -> Self {
         Uart {
-            uart,
+            base_addr,
+            size: UART_SIZE as usize,
+            irq,
+            backend,
+            ier: 0,
+            // 8N1 as firmware would leave it
+            lcr: 0x03,
+            mcr: McrMask::OUT2 as u8,
+            scr: 0,
             dll: 0x0c,
             dlm: 0,
+            fcr: 0,
+            lsr: 0,
+            msr: MSR_CONNECTED,
+            rx_fifo: VecDeque::new(),
+            thr_ipending: false,
+            timeout_ipending: false,
+            rx_idle_ticks: 0,
             backoff_counter: 0,
-            base_addr: 0x1000_0000,
-            size: UART_SIZE,
-            rx_queue: VecDeque::new(),
-            stdin_channel: rx,
-        }
-    }
-
-    pub fn load8_with_plic(
-        &mut self,
-        addr: u64,
-        plic: &mut Plic,
-    ) -> Result<u64, (Option<u64>, TrapCause, String)> {
-        const RX: usize = UartRegister::RX_TX as usize;
-        const IER: usize = UartRegister::IER as usize;
-        const IIR: usize = UartRegister::IIR_FCR as usize;
-        let index = self.addr2index(addr);
-
-        match index {
-            RX => {
-                let data = if self.uart[UartRegister::LCR as usize] & LcrMask::DLAB as u8 != 0 {
-                    self.dll
-                } else {
-                    self.rx_byte()
-                };
-                self.update_interrupt(plic);
-
-                Ok(data as u64)
-            }
-            IER => {
-                if self.uart[UartRegister::LCR as usize] & LcrMask::DLAB as u8 != 0 {
-                    Ok(self.dlm as i8 as i64 as u64)
-                } else {
-                    Ok(self.uart[UartRegister::IER as usize] as i8 as i64 as u64)
-                }
-            }
-            IIR => Ok((self.uart[index] | UART_IIR_TYPE_BITS) as i8 as i64 as u64),
-            _ => Ok(self.uart[index] as i8 as i64 as u64),
-        }
-    }
-
-    pub fn store8_with_plic(
-        &mut self,
-        addr: u64,
-        data: u64,
-        plic: &mut Plic,
-    ) -> Result<(), (Option<u64>, TrapCause, String)> {
-        const TX: usize = UartRegister::RX_TX as usize;
-        const IER: usize = UartRegister::IER as usize;
-        const FCR: usize = UartRegister::IIR_FCR as usize;
-        const LCR: usize = UartRegister::LCR as usize;
-        const MCR: usize = UartRegister::MCR as usize;
-        let index = self.addr2index(addr);
-
-        match index {
-            TX => {
-                if self.uart[LCR] & LcrMask::DLAB as u8 != 0 {
-                    self.dll = data as u8;
-                    self.update_interrupt(plic);
-                    return Ok(());
-                }
-
-                if self.uart[MCR] & McrMask::LOOP as u8 != 0 {
-                    if self.rx_queue.len() < UART_QUEUE_SIZE {
-                        self.rx_queue.push_back(data as u8);
-                        self.uart[UartRegister::LSR as usize] |= LsrMask::DR as u8;
-                    }
-                    self.update_interrupt(plic);
-                    return Ok(());
-                }
-
-                self.tx_byte(char::from_u32(data as u32).unwrap())
-            }
-            IER => {
-                if self.uart[LCR] & LcrMask::DLAB as u8 == 0 {
-                    self.uart[IER] = data as u8 & 0x0f;
-                } else {
-                    self.dlm = data as u8;
-                }
-            }
-            _ => self.uart[index] = (data & 0xFF) as u8,
-        }
-
-        match index {
-            IER | FCR | LCR | MCR => self.update_interrupt(plic),
-            _ => (),
-        }
-
-        Ok(())
-    }
-
-    pub fn tick(&mut self, plic: &mut Plic) {
-        if (self.uart[UartRegister::IIR_FCR as usize] & FcrMask::ENABLE_FIFO as u8 == 0)
-            || (self.uart[UartRegister::MCR as usize] & McrMask::LOOP as u8 != 0)
-            || (UART_QUEUE_SIZE <= self.rx_queue.len())
-        {
-            return;
-        }
-
-        if self.backoff_counter > 0 && self.backoff_counter < MAX_BACKOFF {
-            self.backoff_counter += 1;
-            return;
-        }
-
-        match self.stdin_channel.try_recv() {
-            Ok(input) => {
-                self.backoff_counter = 0;
-
-                for c in input.chars() {
-                    self.rx_queue.push_back(c as u8);
-                }
-                self.uart[UartRegister::LSR as usize] |= LsrMask::DR as u8;
-                self.update_interrupt(plic);
-            }
-            Err(_) => {
-                self.backoff_counter = 1;
-            }
         }
     }
 }
@@ -262,8 +182,31 @@ impl Device for Uart {
     }
 
     // store
-    fn store8(&mut self, _addr: u64, _data: u64) -> Result<(), (Option<u64>, TrapCause, String)> {
-        unreachable!()
+    fn store8(&mut self, addr: u64, data: u64) -> Result<(), (Option<u64>, TrapCause, String)> {
+        const THR: usize = UartRegister::RX_TX as usize;
+        const IER: usize = UartRegister::IER as usize;
+        const FCR: usize = UartRegister::IIR_FCR as usize;
+        const LCR: usize = UartRegister::LCR as usize;
+        const MCR: usize = UartRegister::MCR as usize;
+        const SCR: usize = UartRegister::SCR as usize;
+        let dlab = self.lcr & LcrMask::DLAB as u8 != 0;
+        let data = data as u8;
+
+        match self.addr2index(addr) {
+            THR if dlab => self.dll = data,
+            THR => self.tx_byte(data),
+            IER if dlab => self.dlm = data,
+            IER => self.write_ier(data),
+            FCR => self.write_fcr(data),
+            LCR => self.lcr = data,
+            MCR => self.write_mcr(data),
+            SCR => self.scr = data,
+            // LSR and MSR are read only
+            _ => (),
+        }
+        self.update_interrupt();
+
+        Ok(())
     }
 
     fn store16(&mut self, addr: u64, _data: u64) -> Result<(), (Option<u64>, TrapCause, String)> {
@@ -291,8 +234,32 @@ impl Device for Uart {
     }
 
     // load
-    fn load8(&mut self, _addr: u64) -> Result<u64, (Option<u64>, TrapCause, String)> {
-        unreachable!()
+    fn load8(&mut self, addr: u64) -> Result<u64, (Option<u64>, TrapCause, String)> {
+        const RBR: usize = UartRegister::RX_TX as usize;
+        const IIR: usize = UartRegister::IIR_FCR as usize;
+        const LSR: usize = UartRegister::LSR as usize;
+        const MSR: usize = UartRegister::MSR as usize;
+        let index = self.addr2index(addr);
+        let dlab = self.lcr & LcrMask::DLAB as u8 != 0;
+
+        let data = match index {
+            RBR if !dlab => self.rx_byte(),
+            IIR => self.read_iir(),
+            LSR => {
+                let lsr = self.lsr_value();
+                self.lsr &= !(LsrMask::BRK_ERROR_BITS as u8);
+                lsr
+            }
+            MSR => {
+                let msr = self.msr;
+                self.msr &= !(MsrMask::ANY_DELTA as u8);
+                msr
+            }
+            _ => self.peek(index),
+        };
+        self.update_interrupt();
+
+        Ok(data as i8 as i64 as u64)
     }
 
     fn load16(&self, addr: u64) -> Result<u64, (Option<u64>, TrapCause, String)> {
@@ -320,8 +287,7 @@ impl Device for Uart {
     }
 
     fn load_u8(&self, addr: u64) -> Result<u64, (Option<u64>, TrapCause, String)> {
-        let index = self.addr2index(addr);
-        Ok(self.uart[index] as u64)
+        Ok(self.peek(self.addr2index(addr)) as u64)
     }
 
     fn load_u16(&self, addr: u64) -> Result<u64, (Option<u64>, TrapCause, String)> {
@@ -339,4 +305,318 @@ impl Device for Uart {
             "uart only allows load/store8 but try load_u32".to_string(),
         ))
     }
+
+    fn tick(&mut self) {
+        // characters left below the trigger level are reported after a while
+        if self.fifo_enabled() && !self.rx_fifo.is_empty() && !self.timeout_ipending {
+            self.rx_idle_ticks += 1;
+            if self.rx_idle_ticks >= RX_TIMEOUT_TICKS {
+                self.timeout_ipending = true;
+                self.update_interrupt();
+            }
+        }
+
+        // the receiver is disconnected from the line in loopback
+        if self.mcr & McrMask::LOOP as u8 != 0 || self.rx_fifo.len() >= self.rx_capacity() {
+            return;
+        }
+
+        if self.backend.take_break() {
+            self.receive(0, LsrMask::BI as u8);
+        }
+
+        if self.backoff_counter > 0 && self.backoff_counter < MAX_BACKOFF {
+            self.backoff_counter += 1;
+            return;
+        }
+
+        let mut buf = [0; FIFO_SIZE];
+        let len = self
+            .backend
+            .read(&mut buf[..self.rx_capacity() - self.rx_fifo.len()]);
+        if len > 0 {
+            self.backoff_counter = 0;
+            for byte in &buf[..len] {
+                self.receive(*byte, 0);
+            }
+        } else {
+            self.backoff_counter = 1;
+        }
+    }
+
+    fn save_state(&self, w: &mut Writer) -> std::io::Result<()> {
+        w.tag(b"UART")?;
+        w.bytes(&[
+            self.ier, self.lcr, self.mcr, self.scr, self.dll, self.dlm, self.fcr, self.lsr,
+            self.msr,
+        ])?;
+        w.u8(self.thr_ipending as u8)?;
+        w.u8(self.timeout_ipending as u8)?;
+        w.u64(self.rx_idle_ticks)?;
+        w.u64(self.backoff_counter)?;
+        w.bytes(
+            &self
+                .rx_fifo
+                .iter()
+                .map(|(data, _)| *data)
+                .collect::<Vec<u8>>(),
+        )?;
+        w.bytes(
+            &self
+                .rx_fifo
+                .iter()
+                .map(|(_, errors)| *errors)
+                .collect::<Vec<u8>>(),
+        )
+    }
+
+    fn restore_state(&mut self, r: &mut Reader) -> std::io::Result<()> {
+        r.tag(b"UART")?;
+        let mut regs = [0; 9];
+        r.bytes_into(&mut regs)?;
+        [
+            self.ier, self.lcr, self.mcr, self.scr, self.dll, self.dlm, self.fcr, self.lsr,
+            self.msr,
+        ] = regs;
+        self.thr_ipending = r.u8()? != 0;
+        self.timeout_ipending = r.u8()? != 0;
+        self.rx_idle_ticks = r.u64()?;
+        self.backoff_counter = r.u64()?;
+        let data = r.bytes()?;
+        let errors = r.bytes()?;
+        self.rx_fifo = data.into_iter().zip(errors).collect();
+        self.update_interrupt();
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const BASE: u64 = 0x1000_0000;
+    const RBR: u64 = BASE;
+    const THR: u64 = BASE;
+    const IER: u64 = BASE + 1;
+    const IIR: u64 = BASE + 2;
+    const FCR: u64 = BASE + 2;
+    const LCR: u64 = BASE + 3;
+    const MCR: u64 = BASE + 4;
+    const LSR: u64 = BASE + 5;
+    const MSR: u64 = BASE + 6;
+    const SCR: u64 = BASE + 7;
+
+    struct TestUart {
+        uart: Uart,
+        irq: IrqLine,
+        log: std::path::PathBuf,
+    }
+
+    impl TestUart {
+        // output goes to a log file and input comes from a script
+        fn new(name: &str, input: &[u8]) -> Self {
+            let dir = std::env::temp_dir();
+            let log = dir.join(format!("carron-{name}-{}.log", std::process::id()));
+            let script = dir.join(format!("carron-{name}-{}.in", std::process::id()));
+            fs::write(&script, input).unwrap();
+            let spec = format!("file:{}", log.display());
+            let backend = Backend::open(&spec, script.to_str(), false, false).unwrap();
+            fs::remove_file(&script).unwrap();
+
+            let irq = IrqLine::new(1);
+            TestUart {
+                uart: Uart::new(BASE, irq.clone(), backend),
+                irq,
+                log,
+            }
+        }
+
+        fn read(&mut self, addr: u64) -> u8 {
+            self.uart.load8(addr).unwrap() as u8
+        }
+
+        fn write(&mut self, addr: u64, data: u8) {
+            self.uart.store8(addr, data as u64).unwrap();
+        }
+
+        fn output(&self) -> Vec<u8> {
+            fs::read(&self.log).unwrap_or_default()
+        }
+    }
+
+    impl Drop for TestUart {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.log);
+        }
+    }
+
+    #[test]
+    fn registers_test() {
+        let mut t = TestUart::new("uart-regs", b"");
+        assert_eq!(t.read(IIR), 0x01);
+        assert_eq!(t.read(LSR), 0x60);
+        assert_eq!(t.read(MSR), 0xb0);
+        assert_eq!(t.read(MCR), 0x08);
+        assert_eq!(t.read(LCR), 0x03);
+
+        // the divisor latch shares its addresses with RBR/THR and IER
+        t.write(LCR, 0x83);
+        t.write(THR, 0x01);
+        t.write(IER, 0x02);
+        assert_eq!((t.read(RBR), t.read(IER)), (0x01, 0x02));
+        t.write(LCR, 0x03);
+        assert_eq!(t.read(IER), 0x00);
+        assert!(t.output().is_empty());
+
+        t.write(SCR, 0x5a);
+        assert_eq!(t.read(SCR), 0x5a);
+
+        // characters are cut to the word length
+        t.write(THR, b'A');
+        t.write(LCR, 0x00);
+        t.write(THR, 0xff);
+        // and nothing is sent during a break
+        t.write(LCR, 0x43);
+        t.write(THR, b'B');
+        assert_eq!(t.output(), b"A\x1f");
+    }
+
+    #[test]
+    fn fifo_test() {
+        let mut t = TestUart::new("uart-fifo", b"");
+        // FIFOs enabled with the trigger level at 8, in loopback
+        t.write(FCR, 0x81);
+        t.write(IER, 0x01);
+        t.write(MCR, 0x10);
+        assert_eq!(t.read(IIR), 0xc1);
+        for byte in 0..7 {
+            t.write(THR, byte);
+        }
+        assert!(!t.irq.is_raised());
+        t.write(THR, 7);
+        assert!(t.irq.is_raised());
+        assert_eq!(t.read(IIR), 0xc4);
+        assert_eq!(t.read(LSR), 0x61);
+        for byte in 0..8 {
+            assert_eq!(t.read(RBR), byte);
+        }
+        assert_eq!(t.read(IIR), 0xc1);
+        assert_eq!(t.read(LSR), 0x60);
+        // nothing went out to the line
+        assert!(t.output().is_empty());
+
+        // characters below the trigger level time out
+        t.write(THR, b'x');
+        for _ in 0..RX_TIMEOUT_TICKS - 1 {
+            t.uart.tick();
+        }
+        assert!(!t.irq.is_raised());
+        t.uart.tick();
+        assert_eq!(t.read(IIR), 0xcc);
+        assert_eq!(t.read(RBR), b'x');
+        assert_eq!(t.read(IIR), 0xc1);
+
+        // clearing the receiver FIFO
+        t.write(THR, b'y');
+        t.write(FCR, 0x83);
+        assert_eq!(t.read(LSR) & 0x01, 0);
+
+        // without FIFOs every character raises the interrupt
+        t.write(FCR, 0x00);
+        assert_eq!(t.read(IIR), 0x01);
+        t.write(THR, b'z');
+        assert_eq!(t.read(IIR), 0x04);
+        assert_eq!(t.read(RBR), b'z');
+    }
+
+    #[test]
+    fn line_status_test() {
+        let mut t = TestUart::new("uart-lsr", b"");
+        t.write(FCR, 0x01);
+        t.write(IER, 0x04);
+        t.write(MCR, 0x10);
+
+        // the 17th character overruns the FIFO
+        for byte in 0..17 {
+            t.write(THR, byte);
+        }
+        assert_eq!(t.read(IIR), 0xc6);
+        assert_eq!(t.read(LSR), 0x63);
+        assert_eq!(t.read(LSR), 0x61);
+        assert_eq!(t.read(IIR), 0xc1);
+        for _ in 0..16 {
+            t.read(RBR);
+        }
+
+        // a break behind a character is reported when it reaches the top
+        t.write(THR, b'a');
+        t.write(LCR, 0x43);
+        t.write(THR, b'b');
+        t.write(LCR, 0x03);
+        assert_eq!(t.read(LSR), 0xe1);
+        assert_eq!(t.read(RBR), b'a');
+        assert_eq!(t.read(IIR), 0xc6);
+        assert_eq!(t.read(LSR), 0xf1);
+        assert_eq!(t.read(RBR), 0);
+        assert_eq!(t.read(LSR), 0x60);
+        assert!(!t.irq.is_raised());
+    }
+
+    #[test]
+    fn modem_status_test() {
+        let mut t = TestUart::new("uart-msr", b"");
+        t.write(IER, 0x08);
+        // entering loopback with RTS set drops DCD and DSR
+        t.write(MCR, 0x12);
+        assert_eq!(t.read(IIR), 0x00);
+        assert_eq!(t.read(MSR), 0x1a);
+        assert_eq!(t.read(MSR), 0x10);
+        assert_eq!(t.read(IIR), 0x01);
+
+        // RI reports its trailing edge only
+        t.write(MCR, 0x16);
+        assert_eq!(t.read(MSR), 0x50);
+        t.write(MCR, 0x12);
+        assert_eq!(t.read(MSR), 0x14);
+
+        t.write(MCR, 0x08);
+        assert_eq!(t.read(MSR), 0xba);
+    }
+
+    #[test]
+    fn interrupt_test() {
+        let mut t = TestUart::new("uart-irq", b"hello");
+        // enabling THRI with an empty holding register raises it
+        t.write(IER, 0x02);
+        assert!(t.irq.is_raised());
+        assert_eq!(t.read(IIR), 0x02);
+        assert_eq!(t.read(IIR), 0x01);
+        assert!(!t.irq.is_raised());
+        t.write(THR, b'!');
+        assert_eq!(t.read(IIR), 0x02);
+
+        // input from the backend has priority over the transmitter
+        t.write(FCR, 0x41);
+        t.write(IER, 0x03);
+        t.uart.tick();
+        assert_eq!(t.read(IIR), 0xc4);
+        let input: Vec<u8> = (0..5).map(|_| t.read(RBR)).collect();
+        assert_eq!(input, b"hello");
+        assert_eq!(t.read(IIR), 0xc1);
+        t.write(THR, b'?');
+
+        // the snapshot keeps the register state
+        let mut buf = Vec::new();
+        t.uart.save_state(&mut Writer::new(&mut buf)).unwrap();
+        let mut u = TestUart::new("uart-irq-restored", b"");
+        u.uart
+            .restore_state(&mut Reader::new(&mut &buf[..]))
+            .unwrap();
+        assert!(u.irq.is_raised());
+        assert_eq!(u.read(IIR), 0xc2);
+        assert!(!u.irq.is_raised());
+        assert_eq!(t.output(), b"!?");
+    }
 }
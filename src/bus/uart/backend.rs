@@ -0,0 +1,202 @@
+use super::term;
+use crate::socket::{Listener, Server};
+use std::collections::VecDeque;
+use std::ffi::CStr;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::sync::mpsc;
+
+// where the UART output goes and its input comes from
+enum Endpoint {
+    // stdin is read on another thread, which also handles the escape keys
+    Stdio {
+        rx: mpsc::Receiver<Vec<u8>>,
+        pending: VecDeque<u8>,
+    },
+    // the master side of a host pseudo terminal
+    Pty(File),
+    // a listening socket that serves one client at a time
+    Server(Server),
+    // output only
+    File(File),
+}
+
+pub struct Backend {
+    endpoint: Endpoint,
+    // input fed to the guest ahead of the endpoint's
+    script: Option<File>,
+    // copy the output to stdout as well
+    tee: bool,
+}
+
+impl Backend {
+    // "stdio", "pty", "tcp:ADDR:PORT", "unix:PATH" or "file:PATH"
+    pub fn open(spec: &str, script: Option<&str>, tee: bool, raw: bool) -> io::Result<Self> {
+        let endpoint = match spec.split_once(':') {
+            None if spec == "stdio" => Endpoint::Stdio {
+                rx: term::spawn_reader(raw),
+                pending: VecDeque::new(),
+            },
+            None if spec == "pty" => Endpoint::Pty(open_pty()?),
+            Some(("tcp", addr)) => {
+                let listener = Listener::bind_tcp(addr)?;
+                eprintln!("uart: listening on {listener}");
+                Endpoint::Server(Server::new(listener)?)
+            }
+            Some(("unix", path)) => Endpoint::Server(Server::new(Listener::bind_unix(path)?)?),
+            Some(("file", path)) => {
+                Endpoint::File(OpenOptions::new().create(true).append(true).open(path)?)
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("unknown uart backend: {spec}"),
+                ))
+            }
+        };
+
+        Ok(Backend {
+            endpoint,
+            script: script.map(File::open).transpose()?,
+            tee,
+        })
+    }
+
+    // output that nobody can take is dropped
+    pub fn write(&mut self, data: &[u8]) {
+        match &mut self.endpoint {
+            Endpoint::Stdio { .. } => {
+                let _ = write_stdout(data);
+            }
+            // fails too when nobody has the slave open and its buffer is full
+            Endpoint::Pty(master) => {
+                let _ = master.write_all(data);
+            }
+            Endpoint::Server(server) => server.write(data),
+            Endpoint::File(file) => {
+                let _ = file.write_all(data);
+            }
+        }
+
+        if self.tee && !matches!(self.endpoint, Endpoint::Stdio { .. }) {
+            let _ = write_stdout(data);
+        }
+    }
+
+    // only the terminal can send a break
+    pub fn take_break(&mut self) -> bool {
+        matches!(self.endpoint, Endpoint::Stdio { .. }) && term::take_break()
+    }
+
+    // never blocks; 0 when there is no input
+    pub fn read(&mut self, buf: &mut [u8]) -> usize {
+        if let Some(script) = &mut self.script {
+            match script.read(buf) {
+                Ok(len) if len > 0 => return len,
+                _ => self.script = None,
+            }
+        }
+
+        match &mut self.endpoint {
+            Endpoint::Stdio { rx, pending } => {
+                while let Ok(input) = rx.try_recv() {
+                    pending.extend(input);
+                }
+                let len = buf.len().min(pending.len());
+                for (dst, src) in buf.iter_mut().zip(pending.drain(..len)) {
+                    *dst = src;
+                }
+                len
+            }
+            // EIO while the slave is closed is no input either
+            Endpoint::Pty(master) => master.read(buf).unwrap_or(0),
+            Endpoint::Server(server) => server.read(buf),
+            Endpoint::File(_) => 0,
+        }
+    }
+}
+
+fn write_stdout(data: &[u8]) -> io::Result<()> {
+    let mut stdout = io::stdout();
+    stdout.write_all(data).and_then(|_| stdout.flush())
+}
+
+// a raw, nonblocking pty master; the slave path is announced on stderr
+fn open_pty() -> io::Result<File> {
+    let fd = unsafe { libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let master = unsafe { File::from_raw_fd(fd) };
+
+    let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+    let ok = unsafe {
+        libc::grantpt(fd) == 0
+            && libc::unlockpt(fd) == 0
+            && libc::tcgetattr(fd, &mut termios) == 0
+            && {
+                libc::cfmakeraw(&mut termios);
+                libc::tcsetattr(fd, libc::TCSANOW, &termios) == 0
+            }
+            && libc::fcntl(fd, libc::F_SETFL, libc::O_NONBLOCK) == 0
+    };
+    if !ok {
+        return Err(io::Error::last_os_error());
+    }
+
+    let name = unsafe { libc::ptsname(master.as_raw_fd()) };
+    if name.is_null() {
+        return Err(io::Error::last_os_error());
+    }
+    let path = unsafe { CStr::from_ptr(name) }.to_string_lossy();
+    eprintln!("uart: redirected to {path}");
+    Ok(master)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::net::TcpStream;
+
+    #[test]
+    fn backend_test() {
+        let dir = std::env::temp_dir();
+        let log = dir.join(format!("carron-uart-{}.log", std::process::id()));
+        let script = dir.join(format!("carron-uart-{}.in", std::process::id()));
+        fs::write(&script, b"root\n").unwrap();
+
+        let spec = format!("file:{}", log.display());
+        let mut backend = Backend::open(&spec, script.to_str(), false, false).unwrap();
+        let mut buf = [0; 4];
+        assert_eq!(backend.read(&mut buf), 4);
+        assert_eq!(&buf, b"root");
+        assert_eq!(backend.read(&mut buf), 1);
+        assert_eq!(backend.read(&mut buf), 0);
+        backend.write(b"login: \xff");
+        assert_eq!(fs::read(&log).unwrap(), b"login: \xff");
+
+        // a socket client gets the output and types the input
+        let mut backend = Backend::open("tcp:127.0.0.1:0", None, false, false).unwrap();
+        let Endpoint::Server(server) = &backend.endpoint else {
+            panic!("not a server");
+        };
+        let Some(Listener::Tcp(listener)) = server.listener() else {
+            panic!("not a tcp server");
+        };
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.write_all(b"ls\r").unwrap();
+        let mut len = 0;
+        while len == 0 {
+            len = backend.read(&mut buf);
+        }
+        assert_eq!(&buf[..len], &b"ls\r"[..len]);
+        backend.write(b"ok");
+        client.read_exact(&mut buf[..2]).unwrap();
+        assert_eq!(&buf[..2], b"ok");
+
+        fs::remove_file(&log).unwrap();
+        fs::remove_file(&script).unwrap();
+    }
+}
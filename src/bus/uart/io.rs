@@ -1,70 +1,224 @@
-use super::{FcrMask, IerMask, IirMask, LsrMask, Uart, UartRegister};
-use crate::bus::Plic;
-use std::io;
-use std::io::Write;
+use super::{
+    FcrMask, IerMask, IirMask, LcrMask, LsrMask, McrMask, MsrMask, Uart, UartRegister, FIFO_SIZE,
+    MSR_CONNECTED,
+};
+
+// characters that raise the receive interrupt, by FCR[7:6]
+const RX_TRIGGER_LEVELS: [usize; 4] = [1, 4, 8, 14];
 
 impl Uart {
-    pub fn update_interrupt(&mut self, plic: &mut Plic) {
-        const UART_INTERRUPT_ID: u32 = 1;
-        let mut interrupts = 0;
-        if self.uart[UartRegister::LCR as usize] & FcrMask::CLEAR_RCVR as u8 != 0 {
-            self.uart[UartRegister::LCR as usize] &= !(FcrMask::CLEAR_RCVR as u8);
-            self.rx_queue.clear();
-            self.uart[UartRegister::LSR as usize] &= !(LsrMask::DR as u8);
+    pub fn fifo_enabled(&self) -> bool {
+        self.fcr & FcrMask::ENABLE_FIFO as u8 != 0
+    }
+
+    pub fn rx_capacity(&self) -> usize {
+        if self.fifo_enabled() {
+            FIFO_SIZE
+        } else {
+            1
         }
+    }
 
-        if self.uart[UartRegister::LCR as usize] & FcrMask::CLEAR_XMIT as u8 != 0 {
-            self.uart[UartRegister::LCR as usize] &= !(FcrMask::CLEAR_XMIT as u8);
-            self.uart[UartRegister::LSR as usize] |= LsrMask::TEMT as u8 | LsrMask::THRE as u8;
+    fn rx_trigger(&self) -> usize {
+        if self.fifo_enabled() {
+            RX_TRIGGER_LEVELS[(self.fcr >> 6) as usize]
+        } else {
+            1
         }
+    }
 
-        if self.uart[UartRegister::IER as usize] & IerMask::RDI as u8 != 0
-            && self.uart[UartRegister::LSR as usize] & LsrMask::DR as u8 != 0
-        {
-            interrupts |= IirMask::RDI as u8;
+    // the highest priority source, or NO_INT
+    fn pending_interrupt(&self) -> IirMask {
+        let lsr = self.lsr_value();
+        if self.ier & IerMask::RLSI as u8 != 0 && lsr & LsrMask::BRK_ERROR_BITS as u8 != 0 {
+            IirMask::RLSI
+        } else if self.ier & IerMask::RDI as u8 != 0 && self.timeout_ipending {
+            IirMask::RX_TIMEOUT
+        } else if self.ier & IerMask::RDI as u8 != 0 && self.rx_fifo.len() >= self.rx_trigger() {
+            IirMask::RDI
+        } else if self.ier & IerMask::THRI as u8 != 0 && self.thr_ipending {
+            IirMask::THRI
+        } else if self.ier & IerMask::MSI as u8 != 0 && self.msr & MsrMask::ANY_DELTA as u8 != 0 {
+            IirMask::MSI
+        } else {
+            IirMask::NO_INT
+        }
+    }
+
+    pub fn update_interrupt(&mut self) {
+        let pending = self.pending_interrupt();
+        self.irq.set_level(!matches!(pending, IirMask::NO_INT));
+    }
+
+    pub fn read_iir(&mut self) -> u8 {
+        let iir = self.peek(UartRegister::IIR_FCR as usize);
+        // reading IIR acknowledges the transmitter interrupt it reports
+        if iir & IirMask::ID as u8 == IirMask::THRI as u8 {
+            self.thr_ipending = false;
+        }
+        iir
+    }
+
+    pub fn lsr_value(&self) -> u8 {
+        let mut lsr = self.lsr | LsrMask::TEMT as u8 | LsrMask::THRE as u8;
+        if !self.rx_fifo.is_empty() {
+            lsr |= LsrMask::DR as u8;
+        }
+        if self.fifo_enabled() && self.rx_fifo.iter().any(|(_, errors)| *errors != 0) {
+            lsr |= LsrMask::FIFOE as u8;
+        }
+        lsr
+    }
+
+    // register contents without the side effects of reading them
+    pub fn peek(&self, index: usize) -> u8 {
+        const RBR: usize = UartRegister::RX_TX as usize;
+        const IER: usize = UartRegister::IER as usize;
+        const IIR: usize = UartRegister::IIR_FCR as usize;
+        const LCR: usize = UartRegister::LCR as usize;
+        const MCR: usize = UartRegister::MCR as usize;
+        const LSR: usize = UartRegister::LSR as usize;
+        const MSR: usize = UartRegister::MSR as usize;
+        const SCR: usize = UartRegister::SCR as usize;
+        let dlab = self.lcr & LcrMask::DLAB as u8 != 0;
+
+        match index {
+            RBR if dlab => self.dll,
+            RBR => self.rx_fifo.front().map_or(0, |(data, _)| *data),
+            IER if dlab => self.dlm,
+            IER => self.ier,
+            IIR => {
+                let fifo = if self.fifo_enabled() {
+                    IirMask::FIFO_ENABLED as u8
+                } else {
+                    0
+                };
+                self.pending_interrupt() as u8 | fifo
+            }
+            LCR => self.lcr,
+            MCR => self.mcr,
+            LSR => self.lsr_value(),
+            MSR => self.msr,
+            SCR => self.scr,
+            _ => 0,
+        }
+    }
+
+    pub fn write_ier(&mut self, data: u8) {
+        let changed = (self.ier ^ data) & IerMask::THRI as u8;
+        self.ier = data & 0x0f;
+        // enabling the interrupt while the holding register is empty raises it at once
+        if changed != 0 {
+            self.thr_ipending = self.ier & IerMask::THRI as u8 != 0;
         }
+    }
 
-        if self.uart[UartRegister::IER as usize] & IerMask::THRI as u8 != 0
-            && self.uart[UartRegister::LSR as usize] & LsrMask::TEMT as u8 != 0
+    pub fn write_fcr(&mut self, data: u8) {
+        let enable = data & FcrMask::ENABLE_FIFO as u8;
+        if (self.fcr ^ data) & FcrMask::ENABLE_FIFO as u8 != 0
+            || data & FcrMask::CLEAR_RCVR as u8 != 0
         {
-            interrupts |= IirMask::THRI as u8;
+            self.rx_fifo.clear();
+            self.lsr &= !(LsrMask::BRK_ERROR_BITS as u8);
+            self.timeout_ipending = false;
+            self.rx_idle_ticks = 0;
         }
+        // the transmitter FIFO is always empty, so CLEAR_XMIT has nothing to do
+        // and the other bits only take effect with the FIFOs enabled
+        self.fcr = if enable != 0 {
+            data & (FcrMask::ENABLE_FIFO as u8 | FcrMask::DMA_SELECT as u8 | FcrMask::TRIGGER as u8)
+        } else {
+            0
+        };
+    }
 
-        if interrupts == 0 {
-            self.uart[UartRegister::IIR_FCR as usize] = IirMask::NO_INT as u8;
-            plic.set_interrupt_level(UART_INTERRUPT_ID, 0);
+    pub fn write_mcr(&mut self, data: u8) {
+        self.mcr = data & 0x1f;
+
+        // in loopback the modem outputs are wired to the inputs
+        let inputs = if self.mcr & McrMask::LOOP as u8 != 0 {
+            [
+                (McrMask::RTS as u8, MsrMask::CTS as u8),
+                (McrMask::DTR as u8, MsrMask::DSR as u8),
+                (McrMask::OUT1 as u8, MsrMask::RI as u8),
+                (McrMask::OUT2 as u8, MsrMask::DCD as u8),
+            ]
+            .into_iter()
+            .filter(|(output, _)| self.mcr & output != 0)
+            .fold(0, |msr, (_, input)| msr | input)
         } else {
-            self.uart[UartRegister::IIR_FCR as usize] = interrupts;
-            plic.set_interrupt_level(UART_INTERRUPT_ID, 1);
-        }
+            MSR_CONNECTED
+        };
 
-        if self.uart[UartRegister::IER as usize] & IerMask::THRI as u8 == 0 {
-            self.uart[UartRegister::LSR as usize] |= LsrMask::TEMT as u8 | LsrMask::THRE as u8;
+        let changed = (self.msr ^ inputs) & 0xf0;
+        let mut deltas = 0;
+        if changed & MsrMask::CTS as u8 != 0 {
+            deltas |= MsrMask::DCTS as u8;
         }
+        if changed & MsrMask::DSR as u8 != 0 {
+            deltas |= MsrMask::DDSR as u8;
+        }
+        // RI only reports its trailing edge
+        if changed & MsrMask::RI as u8 != 0 && inputs & MsrMask::RI as u8 == 0 {
+            deltas |= MsrMask::TERI as u8;
+        }
+        if changed & MsrMask::DCD as u8 != 0 {
+            deltas |= MsrMask::DDCD as u8;
+        }
+        self.msr = inputs | (self.msr & MsrMask::ANY_DELTA as u8) | deltas;
     }
 
-    pub fn rx_byte(&mut self) -> u8 {
-        if self.rx_queue.is_empty() {
-            self.uart[UartRegister::LSR as usize] &= !(LsrMask::DR as u8);
-            return 0;
+    // a character arriving at the receiver
+    pub fn receive(&mut self, data: u8, errors: u8) {
+        let data = data & self.word_mask();
+        if self.rx_fifo.len() < self.rx_capacity() {
+            if self.rx_fifo.is_empty() {
+                self.lsr |= errors;
+            }
+            self.rx_fifo.push_back((data, errors));
+        } else if self.fifo_enabled() {
+            // the character in the shift register is lost
+            self.lsr |= LsrMask::OE as u8;
+        } else {
+            // the receiver buffer is overwritten
+            self.rx_fifo[0] = (data, errors);
+            self.lsr |= LsrMask::OE as u8 | errors;
         }
+        self.timeout_ipending = false;
+        self.rx_idle_ticks = 0;
+        self.update_interrupt();
+    }
 
-        if self.uart[UartRegister::LSR as usize] & LsrMask::BI as u8 != 0 {
-            self.uart[UartRegister::LSR as usize] &= !(LsrMask::BI as u8);
+    pub fn rx_byte(&mut self) -> u8 {
+        self.timeout_ipending = false;
+        self.rx_idle_ticks = 0;
+        let Some((data, _)) = self.rx_fifo.pop_front() else {
             return 0;
+        };
+        // the errors of a character show up in LSR once it reaches the top
+        if let Some((_, errors)) = self.rx_fifo.front() {
+            self.lsr |= errors;
         }
+        data
+    }
 
-        let front = self.rx_queue.pop_front().unwrap();
-        if self.rx_queue.is_empty() {
-            self.uart[UartRegister::LSR as usize] &= !(LsrMask::DR as u8);
+    pub fn tx_byte(&mut self, data: u8) {
+        let data = data & self.word_mask();
+        let break_control = self.lcr & LcrMask::SBC as u8 != 0;
+        if self.mcr & McrMask::LOOP as u8 != 0 {
+            if break_control {
+                self.receive(0, LsrMask::BI as u8);
+            } else {
+                self.receive(data, 0);
+            }
+        } else if !break_control {
+            self.backend.write(&[data]);
         }
-
-        front
+        self.thr_ipending = true;
     }
 
-    pub fn tx_byte(&mut self, data: char) {
-        self.uart[UartRegister::LSR as usize] |= (LsrMask::TEMT as u8) | (LsrMask::THRE as u8);
-        print!("{}", char::from_u32(data as u32).unwrap());
-        io::stdout().flush().expect("stdout flush failed");
+    // 5 to 8 data bits
+    fn word_mask(&self) -> u8 {
+        0xff >> (3 - (self.lcr & LcrMask::WLEN as u8))
     }
 }
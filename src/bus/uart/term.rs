@@ -0,0 +1,251 @@
+use once_cell::sync::OnceCell;
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+// Ctrl-A, as in QEMU
+const ESCAPE: u8 = 0x01;
+
+const HELP: &str = "
+C-a h    print this help
+C-a x    exit emulator
+C-a s    write a snapshot
+C-a b    send a break
+C-a c    switch to the monitor
+C-a C-a  send C-a
+";
+
+// what the escape sequences ask the emulation loop for
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Request {
+    Quit = 1,
+    Monitor = 2,
+}
+
+static REQUEST: AtomicU8 = AtomicU8::new(0);
+// the reader leaves stdin to the monitor while this is set
+static MONITOR_ACTIVE: AtomicBool = AtomicBool::new(false);
+static BREAK: AtomicBool = AtomicBool::new(false);
+// the terminal settings to go back to
+static ORIGINAL: OnceCell<libc::termios> = OnceCell::new();
+
+pub fn take_request() -> Option<Request> {
+    match REQUEST.swap(0, Ordering::Relaxed) {
+        1 => Some(Request::Quit),
+        2 => Some(Request::Monitor),
+        _ => None,
+    }
+}
+
+// a break condition on the line, as for the magic SysRq key
+pub fn take_break() -> bool {
+    BREAK.swap(false, Ordering::Relaxed)
+}
+
+#[derive(Debug, PartialEq)]
+enum Key {
+    Input(u8),
+    Request(Request),
+    Snapshot,
+    Break,
+    Help,
+    Ignored,
+}
+
+#[derive(Default)]
+struct Escape {
+    pending: bool,
+}
+
+impl Escape {
+    fn feed(&mut self, byte: u8) -> Key {
+        if !self.pending {
+            if byte == ESCAPE {
+                self.pending = true;
+                return Key::Ignored;
+            }
+            return Key::Input(byte);
+        }
+
+        self.pending = false;
+        match byte {
+            ESCAPE => Key::Input(ESCAPE),
+            b'x' => Key::Request(Request::Quit),
+            b'c' => Key::Request(Request::Monitor),
+            b's' => Key::Snapshot,
+            b'b' => Key::Break,
+            b'h' | b'?' => Key::Help,
+            _ => Key::Ignored,
+        }
+    }
+}
+
+// feed stdin to the UART; raw mode forwards every keystroke as it is typed
+pub fn spawn_reader(raw: bool) -> mpsc::Receiver<Vec<u8>> {
+    let (tx, rx) = mpsc::channel::<Vec<u8>>();
+    if raw {
+        if let Err(e) = enter_raw_mode() {
+            eprintln!("stdin stays in line mode: {e}");
+            return spawn_reader(false);
+        }
+        thread::spawn(move || read_raw(tx));
+    } else {
+        thread::spawn(move || loop {
+            let mut buffer = String::new();
+            match io::stdin().read_line(&mut buffer) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {
+                    if tx.send(buffer.into_bytes()).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+    }
+    rx
+}
+
+fn read_raw(tx: mpsc::Sender<Vec<u8>>) {
+    let mut escape = Escape::default();
+    let mut buf = [0; 64];
+    loop {
+        while MONITOR_ACTIVE.load(Ordering::Relaxed) {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let len = match io::stdin().read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(len) => len,
+        };
+        let mut input = Vec::new();
+        for &byte in &buf[..len] {
+            match escape.feed(byte) {
+                Key::Input(byte) => input.push(byte),
+                Key::Request(request) => {
+                    if request == Request::Monitor {
+                        MONITOR_ACTIVE.store(true, Ordering::Relaxed);
+                    }
+                    REQUEST.store(request as u8, Ordering::Relaxed);
+                }
+                Key::Snapshot => crate::snapshot::request(),
+                Key::Break => BREAK.store(true, Ordering::Relaxed),
+                Key::Help => {
+                    let mut stderr = io::stderr();
+                    let _ = stderr.write_all(HELP.as_bytes());
+                }
+                Key::Ignored => (),
+            }
+        }
+        if !input.is_empty() && tx.send(input).is_err() {
+            break;
+        }
+    }
+}
+
+fn enter_raw_mode() -> io::Result<()> {
+    let fd = libc::STDIN_FILENO;
+    if unsafe { libc::isatty(fd) } == 0 {
+        return Err(io::Error::new(io::ErrorKind::Unsupported, "not a terminal"));
+    }
+
+    let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+    if unsafe { libc::tcgetattr(fd, &mut termios) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if ORIGINAL.set(termios).is_ok() {
+        // process::exit skips destructors, so the terminal is put back from atexit
+        unsafe { libc::atexit(restore_at_exit) };
+        let hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            restore();
+            hook(info);
+        }));
+    }
+    set_raw(true)
+}
+
+// OPOST is kept so that the emulator's own messages still start on a new line
+fn set_raw(raw: bool) -> io::Result<()> {
+    let Some(original) = ORIGINAL.get() else {
+        return Ok(());
+    };
+    let mut termios = *original;
+    if raw {
+        termios.c_iflag &= !(libc::IGNBRK
+            | libc::BRKINT
+            | libc::PARMRK
+            | libc::ISTRIP
+            | libc::INLCR
+            | libc::IGNCR
+            | libc::ICRNL
+            | libc::IXON);
+        termios.c_lflag &= !(libc::ECHO | libc::ECHONL | libc::ICANON | libc::ISIG | libc::IEXTEN);
+        termios.c_cflag &= !(libc::CSIZE | libc::PARENB);
+        termios.c_cflag |= libc::CS8;
+        termios.c_cc[libc::VMIN] = 1;
+        termios.c_cc[libc::VTIME] = 0;
+    }
+    if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+pub fn restore() {
+    let _ = set_raw(false);
+}
+
+extern "C" fn restore_at_exit() {
+    restore();
+}
+
+// the monitor reads whole lines from a cooked terminal
+pub fn enter_monitor() {
+    MONITOR_ACTIVE.store(true, Ordering::Relaxed);
+    restore();
+}
+
+pub fn leave_monitor() {
+    let _ = set_raw(true);
+    MONITOR_ACTIVE.store(false, Ordering::Relaxed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_test() {
+        let mut escape = Escape::default();
+        let keys: Vec<Key> = [
+            b'a', 0x03, ESCAPE, ESCAPE, ESCAPE, b's', ESCAPE, b'z', b'\r',
+        ]
+        .into_iter()
+        .map(|byte| escape.feed(byte))
+        .collect();
+        assert_eq!(
+            keys,
+            vec![
+                Key::Input(b'a'),
+                // Ctrl-C goes to the guest
+                Key::Input(0x03),
+                Key::Ignored,
+                Key::Input(ESCAPE),
+                Key::Ignored,
+                Key::Snapshot,
+                Key::Ignored,
+                Key::Ignored,
+                Key::Input(b'\r'),
+            ]
+        );
+
+        assert_eq!(escape.feed(ESCAPE), Key::Ignored);
+        assert_eq!(escape.feed(b'x'), Key::Request(Request::Quit));
+        assert_eq!(escape.feed(ESCAPE), Key::Ignored);
+        assert_eq!(escape.feed(b'c'), Key::Request(Request::Monitor));
+        assert_eq!(escape.feed(ESCAPE), Key::Ignored);
+        assert_eq!(escape.feed(b'b'), Key::Break);
+    }
+}
@@ -0,0 +1,410 @@
+mod block;
+mod console;
+mod net;
+mod queue;
+mod rng;
+#[cfg(test)]
+mod test_driver;
+
+use super::{Device, Dma, IrqLine};
+use crate::snapshot::{self, Reader, Snapshot, Writer};
+use crate::{log, TrapCause};
+pub use block::Block;
+pub use console::Console;
+pub use net::Net;
+use queue::{Virtqueue, QUEUE_NUM_MAX};
+pub use rng::Rng;
+use std::io;
+
+const MAGIC_VALUE: u32 = 0x7472_6976; // "virt"
+const MMIO_VERSION: u32 = 2;
+const VENDOR_ID: u32 = 0x4e52_4143; // "CARN"
+
+pub const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+
+const STATUS_DRIVER_OK: u32 = 4;
+const STATUS_DEVICE_NEEDS_RESET: u32 = 64;
+
+const INTERRUPT_USED_RING: u32 = 1;
+const INTERRUPT_CONFIG_CHANGE: u32 = 2;
+
+// register offsets of the virtio-mmio transport (version 2)
+const MAGIC: usize = 0x000;
+const VERSION: usize = 0x004;
+const DEVICE_ID: usize = 0x008;
+const VENDOR: usize = 0x00c;
+const DEVICE_FEATURES: usize = 0x010;
+const DEVICE_FEATURES_SEL: usize = 0x014;
+const DRIVER_FEATURES: usize = 0x020;
+const DRIVER_FEATURES_SEL: usize = 0x024;
+const QUEUE_SEL: usize = 0x030;
+const QUEUE_NUM_MAX_REG: usize = 0x034;
+const QUEUE_NUM: usize = 0x038;
+const QUEUE_READY: usize = 0x044;
+const QUEUE_NOTIFY: usize = 0x050;
+const INTERRUPT_STATUS: usize = 0x060;
+const INTERRUPT_ACK: usize = 0x064;
+const STATUS: usize = 0x070;
+const QUEUE_DESC_LOW: usize = 0x080;
+const QUEUE_DESC_HIGH: usize = 0x084;
+const QUEUE_DRIVER_LOW: usize = 0x090;
+const QUEUE_DRIVER_HIGH: usize = 0x094;
+const QUEUE_DEVICE_LOW: usize = 0x0a0;
+const QUEUE_DEVICE_HIGH: usize = 0x0a4;
+const CONFIG_GENERATION: usize = 0x0fc;
+const CONFIG: usize = 0x100;
+
+// the device specific part behind the virtio-mmio transport
+pub trait VirtioDevice {
+    fn device_id(&self) -> u32;
+    // device specific feature bits; VIRTIO_F_VERSION_1 is added by the transport
+    fn features(&self) -> u64;
+    fn num_queues(&self) -> usize;
+    fn read_config(&self, offset: usize, buf: &mut [u8]);
+    fn write_config(&mut self, _offset: usize, _data: &[u8]) {}
+    // serve the requests made available on a notified queue; true if any were used
+    fn process_queue(
+        &mut self,
+        index: usize,
+        queues: &mut [Virtqueue],
+        mem: &mut Dma,
+    ) -> Result<bool, String>;
+    // hand over data that arrived from the host side, after every access and tick
+    fn poll(&mut self, _queues: &mut [Virtqueue], _mem: &mut Dma) -> Result<bool, String> {
+        Ok(false)
+    }
+    fn reset(&mut self) {}
+    fn save_state(&self, _w: &mut Writer) -> io::Result<()> {
+        Ok(())
+    }
+    fn restore_state(&mut self, _r: &mut Reader) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+pub struct VirtioMmio<D: VirtioDevice> {
+    base_addr: u64,
+    irq: IrqLine,
+    device: D,
+    status: u32,
+    device_features_sel: u32,
+    driver_features: u64,
+    driver_features_sel: u32,
+    queue_sel: u32,
+    queues: Vec<Virtqueue>,
+    // bit n is set when queue n has been notified but not processed yet
+    notified: u64,
+    interrupt_status: u32,
+}
+
+impl<D: VirtioDevice> VirtioMmio<D> {
+    pub fn new(base_addr: u64, irq: IrqLine, device: D) -> Self {
+        let queues = (0..device.num_queues())
+            .map(|_| Virtqueue::default())
+            .collect();
+        VirtioMmio {
+            base_addr,
+            irq,
+            device,
+            status: 0,
+            device_features_sel: 0,
+            driver_features: 0,
+            driver_features_sel: 0,
+            queue_sel: 0,
+            queues,
+            notified: 0,
+            interrupt_status: 0,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.status = 0;
+        self.device_features_sel = 0;
+        self.driver_features = 0;
+        self.driver_features_sel = 0;
+        self.queue_sel = 0;
+        self.queues.iter_mut().for_each(|queue| queue.reset());
+        self.notified = 0;
+        self.interrupt_status = 0;
+        self.device.reset();
+        self.irq.lower();
+    }
+
+    fn features(&self) -> u64 {
+        self.device.features() | VIRTIO_F_VERSION_1
+    }
+
+    fn selected_queue(&mut self) -> Option<&mut Virtqueue> {
+        self.queues.get_mut(self.queue_sel as usize)
+    }
+
+    // replace the upper or lower half of a 64-bit register
+    fn set_half(reg: &mut u64, high: bool, val: u32) {
+        *reg = if high {
+            (*reg & 0xffff_ffff) | (val as u64) << 32
+        } else {
+            (*reg & !0xffff_ffff) | val as u64
+        };
+    }
+
+    fn read_reg(&self, offset: usize) -> u32 {
+        match offset {
+            MAGIC => MAGIC_VALUE,
+            VERSION => MMIO_VERSION,
+            DEVICE_ID => self.device.device_id(),
+            VENDOR => VENDOR_ID,
+            DEVICE_FEATURES => match self.device_features_sel {
+                0 => self.features() as u32,
+                1 => (self.features() >> 32) as u32,
+                _ => 0,
+            },
+            QUEUE_NUM_MAX_REG => self
+                .queues
+                .get(self.queue_sel as usize)
+                .map_or(0, |_| QUEUE_NUM_MAX as u32),
+            QUEUE_READY => self
+                .queues
+                .get(self.queue_sel as usize)
+                .map_or(0, |queue| queue.ready as u32),
+            INTERRUPT_STATUS => self.interrupt_status,
+            STATUS => self.status,
+            CONFIG_GENERATION => 0,
+            _ => 0,
+        }
+    }
+
+    fn write_reg(&mut self, offset: usize, val: u32) {
+        match offset {
+            DEVICE_FEATURES_SEL => self.device_features_sel = val,
+            DRIVER_FEATURES if self.driver_features_sel < 2 => {
+                let features = self.features();
+                let high = self.driver_features_sel == 1;
+                Self::set_half(&mut self.driver_features, high, val);
+                self.driver_features &= features;
+            }
+            DRIVER_FEATURES_SEL => self.driver_features_sel = val,
+            QUEUE_SEL => self.queue_sel = val,
+            QUEUE_NUM => {
+                if let Some(queue) = self.selected_queue() {
+                    queue.num = (val as u16).clamp(1, QUEUE_NUM_MAX);
+                }
+            }
+            QUEUE_READY => {
+                if let Some(queue) = self.selected_queue() {
+                    queue.ready = val & 1 != 0;
+                }
+            }
+            QUEUE_NOTIFY if (val as usize) < self.queues.len() => self.notified |= 1 << val,
+            INTERRUPT_ACK => {
+                self.interrupt_status &= !val;
+                self.irq.set_level(self.interrupt_status != 0);
+            }
+            STATUS => {
+                if val == 0 {
+                    self.reset();
+                } else {
+                    self.status = val;
+                }
+            }
+            QUEUE_DESC_LOW | QUEUE_DESC_HIGH => {
+                if let Some(queue) = self.selected_queue() {
+                    Self::set_half(&mut queue.desc_addr, offset == QUEUE_DESC_HIGH, val);
+                }
+            }
+            QUEUE_DRIVER_LOW | QUEUE_DRIVER_HIGH => {
+                if let Some(queue) = self.selected_queue() {
+                    Self::set_half(&mut queue.driver_addr, offset == QUEUE_DRIVER_HIGH, val);
+                }
+            }
+            QUEUE_DEVICE_LOW | QUEUE_DEVICE_HIGH => {
+                if let Some(queue) = self.selected_queue() {
+                    Self::set_half(&mut queue.device_addr, offset == QUEUE_DEVICE_HIGH, val);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn read_config(&self, offset: usize, size: usize) -> u64 {
+        let mut buf = [0; 8];
+        self.device.read_config(offset - CONFIG, &mut buf[..size]);
+        u64::from_le_bytes(buf)
+    }
+
+    fn access_fault(
+        &self,
+        addr: u64,
+        cause: TrapCause,
+        access: &str,
+    ) -> (Option<u64>, TrapCause, String) {
+        (
+            Some(addr),
+            cause,
+            format!("virtio-mmio registers only allow {access} in the config space"),
+        )
+    }
+
+    fn process_queues(&mut self, mem: &mut Dma) -> Result<bool, String> {
+        let mut used = false;
+        while self.notified != 0 {
+            let index = self.notified.trailing_zeros() as usize;
+            self.notified &= !(1 << index);
+            used |= self.device.process_queue(index, &mut self.queues, mem)?;
+        }
+        used |= self.device.poll(&mut self.queues, mem)?;
+        Ok(used)
+    }
+}
+
+#[allow(clippy::identity_op)]
+impl<D: VirtioDevice> Device for VirtioMmio<D> {
+    // is addr in device address space
+    fn in_range(&self, addr: u64) -> bool {
+        (self.base_addr..self.base_addr + crate::machine::VIRTIO_SIZE).contains(&addr)
+    }
+
+    // address to raw index
+    fn addr2index(&self, addr: u64) -> usize {
+        (addr - self.base_addr) as usize
+    }
+
+    // store
+    fn store8(&mut self, addr: u64, data: u64) -> Result<(), (Option<u64>, TrapCause, String)> {
+        match self.addr2index(addr) {
+            offset @ CONFIG.. => {
+                self.device.write_config(offset - CONFIG, &[data as u8]);
+                Ok(())
+            }
+            _ => Err(self.access_fault(addr, TrapCause::StoreAMOAccessFault, "store8")),
+        }
+    }
+
+    fn store16(&mut self, addr: u64, data: u64) -> Result<(), (Option<u64>, TrapCause, String)> {
+        match self.addr2index(addr) {
+            offset @ CONFIG.. => {
+                self.device
+                    .write_config(offset - CONFIG, &(data as u16).to_le_bytes());
+                Ok(())
+            }
+            _ => Err(self.access_fault(addr, TrapCause::StoreAMOAccessFault, "store16")),
+        }
+    }
+
+    fn store32(&mut self, addr: u64, data: u64) -> Result<(), (Option<u64>, TrapCause, String)> {
+        match self.addr2index(addr) {
+            offset @ CONFIG.. => self
+                .device
+                .write_config(offset - CONFIG, &(data as u32).to_le_bytes()),
+            offset => self.write_reg(offset, data as u32),
+        }
+        Ok(())
+    }
+
+    fn store64(&mut self, addr: u64, data: u64) -> Result<(), (Option<u64>, TrapCause, String)> {
+        match self.addr2index(addr) {
+            offset @ CONFIG.. => {
+                self.device
+                    .write_config(offset - CONFIG, &data.to_le_bytes());
+                Ok(())
+            }
+            _ => Err(self.access_fault(addr, TrapCause::StoreAMOAccessFault, "store64")),
+        }
+    }
+
+    // load
+    fn load8(&mut self, addr: u64) -> Result<u64, (Option<u64>, TrapCause, String)> {
+        self.load_u8(addr).map(|data| data as i8 as i64 as u64)
+    }
+
+    fn load16(&self, addr: u64) -> Result<u64, (Option<u64>, TrapCause, String)> {
+        self.load_u16(addr).map(|data| data as i16 as i64 as u64)
+    }
+
+    fn load32(&mut self, addr: u64) -> Result<u64, (Option<u64>, TrapCause, String)> {
+        self.load_u32(addr).map(|data| data as i32 as i64 as u64)
+    }
+
+    fn load64(&mut self, addr: u64) -> Result<u64, (Option<u64>, TrapCause, String)> {
+        match self.addr2index(addr) {
+            offset @ CONFIG.. => Ok(self.read_config(offset, 8)),
+            _ => Err(self.access_fault(addr, TrapCause::LoadAccessFault, "load64")),
+        }
+    }
+
+    fn load_u8(&self, addr: u64) -> Result<u64, (Option<u64>, TrapCause, String)> {
+        match self.addr2index(addr) {
+            offset @ CONFIG.. => Ok(self.read_config(offset, 1)),
+            _ => Err(self.access_fault(addr, TrapCause::LoadAccessFault, "load8")),
+        }
+    }
+
+    fn load_u16(&self, addr: u64) -> Result<u64, (Option<u64>, TrapCause, String)> {
+        match self.addr2index(addr) {
+            offset @ CONFIG.. => Ok(self.read_config(offset, 2)),
+            _ => Err(self.access_fault(addr, TrapCause::LoadAccessFault, "load16")),
+        }
+    }
+
+    fn load_u32(&self, addr: u64) -> Result<u64, (Option<u64>, TrapCause, String)> {
+        match self.addr2index(addr) {
+            offset @ CONFIG.. => Ok(self.read_config(offset, 4)),
+            offset => Ok(self.read_reg(offset) as u64),
+        }
+    }
+
+    fn dma(&mut self, mem: &mut Dma) {
+        if self.status & STATUS_DRIVER_OK == 0 || self.status & STATUS_DEVICE_NEEDS_RESET != 0 {
+            return;
+        }
+
+        match self.process_queues(mem) {
+            Ok(true) => self.interrupt_status |= INTERRUPT_USED_RING,
+            Ok(false) => (),
+            Err(msg) => {
+                log::infoln!("[virtio] device {} failed: {msg}", self.device.device_id());
+                self.status |= STATUS_DEVICE_NEEDS_RESET;
+                self.interrupt_status |= INTERRUPT_CONFIG_CHANGE;
+            }
+        }
+        self.irq.set_level(self.interrupt_status != 0);
+    }
+
+    fn save_state(&self, w: &mut Writer) -> io::Result<()> {
+        w.tag(b"VIRT")?;
+        w.u32(self.status)?;
+        w.u32(self.device_features_sel)?;
+        w.u64(self.driver_features)?;
+        w.u32(self.driver_features_sel)?;
+        w.u32(self.queue_sel)?;
+        w.u64(self.notified)?;
+        w.u32(self.interrupt_status)?;
+        w.u64(self.queues.len() as u64)?;
+        self.queues.iter().try_for_each(|queue| queue.save(w))?;
+        self.device.save_state(w)
+    }
+
+    fn restore_state(&mut self, r: &mut Reader) -> io::Result<()> {
+        r.tag(b"VIRT")?;
+        self.status = r.u32()?;
+        self.device_features_sel = r.u32()?;
+        self.driver_features = r.u64()?;
+        self.driver_features_sel = r.u32()?;
+        self.queue_sel = r.u32()?;
+        self.notified = r.u64()?;
+        self.interrupt_status = r.u32()?;
+        let num = r.u64()? as usize;
+        if num != self.queues.len() {
+            return Err(snapshot::invalid_data(format!(
+                "expected {} virtqueues but found {num}",
+                self.queues.len()
+            )));
+        }
+        self.queues
+            .iter_mut()
+            .try_for_each(|queue| queue.restore(r))?;
+        self.device.restore_state(r)?;
+        self.irq.set_level(self.interrupt_status != 0);
+
+        Ok(())
+    }
+}
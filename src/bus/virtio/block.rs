@@ -0,0 +1,269 @@
+use super::queue::{DescChain, Virtqueue};
+use super::VirtioDevice;
+use crate::bus::Dma;
+use crate::log;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::fs::FileExt;
+
+const VIRTIO_ID_BLOCK: u32 = 2;
+const VIRTIO_BLK_F_SIZE_MAX: u64 = 1 << 1;
+const VIRTIO_BLK_F_SEG_MAX: u64 = 1 << 2;
+const VIRTIO_BLK_F_RO: u64 = 1 << 5;
+const VIRTIO_BLK_F_FLUSH: u64 = 1 << 9;
+
+const SECTOR_SIZE: u64 = 512;
+const REQ_HEADER_SIZE: usize = 16;
+const ID_BYTES: usize = 20;
+
+// the data of a request is limited through size_max and seg_max
+const SIZE_MAX: u32 = 0x1_0000;
+const SEG_MAX: u32 = 128;
+const MAX_REQUEST: usize = REQ_HEADER_SIZE + (SEG_MAX * SIZE_MAX) as usize + 1;
+
+const VIRTIO_BLK_T_IN: u32 = 0;
+const VIRTIO_BLK_T_OUT: u32 = 1;
+const VIRTIO_BLK_T_FLUSH: u32 = 4;
+const VIRTIO_BLK_T_GET_ID: u32 = 8;
+
+const VIRTIO_BLK_S_OK: u8 = 0;
+const VIRTIO_BLK_S_IOERR: u8 = 1;
+const VIRTIO_BLK_S_UNSUPP: u8 = 2;
+
+// virtio-blk backed by a raw disk image on the host
+pub struct Block {
+    disk: File,
+    // in 512-byte sectors
+    capacity: u64,
+    readonly: bool,
+}
+
+impl Block {
+    pub fn new(path: &str, readonly: bool) -> io::Result<Self> {
+        let disk = OpenOptions::new().read(true).write(!readonly).open(path)?;
+        let capacity = disk.metadata()?.len() / SECTOR_SIZE;
+
+        Ok(Block {
+            disk,
+            capacity,
+            readonly,
+        })
+    }
+
+    // byte offset of an access that stays within the disk
+    fn disk_offset(&self, sector: u64, len: usize) -> Option<u64> {
+        let offset = sector.checked_mul(SECTOR_SIZE)?;
+        offset
+            .checked_add(len as u64)
+            .filter(|&end| end <= self.capacity * SECTOR_SIZE)
+            .map(|_| offset)
+    }
+
+    fn io_status(result: io::Result<()>) -> u8 {
+        match result {
+            Ok(()) => VIRTIO_BLK_S_OK,
+            Err(e) => {
+                log::infoln!("[virtio-blk] {e}");
+                VIRTIO_BLK_S_IOERR
+            }
+        }
+    }
+
+    // serve a request and return the number of bytes written into it
+    fn handle(&mut self, chain: &DescChain, mem: &mut Dma) -> Result<u32, String> {
+        let mut header = [0; REQ_HEADER_SIZE];
+        if chain.read(mem, 0, &mut header)? < REQ_HEADER_SIZE || chain.writable_len() == 0 {
+            return Err("malformed block request".to_string());
+        }
+        let req_type = u32::from_le_bytes(header[0..4].try_into().unwrap());
+        let sector = u64::from_le_bytes(header[8..16].try_into().unwrap());
+
+        // the last writable byte receives the status
+        let status_offset = chain.writable_len() - 1;
+        let (status, written) = match req_type {
+            VIRTIO_BLK_T_IN => match self.disk_offset(sector, status_offset) {
+                Some(offset) => {
+                    let mut data = vec![0; status_offset];
+                    let status = Self::io_status(self.disk.read_exact_at(&mut data, offset));
+                    chain.write(mem, 0, &data)?;
+                    (status, status_offset)
+                }
+                None => (VIRTIO_BLK_S_IOERR, 0),
+            },
+            VIRTIO_BLK_T_OUT => {
+                let mut data = vec![0; chain.readable_len() - REQ_HEADER_SIZE];
+                chain.read(mem, REQ_HEADER_SIZE, &mut data)?;
+                match self.disk_offset(sector, data.len()) {
+                    Some(offset) if !self.readonly => {
+                        (Self::io_status(self.disk.write_all_at(&data, offset)), 0)
+                    }
+                    _ => (VIRTIO_BLK_S_IOERR, 0),
+                }
+            }
+            VIRTIO_BLK_T_FLUSH if !self.readonly => (Self::io_status(self.disk.sync_data()), 0),
+            VIRTIO_BLK_T_GET_ID => {
+                let mut id = [0; ID_BYTES];
+                id[..17].copy_from_slice(b"carron-virtio-blk");
+                let len = ID_BYTES.min(status_offset);
+                chain.write(mem, 0, &id[..len])?;
+                (VIRTIO_BLK_S_OK, len)
+            }
+            _ => (VIRTIO_BLK_S_UNSUPP, 0),
+        };
+        chain.write(mem, status_offset, &[status])?;
+
+        Ok(written as u32 + 1)
+    }
+}
+
+impl VirtioDevice for Block {
+    fn device_id(&self) -> u32 {
+        VIRTIO_ID_BLOCK
+    }
+
+    fn features(&self) -> u64 {
+        let features = VIRTIO_BLK_F_SIZE_MAX | VIRTIO_BLK_F_SEG_MAX;
+        if self.readonly {
+            features | VIRTIO_BLK_F_RO
+        } else {
+            features | VIRTIO_BLK_F_FLUSH
+        }
+    }
+
+    fn num_queues(&self) -> usize {
+        1
+    }
+
+    // capacity, size_max and seg_max
+    fn read_config(&self, offset: usize, buf: &mut [u8]) {
+        let config = [
+            &self.capacity.to_le_bytes()[..],
+            &SIZE_MAX.to_le_bytes(),
+            &SEG_MAX.to_le_bytes(),
+        ]
+        .concat();
+        for (i, byte) in buf.iter_mut().enumerate() {
+            *byte = config.get(offset + i).copied().unwrap_or(0);
+        }
+    }
+
+    fn process_queue(
+        &mut self,
+        index: usize,
+        queues: &mut [Virtqueue],
+        mem: &mut Dma,
+    ) -> Result<bool, String> {
+        let queue = &mut queues[index];
+        let mut used = false;
+        while let Some(chain) = queue.pop(mem, MAX_REQUEST)? {
+            let len = self.handle(&chain, mem)?;
+            queue.push_used(mem, chain.head, len)?;
+            used = true;
+        }
+        Ok(used)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_driver::{read_bytes, write_bytes, Driver};
+    use crate::bus::Bus;
+    use crate::cmdline::Arguments;
+    use crate::Isa;
+    use std::io::Write;
+
+    const VIRTIO_BASE: u64 = 0x1000_1000;
+    const VIRTIO_IRQ: u32 = 2;
+
+    // a request of header, one sector of data and status
+    fn submit(bus: &mut Bus, driver: &mut Driver, req_type: u32, sector: u64) -> (u64, u64) {
+        let (header, data, status) = (driver.alloc(16), driver.alloc(512), driver.alloc(1));
+        bus.store32(header, req_type as u64).unwrap();
+        bus.store64(header + 8, sector).unwrap();
+        driver.submit(
+            bus,
+            0,
+            &[
+                (header, 16, false),
+                (data, 512, req_type == 0),
+                (status, 1, true),
+            ],
+        );
+        (data, status)
+    }
+
+    #[test]
+    fn block_request_test() {
+        let path = std::env::temp_dir().join(format!("carron-disk-{}", std::process::id()));
+        let image: Vec<u8> = (0..4 * 512).map(|i| (i / 512 + i % 7) as u8).collect();
+        std::fs::File::create(&path)
+            .unwrap()
+            .write_all(&image)
+            .unwrap();
+        let mut args = Arguments::for_elf("./HelloWorld");
+        args.machine
+            .set("virtio_blk_image", path.to_str().unwrap())
+            .unwrap();
+        let mut bus = Bus::for_test(&args, Isa::Rv32);
+
+        assert_eq!(bus.load32(VIRTIO_BASE).unwrap(), 0x7472_6976);
+        assert_eq!(bus.load32(VIRTIO_BASE + 0x008).unwrap(), 2);
+        // the capacity in sectors
+        assert_eq!(bus.load64(VIRTIO_BASE + 0x100).unwrap(), 4);
+
+        // read sector 1 into DRAM
+        let mut driver = Driver::new(&mut bus, VIRTIO_BASE, 1);
+        let (data, status) = submit(&mut bus, &mut driver, 0, 1);
+        assert_eq!(read_bytes(&bus, data, 512), &image[512..1024]);
+        assert_eq!(bus.load_u8(status).unwrap(), 0);
+        assert_eq!(driver.used_idx(&bus, 0), 1);
+        assert_eq!(driver.used(&bus, 0, 0), (0, 513));
+        assert_eq!(bus.load32(VIRTIO_BASE + 0x060).unwrap(), 1);
+        assert!(bus.plic.interrupt_level(VIRTIO_IRQ));
+
+        bus.store32(VIRTIO_BASE + 0x064, 1).unwrap();
+        assert!(!bus.plic.interrupt_level(VIRTIO_IRQ));
+
+        // write sector 3 from DRAM
+        let (header, data, status) = (driver.alloc(16), driver.alloc(512), driver.alloc(1));
+        bus.store32(header, 1).unwrap();
+        bus.store64(header + 8, 3).unwrap();
+        write_bytes(&mut bus, data, &[0x5a; 512]);
+        driver.submit(
+            &mut bus,
+            0,
+            &[(header, 16, false), (data, 512, false), (status, 1, true)],
+        );
+        assert_eq!(bus.load_u8(status).unwrap(), 0);
+        assert_eq!(std::fs::read(&path).unwrap()[3 * 512..], [0x5a; 512]);
+
+        // a request beyond the end of the disk fails
+        let (_, status) = submit(&mut bus, &mut driver, 0, 4);
+        assert_eq!(bus.load_u8(status).unwrap(), 1);
+
+        // a read-only disk rejects writes
+        args.machine.virtio_blk_readonly = true;
+        let mut bus = Bus::for_test(&args, Isa::Rv32);
+        assert_ne!(bus.load32(VIRTIO_BASE + 0x010).unwrap() & 1 << 5, 0);
+        let mut driver = Driver::new(&mut bus, VIRTIO_BASE, 1);
+        let (_, status) = submit(&mut bus, &mut driver, 1, 0);
+        assert_eq!(bus.load_u8(status).unwrap(), 1);
+        assert_eq!(std::fs::read(&path).unwrap()[..512], image[..512]);
+
+        // a buffer that isn't in DRAM or is too long breaks the device
+        for len in [u32::MAX, 0x4000_0000] {
+            let mut bus = Bus::for_test(&args, Isa::Rv32);
+            let mut driver = Driver::new(&mut bus, VIRTIO_BASE, 1);
+            let (header, status) = (driver.alloc(16), driver.alloc(1));
+            driver.submit(
+                &mut bus,
+                0,
+                &[(header, 16, false), (header, len, true), (status, 1, true)],
+            );
+            assert_eq!(driver.used_idx(&bus, 0), 0);
+            assert_ne!(bus.load32(VIRTIO_BASE + 0x070).unwrap() & 0x40, 0);
+        }
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,371 @@
+mod port;
+
+use super::queue::Virtqueue;
+use super::VirtioDevice;
+use crate::bus::Dma;
+use crate::snapshot::{self, Reader, Writer};
+use port::Backend;
+use std::collections::VecDeque;
+use std::io;
+
+const VIRTIO_ID_CONSOLE: u32 = 3;
+const VIRTIO_CONSOLE_F_MULTIPORT: u64 = 1 << 1;
+const VIRTIO_CONSOLE_F_EMERG_WRITE: u64 = 1 << 2;
+
+// events of the control queues
+const DEVICE_READY: u16 = 0;
+const DEVICE_ADD: u16 = 1;
+const PORT_READY: u16 = 3;
+const CONSOLE_PORT: u16 = 4;
+const PORT_OPEN: u16 = 6;
+const PORT_NAME: u16 = 7;
+
+const CONTROL_RX: usize = 2;
+const CONTROL_TX: usize = 3;
+const CONTROL_SIZE: usize = 8;
+const EMERG_WR: usize = 8;
+
+// Linux writes at most 32KiB at once and receives into single pages
+const MAX_BUFFER: usize = 0x1_0000;
+// input held back until the driver provides receive buffers
+const INPUT_LIMIT: usize = 4096;
+
+struct Port {
+    backend: Backend,
+    input: VecDeque<u8>,
+    // opened by a guest process
+    open: bool,
+}
+
+// virtio-console; port 0 is the console and the others are named "portN"
+pub struct Console {
+    ports: Vec<Port>,
+    // messages waiting for a buffer on the control receive queue
+    control: VecDeque<Vec<u8>>,
+}
+
+// queues are laid out as port 0 rx/tx, control rx/tx, then rx/tx of port 1, 2, ...
+fn rx_queue(port: usize) -> usize {
+    if port == 0 {
+        0
+    } else {
+        2 + 2 * port
+    }
+}
+
+fn tx_port(queue: usize) -> Option<usize> {
+    match queue {
+        1 => Some(0),
+        CONTROL_TX => None,
+        n if n > CONTROL_TX && n % 2 == 1 => Some((n - 2) / 2),
+        _ => None,
+    }
+}
+
+fn control_message(id: u32, event: u16, value: u16) -> Vec<u8> {
+    [
+        &id.to_le_bytes()[..],
+        &event.to_le_bytes(),
+        &value.to_le_bytes(),
+    ]
+    .concat()
+}
+
+impl Console {
+    pub fn new(specs: &[String]) -> io::Result<Self> {
+        let ports = specs
+            .iter()
+            .map(|spec| {
+                Ok(Port {
+                    backend: Backend::open(spec)?,
+                    input: VecDeque::new(),
+                    open: false,
+                })
+            })
+            .collect::<io::Result<Vec<Port>>>()?;
+
+        Ok(Console {
+            ports,
+            control: VecDeque::new(),
+        })
+    }
+
+    fn multiport(&self) -> bool {
+        self.ports.len() > 1
+    }
+
+    fn handle_control(&mut self, msg: &[u8]) {
+        let id = u32::from_le_bytes(msg[0..4].try_into().unwrap());
+        let event = u16::from_le_bytes(msg[4..6].try_into().unwrap());
+        let value = u16::from_le_bytes(msg[6..8].try_into().unwrap());
+        match event {
+            DEVICE_READY if value == 1 => {
+                for id in 0..self.ports.len() {
+                    self.control
+                        .push_back(control_message(id as u32, DEVICE_ADD, 1));
+                }
+            }
+            PORT_READY if value == 1 && (id as usize) < self.ports.len() => {
+                if id == 0 {
+                    self.control.push_back(control_message(id, CONSOLE_PORT, 1));
+                } else {
+                    let mut msg = control_message(id, PORT_NAME, 1);
+                    msg.extend(format!("port{id}").as_bytes());
+                    self.control.push_back(msg);
+                }
+                self.control.push_back(control_message(id, PORT_OPEN, 1));
+            }
+            PORT_OPEN if (id as usize) < self.ports.len() => {
+                self.ports[id as usize].open = value != 0;
+            }
+            _ => (),
+        }
+    }
+
+    // fill receive buffers with as much of the data as they take
+    fn deliver(
+        queue: &mut Virtqueue,
+        data: &mut VecDeque<u8>,
+        mem: &mut Dma,
+    ) -> Result<bool, String> {
+        let mut used = false;
+        while !data.is_empty() {
+            let Some(chain) = queue.pop(mem, MAX_BUFFER)? else {
+                break;
+            };
+            let len = chain.writable_len().min(data.len());
+            let bytes: Vec<u8> = data.drain(..len).collect();
+            chain.write(mem, 0, &bytes)?;
+            queue.push_used(mem, chain.head, len as u32)?;
+            used = true;
+        }
+        Ok(used)
+    }
+}
+
+impl VirtioDevice for Console {
+    fn device_id(&self) -> u32 {
+        VIRTIO_ID_CONSOLE
+    }
+
+    fn features(&self) -> u64 {
+        if self.multiport() {
+            VIRTIO_CONSOLE_F_MULTIPORT | VIRTIO_CONSOLE_F_EMERG_WRITE
+        } else {
+            VIRTIO_CONSOLE_F_EMERG_WRITE
+        }
+    }
+
+    fn num_queues(&self) -> usize {
+        if self.multiport() {
+            2 * (self.ports.len() + 1)
+        } else {
+            2
+        }
+    }
+
+    // cols, rows, max_nr_ports and emerg_wr
+    fn read_config(&self, offset: usize, buf: &mut [u8]) {
+        let config = [
+            &0u16.to_le_bytes()[..],
+            &0u16.to_le_bytes(),
+            &(self.ports.len() as u32).to_le_bytes(),
+            &0u32.to_le_bytes(),
+        ]
+        .concat();
+        for (i, byte) in buf.iter_mut().enumerate() {
+            *byte = config.get(offset + i).copied().unwrap_or(0);
+        }
+    }
+
+    // a write to emerg_wr puts the character out on port 0
+    fn write_config(&mut self, offset: usize, data: &[u8]) {
+        if offset == EMERG_WR {
+            self.ports[0].backend.write(&data[..1]);
+        }
+    }
+
+    fn process_queue(
+        &mut self,
+        index: usize,
+        queues: &mut [Virtqueue],
+        mem: &mut Dma,
+    ) -> Result<bool, String> {
+        if index != CONTROL_TX && tx_port(index).is_none() {
+            // receive buffers are filled on poll
+            return Ok(false);
+        }
+
+        let queue = &mut queues[index];
+        let mut used = false;
+        while let Some(chain) = queue.pop(mem, MAX_BUFFER)? {
+            let mut data = vec![0; chain.readable_len()];
+            chain.read(mem, 0, &mut data)?;
+            match tx_port(index) {
+                Some(port) => self.ports[port].backend.write(&data),
+                None if data.len() >= CONTROL_SIZE => self.handle_control(&data),
+                None => return Err("short control message".to_string()),
+            }
+            queue.push_used(mem, chain.head, 0)?;
+            used = true;
+        }
+        Ok(used)
+    }
+
+    fn poll(&mut self, queues: &mut [Virtqueue], mem: &mut Dma) -> Result<bool, String> {
+        let mut used = false;
+        for (id, port) in self.ports.iter_mut().enumerate() {
+            let mut buf = [0; 256];
+            while port.input.len() < INPUT_LIMIT {
+                let len = port.backend.read(&mut buf);
+                if len == 0 {
+                    break;
+                }
+                port.input.extend(&buf[..len]);
+            }
+            // ports other than the console hold their input until a guest process opens them
+            if id == 0 || port.open {
+                used |= Self::deliver(&mut queues[rx_queue(id)], &mut port.input, mem)?;
+            }
+        }
+
+        if self.multiport() {
+            let queue = &mut queues[CONTROL_RX];
+            while !self.control.is_empty() {
+                let Some(chain) = queue.pop(mem, MAX_BUFFER)? else {
+                    break;
+                };
+                let msg = self.control.pop_front().unwrap();
+                let len = chain.write(mem, 0, &msg)?;
+                queue.push_used(mem, chain.head, len as u32)?;
+                used = true;
+            }
+        }
+        Ok(used)
+    }
+
+    fn reset(&mut self) {
+        self.control.clear();
+        for port in self.ports.iter_mut() {
+            port.input.clear();
+            port.open = false;
+        }
+    }
+
+    fn save_state(&self, w: &mut Writer) -> io::Result<()> {
+        w.tag(b"CONS")?;
+        w.u64(self.ports.len() as u64)?;
+        for port in self.ports.iter() {
+            w.u8(port.open as u8)?;
+            w.bytes(&port.input.iter().copied().collect::<Vec<u8>>())?;
+        }
+        w.u64(self.control.len() as u64)?;
+        self.control.iter().try_for_each(|msg| w.bytes(msg))
+    }
+
+    fn restore_state(&mut self, r: &mut Reader) -> io::Result<()> {
+        r.tag(b"CONS")?;
+        let num = r.u64()? as usize;
+        if num != self.ports.len() {
+            return Err(snapshot::invalid_data(format!(
+                "expected {} console ports but found {num}",
+                self.ports.len()
+            )));
+        }
+        for port in self.ports.iter_mut() {
+            port.open = r.u8()? != 0;
+            port.input = r.bytes()?.into();
+        }
+        let num = r.u64()? as usize;
+        self.control = (0..num).map(|_| r.bytes()).collect::<io::Result<_>>()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_driver::{read_bytes, write_bytes, Driver};
+    use crate::bus::Bus;
+    use crate::cmdline::Arguments;
+    use crate::Isa;
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixStream;
+
+    const VIRTIO_BASE: u64 = 0x1000_1000;
+
+    fn send(bus: &mut Bus, driver: &mut Driver, queue: usize, data: &[u8]) {
+        let buf = driver.alloc(data.len() as u64);
+        write_bytes(bus, buf, data);
+        driver.submit(bus, queue, &[(buf, data.len() as u32, false)]);
+    }
+
+    fn receive_buffer(bus: &mut Bus, driver: &mut Driver, queue: usize) -> u64 {
+        let buf = driver.alloc(64);
+        driver.submit(bus, queue, &[(buf, 64, true)]);
+        buf
+    }
+
+    #[test]
+    fn multiport_test() {
+        let dir = std::env::temp_dir();
+        let log = dir.join(format!("carron-console-{}", std::process::id()));
+        let sock = dir.join(format!("carron-port1-{}", std::process::id()));
+        let mut args = Arguments::for_elf("./HelloWorld");
+        args.machine
+            .set(
+                "virtio_console",
+                &format!("file:{}, unix:{}", log.display(), sock.display()),
+            )
+            .unwrap();
+        let mut bus = Bus::for_test(&args, Isa::Rv32);
+        assert_eq!(bus.load32(VIRTIO_BASE + 0x008).unwrap(), 3);
+        // max_nr_ports
+        assert_eq!(bus.load32(VIRTIO_BASE + 0x104).unwrap(), 2);
+        let mut driver = Driver::new(&mut bus, VIRTIO_BASE, 6);
+
+        // the device announces both ports once the driver is ready
+        let control: Vec<u64> = (0..2)
+            .map(|_| receive_buffer(&mut bus, &mut driver, 2))
+            .collect();
+        send(&mut bus, &mut driver, 3, &[0, 0, 0, 0, 0, 0, 1, 0]);
+        assert_eq!(driver.used_idx(&bus, 2), 2);
+        assert_eq!(read_bytes(&bus, control[0], 8), [0, 0, 0, 0, 1, 0, 1, 0]);
+        assert_eq!(read_bytes(&bus, control[1], 8), [1, 0, 0, 0, 1, 0, 1, 0]);
+
+        // and names port 1 when it is ready
+        let control: Vec<u64> = (0..2)
+            .map(|_| receive_buffer(&mut bus, &mut driver, 2))
+            .collect();
+        send(&mut bus, &mut driver, 3, &[1, 0, 0, 0, 3, 0, 1, 0]);
+        assert_eq!(driver.used(&bus, 2, 2).1, 13);
+        assert_eq!(
+            read_bytes(&bus, control[0], 13),
+            b"\x01\0\0\0\x07\0\x01\0port1"
+        );
+        assert_eq!(read_bytes(&bus, control[1], 8), [1, 0, 0, 0, 6, 0, 1, 0]);
+
+        // output of port 0 goes to the file
+        send(&mut bus, &mut driver, 1, b"hello");
+        assert_eq!(std::fs::read(&log).unwrap(), b"hello");
+
+        // port 1 talks to a client on the socket once a guest process opens it
+        let mut client = UnixStream::connect(&sock).unwrap();
+        client.write_all(b"ping").unwrap();
+        let rx = receive_buffer(&mut bus, &mut driver, 4);
+        bus.tick();
+        assert_eq!(driver.used_idx(&bus, 4), 0);
+        send(&mut bus, &mut driver, 3, &[1, 0, 0, 0, 6, 0, 1, 0]);
+        bus.tick();
+        assert_eq!(driver.used(&bus, 4, 0).1, 4);
+        assert_eq!(read_bytes(&bus, rx, 4), b"ping");
+
+        send(&mut bus, &mut driver, 5, b"pong");
+        let mut buf = [0; 4];
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"pong");
+
+        std::fs::remove_file(&log).unwrap();
+        std::fs::remove_file(&sock).unwrap();
+    }
+}
@@ -0,0 +1,55 @@
+use crate::socket::{Listener, Server};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+
+// where the host side of a console port is connected
+pub enum Backend {
+    Stdout,
+    // output only, appended to the file
+    File(File),
+    // a listening socket that serves one client at a time
+    Unix(Server),
+}
+
+impl Backend {
+    // "stdout", "file:PATH" or "unix:PATH"
+    pub fn open(spec: &str) -> io::Result<Self> {
+        if spec == "stdout" {
+            return Ok(Backend::Stdout);
+        }
+        if let Some(path) = spec.strip_prefix("file:") {
+            let file = OpenOptions::new().create(true).append(true).open(path)?;
+            return Ok(Backend::File(file));
+        }
+        if let Some(path) = spec.strip_prefix("unix:") {
+            return Ok(Backend::Unix(Server::new(Listener::bind_unix(path)?)?));
+        }
+
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("unknown console backend: {spec}"),
+        ))
+    }
+
+    // output is dropped while no client is connected
+    pub fn write(&mut self, data: &[u8]) {
+        match self {
+            Backend::Stdout => {
+                let mut stdout = io::stdout();
+                let _ = stdout.write_all(data).and_then(|_| stdout.flush());
+            }
+            Backend::File(file) => {
+                let _ = file.write_all(data);
+            }
+            Backend::Unix(server) => server.write(data),
+        }
+    }
+
+    // never blocks; 0 when there is no input
+    pub fn read(&mut self, buf: &mut [u8]) -> usize {
+        match self {
+            Backend::Unix(server) => server.read(buf),
+            _ => 0,
+        }
+    }
+}
@@ -0,0 +1,211 @@
+mod backend;
+
+use super::queue::Virtqueue;
+use super::VirtioDevice;
+use crate::bus::Dma;
+use crate::snapshot::{self, Reader, Writer};
+use backend::Backend;
+use std::io;
+
+const VIRTIO_ID_NET: u32 = 1;
+const VIRTIO_NET_F_MAC: u64 = 1 << 5;
+const VIRTIO_NET_F_STATUS: u64 = 1 << 16;
+const VIRTIO_NET_S_LINK_UP: u16 = 1;
+
+const RX: usize = 0;
+const TX: usize = 1;
+
+// struct virtio_net_hdr, which includes num_buffers with VIRTIO_F_VERSION_1
+const HDR_SIZE: usize = 12;
+const NUM_BUFFERS: usize = 10;
+// a header and a frame of the longest kind the backends carry
+const MAX_PACKET: usize = HDR_SIZE + backend::MAX_FRAME;
+
+// virtio-net without offloads; every frame takes one receive buffer
+pub struct Net {
+    mac: [u8; 6],
+    backend: Backend,
+}
+
+impl Net {
+    pub fn new(spec: &str, mac: [u8; 6]) -> io::Result<Self> {
+        Ok(Net {
+            mac,
+            backend: Backend::open(spec)?,
+        })
+    }
+}
+
+impl VirtioDevice for Net {
+    fn device_id(&self) -> u32 {
+        VIRTIO_ID_NET
+    }
+
+    fn features(&self) -> u64 {
+        VIRTIO_NET_F_MAC | VIRTIO_NET_F_STATUS
+    }
+
+    fn num_queues(&self) -> usize {
+        2
+    }
+
+    // mac and status
+    fn read_config(&self, offset: usize, buf: &mut [u8]) {
+        let config = [&self.mac[..], &VIRTIO_NET_S_LINK_UP.to_le_bytes()].concat();
+        for (i, byte) in buf.iter_mut().enumerate() {
+            *byte = config.get(offset + i).copied().unwrap_or(0);
+        }
+    }
+
+    fn process_queue(
+        &mut self,
+        index: usize,
+        queues: &mut [Virtqueue],
+        mem: &mut Dma,
+    ) -> Result<bool, String> {
+        if index != TX {
+            // receive buffers are filled on poll
+            return Ok(false);
+        }
+
+        let queue = &mut queues[TX];
+        let mut used = false;
+        while let Some(chain) = queue.pop(mem, MAX_PACKET)? {
+            let len = chain.readable_len();
+            if len < HDR_SIZE {
+                return Err(format!("transmitted packet of {len} bytes"));
+            }
+            let mut frame = vec![0; len - HDR_SIZE];
+            chain.read(mem, HDR_SIZE, &mut frame)?;
+            self.backend.send(&frame);
+            queue.push_used(mem, chain.head, 0)?;
+            used = true;
+        }
+        Ok(used)
+    }
+
+    fn poll(&mut self, queues: &mut [Virtqueue], mem: &mut Dma) -> Result<bool, String> {
+        let queue = &mut queues[RX];
+        let mut used = false;
+        // frames stay in the backend until there is a buffer to take them
+        while queue.has_available(mem)? {
+            let Some(frame) = self.backend.recv() else {
+                break;
+            };
+            let chain = queue.pop(mem, MAX_PACKET)?.unwrap();
+            if chain.writable_len() < HDR_SIZE + frame.len() {
+                // too long for the buffer, so it is dropped as a NIC would
+                queue.push_used(mem, chain.head, 0)?;
+            } else {
+                let mut hdr = [0; HDR_SIZE];
+                hdr[NUM_BUFFERS] = 1;
+                chain.write(mem, 0, &hdr)?;
+                chain.write(mem, HDR_SIZE, &frame)?;
+                queue.push_used(mem, chain.head, (HDR_SIZE + frame.len()) as u32)?;
+            }
+            used = true;
+        }
+        Ok(used)
+    }
+
+    // the frames replayed from a pcap file are skipped again on restore
+    fn save_state(&self, w: &mut Writer) -> io::Result<()> {
+        w.tag(b"NET ")?;
+        w.u64(self.backend.replayed())
+    }
+
+    fn restore_state(&mut self, r: &mut Reader) -> io::Result<()> {
+        r.tag(b"NET ")?;
+        let replayed = r.u64()?;
+        while self.backend.replayed() < replayed {
+            if self.backend.recv().is_none() {
+                return Err(snapshot::invalid_data(format!(
+                    "the pcap file has fewer than {replayed} frames"
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_driver::{read_bytes, write_bytes, Driver};
+    use crate::bus::Bus;
+    use crate::cmdline::Arguments;
+    use crate::Isa;
+
+    const VIRTIO_BASE: u64 = 0x1000_1000;
+    const FRAME: &[u8] = b"\xff\xff\xff\xff\xff\xff\x52\x54\x00\x12\x34\x56\x08\x06arp";
+
+    fn net_bus(backend: &str) -> (Bus, Driver) {
+        let mut args = Arguments::for_elf("./HelloWorld");
+        args.machine.set("virtio_net", backend).unwrap();
+        let mut bus = Bus::for_test(&args, Isa::Rv32);
+        assert_eq!(bus.load32(VIRTIO_BASE + 0x008).unwrap(), 1);
+        let driver = Driver::new(&mut bus, VIRTIO_BASE, 2);
+        (bus, driver)
+    }
+
+    fn transmit(bus: &mut Bus, driver: &mut Driver, frame: &[u8]) {
+        let buf = driver.alloc(12 + frame.len() as u64);
+        write_bytes(bus, buf + 12, frame);
+        driver.submit(
+            bus,
+            1,
+            &[(buf, 12, false), (buf + 12, frame.len() as u32, false)],
+        );
+    }
+
+    fn receive_buffer(bus: &mut Bus, driver: &mut Driver) -> u64 {
+        let buf = driver.alloc(1526);
+        driver.submit(bus, 0, &[(buf, 1526, true)]);
+        buf
+    }
+
+    #[test]
+    fn loopback_test() {
+        let sock = std::env::temp_dir().join(format!("carron-net-{}", std::process::id()));
+        let spec = format!("loopback:{}", sock.display());
+        let (mut a, mut driver_a) = net_bus(&spec);
+        let (mut b, mut driver_b) = net_bus(&spec);
+        // mac
+        assert_eq!(a.load32(VIRTIO_BASE + 0x100).unwrap(), 0x1200_5452);
+
+        let rx = receive_buffer(&mut a, &mut driver_a);
+        a.tick();
+        transmit(&mut b, &mut driver_b, FRAME);
+        assert_eq!(driver_b.used_idx(&b, 1), 1);
+        a.tick();
+        assert_eq!(driver_a.used(&a, 0, 0).1, 12 + FRAME.len() as u32);
+        // num_buffers
+        assert_eq!(read_bytes(&a, rx + 10, 2), [1, 0]);
+        assert_eq!(read_bytes(&a, rx + 12, FRAME.len()), FRAME);
+
+        std::fs::remove_file(&sock).unwrap();
+    }
+
+    #[test]
+    fn pcap_test() {
+        let pcap = std::env::temp_dir().join(format!("carron-net-{}.pcap", std::process::id()));
+        let (mut bus, mut driver) = net_bus(&format!("pcap:tx={}", pcap.display()));
+        transmit(&mut bus, &mut driver, FRAME);
+        transmit(&mut bus, &mut driver, &FRAME[..14]);
+        let capture = std::fs::read(&pcap).unwrap();
+        assert_eq!(capture.len(), 24 + 16 + FRAME.len() + 16 + 14);
+        assert_eq!(capture[20..24], [1, 0, 0, 0]);
+
+        // the capture is replayed as far as there are buffers to take it
+        let (mut bus, mut driver) = net_bus(&format!("pcap:rx={}", pcap.display()));
+        let rx = receive_buffer(&mut bus, &mut driver);
+        bus.tick();
+        assert_eq!(driver.used_idx(&bus, 0), 1);
+        assert_eq!(read_bytes(&bus, rx + 12, FRAME.len()), FRAME);
+        bus.tick();
+        assert_eq!(driver.used_idx(&bus, 0), 1);
+        receive_buffer(&mut bus, &mut driver);
+        assert_eq!(driver.used(&bus, 0, 1).1, 12 + 14);
+
+        std::fs::remove_file(&pcap).unwrap();
+    }
+}
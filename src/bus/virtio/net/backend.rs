@@ -0,0 +1,226 @@
+use crate::socket::{Listener, Server};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+const PCAP_MAGIC_NSEC: u32 = 0xa1b2_3c4d;
+const LINKTYPE_ETHERNET: u32 = 1;
+const SNAPLEN: u32 = 0xffff;
+
+// frames longer than this are not Ethernet frames we can take
+pub const MAX_FRAME: usize = 0x1_0000;
+
+// where the frames the guest sends go and the ones it receives come from
+pub enum Backend {
+    // frames are exchanged with a 4-byte big-endian length prefix, which is
+    // how QEMU's stream netdev frames them too
+    Stream {
+        server: Server,
+        // bytes of a partially received frame
+        pending: Vec<u8>,
+    },
+    // transmitted frames are captured and received frames are replayed
+    Pcap {
+        capture: Option<BufWriter<File>>,
+        replay: Option<PcapReader>,
+    },
+}
+
+pub struct PcapReader {
+    file: BufReader<File>,
+    swapped: bool,
+    // frames handed to the guest so far
+    count: u64,
+}
+
+fn invalid_input(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+fn listen(path: &str) -> io::Result<Server> {
+    Server::new(Listener::bind_unix(path)?)
+}
+
+impl Backend {
+    // "unix:PATH", "loopback:PATH" or "pcap:tx=PATH,rx=PATH" with either file optional
+    pub fn open(spec: &str) -> io::Result<Self> {
+        if let Some(path) = spec.strip_prefix("unix:") {
+            return Ok(Backend::Stream {
+                server: listen(path)?,
+                pending: Vec::new(),
+            });
+        }
+        if let Some(path) = spec.strip_prefix("loopback:") {
+            // the first instance listens and the second one connects to it
+            let server = match UnixStream::connect(path) {
+                Ok(stream) => Server::connected_to(Box::new(stream))?,
+                Err(_) => listen(path)?,
+            };
+            return Ok(Backend::Stream {
+                server,
+                pending: Vec::new(),
+            });
+        }
+        if let Some(files) = spec.strip_prefix("pcap:") {
+            let mut capture = None;
+            let mut replay = None;
+            for file in files.split(',') {
+                match file.trim().split_once('=') {
+                    Some(("tx", path)) => capture = Some(create_pcap(path)?),
+                    Some(("rx", path)) => replay = Some(PcapReader::open(path)?),
+                    _ => return Err(invalid_input(format!("invalid pcap file: {file}"))),
+                }
+            }
+            return Ok(Backend::Pcap { capture, replay });
+        }
+
+        Err(invalid_input(format!("unknown network backend: {spec}")))
+    }
+
+    // frames are dropped while no peer is connected, as on an unplugged cable
+    pub fn send(&mut self, frame: &[u8]) {
+        match self {
+            Backend::Stream { server, .. } => {
+                server.write(&[&(frame.len() as u32).to_be_bytes(), frame].concat());
+            }
+            Backend::Pcap { capture, .. } => {
+                if let Some(file) = capture {
+                    if write_record(file, frame).is_err() {
+                        *capture = None;
+                    }
+                }
+            }
+        }
+    }
+
+    // never blocks; None when no whole frame has arrived
+    pub fn recv(&mut self) -> Option<Vec<u8>> {
+        match self {
+            Backend::Stream { server, pending } => {
+                if server.accept() {
+                    pending.clear();
+                }
+
+                let mut buf = [0; 4096];
+                loop {
+                    if let Some(frame) = take_frame(pending) {
+                        return Some(frame);
+                    }
+                    let len = server.read(&mut buf);
+                    if len == 0 {
+                        // the peer went away
+                        if !server.connected() {
+                            pending.clear();
+                        }
+                        return None;
+                    }
+                    pending.extend(&buf[..len]);
+                }
+            }
+            // the reader stays at the end of the file to keep its count
+            Backend::Pcap { replay, .. } => replay.as_mut()?.next_frame().ok().flatten(),
+        }
+    }
+
+    pub fn replayed(&self) -> u64 {
+        match self {
+            Backend::Pcap {
+                replay: Some(reader),
+                ..
+            } => reader.count,
+            _ => 0,
+        }
+    }
+}
+
+// split the first length-prefixed frame off the received bytes
+fn take_frame(pending: &mut Vec<u8>) -> Option<Vec<u8>> {
+    let len = u32::from_be_bytes(pending.get(..4)?.try_into().unwrap()) as usize;
+    if pending.len() < 4 + len {
+        return None;
+    }
+    let frame = pending[4..4 + len].to_vec();
+    pending.drain(..4 + len);
+    Some(frame)
+}
+
+fn create_pcap(path: &str) -> io::Result<BufWriter<File>> {
+    let mut file = BufWriter::new(File::create(path)?);
+    for field in [PCAP_MAGIC, 0x0004_0002, 0, 0, SNAPLEN, LINKTYPE_ETHERNET] {
+        file.write_all(&field.to_le_bytes())?;
+    }
+    file.flush()?;
+    Ok(file)
+}
+
+fn write_record(file: &mut BufWriter<File>, frame: &[u8]) -> io::Result<()> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let len = frame.len() as u32;
+    for field in [
+        now.as_secs() as u32,
+        now.subsec_micros(),
+        len.min(SNAPLEN),
+        len,
+    ] {
+        file.write_all(&field.to_le_bytes())?;
+    }
+    file.write_all(&frame[..len.min(SNAPLEN) as usize])?;
+    file.flush()
+}
+
+impl PcapReader {
+    fn open(path: &str) -> io::Result<Self> {
+        let mut file = BufReader::new(File::open(path)?);
+        let mut header = [0; 24];
+        file.read_exact(&mut header)?;
+
+        let magic = u32::from_le_bytes(header[0..4].try_into().unwrap());
+        let swapped = match magic {
+            PCAP_MAGIC | PCAP_MAGIC_NSEC => false,
+            _ if [PCAP_MAGIC, PCAP_MAGIC_NSEC].contains(&magic.swap_bytes()) => true,
+            _ => return Err(invalid_input(format!("{path} is not a pcap file"))),
+        };
+        let reader = PcapReader {
+            file,
+            swapped,
+            count: 0,
+        };
+        let linktype = reader.field(&header[20..24]);
+        if linktype != LINKTYPE_ETHERNET {
+            return Err(invalid_input(format!(
+                "{path} has link type {linktype}, not Ethernet"
+            )));
+        }
+        Ok(reader)
+    }
+
+    fn field(&self, bytes: &[u8]) -> u32 {
+        let value = u32::from_le_bytes(bytes.try_into().unwrap());
+        if self.swapped {
+            value.swap_bytes()
+        } else {
+            value
+        }
+    }
+
+    // the timestamps are ignored; frames are replayed as fast as the guest takes them
+    pub fn next_frame(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut header = [0; 16];
+        match self.file.read_exact(&mut header) {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            result => result?,
+        }
+        let len = self.field(&header[8..12]) as usize;
+        if len > MAX_FRAME {
+            return Err(invalid_input(format!("pcap record of {len} bytes")));
+        }
+        let mut frame = vec![0; len];
+        self.file.read_exact(&mut frame)?;
+        self.count += 1;
+        Ok(Some(frame))
+    }
+}
@@ -0,0 +1,174 @@
+use crate::bus::Dma;
+use crate::snapshot::{Reader, Snapshot, Writer};
+use std::io;
+
+pub const QUEUE_NUM_MAX: u16 = 256;
+
+const VIRTQ_DESC_F_NEXT: u16 = 1;
+const VIRTQ_DESC_F_WRITE: u16 = 2;
+const VIRTQ_DESC_SIZE: u64 = 16;
+const VIRTQ_USED_ELEM_SIZE: u64 = 8;
+
+// split virtqueue configured by the driver through the transport registers
+#[derive(Default)]
+pub struct Virtqueue {
+    pub num: u16,
+    pub ready: bool,
+    pub desc_addr: u64,
+    pub driver_addr: u64,
+    pub device_addr: u64,
+    last_avail_idx: u16,
+}
+
+// buffers of one request; the driver-readable ones always precede the writable ones
+pub struct DescChain {
+    pub head: u16,
+    readable: Vec<(u64, u32)>,
+    writable: Vec<(u64, u32)>,
+}
+
+impl Virtqueue {
+    pub fn reset(&mut self) {
+        *self = Virtqueue::default();
+    }
+
+    pub fn has_available(&self, mem: &Dma) -> Result<bool, String> {
+        Ok(
+            self.ready
+                && self.num > 0
+                && mem.read_u16(self.driver_addr + 2)? != self.last_avail_idx,
+        )
+    }
+
+    // take the next request the driver made available; the device sizes host
+    // buffers by the chain, so it must lie in DRAM and be at most max_len long
+    pub fn pop(&mut self, mem: &Dma, max_len: usize) -> Result<Option<DescChain>, String> {
+        if !self.has_available(mem)? {
+            return Ok(None);
+        }
+
+        let slot = (self.last_avail_idx % self.num) as u64;
+        let head = mem.read_u16(self.driver_addr + 4 + 2 * slot)?;
+        self.last_avail_idx = self.last_avail_idx.wrapping_add(1);
+
+        let mut chain = DescChain {
+            head,
+            readable: Vec::new(),
+            writable: Vec::new(),
+        };
+        let mut index = head;
+        let mut total = 0;
+        // a chain longer than the queue must have a loop in it
+        for _ in 0..self.num {
+            if index >= self.num {
+                return Err(format!("descriptor index {index} is out of range"));
+            }
+            let desc = self.desc_addr + VIRTQ_DESC_SIZE * index as u64;
+            let addr = mem.read_u64(desc)?;
+            let len = mem.read_u32(desc + 8)?;
+            let flags = mem.read_u16(desc + 12)?;
+            if !mem.contains(addr, len as usize) {
+                return Err(format!(
+                    "descriptor buffer of {len:#x} bytes at {addr:#x} is outside of DRAM"
+                ));
+            }
+            total += len as usize;
+            if total > max_len {
+                return Err(format!(
+                    "descriptor chain from {head} is longer than {max_len:#x} bytes"
+                ));
+            }
+            if flags & VIRTQ_DESC_F_WRITE != 0 {
+                chain.writable.push((addr, len));
+            } else if chain.writable.is_empty() {
+                chain.readable.push((addr, len));
+            } else {
+                return Err("readable descriptor after a writable one".to_string());
+            }
+
+            if flags & VIRTQ_DESC_F_NEXT == 0 {
+                return Ok(Some(chain));
+            }
+            index = mem.read_u16(desc + 14)?;
+        }
+
+        Err(format!("descriptor chain from {head} does not terminate"))
+    }
+
+    // return a request to the driver with the number of bytes written into it
+    pub fn push_used(&mut self, mem: &mut Dma, head: u16, len: u32) -> Result<(), String> {
+        let used_idx = mem.read_u16(self.device_addr + 2)?;
+        let elem = self.device_addr + 4 + VIRTQ_USED_ELEM_SIZE * (used_idx % self.num) as u64;
+        mem.write_u32(elem, head as u32)?;
+        mem.write_u32(elem + 4, len)?;
+        mem.write_u16(self.device_addr + 2, used_idx.wrapping_add(1))
+    }
+}
+
+impl Snapshot for Virtqueue {
+    fn save(&self, w: &mut Writer) -> io::Result<()> {
+        w.u32(self.num as u32)?;
+        w.u8(self.ready as u8)?;
+        w.u64(self.desc_addr)?;
+        w.u64(self.driver_addr)?;
+        w.u64(self.device_addr)?;
+        w.u32(self.last_avail_idx as u32)
+    }
+
+    fn restore(&mut self, r: &mut Reader) -> io::Result<()> {
+        self.num = r.u32()? as u16;
+        self.ready = r.u8()? != 0;
+        self.desc_addr = r.u64()?;
+        self.driver_addr = r.u64()?;
+        self.device_addr = r.u64()?;
+        self.last_avail_idx = r.u32()? as u16;
+        Ok(())
+    }
+}
+
+impl DescChain {
+    pub fn readable_len(&self) -> usize {
+        self.readable.iter().map(|(_, len)| *len as usize).sum()
+    }
+
+    pub fn writable_len(&self) -> usize {
+        self.writable.iter().map(|(_, len)| *len as usize).sum()
+    }
+
+    // gather driver-readable bytes starting at offset, as far as the buffer goes
+    pub fn read(&self, mem: &Dma, offset: usize, buf: &mut [u8]) -> Result<usize, String> {
+        Self::copy(&self.readable, offset, buf.len(), |addr, range| {
+            mem.read(addr, &mut buf[range])
+        })
+    }
+
+    // scatter bytes into the driver-writable buffers starting at offset
+    pub fn write(&self, mem: &mut Dma, offset: usize, data: &[u8]) -> Result<usize, String> {
+        Self::copy(&self.writable, offset, data.len(), |addr, range| {
+            mem.write(addr, &data[range])
+        })
+    }
+
+    fn copy(
+        bufs: &[(u64, u32)],
+        mut offset: usize,
+        len: usize,
+        mut f: impl FnMut(u64, std::ops::Range<usize>) -> Result<(), String>,
+    ) -> Result<usize, String> {
+        let mut done = 0;
+        for (addr, buf_len) in bufs.iter().map(|(addr, len)| (*addr, *len as usize)) {
+            if done == len {
+                break;
+            }
+            if offset >= buf_len {
+                offset -= buf_len;
+                continue;
+            }
+            let n = (buf_len - offset).min(len - done);
+            f(addr + offset as u64, done..done + n)?;
+            done += n;
+            offset = 0;
+        }
+        Ok(done)
+    }
+}
@@ -0,0 +1,102 @@
+use super::queue::Virtqueue;
+use super::VirtioDevice;
+use crate::bus::Dma;
+use crate::snapshot::{Reader, Writer};
+use std::io;
+
+const VIRTIO_ID_RNG: u32 = 4;
+// drivers ask for a few cache lines at a time
+const MAX_REQUEST: usize = 0x1_0000;
+
+// virtio-rng fed by splitmix64, so that runs with the same seed see the same entropy
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng { state: seed }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn fill(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+}
+
+impl VirtioDevice for Rng {
+    fn device_id(&self) -> u32 {
+        VIRTIO_ID_RNG
+    }
+
+    fn features(&self) -> u64 {
+        0
+    }
+
+    fn num_queues(&self) -> usize {
+        1
+    }
+
+    // there is no config space
+    fn read_config(&self, _offset: usize, buf: &mut [u8]) {
+        buf.fill(0);
+    }
+
+    fn process_queue(
+        &mut self,
+        index: usize,
+        queues: &mut [Virtqueue],
+        mem: &mut Dma,
+    ) -> Result<bool, String> {
+        let queue = &mut queues[index];
+        let mut used = false;
+        while let Some(chain) = queue.pop(mem, MAX_REQUEST)? {
+            let mut entropy = vec![0; chain.writable_len()];
+            self.fill(&mut entropy);
+            let len = chain.write(mem, 0, &entropy)?;
+            queue.push_used(mem, chain.head, len as u32)?;
+            used = true;
+        }
+        Ok(used)
+    }
+
+    fn save_state(&self, w: &mut Writer) -> io::Result<()> {
+        w.tag(b"RNG ")?;
+        w.u64(self.state)
+    }
+
+    fn restore_state(&mut self, r: &mut Reader) -> io::Result<()> {
+        r.tag(b"RNG ")?;
+        self.state = r.u64()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deterministic_entropy_test() {
+        let (mut a, mut b) = (Rng::new(42), Rng::new(42));
+        let (mut buf_a, mut buf_b) = ([0; 13], [0; 13]);
+        a.fill(&mut buf_a);
+        b.fill(&mut buf_b);
+        assert_eq!(buf_a, buf_b);
+        assert_ne!(buf_a, [0; 13]);
+
+        b.fill(&mut buf_b);
+        assert_ne!(buf_a, buf_b);
+        assert_ne!(Rng::new(43).next_u64(), Rng::new(42).next_u64());
+    }
+}
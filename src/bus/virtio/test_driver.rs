@@ -0,0 +1,100 @@
+use crate::bus::Bus;
+
+pub const QUEUE_SIZE: u64 = 8;
+const NEXT: u16 = 1;
+const WRITE: u16 = 2;
+
+pub fn write_bytes(bus: &mut Bus, addr: u64, data: &[u8]) {
+    for (i, byte) in data.iter().enumerate() {
+        bus.store8(addr + i as u64, *byte as u64).unwrap();
+    }
+}
+
+pub fn read_bytes(bus: &Bus, addr: u64, len: usize) -> Vec<u8> {
+    (0..len)
+        .map(|i| bus.load_u8(addr + i as u64).unwrap() as u8)
+        .collect()
+}
+
+// a minimal guest driver that keeps its rings and buffers in DRAM
+pub struct Driver {
+    pub base: u64,
+    rings: u64,
+    heap: u64,
+    avail_idx: Vec<u16>,
+}
+
+impl Driver {
+    pub fn new(bus: &mut Bus, base: u64, num_queues: usize) -> Self {
+        let rings = (bus.dram.base_addr + 0x1_0fff) & !0xfff;
+        let driver = Driver {
+            base,
+            rings,
+            heap: rings + 0x1000 * num_queues as u64,
+            avail_idx: vec![0; num_queues],
+        };
+
+        for queue in 0..num_queues as u64 {
+            let (desc, avail, used) = driver.ring(queue as usize);
+            bus.store32(base + 0x030, queue).unwrap();
+            bus.store32(base + 0x038, QUEUE_SIZE).unwrap();
+            bus.store32(base + 0x080, desc).unwrap();
+            bus.store32(base + 0x090, avail).unwrap();
+            bus.store32(base + 0x0a0, used).unwrap();
+            bus.store32(base + 0x044, 1).unwrap();
+        }
+        // ACKNOWLEDGE | DRIVER | FEATURES_OK | DRIVER_OK
+        bus.store32(base + 0x070, 0xf).unwrap();
+        driver
+    }
+
+    // descriptor table, available ring and used ring of a queue
+    fn ring(&self, queue: usize) -> (u64, u64, u64) {
+        let desc = self.rings + 0x1000 * queue as u64;
+        (desc, desc + 0x400, desc + 0x800)
+    }
+
+    pub fn alloc(&mut self, len: u64) -> u64 {
+        let addr = self.heap;
+        self.heap += (len + 0xf) & !0xf;
+        addr
+    }
+
+    // make a chain of (addr, len, writable) buffers available and notify the device
+    pub fn submit(&mut self, bus: &mut Bus, queue: usize, bufs: &[(u64, u32, bool)]) {
+        let (desc, avail, _) = self.ring(queue);
+        let idx = self.avail_idx[queue];
+        // each request occupies the descriptors from its slot on, which is enough for short chains
+        let head = (idx as u64 * 2) % QUEUE_SIZE;
+        for (i, (addr, len, writable)) in bufs.iter().enumerate() {
+            let index = (head + i as u64) % QUEUE_SIZE;
+            let next = (index + 1) % QUEUE_SIZE;
+            let flags =
+                if *writable { WRITE } else { 0 } | if i + 1 < bufs.len() { NEXT } else { 0 };
+            let entry = desc + 16 * index;
+            bus.store64(entry, *addr).unwrap();
+            bus.store32(entry + 8, *len as u64).unwrap();
+            bus.store16(entry + 12, flags as u64).unwrap();
+            bus.store16(entry + 14, next).unwrap();
+        }
+        bus.store16(avail + 4 + 2 * (idx as u64 % QUEUE_SIZE), head)
+            .unwrap();
+        self.avail_idx[queue] = idx.wrapping_add(1);
+        bus.store16(avail + 2, self.avail_idx[queue] as u64)
+            .unwrap();
+        bus.store32(self.base + 0x050, queue as u64).unwrap();
+    }
+
+    pub fn used_idx(&self, bus: &Bus, queue: usize) -> u16 {
+        bus.load_u16(self.ring(queue).2 + 2).unwrap() as u16
+    }
+
+    // (descriptor id, written length) of the n-th used element
+    pub fn used(&self, bus: &Bus, queue: usize, n: u16) -> (u32, u32) {
+        let elem = self.ring(queue).2 + 4 + 8 * (n as u64 % QUEUE_SIZE);
+        (
+            bus.load_u32(elem).unwrap() as u32,
+            bus.load_u32(elem + 4).unwrap() as u32,
+        )
+    }
+}
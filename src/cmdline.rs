@@ -1,4 +1,7 @@
+use crate::cpu::AddrTransMode;
 use crate::log::{LogLv, LOG_LEVEL};
+use crate::machine::MachineConfig;
+use crate::Isa;
 use clap::{arg, AppSettings, Arg, ArgGroup};
 
 #[allow(non_camel_case_types)]
@@ -19,6 +22,20 @@ pub struct Arguments {
     pub initrd_path: Option<String>,
     pub init_pc: Option<u64>,
     pub main_args: Vec<String>,
+    pub gdb_target: Option<String>,
+    pub jtag_target: Option<String>,
+    pub trans_mode: Option<AddrTransMode>,
+    pub machine: MachineConfig,
+    pub svadu: bool,
+    pub sstc: bool,
+    pub hypervisor: bool,
+    pub sc_fail_every: Option<u64>,
+    pub snapshot_path: String,
+    pub snapshot_at: Option<u64>,
+    pub restore_path: Option<String>,
+    pub tlb_stats: bool,
+    // forward keystrokes to the UART one by one instead of line by line
+    pub raw_console: bool,
 }
 
 impl Arguments {
@@ -39,6 +56,88 @@ impl Arguments {
             .arg(arg!(--kernel <kernel> "Run with kernel").required(false))
             .arg(arg!(--initrd <initrd> "Set initrd").required(false))
             .arg(arg!(--pc <init_pc> ... "Set entry address as hex").required(false))
+            .arg(
+                arg!(--gdb <port_or_path> "Wait for GDB on TCP port or unix socket")
+                    .required(false),
+            )
+            .arg(
+                arg!(--jtag <port_or_path> "Serve JTAG to OpenOCD's remote_bitbang driver on TCP port or unix socket")
+                    .required(false),
+            )
+            .arg(
+                arg!(--mmu <mmu_type> "Set the widest translation mode (sv39, sv48 or sv57)")
+                    .required(false),
+            )
+            .arg(
+                arg!(--machine <config> "Load the memory map from a \"key = value\" file")
+                    .required(false),
+            )
+            .arg(arg!(--harts <num_harts> "Set the number of harts (default: 1)").required(false))
+            .arg(
+                arg!(--"dram-base" <addr> "Set the DRAM base address (default: ELF entry point)")
+                    .required(false),
+            )
+            .arg(
+                arg!(--"dram-size" <size> "Set the DRAM size (e.g. 256M, default: 2G)")
+                    .required(false),
+            )
+            .arg(
+                arg!(--"dram-image" <path> "Back DRAM with a raw image mapped copy-on-write")
+                    .required(false),
+            )
+            .arg(
+                arg!(--uart <backend> "Connect the UART to stdio, pty, tcp:ADDR:PORT, unix:PATH or file:PATH")
+                    .required(false),
+            )
+            .arg(
+                arg!(--"uart-input" <path> "Type the contents of the file into the UART")
+                    .required(false),
+            )
+            .arg(arg!(--"uart-tee" "Copy the UART output to stdout as well"))
+            .arg(
+                arg!(--"virtio-blk" <image> "Attach a raw disk image as a virtio block device")
+                    .required(false),
+            )
+            .arg(arg!(--"virtio-blk-ro" "Make the virtio block device read-only"))
+            .arg(
+                arg!(--"virtio-console" <port> "Add a virtio console port (stdout, file:PATH or unix:PATH)")
+                    .required(false)
+                    .multiple_occurrences(true),
+            )
+            .arg(
+                arg!(--"virtio-rng" <seed> "Attach a virtio entropy device with a fixed seed")
+                    .required(false),
+            )
+            .arg(
+                arg!(--"virtio-net" <backend> "Attach a virtio network device (unix:PATH, loopback:PATH or pcap:tx=PATH,rx=PATH)")
+                    .required(false),
+            )
+            .arg(
+                arg!(--"virtio-net-mac" <mac> "Set the MAC address of the virtio network device")
+                    .required(false),
+            )
+            .arg(arg!(--svadu "Update the PTE A/D bits in hardware (Svadu)"))
+            .arg(arg!(--sstc "Let the supervisor program its own timer (Sstc)"))
+            .arg(arg!(--hypervisor "Implement the hypervisor extension (H, rv64 only)"))
+            .arg(arg!(--"tlb-stats" "Show the TLB and page walk cache hit rates at exit"))
+            .arg(
+                arg!(--"sc-fail-every" <n> "Make every n-th successful SC fail spuriously")
+                    .required(false),
+            )
+            .arg(
+                arg!(--snapshot <path> "Set the snapshot file written on SIGUSR1 or --snapshot-at")
+                    .required(false),
+            )
+            .arg(
+                arg!(--"snapshot-at" <inst_count> "Write a snapshot after the instruction count")
+                    .required(false),
+            )
+            .arg(arg!(--restore <path> "Resume from the snapshot").required(false))
+            .arg(
+                arg!(--console <mode> "Read the UART input in raw or line mode (default: raw on a terminal)")
+                    .required(false)
+                    .possible_values(["raw", "line"]),
+            )
             .arg(arg!(--loglv <log_level> ... "Set log level").required(false))
             .arg(Arg::new("main_args").multiple_values(true))
             .setting(AppSettings::DeriveDisplayOrder)
@@ -74,6 +173,65 @@ impl Arguments {
                 .expect("invalid pc\nplease set value as hex (e.g. --pc=0x80000000)")
         });
 
+        let trans_mode = app.value_of("mmu").map(|x| match x {
+            "sv39" => AddrTransMode::Sv39,
+            "sv48" => AddrTransMode::Sv48,
+            "sv57" => AddrTransMode::Sv57,
+            _ => panic!("invalid mmu type\nplease set sv39, sv48 or sv57 (e.g. --mmu=sv48)"),
+        });
+
+        // command line options override the configuration file
+        let mut machine = app
+            .value_of("machine")
+            .map_or(MachineConfig::default(), |path| {
+                MachineConfig::from_file(path)
+                    .unwrap_or_else(|e| panic!("invalid machine config: {e}"))
+            });
+        for (opt, key) in [
+            ("harts", "harts"),
+            ("dram-base", "dram_base"),
+            ("dram-size", "dram_size"),
+            ("dram-image", "dram_image"),
+            ("uart", "uart_backend"),
+            ("uart-input", "uart_input"),
+            ("virtio-blk", "virtio_blk_image"),
+            ("virtio-rng", "virtio_rng_seed"),
+            ("virtio-net", "virtio_net"),
+            ("virtio-net-mac", "virtio_net_mac"),
+        ] {
+            if let Some(value) = app.value_of(opt) {
+                machine
+                    .set(key, value)
+                    .unwrap_or_else(|e| panic!("invalid --{opt}: {e}"));
+            }
+        }
+        if let Some(ports) = app.values_of("virtio-console") {
+            machine
+                .set("virtio_console", &ports.collect::<Vec<&str>>().join(","))
+                .unwrap_or_else(|e| panic!("invalid --virtio-console: {e}"));
+        }
+        if app.is_present("uart-tee") {
+            machine.uart_tee = true;
+        }
+        if app.is_present("virtio-blk-ro") {
+            machine.virtio_blk_readonly = true;
+        }
+        machine
+            .check()
+            .unwrap_or_else(|e| panic!("invalid memory map: {e}"));
+
+        let sc_fail_every = app.value_of("sc-fail-every").map(|x| {
+            x.parse::<u64>()
+                .ok()
+                .filter(|&n| n > 0)
+                .expect("invalid SC failure interval\nplease set a positive decimal (e.g. --sc-fail-every=3)")
+        });
+
+        let snapshot_at = app.value_of("snapshot-at").map(|x| {
+            x.parse::<u64>()
+                .expect("invalid instruction count\nplease set value as decimal (e.g. --snapshot-at=100000)")
+        });
+
         LOG_LEVEL.get_or_init(|| match app.value_of("loglv") {
             Some("nolog") => LogLv::NoLog,
             Some("diff") => LogLv::Diff,
@@ -83,7 +241,7 @@ impl Arguments {
             _ => LogLv::NoLog,
         });
 
-        let mut main_args = vec![pk_path.clone(), Some(filename.clone())]
+        let mut main_args = [pk_path.clone(), Some(filename.clone())]
             .iter()
             .flat_map(|x| x.clone())
             .collect::<Vec<String>>();
@@ -103,6 +261,68 @@ impl Arguments {
             initrd_path: app.value_of("initrd").map(|s| s.to_string()),
             init_pc,
             main_args,
+            gdb_target: app.value_of("gdb").map(|s| s.to_string()),
+            jtag_target: app.value_of("jtag").map(|s| s.to_string()),
+            trans_mode,
+            machine,
+            svadu: app.is_present("svadu"),
+            sstc: app.is_present("sstc"),
+            hypervisor: app.is_present("hypervisor"),
+            sc_fail_every,
+            snapshot_path: app
+                .value_of("snapshot")
+                .unwrap_or("carron.snapshot")
+                .to_string(),
+            snapshot_at,
+            restore_path: app.value_of("restore").map(|s| s.to_string()),
+            tlb_stats: app.is_present("tlb-stats"),
+            raw_console: match app.value_of("console") {
+                Some(mode) => mode == "raw",
+                None => unsafe { libc::isatty(libc::STDIN_FILENO) != 0 },
+            },
+        }
+    }
+
+    // riscv,isa with the optional extensions
+    pub fn riscv_isa(&self, isa: Isa) -> String {
+        let (base, hypervisor) = match isa {
+            Isa::Rv32 => ("rv32imafdc", false),
+            Isa::Rv64 => ("rv64imafdc", self.hypervisor),
+        };
+        let ext: String = [(self.sstc, "_sstc"), (self.svadu, "_svadu")]
+            .into_iter()
+            .filter_map(|(enabled, ext)| enabled.then_some(ext))
+            .collect();
+        format!(
+            "{base}{}_zicntr_zicsr_zifencei_zihpm_sscofpmf{ext}",
+            if hypervisor { "h" } else { "" }
+        )
+    }
+
+    // the options left at their defaults, for the tests that run an ELF
+    #[cfg(test)]
+    pub fn for_elf(filename: &str) -> Self {
+        Arguments {
+            filename: filename.to_string(),
+            exe_option: ExeOption::OPT_DEFAULT,
+            pk_path: None,
+            kernel_path: None,
+            initrd_path: None,
+            init_pc: None,
+            main_args: Vec::new(),
+            gdb_target: None,
+            jtag_target: None,
+            trans_mode: None,
+            machine: MachineConfig::default(),
+            svadu: false,
+            sstc: false,
+            hypervisor: false,
+            sc_fail_every: None,
+            snapshot_path: "carron.snapshot".to_string(),
+            snapshot_at: None,
+            restore_path: None,
+            tlb_stats: false,
+            raw_console: false,
         }
     }
 }
@@ -1,15 +1,25 @@
 pub mod csr;
+mod debug;
 pub mod decode;
 pub mod execution;
 pub mod fetch;
+mod freg;
+mod hart;
+mod icache;
 mod instruction;
 mod mmu;
 mod reg;
+mod softfloat;
 mod trap;
 
+use crate::snapshot::{Reader, Snapshot, Writer};
 use crate::{bus, elfload, log, Arguments, Isa};
-use csr::{CSRname, Xstatus};
+use csr::{CSRname, Event, Events, Xstatus};
+pub use hart::Hart;
+pub use icache::InstCache;
+pub use mmu::AddrTransMode;
 use std::cell::RefCell;
+use std::io;
 use std::rc::Rc;
 
 #[derive(Copy, Clone, Debug)]
@@ -25,16 +35,25 @@ pub enum TrapCause {
     StoreAMOAccessFault = 7,
     UmodeEcall = 8,
     SmodeEcall = 9,
+    VSmodeEcall = 10,
     MmodeEcall = 11,
     InstPageFault = 12,
     LoadPageFault = 13,
     StoreAMOPageFault = 15,
+    InstGuestPageFault = 20,
+    LoadGuestPageFault = 21,
+    VirtualInst = 22,
+    StoreAMOGuestPageFault = 23,
     SupervisorSoftwareInterrupt = (1 << 31) + 1,
+    VirtualSupervisorSoftwareInterrupt = (1 << 31) + 2,
     MachineSoftwareInterrupt = (1 << 31) + 3,
     SupervisorTimerInterrupt = (1 << 31) + 5,
+    VirtualSupervisorTimerInterrupt = (1 << 31) + 6,
     MachineTimerInterrupt = (1 << 31) + 7,
     SupervisorExternalInterrupt = (1 << 31) + 9,
+    VirtualSupervisorExternalInterrupt = (1 << 31) + 10,
     MachineExternalInterrupt = (1 << 31) + 11,
+    CounterOverflowInterrupt = (1 << 31) + 13,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd)]
@@ -45,6 +64,7 @@ pub enum PrivilegedLevel {
     Machine = 0b11,
 }
 
+#[derive(Copy, Clone)]
 pub enum TransAlign {
     Size8 = 1,
     Size16 = 2,
@@ -56,6 +76,8 @@ pub enum TransAlign {
 pub enum TransFor {
     Fetch,
     Load,
+    // hlvx: a load from memory that only has to be executable
+    LoadExec,
     StoreAMO,
     Deleg,
 }
@@ -64,9 +86,9 @@ pub struct Cpu {
     pc: Rc<RefCell<u64>>,
     pub bus: bus::Bus,
     pub regs: reg::Register,
+    pub fregs: freg::FRegister,
     csrs: csr::CSRs,
     mmu: mmu::Mmu,
-    pub reservation_set: Option<usize>,
     isa: Rc<Isa>,
     priv_lv: PrivilegedLevel,
 }
@@ -75,19 +97,13 @@ impl Cpu {
     pub fn new(loader: elfload::ElfLoader, args: &Arguments, isa: Isa) -> Self {
         // initialize bus and get the entry point
         let bus = bus::Bus::new(loader, args, isa);
-        let pc = Rc::new(RefCell::new(args.init_pc.unwrap_or(bus.mrom.base_addr)));
-        let isa = Rc::new(isa);
-
-        Cpu {
-            pc: pc.clone(),
-            bus,
-            regs: reg::Register::new(isa.clone()),
-            csrs: csr::CSRs::new(isa.clone(), pc).init(),
-            mmu: mmu::Mmu::new(isa.clone()),
-            reservation_set: None,
-            isa,
-            priv_lv: PrivilegedLevel::Machine,
-        }
+        let hart = Hart::new(isa, args, Cpu::reset_pc(&bus, args), 0);
+        Cpu::from_hart(bus, hart, isa)
+    }
+
+    // every hart starts from the same reset vector and tells itself apart by mhartid
+    pub fn reset_pc(bus: &bus::Bus, args: &Arguments) -> u64 {
+        args.init_pc.unwrap_or(bus.mrom.base_addr)
     }
 
     pub fn pc(&self) -> u64 {
@@ -110,13 +126,180 @@ impl Cpu {
         self.priv_lv = new_priv
     }
 
+    // floating-point instructions are illegal while mstatus.FS == Off
+    fn check_fpu_enabled(&mut self) -> Result<(), (Option<u64>, TrapCause, String)> {
+        if self
+            .csrs
+            .read_xstatus(PrivilegedLevel::Machine, Xstatus::FS)
+            == 0
+        {
+            return Err((
+                self.inst_bits()?,
+                TrapCause::IllegalInst,
+                "floating-point unit is disabled (mstatus.FS == 0)".to_string(),
+            ));
+        }
+        if !self.csrs.guest_fpu_enabled() {
+            return Err((
+                self.inst_bits()?,
+                TrapCause::IllegalInst,
+                "floating-point unit is disabled (vsstatus.FS == 0)".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn rounding_mode(
+        &mut self,
+        rm: Option<i32>,
+    ) -> Result<softfloat::RoundingMode, (Option<u64>, TrapCause, String)> {
+        // rm == 0b111 selects the dynamic rounding mode in frm
+        let rm = match rm.unwrap() as u64 {
+            0b111 => self.csrs.read(CSRname::frm.wrap())?,
+            static_rm => static_rm,
+        };
+        match softfloat::RoundingMode::from_bits(rm) {
+            Some(rm) => Ok(rm),
+            None => Err((
+                self.inst_bits()?,
+                TrapCause::IllegalInst,
+                format!("invalid rounding mode: {rm:b}"),
+            )),
+        }
+    }
+
+    fn update_fflags(&mut self, flags: u64) {
+        if flags != 0 {
+            self.csrs.bitset(CSRname::fflags.wrap(), flags).unwrap();
+        }
+        self.dirty_fs();
+    }
+
+    fn dirty_fs(&mut self) {
+        self.csrs
+            .write_xstatus(PrivilegedLevel::Machine, Xstatus::FS, 0b11);
+        self.csrs.dirty_guest_fs();
+    }
+
+    pub fn isa(&self) -> Isa {
+        *self.isa
+    }
+
+    pub fn set_pc(&mut self, newpc: u64) {
+        self.update_pc(newpc.fix2regsz(&self.isa));
+    }
+
+    pub fn priv_level(&self) -> PrivilegedLevel {
+        self.priv_lv()
+    }
+
+    pub fn set_priv_level(&mut self, new_priv: PrivilegedLevel) {
+        self.set_priv_lv(new_priv)
+    }
+
+    pub fn read_csr(&self, csr_num: usize) -> Option<u64> {
+        self.csrs.read(Some(csr_num)).ok()
+    }
+
+    pub fn write_csr(&mut self, csr_num: usize, data: u64) -> Option<()> {
+        self.csrs.write(Some(csr_num), data).ok()
+    }
+
+    // (name, number) of the CSRs this hart implements
+    pub fn csr_list(&self) -> Vec<(String, usize)> {
+        self.csrs.implemented().collect()
+    }
+
+    // None for CSRs this hart doesn't implement, otherwise whether it's read-only
+    pub fn csr_read_only(&self, csr_num: usize) -> Option<bool> {
+        self.csrs.describe(csr_num).map(|desc| desc.read_only)
+    }
+
+    // translate address without side effects (for debugger)
+    pub fn debug_trans_addr(&mut self, addr: u64) -> Option<u64> {
+        let addr = addr.fix2regsz(&self.isa);
+        let priv_lv = self.priv_lv();
+        self.mmu.probe_addr(
+            addr,
+            &self.csrs,
+            &mut self.bus.memory(),
+            priv_lv,
+            self.csrs.virt(),
+        )
+    }
+
+    pub fn show_tlb_stats(&self) {
+        self.mmu.show_tlb_stats(self.hartid());
+    }
+
+    // every cycle runs one instruction or takes a trap
     pub fn exec_one_cycle(&mut self) -> Result<(), (Option<u64>, TrapCause, String)> {
+        let (priv_lv, virt) = (self.priv_lv(), self.csrs.virt());
+        let mut events = Events::default();
+        events.add(Event::Cycles, 1);
+
+        let result = self.execute(&mut events);
+        if let Err((_, cause, _)) = result {
+            let event = if cause as u64 & 1 << 31 != 0 {
+                Event::Interrupts
+            } else {
+                Event::Exceptions
+            };
+            events.add(event, 1);
+        }
+        self.csrs.count(priv_lv, virt, &events);
+
+        result
+    }
+
+    fn execute(&mut self, events: &mut Events) -> Result<(), (Option<u64>, TrapCause, String)> {
         use execution::Execution;
         use fetch::fetch;
 
-        self.check_interrupt()?;
+        let priv_lv = self.priv_lv();
+        if !self.csrs.step_masks_interrupts() {
+            self.check_interrupt()?;
+        }
+
+        let tlb_misses = self.mmu.tlb_misses();
+        let inst = fetch(self);
+        let fetched = self.mmu.tlb_misses();
+        events.add(Event::ItlbMisses, fetched - tlb_misses);
+        let inst = inst?;
 
-        fetch(self)?.decode(*self.isa)?.execution(self)
+        let pc = self.pc();
+        let inst_size = match inst.opc_to_extension() {
+            instruction::Extensions::C => 2,
+            _ => 4,
+        };
+        self.check_execute_triggers(pc, inst_size)?;
+        let result = inst.execution(self);
+        events.add(Event::DtlbMisses, self.mmu.tlb_misses() - fetched);
+        result?;
+
+        events.add(Event::Instructions, 1);
+        if let Some(event) = inst.opc_to_event() {
+            events.add(event, 1);
+            if event == Event::Branches && self.pc() != pc + inst_size {
+                events.add(Event::TakenBranches, 1);
+            }
+        }
+
+        self.retire_triggers(priv_lv)
+    }
+
+    // the instruction at pc for the tval of an illegal (or virtual)
+    // instruction; the program buffer run by a debugger isn't in memory
+    fn inst_bits(&mut self) -> Result<Option<u64>, (Option<u64>, TrapCause, String)> {
+        let inst_addr = self.trans_addr(TransFor::Fetch, TransAlign::Size8, self.pc())?;
+        // a compressed instruction is only the lower halfword
+        Ok(self.bus.load_u32(inst_addr).ok().map(|inst| {
+            if inst & 0b11 == 0b11 {
+                inst
+            } else {
+                inst & 0xffff
+            }
+        }))
     }
 
     fn trans_addr(
@@ -125,10 +308,8 @@ impl Cpu {
         align: TransAlign,
         addr: u64,
     ) -> Result<u64, (Option<u64>, TrapCause, String)> {
-        let addr = addr.fix2regsz(&self.isa);
-        self.check_breakpoint(purpose, addr)?;
-
         let mut trans_priv = self.priv_lv();
+        let mut virt = self.csrs.virt();
         if (purpose == TransFor::Load || purpose == TransFor::StoreAMO)
             && self
                 .csrs
@@ -144,20 +325,42 @@ impl Cpu {
                 0b10 => panic!("PrivilegedLevel 0x3 is Reserved."),
                 0b11 => PrivilegedLevel::Machine,
                 _ => panic!("invalid PrivilegedLevel"),
-            }
+            };
+            // mstatus.MPV makes it a guest access
+            virt = self.csrs.mpv() && trans_priv != PrivilegedLevel::Machine;
         }
 
-        match self
-            .mmu
-            .trans_addr(purpose, addr, &self.csrs, &mut self.bus.dram, trans_priv)
-        {
+        self.trans_addr_as(purpose, align, addr, trans_priv, virt)
+    }
+
+    // translate as if in trans_priv (and a guest when virt), which hlv and
+    // hsv use to access the memory of the guest
+    fn trans_addr_as(
+        &mut self,
+        purpose: TransFor,
+        align: TransAlign,
+        addr: u64,
+        trans_priv: PrivilegedLevel,
+        virt: bool,
+    ) -> Result<u64, (Option<u64>, TrapCause, String)> {
+        let addr = addr.fix2regsz(&self.isa);
+        self.check_access_triggers(purpose, addr, align as u64)?;
+
+        match self.mmu.trans_addr(
+            purpose,
+            addr,
+            &self.csrs,
+            &mut self.bus.memory(),
+            trans_priv,
+            virt,
+        ) {
             Ok(vaddr) => {
-                if addr % align as u64 == 0 {
+                if addr.is_multiple_of(align as u64) {
                     Ok(vaddr.fix2regsz(&self.isa))
                 } else {
                     let cause = match purpose {
                         TransFor::Fetch | TransFor::Deleg => TrapCause::InstAddrMisaligned,
-                        TransFor::Load => TrapCause::LoadAddrMisaligned,
+                        TransFor::Load | TransFor::LoadExec => TrapCause::LoadAddrMisaligned,
                         TransFor::StoreAMO => TrapCause::StoreAMOAddrMisaligned,
                     };
                     Err((
@@ -179,13 +382,37 @@ impl Cpu {
     }
 
     pub fn timer_increment(&mut self, inc: u64) {
-        // mtime(clint: 0x0200_BFF8)
-        const MTIME: u64 = 0x0200_BFF8;
-        let mtime: u64 = self.bus.load64(MTIME).unwrap();
-        self.bus.store64(MTIME, mtime + inc).unwrap();
+        let mtime = self.bus.clint.mtime();
+        self.bus.clint.set_mtime(mtime + inc);
 
         // time(CSRs: 0xc01)
-        self.csrs.timer_increment(inc);
+        self.csrs.sync_time(mtime + inc);
+    }
+}
+
+impl Snapshot for Cpu {
+    fn save(&self, w: &mut Writer) -> io::Result<()> {
+        w.tag(b"CPU ")?;
+        w.u64(self.pc())?;
+        self.regs.save(w)?;
+        self.fregs.save(w)?;
+        self.csrs.save(w)?;
+        w.u8(self.priv_lv as u8)
+    }
+
+    fn restore(&mut self, r: &mut Reader) -> io::Result<()> {
+        r.tag(b"CPU ")?;
+        self.update_pc(r.u64()?);
+        self.regs.restore(r)?;
+        self.fregs.restore(r)?;
+        self.csrs.restore(r)?;
+        self.priv_lv = hart::restore_priv_lv(r)?;
+
+        // cached translations and decoded instructions are rebuilt on demand
+        self.mmu.reset();
+        self.bus.icache.flush();
+
+        Ok(())
     }
 }
 
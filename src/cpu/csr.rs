@@ -1,23 +1,63 @@
-mod breakpoint;
+mod counter;
+mod dcsr;
+mod hypervisor;
+mod table;
+mod trigger;
 
-use super::{CrossIsaUtil, PrivilegedLevel, TrapCause};
+use super::{AddrTransMode, CrossIsaUtil, PrivilegedLevel, TrapCause};
+use crate::snapshot::{Reader, Snapshot, Writer};
 use crate::Isa;
-use breakpoint::Triggers;
+use counter::{
+    Counters, CYCLE, CYCLEH, HPMCOUNTER31, HPMCOUNTER31H, MCOUNTINHIBIT, MCYCLE, MCYCLEH,
+    MHPMCOUNTER31, MHPMCOUNTER31H, MHPMEVENT3, MHPMEVENT31, MHPMEVENT31H, MHPMEVENT3H, MIP_LCOFIP,
+    SCOUNTOVF,
+};
+pub use counter::{Event, Events};
+pub use dcsr::DebugCause;
+use dcsr::{DebugState, DCSR, DCSR_RESET};
+use hypervisor::{HGATP, HIE, HIP, HVIP, MIDELEG, MIP_VSSIP, VSATP, VSEPC, VSIE, VSIP, VSSTATUS};
+pub use hypervisor::{HSTATUS_HU, HSTATUS_VTSR, HSTATUS_VTVM, HSTATUS_VTW};
 use std::cell::RefCell;
+use std::io;
 use std::rc::Rc;
+pub use trigger::Action as TriggerAction;
+use trigger::{Triggers, TINFO, TSELECT};
 
+const FFLAGS: usize = CSRname::fflags as usize;
+const FRM: usize = CSRname::frm as usize;
+const FCSR: usize = CSRname::fcsr as usize;
 const MISA: usize = CSRname::misa as usize;
 const USTATUS: usize = CSRname::ustatus as usize;
 const SSTATUS: usize = CSRname::sstatus as usize;
 const MSTATUS: usize = CSRname::mstatus as usize;
 const SIE: usize = CSRname::sie as usize;
 const SIP: usize = CSRname::sip as usize;
-const SIESIPMASK: u64 = 0x0333;
-const MHPMCOUNTER3: usize = CSRname::mhpmcounter3 as usize;
+const STIMECMP: usize = CSRname::stimecmp as usize;
+const STIMECMPH: usize = CSRname::stimecmph as usize;
+const SATP: usize = CSRname::satp as usize;
+const MENVCFG: usize = CSRname::menvcfg as usize;
+const MENVCFGH: usize = CSRname::menvcfgh as usize;
+const MENVCFG_ADUE: u64 = 1 << 61;
+const MENVCFG_STCE: u64 = 1 << 63;
+const MIE: usize = CSRname::mie as usize;
+const MIP: usize = CSRname::mip as usize;
+const MIP_SSIP: u64 = 1 << 1;
+const MIP_STIP: u64 = 1 << 5;
+const MIP_SEIP: u64 = 1 << 9;
+const TIME: usize = CSRname::timer as usize;
+const TIMEH: usize = CSRname::timeh as usize;
+const SIESIPMASK: u64 = 0x2333;
 
 pub struct CSRs {
     csrs: [u64; 4096],
     triggers: Triggers,
+    debug: DebugState,
+    counters: Counters,
+    max_trans_mode: AddrTransMode,
+    svadu: bool,
+    sstc: bool,
+    hypervisor: bool,
+    virt: bool,
     pc: Rc<RefCell<u64>>,
     isa: Rc<Isa>,
 }
@@ -25,24 +65,29 @@ pub struct CSRs {
 #[allow(clippy::identity_op)]
 impl CSRs {
     pub fn new(isa: Rc<Isa>, pc: Rc<RefCell<u64>>) -> Self {
-        CSRs {
+        let mut csrs = CSRs {
             csrs: [0; 4096],
-            triggers: Triggers {
-                tselect: 0,
-                tdata1: [0; 8],
-                tdata2: [0; 8],
-            },
+            triggers: Triggers::new(*isa),
+            debug: DebugState::default(),
+            counters: Counters::default(),
+            max_trans_mode: AddrTransMode::widest(*isa, None),
+            svadu: false,
+            sstc: false,
+            hypervisor: false,
+            virt: false,
             pc,
             isa,
-        }
+        };
+        csrs.csrs[DCSR] = DCSR_RESET;
+        csrs
     }
 
     pub fn init(mut self) -> Self {
         self.write(CSRname::marchid.wrap(), 0x5).unwrap();
         match *self.isa {
-            Isa::Rv32 => self.write(CSRname::misa.wrap(), 0x40141105).unwrap(),
+            Isa::Rv32 => self.write(CSRname::misa.wrap(), 0x4014112d).unwrap(),
             Isa::Rv64 => {
-                self.write(CSRname::misa.wrap(), 0x8000000000141105)
+                self.write(CSRname::misa.wrap(), 0x800000000014112d)
                     .unwrap();
                 self.write(CSRname::mstatus.wrap(), 0x0000000a00000000)
                     .unwrap();
@@ -51,6 +96,127 @@ impl CSRs {
         self
     }
 
+    pub fn with_trans_mode(mut self, max_trans_mode: AddrTransMode) -> Self {
+        self.max_trans_mode = max_trans_mode;
+        self
+    }
+
+    // hardware A/D bit updating is enabled at reset when Svadu is implemented
+    pub fn with_svadu(mut self, svadu: bool) -> Self {
+        self.svadu = svadu;
+        if svadu {
+            self.csrs[MENVCFG] |= MENVCFG_ADUE;
+        }
+        self
+    }
+
+    pub fn with_sstc(mut self, sstc: bool) -> Self {
+        self.sstc = sstc;
+        self
+    }
+
+    pub fn with_hartid(mut self, hartid: usize) -> Self {
+        self.csrs[CSRname::mhartid as usize] = hartid as u64;
+        self
+    }
+
+    pub fn hartid(&self) -> usize {
+        self.csrs[CSRname::mhartid as usize] as usize
+    }
+
+    pub fn adue(&self) -> bool {
+        self.csrs[MENVCFG] & MENVCFG_ADUE != 0
+    }
+
+    pub fn sstc(&self) -> bool {
+        self.sstc
+    }
+
+    // stimecmp is only usable below M-mode while this is set
+    pub fn stce(&self) -> bool {
+        self.csrs[MENVCFG] & MENVCFG_STCE != 0
+    }
+
+    // menvcfgh is the upper half of menvcfg (rv32 only)
+    fn read_menvcfg(&self, dist: usize) -> u64 {
+        match dist {
+            MENVCFGH => self.csrs[MENVCFG] >> 32,
+            _ => self.csrs[MENVCFG].fix2regsz(&self.isa),
+        }
+    }
+
+    fn write_menvcfg(&mut self, dist: usize, src: u64) {
+        let menvcfg = match (dist, *self.isa) {
+            (MENVCFGH, _) => self.csrs[MENVCFG] & 0xffff_ffff | src << 32,
+            (_, Isa::Rv32) => self.csrs[MENVCFG] & !0xffff_ffff | src,
+            (_, Isa::Rv64) => src,
+        };
+        let writable = [(self.svadu, MENVCFG_ADUE), (self.sstc, MENVCFG_STCE)]
+            .into_iter()
+            .filter(|(implemented, _)| *implemented)
+            .fold(0, |writable, (_, bit)| writable | bit);
+        self.csrs[MENVCFG] = menvcfg & writable;
+        self.update_stip();
+    }
+
+    // stimecmph is the upper half of stimecmp (rv32 only)
+    fn read_stimecmp(&self, dist: usize) -> u64 {
+        match dist {
+            STIMECMPH => self.csrs[STIMECMP] >> 32,
+            _ => self.csrs[STIMECMP].fix2regsz(&self.isa),
+        }
+    }
+
+    fn write_stimecmp(&mut self, dist: usize, src: u64) {
+        self.csrs[STIMECMP] = match (dist, *self.isa) {
+            (STIMECMPH, _) => self.csrs[STIMECMP] & 0xffff_ffff | src << 32,
+            (_, Isa::Rv32) => self.csrs[STIMECMP] & !0xffff_ffff | src,
+            (_, Isa::Rv64) => src,
+        };
+        self.update_stip();
+    }
+
+    // MSIP, MTIP and MEIP belong to the interrupt controllers, as does
+    // STIP once Sstc ties it to stimecmp; mip.VSSIP is an alias of hvip's
+    fn write_mip(&mut self, dist: usize, src: u64) {
+        let writable = match dist {
+            SIP => MIP_SSIP | MIP_LCOFIP,
+            _ if self.stce() => MIP_SSIP | MIP_SEIP | MIP_LCOFIP,
+            _ => MIP_SSIP | MIP_STIP | MIP_SEIP | MIP_LCOFIP,
+        };
+        let writable = match dist {
+            MIP if self.hypervisor => writable | MIP_VSSIP,
+            _ => writable,
+        };
+        self.csrs[MIP] = self.csrs[MIP] & !writable | src & writable;
+    }
+
+    // the pending bits driven by the CLINT and the PLIC
+    pub fn drive_mip(&mut self, mask: u64, value: u64) {
+        self.csrs[MIP] = self.csrs[MIP] & !mask | value & mask;
+    }
+
+    // with STCE, STIP follows stimecmp the way MTIP follows mtimecmp
+    fn update_stip(&mut self) {
+        if self.stce() {
+            let pending = if self.csrs[TIME] >= self.csrs[STIMECMP] {
+                MIP_STIP
+            } else {
+                0
+            };
+            self.drive_mip(MIP_STIP, pending);
+        }
+    }
+
+    // satp.MODE is WARL: writes with an unsupported mode have no effect
+    // (vsatp and hgatp too)
+    fn write_satp(&mut self, dist: usize, satp: u64) {
+        match AddrTransMode::from_satp(*self.isa, satp) {
+            Some(mode) if mode <= self.max_trans_mode => self.csrs[dist] = satp,
+            _ => (),
+        }
+    }
+
     fn umask(&self) -> u64 {
         match *self.isa {
             Isa::Rv32 => 0b10000000000011010111100100110011,
@@ -75,7 +241,7 @@ impl CSRs {
     fn mask_warl(&mut self, dst: usize, mask: u64) -> u64 {
         match dst {
             MISA => {
-                if *self.pc.borrow() % 4 != 0 {
+                if !(*self.pc.borrow()).is_multiple_of(4) {
                     mask & !0b100 // clear C extension flag
                 } else {
                     mask
@@ -83,10 +249,9 @@ impl CSRs {
             }
             MSTATUS => match *self.isa {
                 Isa::Rv32 => mask,
-                Isa::Rv64 => mask & !(0b1111 << 32),
+                Isa::Rv64 => mask & !(0b1111 << 32) & self.writable(dst),
             },
-            MHPMCOUNTER3 => 0,
-            _ => mask,
+            _ => mask & self.writable(dst),
         }
     }
 
@@ -100,13 +265,36 @@ impl CSRs {
         let mask = self.mask_warl(dist, src.fix2regsz(&self.isa));
         if mask != 0 {
             match dist {
+                FFLAGS => self.csrs[FCSR] |= mask & 0x1f,
+                FRM => self.csrs[FCSR] |= (mask & 0x7) << 5,
+                FCSR => self.csrs[FCSR] |= mask & 0xff,
                 USTATUS => self.csrs[MSTATUS] |= mask & self.umask(),
                 SSTATUS => self.csrs[MSTATUS] |= mask & self.smask(),
                 SIE => self.csrs[CSRname::mie as usize] |= mask & SIESIPMASK,
-                SIP => self.csrs[CSRname::mip as usize] |= mask & SIESIPMASK,
+                SIP | MIP => self.write_mip(dist, self.csrs[MIP] | mask),
+                SATP | VSATP => self.write_satp(dist, self.csrs[dist] | mask),
+                HGATP => self.write_hgatp(self.csrs[HGATP] | mask),
+                VSSTATUS => self.write_vsstatus(self.csrs[VSSTATUS] | mask),
+                HIE | HIP | HVIP | VSIE | VSIP => self.write_hip(dist, self.read_hip(dist) | mask),
+                MENVCFG | MENVCFGH => self.write_menvcfg(dist, self.read_menvcfg(dist) | mask),
+                STIMECMP | STIMECMPH => self.write_stimecmp(dist, self.read_stimecmp(dist) | mask),
+                MCYCLE..=MHPMCOUNTER31
+                | MCYCLEH..=MHPMCOUNTER31H
+                | CYCLE..=HPMCOUNTER31
+                | CYCLEH..=HPMCOUNTER31H => {
+                    self.write_counter(dist, self.read_counter(dist) | mask)
+                }
+                MCOUNTINHIBIT => self.write_mcountinhibit(self.csrs[MCOUNTINHIBIT] | mask),
+                MHPMEVENT3..=MHPMEVENT31 | MHPMEVENT3H..=MHPMEVENT31H => {
+                    self.write_mhpmevent(dist, self.read_mhpmevent(dist) | mask)
+                }
+                SCOUNTOVF => (),
+                TSELECT..=TINFO => self.write_trigger(dist, self.read_trigger(dist) | mask),
+                DCSR => self.write_dcsr(self.csrs[DCSR] | mask),
                 _ => self.csrs[dist] |= mask,
             }
         }
+        self.update_fp_status(dist);
 
         Ok(())
     }
@@ -121,13 +309,36 @@ impl CSRs {
         let mask = self.mask_warl(dist, src.fix2regsz(&self.isa));
         if mask != 0 {
             match dist {
+                FFLAGS => self.csrs[FCSR] &= !(mask & 0x1f),
+                FRM => self.csrs[FCSR] &= !((mask & 0x7) << 5),
+                FCSR => self.csrs[FCSR] &= !(mask & 0xff),
                 USTATUS => self.csrs[MSTATUS] &= !(mask & self.umask()),
                 SSTATUS => self.csrs[MSTATUS] &= !(mask & self.smask()),
                 SIE => self.csrs[CSRname::mie as usize] &= !(mask & SIESIPMASK),
-                SIP => self.csrs[CSRname::mip as usize] &= !(mask & SIESIPMASK),
+                SIP | MIP => self.write_mip(dist, self.csrs[MIP] & !mask),
+                SATP | VSATP => self.write_satp(dist, self.csrs[dist] & !mask),
+                HGATP => self.write_hgatp(self.csrs[HGATP] & !mask),
+                VSSTATUS => self.write_vsstatus(self.csrs[VSSTATUS] & !mask),
+                HIE | HIP | HVIP | VSIE | VSIP => self.write_hip(dist, self.read_hip(dist) & !mask),
+                MENVCFG | MENVCFGH => self.write_menvcfg(dist, self.read_menvcfg(dist) & !mask),
+                STIMECMP | STIMECMPH => self.write_stimecmp(dist, self.read_stimecmp(dist) & !mask),
+                MCYCLE..=MHPMCOUNTER31
+                | MCYCLEH..=MHPMCOUNTER31H
+                | CYCLE..=HPMCOUNTER31
+                | CYCLEH..=HPMCOUNTER31H => {
+                    self.write_counter(dist, self.read_counter(dist) & !mask)
+                }
+                MCOUNTINHIBIT => self.write_mcountinhibit(self.csrs[MCOUNTINHIBIT] & !mask),
+                MHPMEVENT3..=MHPMEVENT31 | MHPMEVENT3H..=MHPMEVENT31H => {
+                    self.write_mhpmevent(dist, self.read_mhpmevent(dist) & !mask)
+                }
+                SCOUNTOVF => (),
+                TSELECT..=TINFO => self.write_trigger(dist, self.read_trigger(dist) & !mask),
+                DCSR => self.write_dcsr(self.csrs[DCSR] & !mask),
                 _ => self.csrs[dist] &= !mask,
             }
         }
+        self.update_fp_status(dist);
 
         Ok(())
     }
@@ -141,12 +352,19 @@ impl CSRs {
 
         let src = src.fix2regsz(&self.isa);
         match dist {
-            USTATUS => self.csrs[MSTATUS] |= src & self.umask(),
-            SSTATUS => self.csrs[MSTATUS] |= src & self.smask(),
+            FFLAGS => self.csrs[FCSR] = (self.csrs[FCSR] & !0x1f) | (src & 0x1f),
+            FRM => self.csrs[FCSR] = (self.csrs[FCSR] & !(0x7 << 5)) | ((src & 0x7) << 5),
+            FCSR => self.csrs[FCSR] = src & 0xff,
+            USTATUS => {
+                self.csrs[MSTATUS] = (self.csrs[MSTATUS] & !self.umask()) | (src & self.umask())
+            }
+            SSTATUS => {
+                self.csrs[MSTATUS] = (self.csrs[MSTATUS] & !self.smask()) | (src & self.smask())
+            }
             SIE => self.csrs[CSRname::mie as usize] = src & SIESIPMASK,
-            SIP => self.csrs[CSRname::mip as usize] = src & SIESIPMASK,
+            SIP | MIP => self.write_mip(dist, src),
             MISA => {
-                if *self.pc.borrow() % 4 != 0 {
+                if !(*self.pc.borrow()).is_multiple_of(4) {
                     let c_ext_bit = (self.csrs[MISA] >> 2) & 1;
                     self.csrs[MISA] = (src & !0b100) | c_ext_bit
                 } else {
@@ -155,16 +373,54 @@ impl CSRs {
             }
             MSTATUS => match *self.isa {
                 Isa::Rv32 => self.csrs[dist] = src,
-                Isa::Rv64 => self.csrs[dist] = (src & !(0b1111 << 32)) | 0b1010 << 32,
+                Isa::Rv64 => {
+                    self.csrs[dist] = (src & !(0b1111 << 32) & self.writable(dist)) | 0b1010 << 32
+                }
             },
-            MHPMCOUNTER3 => (), // protect from any value
-            other => self.csrs[other] = src,
+            SATP | VSATP => self.write_satp(dist, src),
+            HGATP => self.write_hgatp(src),
+            VSSTATUS => self.write_vsstatus(src),
+            HIE | HIP | HVIP | VSIE | VSIP => self.write_hip(dist, src),
+            MENVCFG | MENVCFGH => self.write_menvcfg(dist, src),
+            STIMECMP | STIMECMPH => self.write_stimecmp(dist, src),
+            MCYCLE..=MHPMCOUNTER31
+            | MCYCLEH..=MHPMCOUNTER31H
+            | CYCLE..=HPMCOUNTER31
+            | CYCLEH..=HPMCOUNTER31H => self.write_counter(dist, src),
+            MCOUNTINHIBIT => self.write_mcountinhibit(src),
+            MHPMEVENT3..=MHPMEVENT31 | MHPMEVENT3H..=MHPMEVENT31H => {
+                self.write_mhpmevent(dist, src)
+            }
+            SCOUNTOVF => (),
+            TSELECT..=TINFO => self.write_trigger(dist, src),
+            DCSR => self.write_dcsr(src),
+            other => self.csrs[other] = self.legalize(other, src),
         }
-        self.update_triggers(dist, src);
+        self.update_fp_status(dist);
 
         Ok(())
     }
 
+    // writing fcsr makes the FP state dirty, and SD summarizes FS/XS
+    fn update_fp_status(&mut self, dist: usize) {
+        if matches!(dist, FFLAGS | FRM | FCSR) {
+            self.csrs[MSTATUS] |= 0b11 << 13;
+            self.dirty_guest_fs();
+        }
+
+        let sd_bit = match *self.isa {
+            Isa::Rv32 => 31,
+            Isa::Rv64 => 63,
+        };
+        let fs = self.csrs[MSTATUS] >> 13 & 0x3;
+        let xs = self.csrs[MSTATUS] >> 15 & 0x3;
+        if fs == 0b11 || xs == 0b11 {
+            self.csrs[MSTATUS] |= 1 << sd_bit;
+        } else {
+            self.csrs[MSTATUS] &= !(1 << sd_bit);
+        }
+    }
+
     fn read_xepc(&self, dist: usize) -> Result<u64, (Option<u64>, TrapCause, String)> {
         if self.csrs[CSRname::misa as usize] >> 2 & 0x1 == 1 {
             // C extension enabled (IALIGN = 16)
@@ -179,11 +435,26 @@ impl CSRs {
         let dist = src.unwrap();
 
         match dist {
+            FFLAGS => Ok(self.csrs[FCSR] & 0x1f),
+            FRM => Ok(self.csrs[FCSR] >> 5 & 0x7),
+            FCSR => Ok(self.csrs[FCSR] & 0xff),
             0x000 => Ok(self.csrs[0x300].fix2regsz(&self.isa) & self.umask()),
             0x100 => Ok(self.csrs[0x300].fix2regsz(&self.isa) & self.smask()),
             SIE => Ok(self.csrs[CSRname::mie as usize].fix2regsz(&self.isa) & SIESIPMASK),
             SIP => Ok(self.csrs[CSRname::mip as usize].fix2regsz(&self.isa) & SIESIPMASK),
-            0x341 | 0x141 => self.read_xepc(dist),
+            0x341 | 0x141 | VSEPC => self.read_xepc(dist),
+            MIDELEG => Ok(self.read_mideleg()),
+            HIE | HIP | HVIP | VSIE | VSIP => Ok(self.read_hip(dist)),
+            TIME if self.virt => Ok(self.guest_time()),
+            MENVCFG | MENVCFGH => Ok(self.read_menvcfg(dist)),
+            STIMECMP | STIMECMPH => Ok(self.read_stimecmp(dist)),
+            MCYCLE..=MHPMCOUNTER31
+            | MCYCLEH..=MHPMCOUNTER31H
+            | CYCLE..=HPMCOUNTER31
+            | CYCLEH..=HPMCOUNTER31H => Ok(self.read_counter(dist)),
+            MHPMEVENT3..=MHPMEVENT31 | MHPMEVENT3H..=MHPMEVENT31H => Ok(self.read_mhpmevent(dist)),
+            SCOUNTOVF => Ok(self.read_scountovf()),
+            TSELECT..=TINFO => Ok(self.read_trigger(dist).fix2regsz(&self.isa)),
             _ => Ok(self.csrs[dist].fix2regsz(&self.isa)),
         }
     }
@@ -332,10 +603,38 @@ impl CSRs {
                 }
             }
         }
+        self.update_fp_status(xstatus);
+    }
+
+    // time is a copy of the CLINT's mtime, never counted on its own
+    pub fn sync_time(&mut self, mtime: u64) {
+        self.csrs[TIME] = mtime;
+        self.csrs[TIMEH] = mtime >> 32;
+        self.update_stip();
+    }
+}
+
+impl Snapshot for CSRs {
+    fn save(&self, w: &mut Writer) -> io::Result<()> {
+        w.tag(b"CSR ")?;
+        w.u64s(&self.csrs)?;
+        w.u64(self.triggers.tselect as u64)?;
+        w.u64s(&self.triggers.tdata1)?;
+        w.u64s(&self.triggers.tdata2)?;
+        w.u8(self.debug.halted as u8)?;
+        w.u8(self.virt as u8)
     }
 
-    pub fn timer_increment(&mut self, inc: u64) {
-        self.csrs[CSRname::timer as usize] += inc;
+    fn restore(&mut self, r: &mut Reader) -> io::Result<()> {
+        r.tag(b"CSR ")?;
+        r.u64s_into(&mut self.csrs)?;
+        self.triggers.tselect = r.u64()? as usize;
+        r.u64s_into(&mut self.triggers.tdata1)?;
+        r.u64s_into(&mut self.triggers.tdata2)?;
+        self.debug.halted = r.u8()? != 0;
+        self.virt = r.u8()? != 0;
+        self.update_active_counters();
+        Ok(())
     }
 }
 
@@ -348,6 +647,9 @@ impl Default for CSRs {
 #[allow(non_camel_case_types)]
 pub enum CSRname {
     ustatus = 0x000,
+    fflags = 0x001,
+    frm = 0x002,
+    fcsr = 0x003,
     utvec = 0x005,
     uepc = 0x041,
     ucause = 0x042,
@@ -360,7 +662,18 @@ pub enum CSRname {
     scause = 0x142,
     stval = 0x143,
     sip = 0x144,
+    stimecmp = 0x14d,
+    stimecmph = 0x15d,
     satp = 0x180,
+    vsstatus = 0x200,
+    vsie = 0x204,
+    vstvec = 0x205,
+    vsscratch = 0x240,
+    vsepc = 0x241,
+    vscause = 0x242,
+    vstval = 0x243,
+    vsip = 0x244,
+    vsatp = 0x280,
     mstatus = 0x300,
     misa = 0x301,
     medeleg = 0x302,
@@ -368,20 +681,56 @@ pub enum CSRname {
     mie = 0x304,
     mtvec = 0x305,
     mcounteren = 0x306,
+    menvcfg = 0x30a,
+    menvcfgh = 0x31a,
+    mcountinhibit = 0x320,
+    mhpmevent3 = 0x323,
     mscratch = 0x340,
     mepc = 0x341,
     mcause = 0x342,
     mtval = 0x343,
     mip = 0x344,
+    mtinst = 0x34a,
+    mtval2 = 0x34b,
+    hstatus = 0x600,
+    hedeleg = 0x602,
+    hideleg = 0x603,
+    hie = 0x604,
+    htimedelta = 0x605,
+    hcounteren = 0x606,
+    hgeie = 0x607,
+    henvcfg = 0x60a,
+    htval = 0x643,
+    hip = 0x644,
+    hvip = 0x645,
+    htinst = 0x64a,
+    hgatp = 0x680,
+    mhpmevent3h = 0x723,
     tselect = 0x7a0,
     tdata1 = 0x7a1,
     tdata2 = 0x7a2,
     tdata3 = 0x7a3,
-    tdata4 = 0x7a4,
-    tdata5 = 0x7a5,
+    tinfo = 0x7a4,
+    tcontrol = 0x7a5,
+    dcsr = 0x7b0,
+    dpc = 0x7b1,
+    dscratch0 = 0x7b2,
+    dscratch1 = 0x7b3,
+    mcycle = 0xb00,
+    minstret = 0xb02,
     mhpmcounter3 = 0xb03,
+    mcycleh = 0xb80,
+    minstreth = 0xb82,
+    cycle = 0xc00,
     timer = 0xc01,
+    instret = 0xc02,
+    cycleh = 0xc80,
+    timeh = 0xc81,
+    instreth = 0xc82,
+    scountovf = 0xda0,
+    hgeip = 0xe12,
     marchid = 0xf12,
+    mhartid = 0xf14,
 }
 
 impl CSRname {
@@ -0,0 +1,267 @@
+use crate::cpu::csr::{CSRname, CSRs};
+use crate::cpu::{CrossIsaUtil, PrivilegedLevel};
+use crate::Isa;
+use std::mem;
+
+pub const MCYCLE: usize = CSRname::mcycle as usize;
+pub const MHPMCOUNTER31: usize = MCYCLE + 31;
+pub const MCYCLEH: usize = CSRname::mcycleh as usize;
+pub const MHPMCOUNTER31H: usize = MCYCLEH + 31;
+pub const CYCLE: usize = CSRname::cycle as usize;
+pub const HPMCOUNTER31: usize = CYCLE + 31;
+pub const CYCLEH: usize = CSRname::cycleh as usize;
+pub const HPMCOUNTER31H: usize = CYCLEH + 31;
+pub const MCOUNTINHIBIT: usize = CSRname::mcountinhibit as usize;
+pub const MHPMEVENT3: usize = CSRname::mhpmevent3 as usize;
+pub const MHPMEVENT31: usize = MCOUNTINHIBIT + 31;
+pub const MHPMEVENT3H: usize = CSRname::mhpmevent3h as usize;
+pub const MHPMEVENT31H: usize = MHPMEVENT3H + 28;
+pub const SCOUNTOVF: usize = CSRname::scountovf as usize;
+pub const MIP_LCOFIP: u64 = 1 << 13;
+
+// counters are numbered as in mcounteren: cycle, time, instret and then the hpm counters
+const CY: usize = 0;
+const TM: usize = 1;
+const IR: usize = 2;
+const HPM_COUNTERS: std::ops::Range<usize> = 3..32;
+
+// Sscofpmf adds the overflow flag and the mode filters to mhpmevent
+const MHPMEVENT_OF: u64 = 1 << 63;
+const MHPMEVENT_MINH: u64 = 1 << 62;
+const MHPMEVENT_SINH: u64 = 1 << 61;
+const MHPMEVENT_UINH: u64 = 1 << 60;
+const MHPMEVENT_VSINH: u64 = 1 << 59;
+const MHPMEVENT_VUINH: u64 = 1 << 58;
+const MHPMEVENT_SELECTOR: u64 = (1 << 56) - 1;
+
+// the values of the mhpmevent event selector
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event {
+    Cycles = 1,
+    Instructions = 2,
+    Loads = 3,
+    Stores = 4,
+    Atomics = 5,
+    Branches = 6,
+    TakenBranches = 7,
+    Exceptions = 8,
+    Interrupts = 9,
+    ItlbMisses = 10,
+    DtlbMisses = 11,
+}
+
+const EVENTS: usize = Event::DtlbMisses as usize + 1;
+
+// how often each event occurred during one instruction
+#[derive(Default)]
+pub struct Events([u64; EVENTS]);
+
+impl Events {
+    pub fn add(&mut self, event: Event, count: u64) {
+        self.0[event as usize] += count;
+    }
+}
+
+#[derive(Default)]
+pub struct Counters {
+    // hpm counters with an event selected and not inhibited
+    active: u32,
+    // counters written by the current instruction, which it doesn't count towards
+    written: u32,
+}
+
+impl CSRs {
+    // the 64-bit counter behind a counter CSR or its upper half
+    pub(super) fn read_counter(&self, dist: usize) -> u64 {
+        let index = dist & 0x1f;
+        let counter = match (dist, index) {
+            (CYCLE..=HPMCOUNTER31 | CYCLEH..=HPMCOUNTER31H, TM) => {
+                self.csrs[CSRname::timer as usize]
+            }
+            (_, TM) => 0,
+            _ => self.csrs[MCYCLE + index],
+        };
+        match dist {
+            MCYCLEH..=MHPMCOUNTER31H | CYCLEH..=HPMCOUNTER31H => counter >> 32,
+            _ => counter.fix2regsz(&self.isa),
+        }
+    }
+
+    // the user counters are read-only shadows of the machine ones
+    pub(super) fn write_counter(&mut self, dist: usize, src: u64) {
+        let index = dist & 0x1f;
+        if index == TM || !matches!(dist, MCYCLE..=MHPMCOUNTER31 | MCYCLEH..=MHPMCOUNTER31H) {
+            return;
+        }
+        let counter = &mut self.csrs[MCYCLE + index];
+        *counter = match (dist, *self.isa) {
+            (MCYCLEH..=MHPMCOUNTER31H, _) => *counter & 0xffff_ffff | src << 32,
+            (_, Isa::Rv32) => *counter & !0xffff_ffff | src,
+            (_, Isa::Rv64) => src,
+        };
+        self.counters.written |= 1 << index;
+    }
+
+    // mhpmeventNh is the upper half of mhpmeventN (rv32 only)
+    pub(super) fn read_mhpmevent(&self, dist: usize) -> u64 {
+        let mhpmevent = self.csrs[MCOUNTINHIBIT + (dist & 0x1f)];
+        match dist {
+            MHPMEVENT3H..=MHPMEVENT31H => mhpmevent >> 32,
+            _ => mhpmevent.fix2regsz(&self.isa),
+        }
+    }
+
+    // events that don't exist select nothing
+    pub(super) fn write_mhpmevent(&mut self, dist: usize, src: u64) {
+        let index = dist & 0x1f;
+        let mhpmevent = self.csrs[MCOUNTINHIBIT + index];
+        let mhpmevent = match (dist, *self.isa) {
+            (MHPMEVENT3H..=MHPMEVENT31H, _) => mhpmevent & 0xffff_ffff | src << 32,
+            (_, Isa::Rv32) => mhpmevent & !0xffff_ffff | src,
+            (_, Isa::Rv64) => src,
+        };
+        let selector = match mhpmevent & MHPMEVENT_SELECTOR {
+            selector if selector < EVENTS as u64 => selector,
+            _ => 0,
+        };
+        let mut filters = MHPMEVENT_OF | MHPMEVENT_MINH | MHPMEVENT_SINH | MHPMEVENT_UINH;
+        // the VS/VU-mode filters are read-only zero without the H extension
+        if self.hypervisor {
+            filters |= MHPMEVENT_VSINH | MHPMEVENT_VUINH;
+        }
+        self.csrs[MCOUNTINHIBIT + index] = mhpmevent & filters | selector;
+        self.update_active_counters();
+    }
+
+    // time can't be inhibited
+    pub(super) fn write_mcountinhibit(&mut self, src: u64) {
+        self.csrs[MCOUNTINHIBIT] = src & 0xffff_fffd;
+        self.update_active_counters();
+    }
+
+    // the overflow flags of the hpm counters
+    pub(super) fn read_scountovf(&self) -> u64 {
+        HPM_COUNTERS
+            .filter(|index| self.csrs[MCOUNTINHIBIT + index] & MHPMEVENT_OF != 0)
+            .fold(0, |scountovf, index| scountovf | 1 << index)
+    }
+
+    pub(super) fn update_active_counters(&mut self) {
+        let inhibit = self.csrs[MCOUNTINHIBIT];
+        self.counters.active = HPM_COUNTERS
+            .filter(|index| {
+                self.csrs[MCOUNTINHIBIT + index] & MHPMEVENT_SELECTOR != 0
+                    && inhibit >> index & 1 == 0
+            })
+            .fold(0, |active, index| active | 1 << index);
+    }
+
+    // advance the counters by what happened in one instruction executed at
+    // priv_lv, in a guest when virt
+    pub fn count(&mut self, priv_lv: PrivilegedLevel, virt: bool, events: &Events) {
+        let written = mem::take(&mut self.counters.written);
+        let stopped = self.csrs[MCOUNTINHIBIT] | written as u64;
+        for (index, event) in [(CY, Event::Cycles), (IR, Event::Instructions)] {
+            if stopped >> index & 1 == 0 {
+                self.csrs[MCYCLE + index] =
+                    self.csrs[MCYCLE + index].wrapping_add(events.0[event as usize]);
+            }
+        }
+
+        let mut active = self.counters.active & !written;
+        while active != 0 {
+            let index = active.trailing_zeros() as usize;
+            active &= active - 1;
+
+            let mhpmevent = self.csrs[MCOUNTINHIBIT + index];
+            let count = events.0[(mhpmevent & MHPMEVENT_SELECTOR) as usize];
+            let inhibited = match (priv_lv, virt) {
+                (PrivilegedLevel::Machine, _) => MHPMEVENT_MINH,
+                (PrivilegedLevel::Supervisor, false) => MHPMEVENT_SINH,
+                (PrivilegedLevel::Supervisor, true) => MHPMEVENT_VSINH,
+                (_, false) => MHPMEVENT_UINH,
+                (_, true) => MHPMEVENT_VUINH,
+            };
+            if count == 0 || mhpmevent & inhibited != 0 {
+                continue;
+            }
+
+            let (counter, overflow) = self.csrs[MCYCLE + index].overflowing_add(count);
+            self.csrs[MCYCLE + index] = counter;
+            // only the first overflow raises the interrupt until OF is cleared
+            if overflow && mhpmevent & MHPMEVENT_OF == 0 {
+                self.csrs[MCOUNTINHIBIT + index] |= MHPMEVENT_OF;
+                self.csrs[CSRname::mip as usize] |= MIP_LCOFIP;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counter_test() {
+        let mut csrs = CSRs::default();
+        let mut load = Events::default();
+        load.add(Event::Cycles, 1);
+        load.add(Event::Instructions, 1);
+        load.add(Event::Loads, 1);
+        let read = |csrs: &CSRs, csr: CSRname| csrs.read(csr.wrap()).unwrap();
+
+        csrs.count(PrivilegedLevel::User, false, &load);
+        assert_eq!(read(&csrs, CSRname::cycle), 1);
+        assert_eq!(read(&csrs, CSRname::instret), 1);
+        // an instruction that writes a counter doesn't count towards it
+        csrs.write(CSRname::minstret.wrap(), 10).unwrap();
+        csrs.count(PrivilegedLevel::Machine, false, &load);
+        assert_eq!(read(&csrs, CSRname::minstret), 10);
+        csrs.write(CSRname::mcountinhibit.wrap(), 0b111).unwrap();
+        assert_eq!(read(&csrs, CSRname::mcountinhibit), 0b101);
+        csrs.count(PrivilegedLevel::Machine, false, &load);
+        assert_eq!(read(&csrs, CSRname::mcycle), 2);
+        assert_eq!(read(&csrs, CSRname::minstret), 10);
+        csrs.write(CSRname::mcountinhibit.wrap(), 0).unwrap();
+
+        // hpm counters only count their event outside the inhibited modes
+        let mhpmevent4 = Some(CSRname::mhpmevent3 as usize + 1);
+        let mhpmcounter4 = Some(CSRname::mhpmcounter3 as usize + 1);
+        csrs.write(mhpmevent4, 0x7f).unwrap();
+        assert_eq!(csrs.read(mhpmevent4).unwrap(), 0);
+        csrs.write(mhpmevent4, MHPMEVENT_MINH | Event::Loads as u64)
+            .unwrap();
+        csrs.count(PrivilegedLevel::Supervisor, false, &load);
+        csrs.count(PrivilegedLevel::Machine, false, &load);
+        assert_eq!(csrs.read(mhpmcounter4).unwrap(), 1);
+        assert_eq!(read(&csrs, CSRname::mhpmcounter3), 0);
+
+        // the first overflow raises the interrupt and sets OF
+        csrs.write(mhpmcounter4, u64::MAX).unwrap();
+        // the csrw that wrote it retires without counting
+        csrs.count(PrivilegedLevel::Machine, false, &Events::default());
+        csrs.count(PrivilegedLevel::User, false, &load);
+        assert_eq!(csrs.read(mhpmcounter4).unwrap(), 0);
+        assert_ne!(csrs.read(mhpmevent4).unwrap() & MHPMEVENT_OF, 0);
+        assert_eq!(read(&csrs, CSRname::scountovf), 1 << 4);
+        assert_eq!(read(&csrs, CSRname::mip), MIP_LCOFIP);
+        csrs.write(CSRname::sip.wrap(), 0).unwrap();
+        csrs.write(mhpmcounter4, u64::MAX).unwrap();
+        csrs.count(PrivilegedLevel::Machine, false, &Events::default());
+        csrs.count(PrivilegedLevel::User, false, &load);
+        assert_eq!(read(&csrs, CSRname::mip), 0);
+
+        // a guest's modes have filters of their own, which need the H extension
+        let vsinh = MHPMEVENT_VSINH | Event::Loads as u64;
+        csrs.write(mhpmevent4, vsinh).unwrap();
+        assert_eq!(csrs.read(mhpmevent4).unwrap(), Event::Loads as u64);
+        let mut csrs = CSRs::default().with_hypervisor(true);
+        csrs.write(mhpmevent4, vsinh).unwrap();
+        assert_eq!(csrs.read(mhpmevent4).unwrap(), vsinh);
+        csrs.count(PrivilegedLevel::Supervisor, true, &load);
+        assert_eq!(csrs.read(mhpmcounter4).unwrap(), 0);
+        csrs.count(PrivilegedLevel::Supervisor, false, &load);
+        csrs.count(PrivilegedLevel::User, true, &load);
+        assert_eq!(csrs.read(mhpmcounter4).unwrap(), 2);
+    }
+}
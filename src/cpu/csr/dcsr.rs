@@ -0,0 +1,140 @@
+use crate::cpu::csr::hypervisor::{DCSR_EBREAKVS, DCSR_EBREAKVU, DCSR_V};
+use crate::cpu::csr::{CSRname, CSRs};
+use crate::cpu::PrivilegedLevel;
+use std::mem;
+
+pub const DCSR: usize = CSRname::dcsr as usize;
+pub const DPC: usize = CSRname::dpc as usize;
+
+// Sdext 1.0
+const DCSR_DEBUGVER: u64 = 4 << 28;
+const DCSR_EBREAKM: u64 = 1 << 15;
+const DCSR_EBREAKS: u64 = 1 << 13;
+const DCSR_EBREAKU: u64 = 1 << 12;
+const DCSR_STEPIE: u64 = 1 << 11;
+// the counters don't run while the hart is halted, the timer does
+const DCSR_STOPCOUNT: u64 = 1 << 10;
+const DCSR_CAUSE: u64 = 0x7 << 6;
+// mstatus.MPRV applies to the accesses made in debug mode
+const DCSR_MPRVEN: u64 = 1 << 4;
+const DCSR_STEP: u64 = 1 << 2;
+const DCSR_PRV: u64 = 0x3;
+pub const DCSR_RESET: u64 =
+    DCSR_DEBUGVER | DCSR_STOPCOUNT | DCSR_MPRVEN | PrivilegedLevel::Machine as u64;
+
+// dcsr.cause
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DebugCause {
+    Ebreak = 1,
+    Trigger = 2,
+    HaltRequest = 3,
+    Step = 4,
+    ResetHaltRequest = 5,
+}
+
+#[derive(Default)]
+pub struct DebugState {
+    pub halted: bool,
+    // an exception raised while halted, which stops the program buffer
+    exception: bool,
+}
+
+impl CSRs {
+    pub fn debug_mode(&self) -> bool {
+        self.debug.halted
+    }
+
+    pub fn raise_debug_exception(&mut self) {
+        self.debug.exception = true;
+    }
+
+    pub fn take_debug_exception(&mut self) -> bool {
+        mem::take(&mut self.debug.exception)
+    }
+
+    // prv can't name the reserved level
+    pub(super) fn write_dcsr(&mut self, src: u64) {
+        let prv = match src & DCSR_PRV {
+            0b10 => self.csrs[DCSR] & DCSR_PRV,
+            prv => prv,
+        };
+        let mut writable = DCSR_EBREAKM | DCSR_EBREAKS | DCSR_EBREAKU | DCSR_STEPIE | DCSR_STEP;
+        if self.hypervisor {
+            writable |= DCSR_V | DCSR_EBREAKVS | DCSR_EBREAKVU;
+        }
+        self.csrs[DCSR] = self.csrs[DCSR] & !(writable | DCSR_PRV) | src & writable | prv;
+    }
+
+    // whether ebreak in priv_lv halts the hart instead of raising a breakpoint
+    pub fn ebreak_halts(&self, priv_lv: PrivilegedLevel) -> bool {
+        let ebreak = match priv_lv {
+            PrivilegedLevel::Machine => DCSR_EBREAKM,
+            PrivilegedLevel::Supervisor if self.virt => DCSR_EBREAKVS,
+            PrivilegedLevel::Supervisor => DCSR_EBREAKS,
+            _ if self.virt => DCSR_EBREAKVU,
+            _ => DCSR_EBREAKU,
+        };
+        self.csrs[DCSR] & ebreak != 0
+    }
+
+    pub fn single_step(&self) -> bool {
+        self.csrs[DCSR] & DCSR_STEP != 0
+    }
+
+    // interrupts stay pending during a single step unless stepie is set
+    pub fn step_masks_interrupts(&self) -> bool {
+        self.single_step() && self.csrs[DCSR] & DCSR_STEPIE == 0
+    }
+
+    // dcsr.v keeps the virtualization mode, as the hart halts in M-mode
+    pub fn enter_debug(&mut self, cause: DebugCause, priv_lv: PrivilegedLevel, pc: u64) {
+        self.csrs[DPC] = pc;
+        self.csrs[DCSR] = self.csrs[DCSR] & !(DCSR_CAUSE | DCSR_PRV | DCSR_V)
+            | (cause as u64) << 6
+            | (self.virt as u64) << 5
+            | priv_lv as u64;
+        self.virt = false;
+        self.debug.halted = true;
+    }
+
+    // where the hart resumes and in which mode
+    pub fn leave_debug(&mut self) -> (u64, PrivilegedLevel) {
+        self.debug.halted = false;
+        let priv_lv = match self.csrs[DCSR] & DCSR_PRV {
+            0b00 => PrivilegedLevel::User,
+            0b01 => PrivilegedLevel::Supervisor,
+            _ => PrivilegedLevel::Machine,
+        };
+        self.virt = self.csrs[DCSR] & DCSR_V != 0 && priv_lv != PrivilegedLevel::Machine;
+        (self.csrs[DPC], priv_lv)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dcsr_test() {
+        let mut csrs = CSRs::default();
+        assert_eq!(csrs.read(CSRname::dcsr.wrap()).unwrap(), DCSR_RESET);
+
+        // cause and debugver are read-only, prv can't be 2
+        csrs.write(CSRname::dcsr.wrap(), DCSR_CAUSE | DCSR_EBREAKS | 0b10)
+            .unwrap();
+        assert_eq!(
+            csrs.read(CSRname::dcsr.wrap()).unwrap(),
+            DCSR_RESET | DCSR_EBREAKS
+        );
+        assert!(csrs.ebreak_halts(PrivilegedLevel::Supervisor));
+        assert!(!csrs.ebreak_halts(PrivilegedLevel::Machine));
+
+        csrs.enter_debug(DebugCause::Step, PrivilegedLevel::User, 0x8000_0000);
+        assert!(csrs.debug_mode());
+        assert_eq!(csrs.read(CSRname::dcsr.wrap()).unwrap() >> 6 & 0x7, 4);
+        csrs.bitset(CSRname::dcsr.wrap(), DCSR_STEP).unwrap();
+        assert!(csrs.step_masks_interrupts());
+        assert_eq!(csrs.leave_debug(), (0x8000_0000, PrivilegedLevel::User));
+        assert!(!csrs.debug_mode());
+    }
+}
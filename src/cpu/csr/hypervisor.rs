@@ -0,0 +1,329 @@
+use crate::cpu::csr::{CSRname, CSRs, MENVCFG_ADUE, MIE, MIP, MSTATUS, SATP, SIE, SIP, SSTATUS};
+use crate::cpu::PrivilegedLevel;
+
+pub const MEDELEG: usize = CSRname::medeleg as usize;
+pub const MIDELEG: usize = CSRname::mideleg as usize;
+const STVEC: usize = CSRname::stvec as usize;
+const SSCRATCH: usize = CSRname::sscratch as usize;
+pub const VSSTATUS: usize = CSRname::vsstatus as usize;
+pub const VSIE: usize = CSRname::vsie as usize;
+pub const VSEPC: usize = CSRname::vsepc as usize;
+pub const VSIP: usize = CSRname::vsip as usize;
+pub const VSATP: usize = CSRname::vsatp as usize;
+pub const HSTATUS: usize = CSRname::hstatus as usize;
+pub const HIDELEG: usize = CSRname::hideleg as usize;
+pub const HIE: usize = CSRname::hie as usize;
+pub const HTIMEDELTA: usize = CSRname::htimedelta as usize;
+pub const HENVCFG: usize = CSRname::henvcfg as usize;
+pub const HIP: usize = CSRname::hip as usize;
+pub const HVIP: usize = CSRname::hvip as usize;
+pub const HGATP: usize = CSRname::hgatp as usize;
+
+// hstatus: VSXL is fixed to 64 bits, GEILEN is 0 and VSBE little-endian
+const HSTATUS_VSXL: u64 = 0b10 << 32;
+pub const HSTATUS_VTSR: u64 = 1 << 22;
+pub const HSTATUS_VTW: u64 = 1 << 21;
+pub const HSTATUS_VTVM: u64 = 1 << 20;
+pub const HSTATUS_HU: u64 = 1 << 9;
+const HSTATUS_SPVP: u64 = 1 << 8;
+const HSTATUS_SPV: u64 = 1 << 7;
+const HSTATUS_GVA: u64 = 1 << 6;
+pub const HSTATUS_WRITABLE: u64 = HSTATUS_VTSR
+    | HSTATUS_VTW
+    | HSTATUS_VTVM
+    | HSTATUS_HU
+    | HSTATUS_SPVP
+    | HSTATUS_SPV
+    | HSTATUS_GVA;
+
+const MSTATUS_MPV: u64 = 1 << 39;
+const MSTATUS_GVA: u64 = 1 << 38;
+
+// the sstatus bits vsstatus keeps, with UXL fixed to 64 bits
+const VSSTATUS_SIE: u64 = 1 << 1;
+const VSSTATUS_SPIE: u64 = 1 << 5;
+const VSSTATUS_SPP: u64 = 1 << 8;
+const VSSTATUS_FS: u64 = 0b11 << 13;
+const VSSTATUS_XS: u64 = 0b11 << 15;
+const VSSTATUS_SUM: u64 = 1 << 18;
+const VSSTATUS_MXR: u64 = 1 << 19;
+const VSSTATUS_UXL: u64 = 0b10 << 32;
+const VSSTATUS_SD: u64 = 1 << 63;
+
+// VSSIP, VSTIP and VSEIP; SGEIP stays 0 as there are no guest external interrupts
+pub const MIP_VS: u64 = 0x444;
+pub const MIP_VSSIP: u64 = 1 << 2;
+
+// ecall from VS-mode and the guest-page faults
+pub const MEDELEG_H: u64 = 1 << 10 | 0xf << 20;
+
+// dcsr.v and the ebreaks of VS-mode and VU-mode
+pub const DCSR_V: u64 = 1 << 5;
+pub const DCSR_EBREAKVS: u64 = 1 << 17;
+pub const DCSR_EBREAKVU: u64 = 1 << 16;
+
+impl CSRs {
+    // rv64 only, like the x4 translation modes it needs
+    pub fn with_hypervisor(mut self, hypervisor: bool) -> Self {
+        self.hypervisor = hypervisor && matches!(*self.isa, crate::Isa::Rv64);
+        if self.hypervisor {
+            self.csrs[CSRname::misa as usize] |= 1 << 7;
+            self.csrs[HSTATUS] = HSTATUS_VSXL;
+            self.csrs[VSSTATUS] = VSSTATUS_UXL;
+        }
+        self
+    }
+
+    pub fn hypervisor(&self) -> bool {
+        self.hypervisor
+    }
+
+    // the virtualization mode: VS-mode or VU-mode when set
+    pub fn virt(&self) -> bool {
+        self.virt
+    }
+
+    pub fn hstatus(&self, field: u64) -> bool {
+        self.csrs[HSTATUS] & field != 0
+    }
+
+    // the mode hlv, hlvx and hsv access memory in
+    pub fn spvp(&self) -> PrivilegedLevel {
+        if self.hstatus(HSTATUS_SPVP) {
+            PrivilegedLevel::Supervisor
+        } else {
+            PrivilegedLevel::User
+        }
+    }
+
+    pub fn mpv(&self) -> bool {
+        self.csrs[MSTATUS] & MSTATUS_MPV != 0
+    }
+
+    // the bits that are read-only zero without the extension they belong to
+    pub(super) fn missing_bits(&self, dist: usize) -> u64 {
+        match dist {
+            MSTATUS if !self.hypervisor => MSTATUS_MPV | MSTATUS_GVA,
+            MEDELEG if !self.hypervisor => MEDELEG_H,
+            MIE if !self.hypervisor => MIP_VS,
+            HENVCFG if !self.svadu => MENVCFG_ADUE,
+            _ => 0,
+        }
+    }
+
+    // a guest accessing an S-level CSR gets the VS one
+    pub fn virtual_csr(&self, dist: usize) -> usize {
+        match dist {
+            SSTATUS | SIE | STVEC | SSCRATCH..=SIP | SATP if self.virt => dist + 0x100,
+            _ => dist,
+        }
+    }
+
+    // the VS-level interrupts are always delegated past M-mode
+    pub(super) fn read_mideleg(&self) -> u64 {
+        if self.hypervisor {
+            self.csrs[MIDELEG] | MIP_VS
+        } else {
+            self.csrs[MIDELEG]
+        }
+    }
+
+    // hie, hip and hvip show the VS-level bits of mie and mip; vsie and vsip
+    // the ones hideleg hands over, at the positions of the S-level ones
+    pub(super) fn read_hip(&self, dist: usize) -> u64 {
+        let hideleg = self.csrs[HIDELEG];
+        match dist {
+            HIE => self.csrs[MIE] & MIP_VS,
+            HIP | HVIP => self.csrs[MIP] & MIP_VS,
+            VSIE => (self.csrs[MIE] & hideleg & MIP_VS) >> 1,
+            _ => (self.csrs[MIP] & hideleg & MIP_VS) >> 1,
+        }
+    }
+
+    // hvip injects the VS-level interrupts, software ones can also be
+    // cleared through hip and vsip
+    pub(super) fn write_hip(&mut self, dist: usize, src: u64) {
+        let hideleg = self.csrs[HIDELEG];
+        let (csr, writable, value) = match dist {
+            HIE => (MIE, MIP_VS, src),
+            HIP => (MIP, MIP_VSSIP, src),
+            HVIP => (MIP, MIP_VS, src),
+            VSIE => (MIE, MIP_VS & hideleg, src << 1),
+            _ => (MIP, MIP_VSSIP & hideleg, src << 1),
+        };
+        self.csrs[csr] = self.csrs[csr] & !writable | value & writable;
+    }
+
+    pub(super) fn write_vsstatus(&mut self, src: u64) {
+        let vsstatus = src & self.smask() & !(0b11 << 32) | VSSTATUS_UXL;
+        self.csrs[VSSTATUS] = vsstatus;
+        self.update_vsstatus_sd();
+    }
+
+    fn update_vsstatus_sd(&mut self) {
+        let vsstatus = self.csrs[VSSTATUS] & !VSSTATUS_SD;
+        let dirty = vsstatus & VSSTATUS_FS == VSSTATUS_FS || vsstatus & VSSTATUS_XS == VSSTATUS_XS;
+        self.csrs[VSSTATUS] = if dirty {
+            vsstatus | VSSTATUS_SD
+        } else {
+            vsstatus
+        };
+    }
+
+    // a guest also needs vsstatus.FS to use the FPU, and dirties both
+    pub fn guest_fpu_enabled(&self) -> bool {
+        !self.virt || self.csrs[VSSTATUS] & VSSTATUS_FS != 0
+    }
+
+    pub fn dirty_guest_fs(&mut self) {
+        if self.virt {
+            self.csrs[VSSTATUS] |= VSSTATUS_FS;
+            self.update_vsstatus_sd();
+        }
+    }
+
+    // vsstatus.SUM and MXR for the VS-stage translation
+    pub fn vsstatus_sum(&self) -> bool {
+        self.csrs[VSSTATUS] & VSSTATUS_SUM != 0
+    }
+
+    pub fn vsstatus_mxr(&self) -> bool {
+        self.csrs[VSSTATUS] & VSSTATUS_MXR != 0
+    }
+
+    // VS-stage A/D bits are updated in hardware when henvcfg.ADUE is set
+    pub fn vs_adue(&self) -> bool {
+        self.csrs[HENVCFG] & MENVCFG_ADUE != 0
+    }
+
+    // the interrupts of VS-mode are enabled by vsstatus.SIE
+    pub fn vsstatus_sie(&self) -> bool {
+        self.csrs[VSSTATUS] & VSSTATUS_SIE != 0
+    }
+
+    // the time a guest sees is shifted by htimedelta
+    pub(super) fn guest_time(&self) -> u64 {
+        self.csrs[CSRname::timer as usize].wrapping_add(self.csrs[HTIMEDELTA])
+    }
+
+    // a trap into VS-mode saves the mode and SIE the way sstatus does
+    pub fn trap_vsstatus(&mut self, prev_priv: PrivilegedLevel) {
+        let vsstatus = self.csrs[VSSTATUS] & !(VSSTATUS_SIE | VSSTATUS_SPIE | VSSTATUS_SPP);
+        let spie = (self.csrs[VSSTATUS] & VSSTATUS_SIE) << 4;
+        let spp = (prev_priv as u64 & 0x1) << 8;
+        self.csrs[VSSTATUS] = vsstatus | spie | spp;
+    }
+
+    // sret in VS-mode: returns the mode in vsstatus.SPP
+    pub fn sret_vsstatus(&mut self) -> PrivilegedLevel {
+        let vsstatus = self.csrs[VSSTATUS];
+        let sie = (vsstatus & VSSTATUS_SPIE) >> 4;
+        self.csrs[VSSTATUS] = vsstatus & !(VSSTATUS_SIE | VSSTATUS_SPP) | sie | VSSTATUS_SPIE;
+        if vsstatus & VSSTATUS_SPP != 0 {
+            PrivilegedLevel::Supervisor
+        } else {
+            PrivilegedLevel::User
+        }
+    }
+
+    // a trap into HS-mode leaves the guest, and hstatus tells where it was
+    pub fn trap_hstatus(&mut self, prev_priv: PrivilegedLevel, prev_virt: bool, gva: bool) {
+        let mut hstatus = self.csrs[HSTATUS] & !(HSTATUS_SPV | HSTATUS_GVA)
+            | (prev_virt as u64) << 7
+            | (gva as u64) << 6;
+        if prev_virt {
+            hstatus = hstatus & !HSTATUS_SPVP | (prev_priv as u64 & 0x1) << 8;
+        }
+        self.csrs[HSTATUS] = hstatus;
+        self.virt = false;
+    }
+
+    pub fn trap_mstatus_mpv(&mut self, prev_virt: bool, gva: bool) {
+        self.csrs[MSTATUS] = self.csrs[MSTATUS] & !(MSTATUS_MPV | MSTATUS_GVA)
+            | (prev_virt as u64) << 39
+            | (gva as u64) << 38;
+        self.virt = false;
+    }
+
+    // sret in HS-mode enters the guest when hstatus.SPV is set
+    pub fn sret_hstatus(&mut self) {
+        self.virt = self.hstatus(HSTATUS_SPV);
+        self.csrs[HSTATUS] &= !HSTATUS_SPV;
+    }
+
+    // mret enters the guest when mstatus.MPV is set and MPP isn't M-mode
+    pub fn mret_mstatus_mpv(&mut self, new_priv: PrivilegedLevel) {
+        self.virt = self.mpv() && new_priv != PrivilegedLevel::Machine;
+        self.csrs[MSTATUS] &= !MSTATUS_MPV;
+    }
+
+    // hgatp.MODE is WARL like satp.MODE; the root table is 16KiB aligned and
+    // VMIDs are 14 bits
+    pub(super) fn write_hgatp(&mut self, src: u64) {
+        const HGATP_PPN_LOW: u64 = 0b11;
+        const HGATP_VMID_HIGH: u64 = 0b11 << 58;
+        let hgatp = src & !(HGATP_VMID_HIGH | HGATP_PPN_LOW);
+        self.write_satp(HGATP, hgatp);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Isa;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn hypervisor_csr_test() {
+        let mut csrs = CSRs::default().init().with_hypervisor(true);
+        assert_ne!(csrs.read(CSRname::misa.wrap()).unwrap() & 1 << 7, 0);
+        assert_eq!(csrs.read(CSRname::hstatus.wrap()).unwrap(), HSTATUS_VSXL);
+        assert_eq!(csrs.read(CSRname::mideleg.wrap()).unwrap(), MIP_VS);
+        csrs.write(CSRname::medeleg.wrap(), u64::MAX).unwrap();
+        assert_eq!(csrs.read(CSRname::medeleg.wrap()).unwrap(), 0xf0_b7ff);
+        csrs.write(CSRname::hedeleg.wrap(), u64::MAX).unwrap();
+        assert_eq!(csrs.read(CSRname::hedeleg.wrap()).unwrap(), 0xb1ff);
+
+        // hvip injects VSTIP, which vsip shows as STIP once hideleg hands it over
+        csrs.write(CSRname::hvip.wrap(), 1 << 6).unwrap();
+        assert_eq!(csrs.read(CSRname::hip.wrap()).unwrap(), 1 << 6);
+        assert_eq!(csrs.read(CSRname::vsip.wrap()).unwrap(), 0);
+        csrs.write(CSRname::hideleg.wrap(), u64::MAX).unwrap();
+        assert_eq!(csrs.read(CSRname::hideleg.wrap()).unwrap(), MIP_VS);
+        assert_eq!(csrs.read(CSRname::vsip.wrap()).unwrap(), 1 << 5);
+        csrs.write(CSRname::vsie.wrap(), u64::MAX).unwrap();
+        assert_eq!(csrs.read(CSRname::mie.wrap()).unwrap(), MIP_VS);
+
+        // hgatp.MODE is WARL and the low bits of the PPN are zero
+        csrs.write(CSRname::hgatp.wrap(), 8 << 60 | 0x80003)
+            .unwrap();
+        assert_eq!(csrs.read(CSRname::hgatp.wrap()).unwrap(), 8 << 60 | 0x80000);
+        csrs.write(CSRname::hgatp.wrap(), 5 << 60).unwrap();
+        assert_eq!(csrs.read(CSRname::hgatp.wrap()).unwrap(), 8 << 60 | 0x80000);
+
+        // a guest sees the VS CSRs in place of the S ones
+        csrs.virt = true;
+        let sstatus = CSRname::sstatus as usize;
+        assert_eq!(csrs.virtual_csr(sstatus), VSSTATUS);
+        csrs.write(Some(VSSTATUS), VSSTATUS_FS).unwrap();
+        assert_eq!(
+            csrs.read(Some(VSSTATUS)).unwrap(),
+            VSSTATUS_SD | VSSTATUS_UXL | VSSTATUS_FS
+        );
+        csrs.trap_hstatus(PrivilegedLevel::Supervisor, true, true);
+        assert!(!csrs.virt());
+        assert_eq!(
+            csrs.read(CSRname::hstatus.wrap()).unwrap(),
+            HSTATUS_VSXL | HSTATUS_SPVP | HSTATUS_SPV | HSTATUS_GVA
+        );
+        assert_eq!(csrs.virtual_csr(sstatus), sstatus);
+        csrs.sret_hstatus();
+        assert!(csrs.virt());
+
+        // without H, or on rv32, none of it exists
+        let csrs = CSRs::new(Isa::Rv32.into(), Rc::new(RefCell::new(0))).with_hypervisor(true);
+        assert!(csrs.describe(CSRname::hstatus as usize).is_none());
+        assert!(CSRs::default().describe(CSRname::vsatp as usize).is_none());
+    }
+}
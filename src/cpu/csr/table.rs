@@ -0,0 +1,300 @@
+use crate::cpu::csr::hypervisor::{HSTATUS_WRITABLE, MIP_VS};
+use crate::cpu::csr::trigger::{TCONTROL_MPTE, TCONTROL_MTE};
+use crate::cpu::csr::CSRs;
+use crate::cpu::csr::MENVCFG_ADUE;
+use crate::cpu::PrivilegedLevel;
+use crate::Isa;
+
+// what a CSR needs beyond the base ISA to exist
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Requires {
+    Base,
+    Sstc,
+    Hypervisor,
+}
+
+// how a CSR number (or a run of numbered ones) is accessed
+#[derive(Clone, Copy, Debug)]
+pub struct CsrDesc {
+    // "{}" stands for the number of each CSR in a run
+    name: &'static str,
+    first: usize,
+    last: usize,
+    // the number of the first CSR in a run
+    first_index: usize,
+    // the lowest privilege level allowed to access it, from address bits 9:8
+    pub priv_lv: PrivilegedLevel,
+    // address bits 11:10 are 0b11
+    pub read_only: bool,
+    // the upper halves of 64-bit CSRs
+    pub rv32_only: bool,
+    // Sdext CSRs only exist in debug mode
+    pub debug_only: bool,
+    pub requires: Requires,
+    // WARL: the bits a write can change, the others keep their value
+    pub writable: u64,
+}
+
+const fn csr(name: &'static str, addr: usize) -> CsrDesc {
+    let priv_lv = match addr >> 8 & 0x3 {
+        0b00 => PrivilegedLevel::User,
+        0b11 => PrivilegedLevel::Machine,
+        // hypervisor CSRs belong to HS-mode
+        _ => PrivilegedLevel::Supervisor,
+    };
+    CsrDesc {
+        name,
+        first: addr,
+        last: addr,
+        first_index: 0,
+        priv_lv,
+        read_only: addr >> 10 == 0b11,
+        rv32_only: false,
+        debug_only: false,
+        requires: Requires::Base,
+        writable: u64::MAX,
+    }
+}
+
+impl CsrDesc {
+    // the same access for the CSRs up to last, numbered from first_index
+    const fn to(mut self, last: usize, first_index: usize) -> Self {
+        self.last = last;
+        self.first_index = first_index;
+        self
+    }
+
+    pub fn name(&self, dist: usize) -> String {
+        self.name
+            .replace("{}", &(self.first_index + dist - self.first).to_string())
+    }
+
+    const fn rv32(mut self) -> Self {
+        self.rv32_only = true;
+        self
+    }
+
+    const fn debug(mut self) -> Self {
+        self.debug_only = true;
+        self
+    }
+
+    const fn requires(mut self, requires: Requires) -> Self {
+        self.requires = requires;
+        self
+    }
+
+    const fn warl(mut self, writable: u64) -> Self {
+        self.writable = writable;
+        self
+    }
+}
+
+// CSRs with their own write handler in CSRs::write keep all bits writable here
+// and legalize the value there. Sorted by number.
+const TABLE: &[CsrDesc] = &[
+    csr("fflags", 0x001),
+    csr("frm", 0x002),
+    csr("fcsr", 0x003),
+    csr("sstatus", 0x100),
+    csr("sie", 0x104),
+    csr("stvec", 0x105).warl(!0b10),
+    csr("scounteren", 0x106).warl(0xffff_ffff),
+    csr("senvcfg", 0x10a).warl(0),
+    csr("sscratch", 0x140),
+    csr("sepc", 0x141).warl(!0b1),
+    csr("scause", 0x142),
+    csr("stval", 0x143),
+    csr("sip", 0x144),
+    csr("stimecmp", 0x14d).requires(Requires::Sstc),
+    csr("stimecmph", 0x15d).rv32().requires(Requires::Sstc),
+    csr("satp", 0x180),
+    csr("vsstatus", 0x200).requires(Requires::Hypervisor),
+    csr("vsie", 0x204).requires(Requires::Hypervisor),
+    csr("vstvec", 0x205)
+        .requires(Requires::Hypervisor)
+        .warl(!0b10),
+    csr("vsscratch", 0x240).requires(Requires::Hypervisor),
+    csr("vsepc", 0x241)
+        .requires(Requires::Hypervisor)
+        .warl(!0b1),
+    csr("vscause", 0x242).requires(Requires::Hypervisor),
+    csr("vstval", 0x243).requires(Requires::Hypervisor),
+    csr("vsip", 0x244).requires(Requires::Hypervisor),
+    csr("vsatp", 0x280).requires(Requires::Hypervisor),
+    csr("mstatus", 0x300),
+    csr("misa", 0x301),
+    // ecall from M-mode can't be delegated; the H causes need the extension
+    csr("medeleg", 0x302).warl(0xf0_b7ff),
+    // only the S-mode interrupts can be delegated, the VS-mode ones always are
+    csr("mideleg", 0x303).warl(0x2222),
+    csr("mie", 0x304).warl(0x2eee),
+    csr("mtvec", 0x305).warl(!0b10),
+    csr("mcounteren", 0x306).warl(0xffff_ffff),
+    csr("menvcfg", 0x30a),
+    // MBE and SBE are hardwired to little-endian
+    csr("mstatush", 0x310).rv32().warl(0),
+    csr("menvcfgh", 0x31a).rv32(),
+    csr("mcountinhibit", 0x320),
+    csr("mhpmevent{}", 0x323).to(0x33f, 3),
+    csr("mscratch", 0x340),
+    csr("mepc", 0x341).warl(!0b1),
+    csr("mcause", 0x342),
+    csr("mtval", 0x343),
+    csr("mip", 0x344),
+    csr("mtinst", 0x34a).requires(Requires::Hypervisor),
+    csr("mtval2", 0x34b).requires(Requires::Hypervisor),
+    csr("pmpcfg0", 0x3a0),
+    csr("pmpcfg1", 0x3a1).rv32(),
+    csr("pmpcfg2", 0x3a2),
+    csr("pmpcfg3", 0x3a3).rv32(),
+    // 16 PMP entries with 56-bit physical addresses
+    csr("pmpaddr{}", 0x3b0).to(0x3bf, 0).warl((1 << 54) - 1),
+    csr("hstatus", 0x600)
+        .requires(Requires::Hypervisor)
+        .warl(HSTATUS_WRITABLE),
+    // ecalls from HS-mode and above and the guest-page faults stay in HS-mode
+    csr("hedeleg", 0x602)
+        .requires(Requires::Hypervisor)
+        .warl(0xb1ff),
+    csr("hideleg", 0x603)
+        .requires(Requires::Hypervisor)
+        .warl(MIP_VS),
+    csr("hie", 0x604).requires(Requires::Hypervisor),
+    csr("htimedelta", 0x605).requires(Requires::Hypervisor),
+    csr("hcounteren", 0x606)
+        .requires(Requires::Hypervisor)
+        .warl(0xffff_ffff),
+    // no guest external interrupts
+    csr("hgeie", 0x607).requires(Requires::Hypervisor).warl(0),
+    csr("henvcfg", 0x60a)
+        .requires(Requires::Hypervisor)
+        .warl(MENVCFG_ADUE),
+    csr("htval", 0x643).requires(Requires::Hypervisor),
+    csr("hip", 0x644).requires(Requires::Hypervisor),
+    csr("hvip", 0x645).requires(Requires::Hypervisor),
+    csr("htinst", 0x64a).requires(Requires::Hypervisor),
+    csr("hgatp", 0x680).requires(Requires::Hypervisor),
+    csr("mhpmevent{}h", 0x723).to(0x73f, 3).rv32(),
+    csr("tselect", 0x7a0),
+    csr("tdata1", 0x7a1),
+    csr("tdata2", 0x7a2),
+    csr("tdata3", 0x7a3),
+    csr("tinfo", 0x7a4),
+    csr("tcontrol", 0x7a5).warl(TCONTROL_MTE | TCONTROL_MPTE),
+    csr("dcsr", 0x7b0).debug(),
+    csr("dpc", 0x7b1).debug().warl(!0b1),
+    csr("dscratch{}", 0x7b2).to(0x7b3, 0).debug(),
+    csr("mcycle", 0xb00),
+    csr("minstret", 0xb02),
+    csr("mhpmcounter{}", 0xb03).to(0xb1f, 3),
+    csr("mcycleh", 0xb80).rv32(),
+    csr("minstreth", 0xb82).rv32(),
+    csr("mhpmcounter{}h", 0xb83).to(0xb9f, 3).rv32(),
+    csr("cycle", 0xc00),
+    csr("time", 0xc01),
+    csr("instret", 0xc02),
+    csr("hpmcounter{}", 0xc03).to(0xc1f, 3),
+    csr("cycleh", 0xc80).rv32(),
+    csr("timeh", 0xc81).rv32(),
+    csr("instreth", 0xc82).rv32(),
+    csr("hpmcounter{}h", 0xc83).to(0xc9f, 3).rv32(),
+    csr("scountovf", 0xda0),
+    csr("hgeip", 0xe12).requires(Requires::Hypervisor),
+    csr("mvendorid", 0xf11),
+    csr("marchid", 0xf12),
+    csr("mimpid", 0xf13),
+    csr("mhartid", 0xf14),
+    csr("mconfigptr", 0xf15),
+];
+
+fn lookup(dist: usize) -> Option<&'static CsrDesc> {
+    TABLE
+        .binary_search_by(|desc| {
+            if desc.last < dist {
+                std::cmp::Ordering::Less
+            } else if desc.first > dist {
+                std::cmp::Ordering::Greater
+            } else {
+                std::cmp::Ordering::Equal
+            }
+        })
+        .ok()
+        .map(|index| &TABLE[index])
+}
+
+impl CSRs {
+    // None for CSRs this hart doesn't implement
+    pub fn describe(&self, dist: usize) -> Option<&'static CsrDesc> {
+        lookup(dist).filter(|desc| {
+            let isa = !desc.rv32_only || matches!(*self.isa, Isa::Rv32);
+            let extension = match desc.requires {
+                Requires::Base => true,
+                Requires::Sstc => self.sstc,
+                Requires::Hypervisor => self.hypervisor,
+            };
+            isa && extension
+        })
+    }
+
+    // (name, number) of every CSR this hart implements, in number order
+    pub fn implemented(&self) -> impl Iterator<Item = (String, usize)> + '_ {
+        TABLE
+            .iter()
+            .flat_map(|desc| desc.first..=desc.last)
+            .filter_map(|dist| Some((self.describe(dist)?.name(dist), dist)))
+    }
+
+    // keep the bits of a CSR that writes can't change
+    pub(super) fn legalize(&self, dist: usize, src: u64) -> u64 {
+        let writable = self.writable(dist);
+        self.csrs[dist] & !writable | src & writable
+    }
+
+    // the bits of a set or clear mask that have an effect
+    pub(super) fn writable(&self, dist: usize) -> u64 {
+        lookup(dist).map_or(u64::MAX, |desc| desc.writable) & !self.missing_bits(dist)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::csr::CSRname;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn table_test() {
+        assert!(TABLE
+            .windows(2)
+            .all(|pair| pair[0].first <= pair[0].last && pair[0].last < pair[1].first));
+
+        let csrs = CSRs::default();
+        let mhpmcounter4 = csrs.describe(CSRname::mhpmcounter3 as usize + 1).unwrap();
+        assert_eq!(mhpmcounter4.priv_lv, PrivilegedLevel::Machine);
+        assert!(!mhpmcounter4.read_only);
+        assert_eq!(
+            mhpmcounter4.name(CSRname::mhpmcounter3 as usize + 1),
+            "mhpmcounter4"
+        );
+        assert!(csrs.describe(CSRname::cycle as usize).unwrap().read_only);
+        assert_eq!(
+            csrs.describe(CSRname::sepc as usize).unwrap().priv_lv,
+            PrivilegedLevel::Supervisor
+        );
+        // the upper halves only exist on rv32, stimecmp only with Sstc
+        assert!(csrs.describe(CSRname::cycleh as usize).is_none());
+        assert!(csrs.describe(CSRname::stimecmp as usize).is_none());
+        assert!(csrs.describe(0x7b4).is_none());
+        assert!(csrs.describe(CSRname::dpc as usize).unwrap().debug_only);
+        let rv32 = CSRs::new(Isa::Rv32.into(), Rc::new(RefCell::new(0))).with_sstc(true);
+        assert!(rv32.describe(CSRname::cycleh as usize).is_some());
+        assert!(rv32.describe(CSRname::stimecmph as usize).is_some());
+
+        let names: Vec<(String, usize)> = rv32.implemented().collect();
+        assert!(names.contains(&("mhpmcounter31h".to_string(), 0xb9f)));
+        assert!(names.contains(&("dscratch1".to_string(), 0x7b3)));
+        assert!(!names.iter().any(|(name, _)| name == "hstatus"));
+    }
+}
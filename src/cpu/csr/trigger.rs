@@ -0,0 +1,575 @@
+use crate::cpu::csr::{CSRname, CSRs, DebugCause};
+use crate::cpu::{Cpu, PrivilegedLevel, TransFor, TrapCause};
+use crate::Isa;
+use std::mem;
+
+pub const TRIGGERS: usize = 8;
+pub const TSELECT: usize = CSRname::tselect as usize;
+pub const TDATA1: usize = CSRname::tdata1 as usize;
+pub const TDATA2: usize = CSRname::tdata2 as usize;
+pub const TINFO: usize = CSRname::tinfo as usize;
+const TCONTROL: usize = CSRname::tcontrol as usize;
+
+// tdata1.type
+const TYPE_MCONTROL: u64 = 2;
+const TYPE_ICOUNT: u64 = 3;
+const TYPE_ITRIGGER: u64 = 4;
+const TYPE_ETRIGGER: u64 = 5;
+const TYPE_MCONTROL6: u64 = 6;
+const TYPE_DISABLED: u64 = 15;
+
+// the supported types and version 1 (Sdtrig 1.0)
+const TINFO_VALUE: u64 = 1 << 24
+    | 1 << TYPE_MCONTROL
+    | 1 << TYPE_ICOUNT
+    | 1 << TYPE_ITRIGGER
+    | 1 << TYPE_ETRIGGER
+    | 1 << TYPE_MCONTROL6
+    | 1 << TYPE_DISABLED;
+
+// breakpoint triggers with action 0 stay quiet in M-mode while mte is clear
+pub const TCONTROL_MTE: u64 = 1 << 3;
+pub const TCONTROL_MPTE: u64 = 1 << 7;
+
+const ACTION_BREAKPOINT: u64 = 0;
+// only triggers that belong to the debugger (dmode) can halt the hart
+const ACTION_DEBUG_MODE: u64 = 1;
+
+// mcontrol and mcontrol6
+const LOAD: u64 = 1 << 0;
+const STORE: u64 = 1 << 1;
+const EXECUTE: u64 = 1 << 2;
+const MC_U: u64 = 1 << 3;
+const MC_S: u64 = 1 << 4;
+const MC_M: u64 = 1 << 6;
+const CHAIN: u64 = 1 << 11;
+const MC_SIZELO: u64 = 0x3 << 16;
+const MC_TIMING: u64 = 1 << 18;
+const MC_HIT: u64 = 1 << 20;
+const MC_SIZEHI: u64 = 0x3 << 21;
+const MC6_SIZE: u64 = 0x7 << 16;
+const MC6_HIT0: u64 = 1 << 22;
+const MC6_HIT1: u64 = 1 << 25;
+
+// icount, itrigger and etrigger
+const IC_U: u64 = 1 << 6;
+const IC_S: u64 = 1 << 7;
+const IC_PENDING: u64 = 1 << 8;
+const IC_M: u64 = 1 << 9;
+const IC_COUNT: u64 = 0x3fff << 10;
+const IC_HIT: u64 = 1 << 24;
+
+// what a trigger that fired on a trap asks for
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    Breakpoint,
+    DebugMode,
+}
+
+// what an address trigger looks at
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Access {
+    Execute,
+    Load,
+    Store,
+}
+
+pub struct Triggers {
+    pub tselect: usize,
+    pub tdata1: [u64; TRIGGERS],
+    pub tdata2: [u64; TRIGGERS],
+    // a hit with timing=after and its action, taken once the instruction retires
+    after: Option<(u64, u64)>,
+    // the instruction wrote tdata1, so it doesn't count towards icount
+    written: bool,
+    // the breakpoint being taken came from a trigger, which etrigger ignores
+    fired: bool,
+}
+
+impl Triggers {
+    pub fn new(isa: Isa) -> Self {
+        Triggers {
+            tselect: 0,
+            tdata1: [TYPE_DISABLED << (xlen(isa) - 4); TRIGGERS],
+            tdata2: [0; TRIGGERS],
+            after: None,
+            written: false,
+            fired: false,
+        }
+    }
+}
+
+fn xlen(isa: Isa) -> u64 {
+    match isa {
+        Isa::Rv32 => 32,
+        Isa::Rv64 => 64,
+    }
+}
+
+// the match field of mcontrol and mcontrol6; bit 3 negates the others
+fn value_match(isa: Isa, match_mode: u64, value: u64, tdata2: u64) -> bool {
+    let half = xlen(isa) / 2;
+    let low = |value: u64| value & ((1 << half) - 1);
+    let matched = match match_mode & 0x7 {
+        0 => value == tdata2,
+        // the trailing ones of tdata2 and the zero above them are ignored
+        1 => {
+            let mask = !(tdata2 ^ tdata2.wrapping_add(1));
+            value & mask == tdata2 & mask
+        }
+        2 => value >= tdata2,
+        3 => value < tdata2,
+        // the upper half of tdata2 masks the compare against its lower half
+        4 => low(value) & low(tdata2 >> half) == low(tdata2) & low(tdata2 >> half),
+        5 => low(value >> half) & low(tdata2 >> half) == low(tdata2) & low(tdata2 >> half),
+        _ => false,
+    };
+    matched != (match_mode & 0x8 != 0)
+}
+
+// the size encoding of mcontrol and mcontrol6 for an access of len bytes
+fn size_code(len: u64) -> u64 {
+    match len {
+        1 => 1,
+        2 => 2,
+        4 => 3,
+        _ => 5,
+    }
+}
+
+impl CSRs {
+    fn trigger_type(&self, tdata1: u64) -> u64 {
+        tdata1 >> (xlen(*self.isa) - 4)
+    }
+
+    // sizehi only exists on rv64, where maskmax doesn't overlap it
+    fn mcontrol_size(&self, tdata1: u64) -> u64 {
+        let sizehi = match *self.isa {
+            Isa::Rv32 => 0,
+            Isa::Rv64 => (tdata1 & MC_SIZEHI) >> 21,
+        };
+        sizehi << 2 | (tdata1 & MC_SIZELO) >> 16
+    }
+
+    fn dmode(&self) -> u64 {
+        1 << (xlen(*self.isa) - 5)
+    }
+
+    // itrigger and etrigger keep hit just below dmode
+    fn trap_hit(&self) -> u64 {
+        1 << (xlen(*self.isa) - 6)
+    }
+
+    // triggers that belong to the debugger can only be changed in debug mode
+    fn locked(&self, index: usize) -> bool {
+        self.triggers.tdata1[index] & self.dmode() != 0 && !self.debug_mode()
+    }
+
+    pub(super) fn read_trigger(&self, dist: usize) -> u64 {
+        let index = self.triggers.tselect;
+        match dist {
+            TSELECT => index as u64,
+            TDATA1 => self.triggers.tdata1[index],
+            TDATA2 => self.triggers.tdata2[index],
+            TINFO => TINFO_VALUE,
+            // no textra matching
+            _ => 0,
+        }
+    }
+
+    pub(super) fn write_trigger(&mut self, dist: usize, src: u64) {
+        let index = self.triggers.tselect;
+        match dist {
+            // a trigger that doesn't exist keeps the old selection
+            TSELECT if (src as usize) < TRIGGERS => self.triggers.tselect = src as usize,
+            TDATA1 | TDATA2 if self.locked(index) => (),
+            TDATA1 => {
+                self.triggers.tdata1[index] = self.legalize_tdata1(src);
+                self.triggers.written = true;
+            }
+            TDATA2 => self.triggers.tdata2[index] = src,
+            _ => (),
+        }
+    }
+
+    // types and fields that aren't supported disable the trigger or read back as 0
+    fn legalize_tdata1(&self, tdata1: u64) -> u64 {
+        let xlen = xlen(*self.isa);
+        let kind = self.trigger_type(tdata1);
+        let match_mode = match tdata1 >> 7 & 0xf {
+            mode @ (0..=5 | 8 | 9 | 12 | 13) => mode << 7,
+            _ => 0,
+        };
+        let legal_size = |size: u64| match size {
+            0..=3 => size,
+            5 if xlen == 64 => size,
+            _ => 0,
+        };
+        let access = CHAIN | MC_M | MC_S | MC_U | EXECUTE | STORE | LOAD;
+        let dmode = match self.debug_mode() {
+            true => tdata1 & self.dmode(),
+            false => 0,
+        };
+        let action = |action: u64| match action {
+            ACTION_DEBUG_MODE if dmode != 0 => ACTION_DEBUG_MODE,
+            _ => ACTION_BREAKPOINT,
+        };
+
+        let data = match kind {
+            TYPE_MCONTROL => {
+                let size = legal_size(self.mcontrol_size(tdata1));
+                // NAPOT ranges can cover the whole address space
+                let maskmax = (xlen - 1) << (xlen - 11);
+                tdata1 & (MC_HIT | MC_TIMING | access)
+                    | match_mode
+                    | action(tdata1 >> 12 & 0xf) << 12
+                    | (size & 0x3) << 16
+                    | (size >> 2) << 21
+                    | maskmax
+            }
+            TYPE_MCONTROL6 => {
+                let size = legal_size((tdata1 & MC6_SIZE) >> 16);
+                tdata1 & (MC6_HIT1 | MC6_HIT0 | access)
+                    | match_mode
+                    | action(tdata1 >> 12 & 0xf) << 12
+                    | size << 16
+            }
+            TYPE_ICOUNT => {
+                tdata1 & (IC_HIT | IC_COUNT | IC_M | IC_PENDING | IC_S | IC_U)
+                    | action(tdata1 & 0x3f)
+            }
+            TYPE_ITRIGGER | TYPE_ETRIGGER => {
+                tdata1 & (self.trap_hit() | IC_M | IC_S | IC_U) | action(tdata1 & 0x3f)
+            }
+            _ => return TYPE_DISABLED << (xlen - 4),
+        };
+        kind << (xlen - 4) | dmode | data
+    }
+
+    // whether a trigger is enabled in priv_lv, with m, s and u at the given bits
+    fn mode_enabled(tdata1: u64, [m, s, u]: [u64; 3], priv_lv: PrivilegedLevel) -> bool {
+        let bit = match priv_lv {
+            PrivilegedLevel::Machine => m,
+            PrivilegedLevel::Supervisor => s,
+            _ => u,
+        };
+        tdata1 & bit != 0
+    }
+
+    fn address_match(
+        &self,
+        index: usize,
+        access: Access,
+        addr: u64,
+        len: u64,
+        priv_lv: PrivilegedLevel,
+    ) -> bool {
+        let tdata1 = self.triggers.tdata1[index];
+        let size = match self.trigger_type(tdata1) {
+            TYPE_MCONTROL => self.mcontrol_size(tdata1),
+            TYPE_MCONTROL6 => (tdata1 & MC6_SIZE) >> 16,
+            _ => return false,
+        };
+        let access = match access {
+            Access::Execute => EXECUTE,
+            Access::Load => LOAD,
+            Access::Store => STORE,
+        };
+        tdata1 & access != 0
+            && Self::mode_enabled(tdata1, [MC_M, MC_S, MC_U], priv_lv)
+            && (size == 0 || size == size_code(len))
+            && value_match(
+                *self.isa,
+                tdata1 >> 7 & 0xf,
+                addr,
+                self.triggers.tdata2[index],
+            )
+    }
+
+    // the first chain whose triggers all match, as the indices of its first and last
+    fn match_chain(&self, matches: impl Fn(usize) -> bool) -> Option<(usize, usize)> {
+        let mut first = 0;
+        let mut matched = true;
+        for index in 0..TRIGGERS {
+            let tdata1 = self.triggers.tdata1[index];
+            let chained = matches!(self.trigger_type(tdata1), TYPE_MCONTROL | TYPE_MCONTROL6)
+                && tdata1 & CHAIN != 0;
+            matched &= matches(index);
+            if chained {
+                continue;
+            }
+            if matched {
+                return Some((first, index));
+            }
+            first = index + 1;
+            matched = true;
+        }
+        None
+    }
+
+    fn set_hit(&mut self, index: usize, after: bool) {
+        let tdata1 = self.triggers.tdata1[index];
+        let hit = match self.trigger_type(tdata1) {
+            TYPE_MCONTROL => MC_HIT,
+            // hit1:hit0 is 1 before the instruction retired and 3 just after
+            TYPE_MCONTROL6 if after => MC6_HIT1 | MC6_HIT0,
+            TYPE_MCONTROL6 => MC6_HIT0,
+            TYPE_ICOUNT => IC_HIT,
+            _ => self.trap_hit(),
+        };
+        self.triggers.tdata1[index] |= hit;
+    }
+
+    // a breakpoint exception can't be raised from M-mode until mret sets mte again
+    fn action_allowed(&self, action: u64, priv_lv: PrivilegedLevel) -> bool {
+        action != ACTION_BREAKPOINT
+            || priv_lv != PrivilegedLevel::Machine
+            || self.csrs[TCONTROL] & TCONTROL_MTE != 0
+    }
+
+    fn action(&self, index: usize) -> u64 {
+        let tdata1 = self.triggers.tdata1[index];
+        match self.trigger_type(tdata1) {
+            TYPE_MCONTROL | TYPE_MCONTROL6 => tdata1 >> 12 & 0xf,
+            _ => tdata1 & 0x3f,
+        }
+    }
+
+    // traps into M-mode save mte in mpte and clear it
+    pub fn trap_tcontrol(&mut self) {
+        let tcontrol = self.csrs[TCONTROL];
+        let mpte = if tcontrol & TCONTROL_MTE != 0 {
+            TCONTROL_MPTE
+        } else {
+            0
+        };
+        self.csrs[TCONTROL] = mpte;
+    }
+
+    pub fn mret_tcontrol(&mut self) {
+        let tcontrol = self.csrs[TCONTROL];
+        if tcontrol & TCONTROL_MPTE != 0 {
+            self.csrs[TCONTROL] |= TCONTROL_MTE;
+        } else {
+            self.csrs[TCONTROL] &= !TCONTROL_MTE;
+        }
+    }
+}
+
+impl Cpu {
+    // raise a breakpoint exception, or halt for the debugger; the error only
+    // stops the instruction then, as exceptions are ignored in debug mode
+    fn fire(
+        &mut self,
+        tval: u64,
+        action: u64,
+        what: &str,
+    ) -> Result<(), (Option<u64>, TrapCause, String)> {
+        if action == ACTION_DEBUG_MODE {
+            self.enter_debug_mode(DebugCause::Trigger);
+            return Err((
+                Some(tval),
+                TrapCause::Breakpoint,
+                format!("{what} trigger halted the hart"),
+            ));
+        }
+        self.csrs.triggers.fired = true;
+        Err((
+            Some(tval),
+            TrapCause::Breakpoint,
+            format!("{what} trigger fired"),
+        ))
+    }
+
+    // an instruction about to run; it starts with nothing pending
+    pub fn check_execute_triggers(
+        &mut self,
+        pc: u64,
+        len: u64,
+    ) -> Result<(), (Option<u64>, TrapCause, String)> {
+        self.csrs.triggers.after = None;
+        self.csrs.triggers.written = false;
+        self.check_address_triggers(Access::Execute, pc, len)
+    }
+
+    // mcontrol and mcontrol6 triggers on the address of an instruction or a memory access
+    fn check_address_triggers(
+        &mut self,
+        access: Access,
+        addr: u64,
+        len: u64,
+    ) -> Result<(), (Option<u64>, TrapCause, String)> {
+        // nothing fires while the debugger runs the program buffer
+        if self.debug_mode() {
+            return Ok(());
+        }
+        let priv_lv = self.priv_lv();
+        let csrs = &self.csrs;
+        let Some((first, last)) =
+            csrs.match_chain(|index| csrs.address_match(index, access, addr, len, priv_lv))
+        else {
+            return Ok(());
+        };
+        let action = self.csrs.action(last);
+        if !self.csrs.action_allowed(action, priv_lv) {
+            return Ok(());
+        }
+
+        let tdata1 = self.csrs.triggers.tdata1[last];
+        let after = self.csrs.trigger_type(tdata1) == TYPE_MCONTROL && tdata1 & MC_TIMING != 0;
+        for index in first..=last {
+            self.csrs.set_hit(index, after);
+        }
+        if after {
+            self.csrs.triggers.after.get_or_insert((addr, action));
+            return Ok(());
+        }
+        let what = match access {
+            Access::Execute => "execute",
+            Access::Load => "load",
+            Access::Store => "store",
+        };
+        self.fire(addr, action, what)
+    }
+
+    // load and store triggers look at the virtual address before it's translated
+    pub fn check_access_triggers(
+        &mut self,
+        purpose: TransFor,
+        addr: u64,
+        len: u64,
+    ) -> Result<(), (Option<u64>, TrapCause, String)> {
+        match purpose {
+            TransFor::Load | TransFor::LoadExec => {
+                self.check_address_triggers(Access::Load, addr, len)
+            }
+            TransFor::StoreAMO => self.check_address_triggers(Access::Store, addr, len),
+            TransFor::Fetch | TransFor::Deleg => Ok(()),
+        }
+    }
+
+    // once an instruction executed in priv_lv retired: timing=after hits and icount
+    pub fn retire_triggers(
+        &mut self,
+        priv_lv: PrivilegedLevel,
+    ) -> Result<(), (Option<u64>, TrapCause, String)> {
+        let after = self.csrs.triggers.after.take();
+        let written = mem::take(&mut self.csrs.triggers.written);
+        // an ebreak that halted the hart doesn't retire
+        if self.debug_mode() {
+            return Ok(());
+        }
+
+        let mut fired = None;
+        for index in 0..TRIGGERS {
+            let tdata1 = self.csrs.triggers.tdata1[index];
+            let count = (tdata1 & IC_COUNT) >> 10;
+            if written
+                || self.csrs.trigger_type(tdata1) != TYPE_ICOUNT
+                || count == 0
+                || !CSRs::mode_enabled(tdata1, [IC_M, IC_S, IC_U], priv_lv)
+            {
+                continue;
+            }
+            self.csrs.triggers.tdata1[index] = tdata1 & !IC_COUNT | (count - 1) << 10;
+            let action = self.csrs.action(index);
+            if count == 1 && self.csrs.action_allowed(action, self.priv_lv()) {
+                self.csrs.set_hit(index, true);
+                fired.get_or_insert(action);
+            }
+        }
+
+        if let Some((tval, action)) = after {
+            return self.fire(tval, action, "address");
+        }
+        if let Some(action) = fired {
+            return self.fire(0, action, "icount");
+        }
+        Ok(())
+    }
+
+    // itrigger and etrigger match the trap taken from priv_lv
+    pub fn check_trap_triggers(
+        &mut self,
+        cause: TrapCause,
+        priv_lv: PrivilegedLevel,
+    ) -> Option<Action> {
+        // the breakpoints raised by triggers don't trigger again
+        if mem::take(&mut self.csrs.triggers.fired) {
+            return None;
+        }
+
+        let interrupt = cause as u64 & 1 << 31 != 0;
+        let code = cause as u64 & 0x7fff_ffff;
+        let kind = if interrupt {
+            TYPE_ITRIGGER
+        } else {
+            TYPE_ETRIGGER
+        };
+        let fired = (0..TRIGGERS).find(|&index| {
+            let tdata1 = self.csrs.triggers.tdata1[index];
+            self.csrs.trigger_type(tdata1) == kind
+                && CSRs::mode_enabled(tdata1, [IC_M, IC_S, IC_U], priv_lv)
+                && code < 64
+                && self.csrs.triggers.tdata2[index] >> code & 1 != 0
+                && self.csrs.action_allowed(self.csrs.action(index), priv_lv)
+        });
+        let index = fired?;
+
+        self.csrs.set_hit(index, true);
+        match self.csrs.action(index) {
+            ACTION_DEBUG_MODE => Some(Action::DebugMode),
+            _ => Some(Action::Breakpoint),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn match_test() {
+        let isa = Isa::Rv64;
+        // napot: 0x1000..0x1fff
+        assert!(value_match(isa, 1, 0x1abc, 0x17ff));
+        assert!(!value_match(isa, 1, 0x2000, 0x17ff));
+        assert!(value_match(isa, 9, 0x2000, 0x17ff));
+        assert!(value_match(isa, 2, 0x2000, 0x2000));
+        assert!(value_match(isa, 3, 0x1fff, 0x2000));
+        // the upper half of tdata2 selects the bits compared
+        assert!(value_match(isa, 4, 0x5_1234, 0xff00_0000_1200));
+        assert!(!value_match(isa, 4, 0x5_1334, 0xff00_0000_1200));
+        assert!(value_match(isa, 5, 0x1234_0000_0000, 0xff00_0000_1200));
+
+        let mut csrs = CSRs::default();
+        // unsupported types are disabled and unsupported matches read back as equal
+        csrs.write_trigger(TDATA1, 1 << 60 | 0x7f);
+        assert_eq!(csrs.read_trigger(TDATA1), TYPE_DISABLED << 60);
+        csrs.write_trigger(
+            TDATA1,
+            TYPE_MCONTROL6 << 60 | 7 << 7 | 3 << 12 | EXECUTE | MC_M,
+        );
+        assert_eq!(
+            csrs.read_trigger(TDATA1),
+            TYPE_MCONTROL6 << 60 | EXECUTE | MC_M
+        );
+        csrs.write_trigger(TSELECT, TRIGGERS as u64);
+        assert_eq!(csrs.read_trigger(TSELECT), 0);
+
+        // a chain only fires when all of its triggers match
+        csrs.write_trigger(TDATA1, TYPE_MCONTROL6 << 60 | CHAIN | 2 << 7 | LOAD | MC_U);
+        csrs.write_trigger(TDATA2, 0x1000);
+        csrs.write_trigger(TSELECT, 1);
+        csrs.write_trigger(TDATA1, TYPE_MCONTROL6 << 60 | 3 << 7 | LOAD | MC_U);
+        csrs.write_trigger(TDATA2, 0x2000);
+        let matches = |addr| {
+            csrs.match_chain(|index| {
+                csrs.address_match(index, Access::Load, addr, 8, PrivilegedLevel::User)
+            })
+        };
+        assert_eq!(matches(0x1800), Some((0, 1)));
+        assert_eq!(matches(0x800), None);
+        assert_eq!(matches(0x2000), None);
+    }
+}
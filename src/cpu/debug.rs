@@ -0,0 +1,124 @@
+use super::csr::DebugCause;
+use super::decode::Decode;
+use super::execution::Execution;
+use super::{Cpu, PrivilegedLevel, TrapCause};
+use crate::log;
+
+// where the program buffer appears to run; nothing else is mapped there
+pub const PROGBUF_ADDR: u64 = 0x800;
+
+// a program buffer that loops is stopped after this many instructions
+const PROGBUF_STEPS_MAX: usize = 0x1_0000;
+
+const EBREAK: u32 = 0x0010_0073;
+const C_EBREAK: u16 = 0x9002;
+
+impl Cpu {
+    pub fn debug_mode(&self) -> bool {
+        self.csrs.debug_mode()
+    }
+
+    // halt in M-mode, keeping the pc and mode to resume with in dpc and dcsr.prv
+    pub fn enter_debug_mode(&mut self, cause: DebugCause) {
+        let pc = self.pc();
+        self.csrs.enter_debug(cause, self.priv_lv(), pc);
+        self.set_priv_lv(PrivilegedLevel::Machine);
+        self.bus.reservations.yield_reservation(self.hartid());
+        log::infoln!("hart {} halted ({:?}) at {:#x}", self.hartid(), cause, pc);
+    }
+
+    pub fn leave_debug_mode(&mut self) {
+        let (pc, priv_lv) = self.csrs.leave_debug();
+        self.update_pc(pc);
+        self.set_priv_lv(priv_lv);
+        log::infoln!("hart {} resumed at {:#x}", self.hartid(), pc);
+    }
+
+    // ebreak and c.ebreak hand over to the debugger when dcsr asks for it
+    pub fn ebreak(&mut self) {
+        if self.csrs.ebreak_halts(self.priv_lv()) {
+            self.enter_debug_mode(DebugCause::Ebreak);
+        } else {
+            self.trap(self.pc(), TrapCause::Breakpoint);
+        }
+    }
+
+    // with dcsr.step, the hart halts again after one instruction or at the
+    // first instruction of the trap handler it entered
+    pub fn check_single_step(&mut self) {
+        if !self.debug_mode() && self.csrs.single_step() {
+            self.enter_debug_mode(DebugCause::Step);
+        }
+    }
+
+    // run the program buffer until an ebreak, a dret or its end, where an
+    // implicit ebreak follows; an exception stops it
+    pub fn exec_progbuf(
+        &mut self,
+        progbuf: &[u32],
+    ) -> Result<(), (Option<u64>, TrapCause, String)> {
+        let pc = self.pc();
+        self.update_pc(PROGBUF_ADDR);
+        self.csrs.take_debug_exception();
+        let result = self.run_progbuf(progbuf);
+        if self.debug_mode() {
+            self.update_pc(pc);
+        }
+        result
+    }
+
+    fn run_progbuf(&mut self, progbuf: &[u32]) -> Result<(), (Option<u64>, TrapCause, String)> {
+        let halfword = |offset: u64| {
+            progbuf
+                .get(offset as usize / 4)
+                .map(|word| (word >> (offset % 4 * 8)) as u16)
+        };
+
+        for step in 0.. {
+            if !self.debug_mode() {
+                break;
+            }
+            if step == PROGBUF_STEPS_MAX {
+                return Err((
+                    Some(self.pc()),
+                    TrapCause::IllegalInst,
+                    format!("the program buffer ran over {PROGBUF_STEPS_MAX} instructions"),
+                ));
+            }
+            let offset = self.pc().wrapping_sub(PROGBUF_ADDR);
+            if offset == progbuf.len() as u64 * 4 {
+                break;
+            }
+            let Some(lower) = halfword(offset) else {
+                return Err((
+                    Some(self.pc()),
+                    TrapCause::InstAccessFault,
+                    "jumped out of the program buffer".to_string(),
+                ));
+            };
+            let raw: Box<dyn Decode> = if lower & 0x3 != 0x3 {
+                if lower == C_EBREAK {
+                    break;
+                }
+                Box::new(lower)
+            } else {
+                let upper = halfword(offset + 2).unwrap_or(0);
+                let inst = (upper as u32) << 16 | lower as u32;
+                if inst == EBREAK {
+                    break;
+                }
+                Box::new(inst)
+            };
+
+            raw.decode(*self.isa)?.execution(self)?;
+            if self.csrs.take_debug_exception() {
+                return Err((
+                    None,
+                    TrapCause::IllegalInst,
+                    "exception in the program buffer".to_string(),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
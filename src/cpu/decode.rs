@@ -34,6 +34,10 @@ pub trait Decode {
         self,
         opkind: &OpecodeKind,
     ) -> Result<Option<usize>, (Option<u64>, TrapCause, String)>;
+    fn parse_rs3(
+        self,
+        opkind: &OpecodeKind,
+    ) -> Result<Option<usize>, (Option<u64>, TrapCause, String)>;
     fn parse_imm(
         self,
         opkind: &OpecodeKind,
@@ -18,6 +18,7 @@ impl Decode for u16 {
             rd: new_rd,
             rs1: new_rs1,
             rs2: new_rs2,
+            rs3: None,
             imm: new_imm,
         })
     }
@@ -59,6 +60,13 @@ impl Decode for u16 {
         }
     }
 
+    fn parse_rs3(
+        self,
+        _opkind: &OpecodeKind,
+    ) -> Result<Option<usize>, (Option<u64>, TrapCause, String)> {
+        Ok(None)
+    }
+
     fn parse_imm(
         self,
         opkind: &OpecodeKind,
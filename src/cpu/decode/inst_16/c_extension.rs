@@ -9,10 +9,18 @@ fn quadrant0(
 ) -> Result<OpecodeKind, (Option<u64>, TrapCause, String)> {
     match opmap {
         0b000 => Ok(OpecodeKind::OP_C_ADDI4SPN),
+        0b001 => Ok(OpecodeKind::OP_C_FLD),
         0b010 => Ok(OpecodeKind::OP_C_LW),
-        0b011 => only_rv64(OpecodeKind::OP_C_LD, isa),
+        0b011 => match isa {
+            Isa::Rv32 => Ok(OpecodeKind::OP_C_FLW),
+            Isa::Rv64 => Ok(OpecodeKind::OP_C_LD),
+        },
+        0b101 => Ok(OpecodeKind::OP_C_FSD),
         0b110 => Ok(OpecodeKind::OP_C_SW),
-        0b111 => only_rv64(OpecodeKind::OP_C_SD, isa),
+        0b111 => match isa {
+            Isa::Rv32 => Ok(OpecodeKind::OP_C_FSW),
+            Isa::Rv64 => Ok(OpecodeKind::OP_C_SD),
+        },
         _ => Err((
             Some(u64::from(inst)),
             TrapCause::IllegalInst,
@@ -91,8 +99,12 @@ fn quadrant2(
 
     match opmap {
         0b000 => Ok(OpecodeKind::OP_C_SLLI),
+        0b001 => Ok(OpecodeKind::OP_C_FLDSP),
         0b010 => Ok(OpecodeKind::OP_C_LWSP),
-        0b011 => only_rv64(OpecodeKind::OP_C_LDSP, isa),
+        0b011 => match isa {
+            Isa::Rv32 => Ok(OpecodeKind::OP_C_FLWSP),
+            Isa::Rv64 => Ok(OpecodeKind::OP_C_LDSP),
+        },
         0b100 => match hi_flag {
             0b0 => match lo_flag {
                 0b0 => Ok(OpecodeKind::OP_C_JR),
@@ -111,8 +123,12 @@ fn quadrant2(
                 format!("opecode decoding failed, {inst:b}"),
             )),
         },
+        0b101 => Ok(OpecodeKind::OP_C_FSDSP),
         0b110 => Ok(OpecodeKind::OP_C_SWSP),
-        0b111 => only_rv64(OpecodeKind::OP_C_SDSP, isa),
+        0b111 => match isa {
+            Isa::Rv32 => Ok(OpecodeKind::OP_C_FSWSP),
+            Isa::Rv64 => Ok(OpecodeKind::OP_C_SDSP),
+        },
         _ => Err((
             Some(u64::from(inst)),
             TrapCause::IllegalInst,
@@ -160,6 +176,8 @@ pub fn parse_rd(
         OpecodeKind::OP_C_ADDI4SPN => Ok(Some(q0_rd)),
         OpecodeKind::OP_C_LW => Ok(Some(q0_rd)),
         OpecodeKind::OP_C_LD => Ok(Some(q0_rd)),
+        OpecodeKind::OP_C_FLD => Ok(Some(q0_rd)),
+        OpecodeKind::OP_C_FLW => Ok(Some(q0_rd)),
         // Quadrant 1
         OpecodeKind::OP_C_SRLI => Ok(Some(q1_rd)),
         OpecodeKind::OP_C_SRAI => Ok(Some(q1_rd)),
@@ -178,6 +196,8 @@ pub fn parse_rd(
         OpecodeKind::OP_C_SLLI => Ok(Some(q2_rd)),
         OpecodeKind::OP_C_LWSP => Ok(Some(q2_rd)),
         OpecodeKind::OP_C_LDSP => Ok(Some(q2_rd)),
+        OpecodeKind::OP_C_FLDSP => Ok(Some(q2_rd)),
+        OpecodeKind::OP_C_FLWSP => Ok(Some(q2_rd)),
         OpecodeKind::OP_C_JR => Ok(Some(q2_rd)),
         OpecodeKind::OP_C_MV => Ok(Some(q2_rd)),
         OpecodeKind::OP_C_EBREAK => Ok(Some(q2_rd)),
@@ -203,6 +223,10 @@ pub fn parse_rs1(
         OpecodeKind::OP_C_LD => Ok(Some(q0_rs1)),
         OpecodeKind::OP_C_SW => Ok(Some(q0_rs1)),
         OpecodeKind::OP_C_SD => Ok(Some(q0_rs1)),
+        OpecodeKind::OP_C_FLD => Ok(Some(q0_rs1)),
+        OpecodeKind::OP_C_FSD => Ok(Some(q0_rs1)),
+        OpecodeKind::OP_C_FLW => Ok(Some(q0_rs1)),
+        OpecodeKind::OP_C_FSW => Ok(Some(q0_rs1)),
         // Quadrant 1
         OpecodeKind::OP_C_ADDI => Ok(Some(q1_addi_rs1)),
         OpecodeKind::OP_C_ADDIW => Ok(Some(q1_addi_rs1)),
@@ -240,6 +264,8 @@ pub fn parse_rs2(
         // Quadrant 0
         OpecodeKind::OP_C_SW => Ok(Some(q0_rs2)),
         OpecodeKind::OP_C_SD => Ok(Some(q0_rs2)),
+        OpecodeKind::OP_C_FSD => Ok(Some(q0_rs2)),
+        OpecodeKind::OP_C_FSW => Ok(Some(q0_rs2)),
         // Quadrant 1
         OpecodeKind::OP_C_SUB => Ok(Some(q1_rs2)),
         OpecodeKind::OP_C_XOR => Ok(Some(q1_rs2)),
@@ -252,6 +278,8 @@ pub fn parse_rs2(
         OpecodeKind::OP_C_ADD => Ok(Some(q2_rs2)),
         OpecodeKind::OP_C_SWSP => Ok(Some(q2_rs2)),
         OpecodeKind::OP_C_SDSP => Ok(Some(q2_rs2)),
+        OpecodeKind::OP_C_FSDSP => Ok(Some(q2_rs2)),
+        OpecodeKind::OP_C_FSWSP => Ok(Some(q2_rs2)),
         _ => Ok(None),
     }
 }
@@ -306,6 +334,10 @@ pub fn parse_imm(
         OpecodeKind::OP_C_LD => Ok(Some(q0_uimm_64())),
         OpecodeKind::OP_C_SW => Ok(Some(q0_uimm())),
         OpecodeKind::OP_C_SD => Ok(Some(q0_uimm_64())),
+        OpecodeKind::OP_C_FLD => Ok(Some(q0_uimm_64())),
+        OpecodeKind::OP_C_FSD => Ok(Some(q0_uimm_64())),
+        OpecodeKind::OP_C_FLW => Ok(Some(q0_uimm())),
+        OpecodeKind::OP_C_FSW => Ok(Some(q0_uimm())),
         // Quadrant1
         OpecodeKind::OP_C_NOP => Ok(Some(q1_nzimm())),
         OpecodeKind::OP_C_ADDI => Ok(Some(q1_nzimm())),
@@ -326,6 +358,10 @@ pub fn parse_imm(
         OpecodeKind::OP_C_LDSP => Ok(Some(q2_ldsp_imm())),
         OpecodeKind::OP_C_SWSP => Ok(Some(q2_swsp_imm())),
         OpecodeKind::OP_C_SDSP => Ok(Some(q2_sdsp_imm())),
+        OpecodeKind::OP_C_FLDSP => Ok(Some(q2_ldsp_imm())),
+        OpecodeKind::OP_C_FSDSP => Ok(Some(q2_sdsp_imm())),
+        OpecodeKind::OP_C_FLWSP => Ok(Some(q2_lwsp_imm())),
+        OpecodeKind::OP_C_FSWSP => Ok(Some(q2_swsp_imm())),
         _ => Ok(None),
     }
 }
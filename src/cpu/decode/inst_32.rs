@@ -1,8 +1,12 @@
 mod a_extension;
 mod base_i;
+mod d_extension;
+mod f_extension;
+mod h_extension;
 mod m_extension;
 mod priv_extension;
 mod zicsr_extension;
+mod zifencei_extension;
 
 use super::{Decode, DecodeUtil};
 use crate::cpu::instruction::{Extensions, Instruction, OpecodeKind};
@@ -15,6 +19,7 @@ impl Decode for u32 {
         let new_rd: Option<usize> = self.parse_rd(&new_opc)?;
         let new_rs1: Option<usize> = self.parse_rs1(&new_opc)?;
         let new_rs2: Option<usize> = self.parse_rs2(&new_opc)?;
+        let new_rs3: Option<usize> = self.parse_rs3(&new_opc)?;
         let new_imm: Option<i32> = self.parse_imm(&new_opc, isa)?;
 
         Ok(Instruction {
@@ -22,6 +27,7 @@ impl Decode for u32 {
             rd: new_rd,
             rs1: new_rs1,
             rs2: new_rs2,
+            rs3: new_rs3,
             imm: new_imm,
         })
     }
@@ -32,7 +38,11 @@ impl Decode for u32 {
             Extensions::M => m_extension::parse_opecode(self, isa),
             Extensions::A => a_extension::parse_opecode(self, isa),
             Extensions::Zicsr => zicsr_extension::parse_opecode(self),
+            Extensions::Zifencei => zifencei_extension::parse_opecode(self),
             Extensions::Priv => priv_extension::parse_opecode(self),
+            Extensions::F => f_extension::parse_opecode(self, isa),
+            Extensions::D => d_extension::parse_opecode(self, isa),
+            Extensions::H => h_extension::parse_opecode(self, isa),
             _ => panic!("This instruction does not matched any extensions."),
         }
     }
@@ -46,7 +56,11 @@ impl Decode for u32 {
             Extensions::M => m_extension::parse_rd(self, opkind),
             Extensions::A => a_extension::parse_rd(self, opkind),
             Extensions::Zicsr => zicsr_extension::parse_rd(self, opkind),
+            Extensions::Zifencei => zifencei_extension::parse_rd(self, opkind),
             Extensions::Priv => priv_extension::parse_rd(self, opkind),
+            Extensions::F => f_extension::parse_rd(self, opkind),
+            Extensions::D => d_extension::parse_rd(self, opkind),
+            Extensions::H => h_extension::parse_rd(self, opkind),
             _ => panic!("This instruction does not matched any extensions."),
         }
     }
@@ -60,7 +74,11 @@ impl Decode for u32 {
             Extensions::M => m_extension::parse_rs1(self, opkind),
             Extensions::A => a_extension::parse_rs1(self, opkind),
             Extensions::Zicsr => zicsr_extension::parse_rs1(self, opkind),
+            Extensions::Zifencei => zifencei_extension::parse_rs1(self, opkind),
             Extensions::Priv => priv_extension::parse_rs1(self, opkind),
+            Extensions::F => f_extension::parse_rs1(self, opkind),
+            Extensions::D => d_extension::parse_rs1(self, opkind),
+            Extensions::H => h_extension::parse_rs1(self, opkind),
             _ => panic!("This instruction does not matched any extensions."),
         }
     }
@@ -74,11 +92,26 @@ impl Decode for u32 {
             Extensions::M => m_extension::parse_rs2(self, opkind),
             Extensions::A => a_extension::parse_rs2(self, opkind),
             Extensions::Zicsr => zicsr_extension::parse_rs2(self, opkind),
+            Extensions::Zifencei => zifencei_extension::parse_rs2(self, opkind),
             Extensions::Priv => priv_extension::parse_rs2(self, opkind),
+            Extensions::F => f_extension::parse_rs2(self, opkind),
+            Extensions::D => d_extension::parse_rs2(self, opkind),
+            Extensions::H => h_extension::parse_rs2(self, opkind),
             _ => panic!("This instruction does not matched any extensions."),
         }
     }
 
+    fn parse_rs3(
+        self,
+        opkind: &OpecodeKind,
+    ) -> Result<Option<usize>, (Option<u64>, TrapCause, String)> {
+        match self.extension() {
+            Extensions::F => f_extension::parse_rs3(self, opkind),
+            Extensions::D => d_extension::parse_rs3(self, opkind),
+            _ => Ok(None),
+        }
+    }
+
     fn parse_imm(
         self,
         opkind: &OpecodeKind,
@@ -89,7 +122,11 @@ impl Decode for u32 {
             Extensions::M => m_extension::parse_imm(self, opkind),
             Extensions::A => a_extension::parse_imm(self, opkind),
             Extensions::Zicsr => zicsr_extension::parse_imm(self, opkind),
+            Extensions::Zifencei => zifencei_extension::parse_imm(self, opkind),
             Extensions::Priv => priv_extension::parse_imm(self, opkind),
+            Extensions::F => f_extension::parse_imm(self, opkind),
+            Extensions::D => d_extension::parse_imm(self, opkind),
+            Extensions::H => h_extension::parse_imm(self, opkind),
             _ => panic!("This instruction does not matched any extensions."),
         }
     }
@@ -115,6 +152,26 @@ impl DecodeUtil for u32 {
         let funct7: u8 = self.slice(31, 25) as u8;
 
         match opmap {
+            0b0000111 | 0b0100111 => match funct3 {
+                0b011 => Extensions::D,
+                0b010 => Extensions::F,
+                _ => Extensions::BaseI,
+            },
+            0b1000011 | 0b1000111 | 0b1001011 | 0b1001111 => match funct7 & 0b11 {
+                0b01 => Extensions::D,
+                _ => Extensions::F,
+            },
+            0b1010011 => match funct7 {
+                0b0100000 => Extensions::D,
+                _ => match funct7 & 0b11 {
+                    0b01 => Extensions::D,
+                    _ => Extensions::F,
+                },
+            },
+            0b0001111 => match funct3 {
+                0b001 => Extensions::Zifencei,
+                _ => Extensions::BaseI,
+            },
             0b0101111 => Extensions::A,
             0b0110011 => match funct7 {
                 0b0000001 => Extensions::M,
@@ -128,8 +185,10 @@ impl DecodeUtil for u32 {
             0b1110011 => match funct3 {
                 0b000 => match funct7 {
                     0b0000000 => Extensions::BaseI,
+                    0b0010001 | 0b0110001 => Extensions::H,
                     _ => Extensions::Priv,
                 },
+                0b100 => Extensions::H,
                 _ => Extensions::Zicsr,
             },
             _ => Extensions::BaseI,
@@ -218,4 +277,101 @@ mod decode_32 {
         );
         test_32(0x00100513, OP_ADDI, Some(10), Some(0), None, Some(1))
     }
+
+    #[test]
+    fn parsing_fp_test() {
+        use OpecodeKind::*;
+        let test_fp = |inst_32: u32,
+                       op: OpecodeKind,
+                       regs: (Option<usize>, Option<usize>, Option<usize>, Option<usize>),
+                       imm: Option<i32>| {
+            let inst = inst_32.decode(Isa::Rv64).unwrap();
+            assert_eq!(format!("{:?}", inst.opc), format!("{op:?}"));
+            assert_eq!((inst.rd, inst.rs1, inst.rs2, inst.rs3), regs);
+            assert_eq!(inst.imm, imm);
+        };
+
+        // fadd.s ft1, ft2, ft3 (dynamic rounding)
+        test_fp(
+            0x003170d3,
+            OP_FADD_S,
+            (Some(1), Some(2), Some(3), None),
+            Some(7),
+        );
+        // fmadd.d ft1, ft2, ft3, ft4, rtz
+        test_fp(
+            0x223110c3,
+            OP_FMADD_D,
+            (Some(1), Some(2), Some(3), Some(4)),
+            Some(1),
+        );
+        // flw ft5, -8(a0)
+        test_fp(
+            0xff852287,
+            OP_FLW,
+            (Some(5), Some(10), None, None),
+            Some(-8),
+        );
+        // fcvt.s.d ft1, ft2
+        test_fp(
+            0x401170d3,
+            OP_FCVT_S_D,
+            (Some(1), Some(2), None, None),
+            Some(7),
+        );
+        // fmv.x.d a0, ft1
+        test_fp(
+            0xe2008553,
+            OP_FMV_X_D,
+            (Some(10), Some(1), None, None),
+            None,
+        );
+        assert!(0xe2008553_u32.decode(Isa::Rv32).is_err());
+    }
+
+    #[test]
+    fn parsing_fence_test() {
+        // fence.i
+        let inst = 0x0000100f_u32.decode(Isa::Rv64).unwrap();
+        assert!(matches!(inst.opc, OpecodeKind::OP_FENCE_I));
+        assert_eq!((inst.rd, inst.rs1, inst.imm), (None, None, None));
+        // fence iorw, iorw
+        let inst = 0x0ff0000f_u32.decode(Isa::Rv32).unwrap();
+        assert!(matches!(inst.opc, OpecodeKind::OP_FENCE));
+        // funct3 = 010 is reserved
+        assert!(0x0000200f_u32.decode(Isa::Rv64).is_err());
+    }
+
+    #[test]
+    fn parsing_h_test() {
+        let decode = |inst_32: u32, isa| {
+            inst_32
+                .decode(isa)
+                .map(|inst| (format!("{:?}", inst.opc), inst.rd, inst.rs1, inst.rs2))
+        };
+
+        // hlv.d a0, (a1)
+        assert_eq!(
+            decode(0x6c05c573, Isa::Rv64).unwrap(),
+            ("OP_HLV_D".to_string(), Some(10), Some(11), None)
+        );
+        assert!(decode(0x6c05c573, Isa::Rv32).is_err());
+        // hlvx.hu a0, (a1)
+        assert_eq!(
+            decode(0x6435c573, Isa::Rv64).unwrap(),
+            ("OP_HLVX_HU".to_string(), Some(10), Some(11), None)
+        );
+        // hsv.w a2, (a1)
+        assert_eq!(
+            decode(0x6ac5c073, Isa::Rv64).unwrap(),
+            ("OP_HSV_W".to_string(), None, Some(11), Some(12))
+        );
+        // hfence.gvma zero, a1
+        assert_eq!(
+            decode(0x62b00073, Isa::Rv64).unwrap(),
+            ("OP_HFENCE_GVMA".to_string(), None, Some(0), Some(11))
+        );
+        // hlv.w with rs2 = 2 is reserved
+        assert!(decode(0x6825c573, Isa::Rv64).is_err());
+    }
 }
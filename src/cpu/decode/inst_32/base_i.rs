@@ -97,7 +97,10 @@ pub fn parse_opecode(inst: u32, isa: Isa) -> Result<OpecodeKind, (Option<u64>, T
             0b111 => Ok(OpecodeKind::OP_AND),
             _ => illegal_inst_exception(),
         },
-        0b0001111 => Ok(OpecodeKind::OP_FENCE),
+        0b0001111 => match funct3 {
+            0b000 => Ok(OpecodeKind::OP_FENCE),
+            _ => illegal_inst_exception(),
+        },
         0b1110011 => match funct3 {
             0b000 => match funct7 {
                 0b0000000 => match funct5 {
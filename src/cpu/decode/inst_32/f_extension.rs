@@ -0,0 +1,262 @@
+use crate::cpu::decode::{only_rv64, DecodeUtil};
+use crate::cpu::instruction::OpecodeKind;
+use crate::cpu::{Isa, TrapCause};
+
+pub fn parse_opecode(inst: u32, isa: Isa) -> Result<OpecodeKind, (Option<u64>, TrapCause, String)> {
+    let opmap: u8 = inst.slice(6, 0) as u8;
+    let funct3: u8 = inst.slice(14, 12) as u8;
+    let funct7: u8 = inst.slice(31, 25) as u8;
+    let fmt: u8 = inst.slice(26, 25) as u8;
+    let rs2: u8 = inst.slice(24, 20) as u8;
+    let illegal_inst_exception = || {
+        Err((
+            Some(u64::from(inst)),
+            TrapCause::IllegalInst,
+            format!("opecode decoding failed in F extension, {inst:b}"),
+        ))
+    };
+
+    match opmap {
+        0b0000111 => match funct3 {
+            0b010 => Ok(OpecodeKind::OP_FLW),
+            _ => illegal_inst_exception(),
+        },
+        0b0100111 => match funct3 {
+            0b010 => Ok(OpecodeKind::OP_FSW),
+            _ => illegal_inst_exception(),
+        },
+        0b1000011 => match fmt {
+            0b00 => Ok(OpecodeKind::OP_FMADD_S),
+            _ => illegal_inst_exception(),
+        },
+        0b1000111 => match fmt {
+            0b00 => Ok(OpecodeKind::OP_FMSUB_S),
+            _ => illegal_inst_exception(),
+        },
+        0b1001011 => match fmt {
+            0b00 => Ok(OpecodeKind::OP_FNMSUB_S),
+            _ => illegal_inst_exception(),
+        },
+        0b1001111 => match fmt {
+            0b00 => Ok(OpecodeKind::OP_FNMADD_S),
+            _ => illegal_inst_exception(),
+        },
+        0b1010011 => match funct7 {
+            0b0000000 => Ok(OpecodeKind::OP_FADD_S),
+            0b0000100 => Ok(OpecodeKind::OP_FSUB_S),
+            0b0001000 => Ok(OpecodeKind::OP_FMUL_S),
+            0b0001100 => Ok(OpecodeKind::OP_FDIV_S),
+            0b0101100 => match rs2 {
+                0b00000 => Ok(OpecodeKind::OP_FSQRT_S),
+                _ => illegal_inst_exception(),
+            },
+            0b0010000 => match funct3 {
+                0b000 => Ok(OpecodeKind::OP_FSGNJ_S),
+                0b001 => Ok(OpecodeKind::OP_FSGNJN_S),
+                0b010 => Ok(OpecodeKind::OP_FSGNJX_S),
+                _ => illegal_inst_exception(),
+            },
+            0b0010100 => match funct3 {
+                0b000 => Ok(OpecodeKind::OP_FMIN_S),
+                0b001 => Ok(OpecodeKind::OP_FMAX_S),
+                _ => illegal_inst_exception(),
+            },
+            0b1010000 => match funct3 {
+                0b010 => Ok(OpecodeKind::OP_FEQ_S),
+                0b001 => Ok(OpecodeKind::OP_FLT_S),
+                0b000 => Ok(OpecodeKind::OP_FLE_S),
+                _ => illegal_inst_exception(),
+            },
+            0b1100000 => match rs2 {
+                0b00000 => Ok(OpecodeKind::OP_FCVT_W_S),
+                0b00001 => Ok(OpecodeKind::OP_FCVT_WU_S),
+                0b00010 => only_rv64(OpecodeKind::OP_FCVT_L_S, isa),
+                0b00011 => only_rv64(OpecodeKind::OP_FCVT_LU_S, isa),
+                _ => illegal_inst_exception(),
+            },
+            0b1101000 => match rs2 {
+                0b00000 => Ok(OpecodeKind::OP_FCVT_S_W),
+                0b00001 => Ok(OpecodeKind::OP_FCVT_S_WU),
+                0b00010 => only_rv64(OpecodeKind::OP_FCVT_S_L, isa),
+                0b00011 => only_rv64(OpecodeKind::OP_FCVT_S_LU, isa),
+                _ => illegal_inst_exception(),
+            },
+            0b1110000 => match (rs2, funct3) {
+                (0b00000, 0b000) => Ok(OpecodeKind::OP_FMV_X_W),
+                (0b00000, 0b001) => Ok(OpecodeKind::OP_FCLASS_S),
+                _ => illegal_inst_exception(),
+            },
+            0b1111000 => match (rs2, funct3) {
+                (0b00000, 0b000) => Ok(OpecodeKind::OP_FMV_W_X),
+                _ => illegal_inst_exception(),
+            },
+            _ => illegal_inst_exception(),
+        },
+        _ => illegal_inst_exception(),
+    }
+}
+
+pub fn parse_rd(
+    inst: u32,
+    opkind: &OpecodeKind,
+) -> Result<Option<usize>, (Option<u64>, TrapCause, String)> {
+    let rd: usize = inst.slice(11, 7) as usize;
+
+    match opkind {
+        OpecodeKind::OP_FLW => Ok(Some(rd)),
+        OpecodeKind::OP_FMADD_S => Ok(Some(rd)),
+        OpecodeKind::OP_FMSUB_S => Ok(Some(rd)),
+        OpecodeKind::OP_FNMSUB_S => Ok(Some(rd)),
+        OpecodeKind::OP_FNMADD_S => Ok(Some(rd)),
+        OpecodeKind::OP_FADD_S => Ok(Some(rd)),
+        OpecodeKind::OP_FSUB_S => Ok(Some(rd)),
+        OpecodeKind::OP_FMUL_S => Ok(Some(rd)),
+        OpecodeKind::OP_FDIV_S => Ok(Some(rd)),
+        OpecodeKind::OP_FSQRT_S => Ok(Some(rd)),
+        OpecodeKind::OP_FSGNJ_S => Ok(Some(rd)),
+        OpecodeKind::OP_FSGNJN_S => Ok(Some(rd)),
+        OpecodeKind::OP_FSGNJX_S => Ok(Some(rd)),
+        OpecodeKind::OP_FMIN_S => Ok(Some(rd)),
+        OpecodeKind::OP_FMAX_S => Ok(Some(rd)),
+        OpecodeKind::OP_FCVT_W_S => Ok(Some(rd)),
+        OpecodeKind::OP_FCVT_WU_S => Ok(Some(rd)),
+        OpecodeKind::OP_FMV_X_W => Ok(Some(rd)),
+        OpecodeKind::OP_FEQ_S => Ok(Some(rd)),
+        OpecodeKind::OP_FLT_S => Ok(Some(rd)),
+        OpecodeKind::OP_FLE_S => Ok(Some(rd)),
+        OpecodeKind::OP_FCLASS_S => Ok(Some(rd)),
+        OpecodeKind::OP_FCVT_S_W => Ok(Some(rd)),
+        OpecodeKind::OP_FCVT_S_WU => Ok(Some(rd)),
+        OpecodeKind::OP_FMV_W_X => Ok(Some(rd)),
+        OpecodeKind::OP_FCVT_L_S => Ok(Some(rd)),
+        OpecodeKind::OP_FCVT_LU_S => Ok(Some(rd)),
+        OpecodeKind::OP_FCVT_S_L => Ok(Some(rd)),
+        OpecodeKind::OP_FCVT_S_LU => Ok(Some(rd)),
+        _ => Ok(None),
+    }
+}
+
+pub fn parse_rs1(
+    inst: u32,
+    opkind: &OpecodeKind,
+) -> Result<Option<usize>, (Option<u64>, TrapCause, String)> {
+    let rs1: usize = inst.slice(19, 15) as usize;
+
+    match opkind {
+        OpecodeKind::OP_FLW => Ok(Some(rs1)),
+        OpecodeKind::OP_FSW => Ok(Some(rs1)),
+        OpecodeKind::OP_FMADD_S => Ok(Some(rs1)),
+        OpecodeKind::OP_FMSUB_S => Ok(Some(rs1)),
+        OpecodeKind::OP_FNMSUB_S => Ok(Some(rs1)),
+        OpecodeKind::OP_FNMADD_S => Ok(Some(rs1)),
+        OpecodeKind::OP_FADD_S => Ok(Some(rs1)),
+        OpecodeKind::OP_FSUB_S => Ok(Some(rs1)),
+        OpecodeKind::OP_FMUL_S => Ok(Some(rs1)),
+        OpecodeKind::OP_FDIV_S => Ok(Some(rs1)),
+        OpecodeKind::OP_FSQRT_S => Ok(Some(rs1)),
+        OpecodeKind::OP_FSGNJ_S => Ok(Some(rs1)),
+        OpecodeKind::OP_FSGNJN_S => Ok(Some(rs1)),
+        OpecodeKind::OP_FSGNJX_S => Ok(Some(rs1)),
+        OpecodeKind::OP_FMIN_S => Ok(Some(rs1)),
+        OpecodeKind::OP_FMAX_S => Ok(Some(rs1)),
+        OpecodeKind::OP_FCVT_W_S => Ok(Some(rs1)),
+        OpecodeKind::OP_FCVT_WU_S => Ok(Some(rs1)),
+        OpecodeKind::OP_FMV_X_W => Ok(Some(rs1)),
+        OpecodeKind::OP_FEQ_S => Ok(Some(rs1)),
+        OpecodeKind::OP_FLT_S => Ok(Some(rs1)),
+        OpecodeKind::OP_FLE_S => Ok(Some(rs1)),
+        OpecodeKind::OP_FCLASS_S => Ok(Some(rs1)),
+        OpecodeKind::OP_FCVT_S_W => Ok(Some(rs1)),
+        OpecodeKind::OP_FCVT_S_WU => Ok(Some(rs1)),
+        OpecodeKind::OP_FMV_W_X => Ok(Some(rs1)),
+        OpecodeKind::OP_FCVT_L_S => Ok(Some(rs1)),
+        OpecodeKind::OP_FCVT_LU_S => Ok(Some(rs1)),
+        OpecodeKind::OP_FCVT_S_L => Ok(Some(rs1)),
+        OpecodeKind::OP_FCVT_S_LU => Ok(Some(rs1)),
+        _ => panic!("rs1 decoding failed in F extension"),
+    }
+}
+
+pub fn parse_rs2(
+    inst: u32,
+    opkind: &OpecodeKind,
+) -> Result<Option<usize>, (Option<u64>, TrapCause, String)> {
+    let rs2: usize = inst.slice(24, 20) as usize;
+
+    match opkind {
+        OpecodeKind::OP_FSW => Ok(Some(rs2)),
+        OpecodeKind::OP_FMADD_S => Ok(Some(rs2)),
+        OpecodeKind::OP_FMSUB_S => Ok(Some(rs2)),
+        OpecodeKind::OP_FNMSUB_S => Ok(Some(rs2)),
+        OpecodeKind::OP_FNMADD_S => Ok(Some(rs2)),
+        OpecodeKind::OP_FADD_S => Ok(Some(rs2)),
+        OpecodeKind::OP_FSUB_S => Ok(Some(rs2)),
+        OpecodeKind::OP_FMUL_S => Ok(Some(rs2)),
+        OpecodeKind::OP_FDIV_S => Ok(Some(rs2)),
+        OpecodeKind::OP_FSGNJ_S => Ok(Some(rs2)),
+        OpecodeKind::OP_FSGNJN_S => Ok(Some(rs2)),
+        OpecodeKind::OP_FSGNJX_S => Ok(Some(rs2)),
+        OpecodeKind::OP_FMIN_S => Ok(Some(rs2)),
+        OpecodeKind::OP_FMAX_S => Ok(Some(rs2)),
+        OpecodeKind::OP_FEQ_S => Ok(Some(rs2)),
+        OpecodeKind::OP_FLT_S => Ok(Some(rs2)),
+        OpecodeKind::OP_FLE_S => Ok(Some(rs2)),
+        _ => Ok(None),
+    }
+}
+
+pub fn parse_rs3(
+    inst: u32,
+    opkind: &OpecodeKind,
+) -> Result<Option<usize>, (Option<u64>, TrapCause, String)> {
+    let rs3: usize = inst.slice(31, 27) as usize;
+
+    match opkind {
+        OpecodeKind::OP_FMADD_S => Ok(Some(rs3)),
+        OpecodeKind::OP_FMSUB_S => Ok(Some(rs3)),
+        OpecodeKind::OP_FNMSUB_S => Ok(Some(rs3)),
+        OpecodeKind::OP_FNMADD_S => Ok(Some(rs3)),
+        _ => Ok(None),
+    }
+}
+
+#[allow(non_snake_case)]
+pub fn parse_imm(
+    inst: u32,
+    opkind: &OpecodeKind,
+) -> Result<Option<i32>, (Option<u64>, TrapCause, String)> {
+    let I_type = || {
+        let imm32 = inst.slice(31, 20) as i32;
+        inst.to_signed_nbit(imm32, 12)
+    };
+    let S_type = || {
+        let imm32 = (inst.slice(11, 7).set(&[4, 3, 2, 1, 0])
+            | inst.slice(31, 25).set(&[11, 10, 9, 8, 7, 6, 5])) as i32;
+        inst.to_signed_nbit(imm32, 12)
+    };
+    // rounding mode
+    let rm = || inst.slice(14, 12) as i32;
+
+    match opkind {
+        OpecodeKind::OP_FLW => Ok(Some(I_type())),
+        OpecodeKind::OP_FSW => Ok(Some(S_type())),
+        OpecodeKind::OP_FMADD_S => Ok(Some(rm())),
+        OpecodeKind::OP_FMSUB_S => Ok(Some(rm())),
+        OpecodeKind::OP_FNMSUB_S => Ok(Some(rm())),
+        OpecodeKind::OP_FNMADD_S => Ok(Some(rm())),
+        OpecodeKind::OP_FADD_S => Ok(Some(rm())),
+        OpecodeKind::OP_FSUB_S => Ok(Some(rm())),
+        OpecodeKind::OP_FMUL_S => Ok(Some(rm())),
+        OpecodeKind::OP_FDIV_S => Ok(Some(rm())),
+        OpecodeKind::OP_FSQRT_S => Ok(Some(rm())),
+        OpecodeKind::OP_FCVT_W_S => Ok(Some(rm())),
+        OpecodeKind::OP_FCVT_WU_S => Ok(Some(rm())),
+        OpecodeKind::OP_FCVT_S_W => Ok(Some(rm())),
+        OpecodeKind::OP_FCVT_S_WU => Ok(Some(rm())),
+        OpecodeKind::OP_FCVT_L_S => Ok(Some(rm())),
+        OpecodeKind::OP_FCVT_LU_S => Ok(Some(rm())),
+        OpecodeKind::OP_FCVT_S_L => Ok(Some(rm())),
+        OpecodeKind::OP_FCVT_S_LU => Ok(Some(rm())),
+        _ => Ok(None),
+    }
+}
@@ -0,0 +1,97 @@
+use crate::cpu::decode::{only_rv64, DecodeUtil};
+use crate::cpu::instruction::OpecodeKind;
+use crate::cpu::{Isa, TrapCause};
+
+pub fn parse_opecode(inst: u32, isa: Isa) -> Result<OpecodeKind, (Option<u64>, TrapCause, String)> {
+    let rd: u8 = inst.slice(11, 7) as u8;
+    let funct3: u8 = inst.slice(14, 12) as u8;
+    let rs2: u8 = inst.slice(24, 20) as u8;
+    let funct7: u8 = inst.slice(31, 25) as u8;
+    let illegal_inst_exception = || {
+        Err((
+            Some(u64::from(inst)),
+            TrapCause::IllegalInst,
+            format!("opecode decoding failed in h extension, {inst:b}"),
+        ))
+    };
+
+    match (funct3, funct7) {
+        (0b000, 0b0010001) if rd == 0 => Ok(OpecodeKind::OP_HFENCE_VVMA),
+        (0b000, 0b0110001) if rd == 0 => Ok(OpecodeKind::OP_HFENCE_GVMA),
+        (0b100, 0b0110000) => match rs2 {
+            0b00000 => Ok(OpecodeKind::OP_HLV_B),
+            0b00001 => Ok(OpecodeKind::OP_HLV_BU),
+            _ => illegal_inst_exception(),
+        },
+        (0b100, 0b0110010) => match rs2 {
+            0b00000 => Ok(OpecodeKind::OP_HLV_H),
+            0b00001 => Ok(OpecodeKind::OP_HLV_HU),
+            0b00011 => Ok(OpecodeKind::OP_HLVX_HU),
+            _ => illegal_inst_exception(),
+        },
+        (0b100, 0b0110100) => match rs2 {
+            0b00000 => Ok(OpecodeKind::OP_HLV_W),
+            0b00001 => only_rv64(OpecodeKind::OP_HLV_WU, isa),
+            0b00011 => Ok(OpecodeKind::OP_HLVX_WU),
+            _ => illegal_inst_exception(),
+        },
+        (0b100, 0b0110110) if rs2 == 0 => only_rv64(OpecodeKind::OP_HLV_D, isa),
+        (0b100, 0b0110001) if rd == 0 => Ok(OpecodeKind::OP_HSV_B),
+        (0b100, 0b0110011) if rd == 0 => Ok(OpecodeKind::OP_HSV_H),
+        (0b100, 0b0110101) if rd == 0 => Ok(OpecodeKind::OP_HSV_W),
+        (0b100, 0b0110111) if rd == 0 => only_rv64(OpecodeKind::OP_HSV_D, isa),
+        _ => illegal_inst_exception(),
+    }
+}
+
+pub fn parse_rd(
+    inst: u32,
+    opkind: &OpecodeKind,
+) -> Result<Option<usize>, (Option<u64>, TrapCause, String)> {
+    let rd: usize = inst.slice(11, 7) as usize;
+
+    match opkind {
+        OpecodeKind::OP_HLV_B
+        | OpecodeKind::OP_HLV_BU
+        | OpecodeKind::OP_HLV_H
+        | OpecodeKind::OP_HLV_HU
+        | OpecodeKind::OP_HLVX_HU
+        | OpecodeKind::OP_HLV_W
+        | OpecodeKind::OP_HLV_WU
+        | OpecodeKind::OP_HLVX_WU
+        | OpecodeKind::OP_HLV_D => Ok(Some(rd)),
+        _ => Ok(None),
+    }
+}
+
+pub fn parse_rs1(
+    inst: u32,
+    _opkind: &OpecodeKind,
+) -> Result<Option<usize>, (Option<u64>, TrapCause, String)> {
+    let rs1: usize = inst.slice(19, 15) as usize;
+    Ok(Some(rs1))
+}
+
+pub fn parse_rs2(
+    inst: u32,
+    opkind: &OpecodeKind,
+) -> Result<Option<usize>, (Option<u64>, TrapCause, String)> {
+    let rs2: usize = inst.slice(24, 20) as usize;
+
+    match opkind {
+        OpecodeKind::OP_HSV_B
+        | OpecodeKind::OP_HSV_H
+        | OpecodeKind::OP_HSV_W
+        | OpecodeKind::OP_HSV_D
+        | OpecodeKind::OP_HFENCE_VVMA
+        | OpecodeKind::OP_HFENCE_GVMA => Ok(Some(rs2)),
+        _ => Ok(None),
+    }
+}
+
+pub fn parse_imm(
+    _inst: u32,
+    _opkind: &OpecodeKind,
+) -> Result<Option<i32>, (Option<u64>, TrapCause, String)> {
+    Ok(None)
+}
@@ -11,6 +11,7 @@ pub fn parse_opecode(inst: u32) -> Result<OpecodeKind, (Option<u64>, TrapCause,
         0b00010000001000000000000001110011 => Ok(OpecodeKind::OP_SRET),
         0b00110000001000000000000001110011 => Ok(OpecodeKind::OP_MRET),
         0b00010000010100000000000001110011 => Ok(OpecodeKind::OP_WFI),
+        0b01111011001000000000000001110011 => Ok(OpecodeKind::OP_DRET),
         _ => match funct7 {
             0b0001001 => Ok(OpecodeKind::OP_SFENCE_VMA),
             _ => Err((
@@ -0,0 +1,53 @@
+use crate::cpu::decode::DecodeUtil;
+use crate::cpu::instruction::OpecodeKind;
+use crate::cpu::TrapCause;
+
+pub fn parse_opecode(inst: u32) -> Result<OpecodeKind, (Option<u64>, TrapCause, String)> {
+    let opmap: u8 = inst.slice(6, 0) as u8;
+    let funct3: u8 = inst.slice(14, 12) as u8;
+
+    match opmap {
+        0b0001111 => match funct3 {
+            0b001 => Ok(OpecodeKind::OP_FENCE_I),
+            _ => Err((
+                Some(u64::from(inst)),
+                TrapCause::IllegalInst,
+                format!("opecode decoding failed in zifencei extension, {inst:b}"),
+            )),
+        },
+        _ => Err((
+            Some(u64::from(inst)),
+            TrapCause::IllegalInst,
+            format!("opecode decoding failed in zifencei extension, {inst:b}"),
+        )),
+    }
+}
+
+// rd, rs1 and imm of FENCE.I are reserved for future use
+pub fn parse_rd(
+    _inst: u32,
+    _opkind: &OpecodeKind,
+) -> Result<Option<usize>, (Option<u64>, TrapCause, String)> {
+    Ok(None)
+}
+
+pub fn parse_rs1(
+    _inst: u32,
+    _opkind: &OpecodeKind,
+) -> Result<Option<usize>, (Option<u64>, TrapCause, String)> {
+    Ok(None)
+}
+
+pub fn parse_rs2(
+    _inst: u32,
+    _opkind: &OpecodeKind,
+) -> Result<Option<usize>, (Option<u64>, TrapCause, String)> {
+    Ok(None)
+}
+
+pub fn parse_imm(
+    _inst: u32,
+    _opkind: &OpecodeKind,
+) -> Result<Option<i32>, (Option<u64>, TrapCause, String)> {
+    Ok(None)
+}
@@ -140,7 +140,7 @@ pub fn exec(inst: &Instruction, cpu: &mut Cpu) -> Result<(), (Option<u64>, TrapC
         OpecodeKind::OP_C_MV => {
             cpu.regs.write(inst.rd, cpu.regs.read(inst.rs2));
         }
-        OpecodeKind::OP_C_EBREAK => { /* NOP */ }
+        OpecodeKind::OP_C_EBREAK => cpu.ebreak(),
         // -- rv64 --
         OpecodeKind::OP_C_LD => {
             let load_addr = cpu.trans_addr(
@@ -193,6 +193,114 @@ pub fn exec(inst: &Instruction, cpu: &mut Cpu) -> Result<(), (Option<u64>, TrapC
             );
         }
         OpecodeKind::OP_C_NOP => { /* NOP */ }
+        OpecodeKind::OP_C_FLD => {
+            cpu.check_fpu_enabled()?;
+            let load_addr = cpu.trans_addr(
+                TransFor::Load,
+                TransAlign::Size64,
+                match *cpu.isa {
+                    Isa::Rv32 => (cpu.regs.read(inst.rs1) as i32 + inst.imm.unwrap()) as u64,
+                    Isa::Rv64 => (cpu.regs.read(inst.rs1) as i64 + inst.imm.unwrap() as i64) as u64,
+                },
+            )?;
+            cpu.fregs.write(inst.rd, cpu.bus.load64(load_addr)?);
+            cpu.dirty_fs();
+        }
+        OpecodeKind::OP_C_FLDSP => {
+            cpu.check_fpu_enabled()?;
+            let load_addr = cpu.trans_addr(
+                TransFor::Load,
+                TransAlign::Size64,
+                match *cpu.isa {
+                    Isa::Rv32 => (cpu.regs.read(Some(REG_SP)) as i32 + inst.imm.unwrap()) as u64,
+                    Isa::Rv64 => {
+                        (cpu.regs.read(Some(REG_SP)) as i64 + inst.imm.unwrap() as i64) as u64
+                    }
+                },
+            )?;
+            cpu.fregs.write(inst.rd, cpu.bus.load64(load_addr)?);
+            cpu.dirty_fs();
+        }
+        OpecodeKind::OP_C_FSD => {
+            cpu.check_fpu_enabled()?;
+            let store_addr = cpu.trans_addr(
+                TransFor::StoreAMO,
+                TransAlign::Size64,
+                match *cpu.isa {
+                    Isa::Rv32 => (cpu.regs.read(inst.rs1) as i32 + inst.imm.unwrap()) as u64,
+                    Isa::Rv64 => (cpu.regs.read(inst.rs1) as i64 + inst.imm.unwrap() as i64) as u64,
+                },
+            )?;
+            cpu.bus.store64(store_addr, cpu.fregs.read(inst.rs2))?;
+        }
+        OpecodeKind::OP_C_FSDSP => {
+            cpu.check_fpu_enabled()?;
+            let store_addr = cpu.trans_addr(
+                TransFor::StoreAMO,
+                TransAlign::Size64,
+                match *cpu.isa {
+                    Isa::Rv32 => (cpu.regs.read(Some(REG_SP)) as i32 + inst.imm.unwrap()) as u64,
+                    Isa::Rv64 => {
+                        (cpu.regs.read(Some(REG_SP)) as i64 + inst.imm.unwrap() as i64) as u64
+                    }
+                },
+            )?;
+            cpu.bus.store64(store_addr, cpu.fregs.read(inst.rs2))?;
+        }
+        OpecodeKind::OP_C_FLW => {
+            cpu.check_fpu_enabled()?;
+            let load_addr = cpu.trans_addr(
+                TransFor::Load,
+                TransAlign::Size32,
+                match *cpu.isa {
+                    Isa::Rv32 => (cpu.regs.read(inst.rs1) as i32 + inst.imm.unwrap()) as u64,
+                    Isa::Rv64 => (cpu.regs.read(inst.rs1) as i64 + inst.imm.unwrap() as i64) as u64,
+                },
+            )?;
+            cpu.fregs.write_s(inst.rd, cpu.bus.load_u32(load_addr)?);
+            cpu.dirty_fs();
+        }
+        OpecodeKind::OP_C_FLWSP => {
+            cpu.check_fpu_enabled()?;
+            let load_addr = cpu.trans_addr(
+                TransFor::Load,
+                TransAlign::Size32,
+                match *cpu.isa {
+                    Isa::Rv32 => (cpu.regs.read(Some(REG_SP)) as i32 + inst.imm.unwrap()) as u64,
+                    Isa::Rv64 => {
+                        (cpu.regs.read(Some(REG_SP)) as i64 + inst.imm.unwrap() as i64) as u64
+                    }
+                },
+            )?;
+            cpu.fregs.write_s(inst.rd, cpu.bus.load_u32(load_addr)?);
+            cpu.dirty_fs();
+        }
+        OpecodeKind::OP_C_FSW => {
+            cpu.check_fpu_enabled()?;
+            let store_addr = cpu.trans_addr(
+                TransFor::StoreAMO,
+                TransAlign::Size32,
+                match *cpu.isa {
+                    Isa::Rv32 => (cpu.regs.read(inst.rs1) as i32 + inst.imm.unwrap()) as u64,
+                    Isa::Rv64 => (cpu.regs.read(inst.rs1) as i64 + inst.imm.unwrap() as i64) as u64,
+                },
+            )?;
+            cpu.bus.store32(store_addr, cpu.fregs.read(inst.rs2))?;
+        }
+        OpecodeKind::OP_C_FSWSP => {
+            cpu.check_fpu_enabled()?;
+            let store_addr = cpu.trans_addr(
+                TransFor::StoreAMO,
+                TransAlign::Size32,
+                match *cpu.isa {
+                    Isa::Rv32 => (cpu.regs.read(Some(REG_SP)) as i32 + inst.imm.unwrap()) as u64,
+                    Isa::Rv64 => {
+                        (cpu.regs.read(Some(REG_SP)) as i64 + inst.imm.unwrap() as i64) as u64
+                    }
+                },
+            )?;
+            cpu.bus.store32(store_addr, cpu.fregs.read(inst.rs2))?;
+        }
         _ => panic!("not a compressed Instruction"),
     }
 
@@ -201,37 +309,25 @@ pub fn exec(inst: &Instruction, cpu: &mut Cpu) -> Result<(), (Option<u64>, TrapC
 
 #[cfg(test)]
 mod exe_16 {
-    use crate::cmdline::ExeOption;
     use crate::cpu::execution::inst_16::c_extension::exec;
     use crate::cpu::instruction::{Instruction, OpecodeKind::*};
-    use crate::cpu::{csr, mmu, reg, Cpu, PrivilegedLevel};
-    use crate::{bus, elfload, Arguments, Isa};
+    use crate::cpu::{csr, freg, mmu, reg, Cpu, PrivilegedLevel};
+    use crate::{bus, Arguments, Isa};
     use std::cell::RefCell;
     use std::rc::Rc;
 
     #[test]
     fn c_extension_test() {
-        let dummy_elf =
-            elfload::ElfLoader::try_new("./HelloWorld").expect("creating dummy_elf failed");
         let isa = Isa::Rv32;
-        let args = Arguments {
-            filename: "./HelloWorld".to_string(),
-            exe_option: ExeOption::OPT_DEFAULT,
-            pk_path: None,
-            kernel_path: None,
-            initrd_path: None,
-            init_pc: None,
-            main_args: Vec::new(),
-        };
-        let bus = bus::Bus::new(dummy_elf, &args, isa);
+        let bus = bus::Bus::for_test(&Arguments::for_elf("./HelloWorld"), isa);
         let pc = Rc::new(RefCell::new(bus.mrom.base_addr));
         let mut cpu: Cpu = Cpu {
             pc: pc.clone(),
             bus,
             regs: reg::Register::new(Rc::new(isa)),
+            fregs: freg::FRegister::new(),
             csrs: csr::CSRs::new(Rc::new(isa), pc).init(),
             mmu: mmu::Mmu::new(Rc::new(isa)),
-            reservation_set: None,
             isa: Rc::new(isa),
             priv_lv: PrivilegedLevel::Machine,
         };
@@ -242,6 +338,7 @@ mod exe_16 {
                 rd: Some(10),
                 rs1: None,
                 rs2: None,
+                rs3: None,
                 imm: Some(42),
             },
             &mut cpu,
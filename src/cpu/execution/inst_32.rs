@@ -1,8 +1,12 @@
 mod a_extension;
 mod base_i;
+mod d_extension;
+mod f_extension;
+mod h_extension;
 mod m_extension;
 mod priv_extension;
 mod zicsr_extension;
+mod zifencei_extension;
 
 use crate::cpu::instruction::{Extensions, Instruction};
 use crate::cpu::{Cpu, TrapCause};
@@ -17,8 +21,12 @@ pub fn exe_inst(inst: &Instruction, cpu: &mut Cpu) -> Result<(), (Option<u64>, T
         Extensions::BaseI => base_i::exec(inst, cpu)?,
         Extensions::A => a_extension::exec(inst, cpu)?,
         Extensions::M => m_extension::exec(inst, cpu)?,
+        Extensions::F => f_extension::exec(inst, cpu)?,
+        Extensions::D => d_extension::exec(inst, cpu)?,
         Extensions::Priv => priv_extension::exec(inst, cpu)?,
         Extensions::Zicsr => zicsr_extension::exec(inst, cpu)?,
+        Extensions::Zifencei => zifencei_extension::exec(inst, cpu)?,
+        Extensions::H => h_extension::exec(inst, cpu)?,
         _ => panic!("not a full size instruction."),
     }
 
@@ -47,7 +47,7 @@ pub fn exec(inst: &Instruction, cpu: &mut Cpu) -> Result<(), (Option<u64>, TrapC
             let _rl = inst.imm.unwrap() & 0x1;
             let _aq = inst.imm.unwrap() >> 1 & 0x1;
             cpu.regs.write(inst.rd, cpu.bus.load32(load_addr)?);
-            cpu.reservation_set = Some(load_addr as usize);
+            cpu.bus.reservations.reserve(cpu.hartid(), load_addr);
         }
         OpecodeKind::OP_SC_W => {
             let store_addr = cpu.trans_addr(
@@ -55,13 +55,16 @@ pub fn exec(inst: &Instruction, cpu: &mut Cpu) -> Result<(), (Option<u64>, TrapC
                 TransAlign::Size32,
                 cpu.regs.read(inst.rs1),
             )?;
-            // cache value == rs1 --> store rs2 to rs1 and assign zero to rd
-            // cache value != rs1 --> ignore and assign non-zero to rd
-            if cpu.reservation_set == Some(store_addr as usize) {
+            // rs1 is in the reservation set --> store rs2 to rs1 and assign zero to rd
+            // otherwise --> ignore and assign non-zero to rd
+            if cpu
+                .bus
+                .reservations
+                .store_conditional(cpu.hartid(), store_addr)
+            {
                 let _rl = inst.imm.unwrap() & 0x1;
                 let _aq = inst.imm.unwrap() >> 1 & 0x1;
                 cpu.bus.store32(store_addr, cpu.regs.read(inst.rs2))?;
-                cpu.reservation_set = None;
                 cpu.regs.write(inst.rd, 0);
             } else {
                 cpu.regs.write(inst.rd, 1);
@@ -116,7 +119,7 @@ pub fn exec(inst: &Instruction, cpu: &mut Cpu) -> Result<(), (Option<u64>, TrapC
             let _rl = inst.imm.unwrap() & 0x1;
             let _aq = inst.imm.unwrap() >> 1 & 0x1;
             cpu.regs.write(inst.rd, cpu.bus.load64(load_addr)?);
-            cpu.reservation_set = Some(load_addr as usize);
+            cpu.bus.reservations.reserve(cpu.hartid(), load_addr);
         }
         OpecodeKind::OP_SC_D => {
             let store_addr = cpu.trans_addr(
@@ -124,13 +127,16 @@ pub fn exec(inst: &Instruction, cpu: &mut Cpu) -> Result<(), (Option<u64>, TrapC
                 TransAlign::Size64,
                 cpu.regs.read(inst.rs1),
             )?;
-            // cache value == rs1 --> store rs2 to rs1 and assign zero to rd
-            // cache value != rs1 --> ignore and assign non-zero to rd
-            if cpu.reservation_set == Some(store_addr as usize) {
+            // rs1 is in the reservation set --> store rs2 to rs1 and assign zero to rd
+            // otherwise --> ignore and assign non-zero to rd
+            if cpu
+                .bus
+                .reservations
+                .store_conditional(cpu.hartid(), store_addr)
+            {
                 let _rl = inst.imm.unwrap() & 0x1;
                 let _aq = inst.imm.unwrap() >> 1 & 0x1;
                 cpu.bus.store64(store_addr, cpu.regs.read(inst.rs2))?;
-                cpu.reservation_set = None;
                 cpu.regs.write(inst.rd, 0);
             } else {
                 cpu.regs.write(inst.rd, 1);
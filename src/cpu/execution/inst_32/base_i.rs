@@ -273,15 +273,14 @@ pub fn exec(inst: &Instruction, cpu: &mut Cpu) -> Result<(), (Option<u64>, TrapC
                 0,
                 match cpu.priv_lv() {
                     PrivilegedLevel::User => TrapCause::UmodeEcall,
+                    PrivilegedLevel::Supervisor if cpu.csrs.virt() => TrapCause::VSmodeEcall,
                     PrivilegedLevel::Supervisor => TrapCause::SmodeEcall,
                     PrivilegedLevel::Machine => TrapCause::MmodeEcall,
                     _ => panic!("cannot enviroment call in current privileged mode."),
                 },
             );
         }
-        OpecodeKind::OP_EBREAK => {
-            cpu.trap(cpu.pc(), TrapCause::Breakpoint);
-        }
+        OpecodeKind::OP_EBREAK => cpu.ebreak(),
         OpecodeKind::OP_LWU => {
             let load_addr = cpu.trans_addr(
                 TransFor::Load,
@@ -0,0 +1,215 @@
+use crate::cpu::instruction::{Instruction, OpecodeKind};
+use crate::cpu::softfloat::{self, FloatFmt};
+use crate::cpu::{Cpu, TransAlign, TransFor, TrapCause};
+use crate::Isa;
+
+const SIGN_BIT: u64 = 0x8000_0000_0000_0000;
+
+pub fn exec(inst: &Instruction, cpu: &mut Cpu) -> Result<(), (Option<u64>, TrapCause, String)> {
+    const FMT: FloatFmt = FloatFmt::Double;
+    cpu.check_fpu_enabled()?;
+
+    let rs1 = cpu.fregs.read(inst.rs1);
+    let mut flags: u64 = 0;
+
+    match inst.opc {
+        OpecodeKind::OP_FLD => {
+            let load_addr = cpu.trans_addr(
+                TransFor::Load,
+                TransAlign::Size64,
+                match *cpu.isa {
+                    Isa::Rv32 => (cpu.regs.read(inst.rs1) as i32 + inst.imm.unwrap()) as u64,
+                    Isa::Rv64 => (cpu.regs.read(inst.rs1) as i64 + inst.imm.unwrap() as i64) as u64,
+                },
+            )?;
+            cpu.fregs.write(inst.rd, cpu.bus.load64(load_addr)?);
+        }
+        OpecodeKind::OP_FSD => {
+            let store_addr = cpu.trans_addr(
+                TransFor::StoreAMO,
+                TransAlign::Size64,
+                match *cpu.isa {
+                    Isa::Rv32 => (cpu.regs.read(inst.rs1) as i32 + inst.imm.unwrap()) as u64,
+                    Isa::Rv64 => (cpu.regs.read(inst.rs1) as i64 + inst.imm.unwrap() as i64) as u64,
+                },
+            )?;
+            cpu.bus.store64(store_addr, cpu.fregs.read(inst.rs2))?;
+            return Ok(());
+        }
+        OpecodeKind::OP_FMADD_D => {
+            let rm = cpu.rounding_mode(inst.imm)?;
+            let (rs2, rs3) = (cpu.fregs.read(inst.rs2), cpu.fregs.read(inst.rs3));
+            cpu.fregs
+                .write(inst.rd, softfloat::fma(rs1, rs2, rs3, FMT, rm, &mut flags));
+        }
+        OpecodeKind::OP_FMSUB_D => {
+            let rm = cpu.rounding_mode(inst.imm)?;
+            let (rs2, rs3) = (cpu.fregs.read(inst.rs2), cpu.fregs.read(inst.rs3));
+            cpu.fregs.write(
+                inst.rd,
+                softfloat::fma(rs1, rs2, rs3 ^ SIGN_BIT, FMT, rm, &mut flags),
+            );
+        }
+        OpecodeKind::OP_FNMSUB_D => {
+            let rm = cpu.rounding_mode(inst.imm)?;
+            let (rs2, rs3) = (cpu.fregs.read(inst.rs2), cpu.fregs.read(inst.rs3));
+            cpu.fregs.write(
+                inst.rd,
+                softfloat::fma(rs1 ^ SIGN_BIT, rs2, rs3, FMT, rm, &mut flags),
+            );
+        }
+        OpecodeKind::OP_FNMADD_D => {
+            let rm = cpu.rounding_mode(inst.imm)?;
+            let (rs2, rs3) = (cpu.fregs.read(inst.rs2), cpu.fregs.read(inst.rs3));
+            cpu.fregs.write(
+                inst.rd,
+                softfloat::fma(rs1 ^ SIGN_BIT, rs2, rs3 ^ SIGN_BIT, FMT, rm, &mut flags),
+            );
+        }
+        OpecodeKind::OP_FADD_D => {
+            let rm = cpu.rounding_mode(inst.imm)?;
+            let rs2 = cpu.fregs.read(inst.rs2);
+            cpu.fregs
+                .write(inst.rd, softfloat::add(rs1, rs2, FMT, rm, &mut flags));
+        }
+        OpecodeKind::OP_FSUB_D => {
+            let rm = cpu.rounding_mode(inst.imm)?;
+            let rs2 = cpu.fregs.read(inst.rs2);
+            cpu.fregs
+                .write(inst.rd, softfloat::sub(rs1, rs2, FMT, rm, &mut flags));
+        }
+        OpecodeKind::OP_FMUL_D => {
+            let rm = cpu.rounding_mode(inst.imm)?;
+            let rs2 = cpu.fregs.read(inst.rs2);
+            cpu.fregs
+                .write(inst.rd, softfloat::mul(rs1, rs2, FMT, rm, &mut flags));
+        }
+        OpecodeKind::OP_FDIV_D => {
+            let rm = cpu.rounding_mode(inst.imm)?;
+            let rs2 = cpu.fregs.read(inst.rs2);
+            cpu.fregs
+                .write(inst.rd, softfloat::div(rs1, rs2, FMT, rm, &mut flags));
+        }
+        OpecodeKind::OP_FSQRT_D => {
+            let rm = cpu.rounding_mode(inst.imm)?;
+            cpu.fregs
+                .write(inst.rd, softfloat::sqrt(rs1, FMT, rm, &mut flags));
+        }
+        OpecodeKind::OP_FSGNJ_D => {
+            let rs2 = cpu.fregs.read(inst.rs2);
+            cpu.fregs.write(inst.rd, rs1 & !SIGN_BIT | rs2 & SIGN_BIT);
+        }
+        OpecodeKind::OP_FSGNJN_D => {
+            let rs2 = cpu.fregs.read(inst.rs2);
+            cpu.fregs.write(inst.rd, rs1 & !SIGN_BIT | !rs2 & SIGN_BIT);
+        }
+        OpecodeKind::OP_FSGNJX_D => {
+            let rs2 = cpu.fregs.read(inst.rs2);
+            cpu.fregs.write(inst.rd, rs1 ^ rs2 & SIGN_BIT);
+        }
+        OpecodeKind::OP_FMIN_D => {
+            let rs2 = cpu.fregs.read(inst.rs2);
+            cpu.fregs
+                .write(inst.rd, softfloat::min(rs1, rs2, FMT, &mut flags));
+        }
+        OpecodeKind::OP_FMAX_D => {
+            let rs2 = cpu.fregs.read(inst.rs2);
+            cpu.fregs
+                .write(inst.rd, softfloat::max(rs1, rs2, FMT, &mut flags));
+        }
+        OpecodeKind::OP_FCVT_S_D => {
+            let rm = cpu.rounding_mode(inst.imm)?;
+            cpu.fregs.write_s(
+                inst.rd,
+                softfloat::convert(rs1, FMT, FloatFmt::Single, rm, &mut flags),
+            );
+        }
+        OpecodeKind::OP_FCVT_D_S => {
+            let rm = cpu.rounding_mode(inst.imm)?;
+            let rs1 = cpu.fregs.read_s(inst.rs1);
+            cpu.fregs.write(
+                inst.rd,
+                softfloat::convert(rs1, FloatFmt::Single, FMT, rm, &mut flags),
+            );
+        }
+        OpecodeKind::OP_FEQ_D => {
+            let rs2 = cpu.fregs.read(inst.rs2);
+            let result = softfloat::eq(rs1, rs2, FMT, &mut flags);
+            cpu.regs.write(inst.rd, result as u64);
+        }
+        OpecodeKind::OP_FLT_D => {
+            let rs2 = cpu.fregs.read(inst.rs2);
+            let result = softfloat::lt(rs1, rs2, FMT, &mut flags);
+            cpu.regs.write(inst.rd, result as u64);
+        }
+        OpecodeKind::OP_FLE_D => {
+            let rs2 = cpu.fregs.read(inst.rs2);
+            let result = softfloat::le(rs1, rs2, FMT, &mut flags);
+            cpu.regs.write(inst.rd, result as u64);
+        }
+        OpecodeKind::OP_FCLASS_D => {
+            cpu.regs.write(inst.rd, softfloat::classify(rs1, FMT));
+            return Ok(());
+        }
+        OpecodeKind::OP_FCVT_W_D => {
+            let rm = cpu.rounding_mode(inst.imm)?;
+            let result = softfloat::to_int(rs1, FMT, 32, true, rm, &mut flags);
+            cpu.regs.write(inst.rd, result as i32 as i64 as u64);
+        }
+        OpecodeKind::OP_FCVT_WU_D => {
+            let rm = cpu.rounding_mode(inst.imm)?;
+            let result = softfloat::to_int(rs1, FMT, 32, false, rm, &mut flags);
+            cpu.regs.write(inst.rd, result as i32 as i64 as u64);
+        }
+        OpecodeKind::OP_FCVT_D_W => {
+            let rm = cpu.rounding_mode(inst.imm)?;
+            let src = cpu.regs.read(inst.rs1) as i32 as i64 as u64;
+            cpu.fregs
+                .write(inst.rd, softfloat::from_int(src, true, FMT, rm, &mut flags));
+        }
+        OpecodeKind::OP_FCVT_D_WU => {
+            let rm = cpu.rounding_mode(inst.imm)?;
+            let src = cpu.regs.read(inst.rs1) as u32 as u64;
+            cpu.fregs.write(
+                inst.rd,
+                softfloat::from_int(src, false, FMT, rm, &mut flags),
+            );
+        }
+        OpecodeKind::OP_FCVT_L_D => {
+            let rm = cpu.rounding_mode(inst.imm)?;
+            let result = softfloat::to_int(rs1, FMT, 64, true, rm, &mut flags);
+            cpu.regs.write(inst.rd, result);
+        }
+        OpecodeKind::OP_FCVT_LU_D => {
+            let rm = cpu.rounding_mode(inst.imm)?;
+            let result = softfloat::to_int(rs1, FMT, 64, false, rm, &mut flags);
+            cpu.regs.write(inst.rd, result);
+        }
+        OpecodeKind::OP_FMV_X_D => {
+            cpu.regs.write(inst.rd, rs1);
+            return Ok(());
+        }
+        OpecodeKind::OP_FCVT_D_L => {
+            let rm = cpu.rounding_mode(inst.imm)?;
+            let src = cpu.regs.read(inst.rs1);
+            cpu.fregs
+                .write(inst.rd, softfloat::from_int(src, true, FMT, rm, &mut flags));
+        }
+        OpecodeKind::OP_FCVT_D_LU => {
+            let rm = cpu.rounding_mode(inst.imm)?;
+            let src = cpu.regs.read(inst.rs1);
+            cpu.fregs.write(
+                inst.rd,
+                softfloat::from_int(src, false, FMT, rm, &mut flags),
+            );
+        }
+        OpecodeKind::OP_FMV_D_X => {
+            cpu.fregs.write(inst.rd, cpu.regs.read(inst.rs1));
+        }
+        _ => panic!("not a D extension"),
+    }
+
+    cpu.update_fflags(flags);
+
+    Ok(())
+}
@@ -0,0 +1,203 @@
+use crate::cpu::instruction::{Instruction, OpecodeKind};
+use crate::cpu::softfloat::{self, FloatFmt};
+use crate::cpu::{Cpu, TransAlign, TransFor, TrapCause};
+use crate::Isa;
+
+const SIGN_BIT: u64 = 0x8000_0000;
+
+pub fn exec(inst: &Instruction, cpu: &mut Cpu) -> Result<(), (Option<u64>, TrapCause, String)> {
+    const FMT: FloatFmt = FloatFmt::Single;
+    cpu.check_fpu_enabled()?;
+
+    let rs1 = cpu.fregs.read_s(inst.rs1);
+    let mut flags: u64 = 0;
+
+    match inst.opc {
+        OpecodeKind::OP_FLW => {
+            let load_addr = cpu.trans_addr(
+                TransFor::Load,
+                TransAlign::Size32,
+                match *cpu.isa {
+                    Isa::Rv32 => (cpu.regs.read(inst.rs1) as i32 + inst.imm.unwrap()) as u64,
+                    Isa::Rv64 => (cpu.regs.read(inst.rs1) as i64 + inst.imm.unwrap() as i64) as u64,
+                },
+            )?;
+            cpu.fregs.write_s(inst.rd, cpu.bus.load_u32(load_addr)?);
+        }
+        OpecodeKind::OP_FSW => {
+            let store_addr = cpu.trans_addr(
+                TransFor::StoreAMO,
+                TransAlign::Size32,
+                match *cpu.isa {
+                    Isa::Rv32 => (cpu.regs.read(inst.rs1) as i32 + inst.imm.unwrap()) as u64,
+                    Isa::Rv64 => (cpu.regs.read(inst.rs1) as i64 + inst.imm.unwrap() as i64) as u64,
+                },
+            )?;
+            // store the raw bits even if it isn't NaN-boxed
+            cpu.bus.store32(store_addr, cpu.fregs.read(inst.rs2))?;
+            return Ok(());
+        }
+        OpecodeKind::OP_FMADD_S => {
+            let rm = cpu.rounding_mode(inst.imm)?;
+            let (rs2, rs3) = (cpu.fregs.read_s(inst.rs2), cpu.fregs.read_s(inst.rs3));
+            cpu.fregs
+                .write_s(inst.rd, softfloat::fma(rs1, rs2, rs3, FMT, rm, &mut flags));
+        }
+        OpecodeKind::OP_FMSUB_S => {
+            let rm = cpu.rounding_mode(inst.imm)?;
+            let (rs2, rs3) = (cpu.fregs.read_s(inst.rs2), cpu.fregs.read_s(inst.rs3));
+            cpu.fregs.write_s(
+                inst.rd,
+                softfloat::fma(rs1, rs2, rs3 ^ SIGN_BIT, FMT, rm, &mut flags),
+            );
+        }
+        OpecodeKind::OP_FNMSUB_S => {
+            let rm = cpu.rounding_mode(inst.imm)?;
+            let (rs2, rs3) = (cpu.fregs.read_s(inst.rs2), cpu.fregs.read_s(inst.rs3));
+            cpu.fregs.write_s(
+                inst.rd,
+                softfloat::fma(rs1 ^ SIGN_BIT, rs2, rs3, FMT, rm, &mut flags),
+            );
+        }
+        OpecodeKind::OP_FNMADD_S => {
+            let rm = cpu.rounding_mode(inst.imm)?;
+            let (rs2, rs3) = (cpu.fregs.read_s(inst.rs2), cpu.fregs.read_s(inst.rs3));
+            cpu.fregs.write_s(
+                inst.rd,
+                softfloat::fma(rs1 ^ SIGN_BIT, rs2, rs3 ^ SIGN_BIT, FMT, rm, &mut flags),
+            );
+        }
+        OpecodeKind::OP_FADD_S => {
+            let rm = cpu.rounding_mode(inst.imm)?;
+            let rs2 = cpu.fregs.read_s(inst.rs2);
+            cpu.fregs
+                .write_s(inst.rd, softfloat::add(rs1, rs2, FMT, rm, &mut flags));
+        }
+        OpecodeKind::OP_FSUB_S => {
+            let rm = cpu.rounding_mode(inst.imm)?;
+            let rs2 = cpu.fregs.read_s(inst.rs2);
+            cpu.fregs
+                .write_s(inst.rd, softfloat::sub(rs1, rs2, FMT, rm, &mut flags));
+        }
+        OpecodeKind::OP_FMUL_S => {
+            let rm = cpu.rounding_mode(inst.imm)?;
+            let rs2 = cpu.fregs.read_s(inst.rs2);
+            cpu.fregs
+                .write_s(inst.rd, softfloat::mul(rs1, rs2, FMT, rm, &mut flags));
+        }
+        OpecodeKind::OP_FDIV_S => {
+            let rm = cpu.rounding_mode(inst.imm)?;
+            let rs2 = cpu.fregs.read_s(inst.rs2);
+            cpu.fregs
+                .write_s(inst.rd, softfloat::div(rs1, rs2, FMT, rm, &mut flags));
+        }
+        OpecodeKind::OP_FSQRT_S => {
+            let rm = cpu.rounding_mode(inst.imm)?;
+            cpu.fregs
+                .write_s(inst.rd, softfloat::sqrt(rs1, FMT, rm, &mut flags));
+        }
+        OpecodeKind::OP_FSGNJ_S => {
+            let rs2 = cpu.fregs.read_s(inst.rs2);
+            cpu.fregs.write_s(inst.rd, rs1 & !SIGN_BIT | rs2 & SIGN_BIT);
+        }
+        OpecodeKind::OP_FSGNJN_S => {
+            let rs2 = cpu.fregs.read_s(inst.rs2);
+            cpu.fregs
+                .write_s(inst.rd, rs1 & !SIGN_BIT | !rs2 & SIGN_BIT);
+        }
+        OpecodeKind::OP_FSGNJX_S => {
+            let rs2 = cpu.fregs.read_s(inst.rs2);
+            cpu.fregs.write_s(inst.rd, rs1 ^ rs2 & SIGN_BIT);
+        }
+        OpecodeKind::OP_FMIN_S => {
+            let rs2 = cpu.fregs.read_s(inst.rs2);
+            cpu.fregs
+                .write_s(inst.rd, softfloat::min(rs1, rs2, FMT, &mut flags));
+        }
+        OpecodeKind::OP_FMAX_S => {
+            let rs2 = cpu.fregs.read_s(inst.rs2);
+            cpu.fregs
+                .write_s(inst.rd, softfloat::max(rs1, rs2, FMT, &mut flags));
+        }
+        OpecodeKind::OP_FCVT_W_S => {
+            let rm = cpu.rounding_mode(inst.imm)?;
+            let result = softfloat::to_int(rs1, FMT, 32, true, rm, &mut flags);
+            cpu.regs.write(inst.rd, result as i32 as i64 as u64);
+        }
+        OpecodeKind::OP_FCVT_WU_S => {
+            let rm = cpu.rounding_mode(inst.imm)?;
+            let result = softfloat::to_int(rs1, FMT, 32, false, rm, &mut flags);
+            cpu.regs.write(inst.rd, result as i32 as i64 as u64);
+        }
+        OpecodeKind::OP_FCVT_L_S => {
+            let rm = cpu.rounding_mode(inst.imm)?;
+            let result = softfloat::to_int(rs1, FMT, 64, true, rm, &mut flags);
+            cpu.regs.write(inst.rd, result);
+        }
+        OpecodeKind::OP_FCVT_LU_S => {
+            let rm = cpu.rounding_mode(inst.imm)?;
+            let result = softfloat::to_int(rs1, FMT, 64, false, rm, &mut flags);
+            cpu.regs.write(inst.rd, result);
+        }
+        OpecodeKind::OP_FMV_X_W => {
+            let raw = cpu.fregs.read(inst.rs1);
+            cpu.regs.write(inst.rd, raw as i32 as i64 as u64);
+            return Ok(());
+        }
+        OpecodeKind::OP_FEQ_S => {
+            let rs2 = cpu.fregs.read_s(inst.rs2);
+            let result = softfloat::eq(rs1, rs2, FMT, &mut flags);
+            cpu.regs.write(inst.rd, result as u64);
+        }
+        OpecodeKind::OP_FLT_S => {
+            let rs2 = cpu.fregs.read_s(inst.rs2);
+            let result = softfloat::lt(rs1, rs2, FMT, &mut flags);
+            cpu.regs.write(inst.rd, result as u64);
+        }
+        OpecodeKind::OP_FLE_S => {
+            let rs2 = cpu.fregs.read_s(inst.rs2);
+            let result = softfloat::le(rs1, rs2, FMT, &mut flags);
+            cpu.regs.write(inst.rd, result as u64);
+        }
+        OpecodeKind::OP_FCLASS_S => {
+            cpu.regs.write(inst.rd, softfloat::classify(rs1, FMT));
+            return Ok(());
+        }
+        OpecodeKind::OP_FCVT_S_W => {
+            let rm = cpu.rounding_mode(inst.imm)?;
+            let src = cpu.regs.read(inst.rs1) as i32 as i64 as u64;
+            cpu.fregs
+                .write_s(inst.rd, softfloat::from_int(src, true, FMT, rm, &mut flags));
+        }
+        OpecodeKind::OP_FCVT_S_WU => {
+            let rm = cpu.rounding_mode(inst.imm)?;
+            let src = cpu.regs.read(inst.rs1) as u32 as u64;
+            cpu.fregs.write_s(
+                inst.rd,
+                softfloat::from_int(src, false, FMT, rm, &mut flags),
+            );
+        }
+        OpecodeKind::OP_FCVT_S_L => {
+            let rm = cpu.rounding_mode(inst.imm)?;
+            let src = cpu.regs.read(inst.rs1);
+            cpu.fregs
+                .write_s(inst.rd, softfloat::from_int(src, true, FMT, rm, &mut flags));
+        }
+        OpecodeKind::OP_FCVT_S_LU => {
+            let rm = cpu.rounding_mode(inst.imm)?;
+            let src = cpu.regs.read(inst.rs1);
+            cpu.fregs.write_s(
+                inst.rd,
+                softfloat::from_int(src, false, FMT, rm, &mut flags),
+            );
+        }
+        OpecodeKind::OP_FMV_W_X => {
+            cpu.fregs.write_s(inst.rd, cpu.regs.read(inst.rs1));
+        }
+        _ => panic!("not an F extension"),
+    }
+
+    cpu.update_fflags(flags);
+
+    Ok(())
+}
@@ -0,0 +1,124 @@
+use crate::cpu::csr::HSTATUS_HU;
+use crate::cpu::instruction::{Instruction, OpecodeKind};
+use crate::cpu::{Cpu, PrivilegedLevel, TransAlign, TransFor, TrapCause, Xstatus};
+
+// hlv, hlvx and hsv access memory as the guest would in hstatus.SPVP
+fn guest_addr(
+    cpu: &mut Cpu,
+    inst: &Instruction,
+    purpose: TransFor,
+    align: TransAlign,
+) -> Result<u64, (Option<u64>, TrapCause, String)> {
+    let addr = cpu.regs.read(inst.rs1);
+    let spvp = cpu.csrs.spvp();
+    cpu.trans_addr_as(purpose, align, addr, spvp, true)
+}
+
+pub fn exec(inst: &Instruction, cpu: &mut Cpu) -> Result<(), (Option<u64>, TrapCause, String)> {
+    let is_fence = matches!(
+        inst.opc,
+        OpecodeKind::OP_HFENCE_VVMA | OpecodeKind::OP_HFENCE_GVMA
+    );
+    if !cpu.csrs.hypervisor() {
+        return Err((
+            cpu.inst_bits()?,
+            TrapCause::IllegalInst,
+            "the hypervisor extension is not implemented".to_string(),
+        ));
+    }
+    if cpu.csrs.virt() {
+        return Err((
+            cpu.inst_bits()?,
+            TrapCause::VirtualInst,
+            format!("exec {} in a guest", inst.opc_to_string()),
+        ));
+    }
+    // U-mode can use hlv and hsv when hstatus.HU is set, but never the fences
+    if cpu.priv_lv() == PrivilegedLevel::User && (is_fence || !cpu.csrs.hstatus(HSTATUS_HU)) {
+        return Err((
+            cpu.inst_bits()?,
+            TrapCause::IllegalInst,
+            format!("exec {} in U-mode", inst.opc_to_string()),
+        ));
+    }
+
+    match inst.opc {
+        OpecodeKind::OP_HFENCE_VVMA => {
+            // rs1 == x0 and rs2 == x0 select all addresses and all address spaces
+            let vaddr = inst.rs1.filter(|&r| r != 0).map(|r| cpu.regs.read(Some(r)));
+            let asid = inst.rs2.filter(|&r| r != 0).map(|r| cpu.regs.read(Some(r)));
+            cpu.mmu.flush_tlb(vaddr, asid, true);
+        }
+        OpecodeKind::OP_HFENCE_GVMA => {
+            if cpu.priv_lv() == PrivilegedLevel::Supervisor
+                && cpu
+                    .csrs
+                    .read_xstatus(PrivilegedLevel::Machine, Xstatus::TVM)
+                    == 1
+            {
+                return Err((
+                    cpu.inst_bits()?,
+                    TrapCause::IllegalInst,
+                    "exec hfence.gvma but mstatus.TVM == 1".to_string(),
+                ));
+            }
+            let vmid = inst.rs2.filter(|&r| r != 0).map(|r| cpu.regs.read(Some(r)));
+            cpu.mmu.flush_guest_tlb(vmid);
+        }
+        OpecodeKind::OP_HLV_B => {
+            let load_addr = guest_addr(cpu, inst, TransFor::Load, TransAlign::Size8)?;
+            cpu.regs.write(inst.rd, cpu.bus.load8(load_addr)?);
+        }
+        OpecodeKind::OP_HLV_BU => {
+            let load_addr = guest_addr(cpu, inst, TransFor::Load, TransAlign::Size8)?;
+            cpu.regs.write(inst.rd, cpu.bus.load_u8(load_addr)?);
+        }
+        OpecodeKind::OP_HLV_H => {
+            let load_addr = guest_addr(cpu, inst, TransFor::Load, TransAlign::Size16)?;
+            cpu.regs.write(inst.rd, cpu.bus.load16(load_addr)?);
+        }
+        OpecodeKind::OP_HLV_HU => {
+            let load_addr = guest_addr(cpu, inst, TransFor::Load, TransAlign::Size16)?;
+            cpu.regs.write(inst.rd, cpu.bus.load_u16(load_addr)?);
+        }
+        OpecodeKind::OP_HLVX_HU => {
+            let load_addr = guest_addr(cpu, inst, TransFor::LoadExec, TransAlign::Size16)?;
+            cpu.regs.write(inst.rd, cpu.bus.load_u16(load_addr)?);
+        }
+        OpecodeKind::OP_HLV_W => {
+            let load_addr = guest_addr(cpu, inst, TransFor::Load, TransAlign::Size32)?;
+            cpu.regs.write(inst.rd, cpu.bus.load32(load_addr)?);
+        }
+        OpecodeKind::OP_HLV_WU => {
+            let load_addr = guest_addr(cpu, inst, TransFor::Load, TransAlign::Size32)?;
+            cpu.regs.write(inst.rd, cpu.bus.load_u32(load_addr)?);
+        }
+        OpecodeKind::OP_HLVX_WU => {
+            let load_addr = guest_addr(cpu, inst, TransFor::LoadExec, TransAlign::Size32)?;
+            cpu.regs.write(inst.rd, cpu.bus.load_u32(load_addr)?);
+        }
+        OpecodeKind::OP_HLV_D => {
+            let load_addr = guest_addr(cpu, inst, TransFor::Load, TransAlign::Size64)?;
+            cpu.regs.write(inst.rd, cpu.bus.load64(load_addr)?);
+        }
+        OpecodeKind::OP_HSV_B => {
+            let store_addr = guest_addr(cpu, inst, TransFor::StoreAMO, TransAlign::Size8)?;
+            cpu.bus.store8(store_addr, cpu.regs.read(inst.rs2))?;
+        }
+        OpecodeKind::OP_HSV_H => {
+            let store_addr = guest_addr(cpu, inst, TransFor::StoreAMO, TransAlign::Size16)?;
+            cpu.bus.store16(store_addr, cpu.regs.read(inst.rs2))?;
+        }
+        OpecodeKind::OP_HSV_W => {
+            let store_addr = guest_addr(cpu, inst, TransFor::StoreAMO, TransAlign::Size32)?;
+            cpu.bus.store32(store_addr, cpu.regs.read(inst.rs2))?;
+        }
+        OpecodeKind::OP_HSV_D => {
+            let store_addr = guest_addr(cpu, inst, TransFor::StoreAMO, TransAlign::Size64)?;
+            cpu.bus.store64(store_addr, cpu.regs.read(inst.rs2))?;
+        }
+        _ => panic!("not an H extension"),
+    }
+
+    Ok(())
+}
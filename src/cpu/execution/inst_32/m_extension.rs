@@ -84,11 +84,8 @@ pub fn exec(inst: &Instruction, cpu: &mut Cpu) -> Result<(), (Option<u64>, TrapC
             }
         }
         OpecodeKind::OP_DIVU => {
-            if rs2 == 0 {
-                cpu.regs.write(inst.rd, (2i32.pow(32) - 1) as u64);
-            } else {
-                cpu.regs.write(inst.rd, rs1 / rs2);
-            }
+            cpu.regs
+                .write(inst.rd, rs1.checked_div(rs2).unwrap_or(u64::MAX));
         }
         OpecodeKind::OP_REM => {
             if rs2 == 0 {
@@ -134,12 +131,11 @@ pub fn exec(inst: &Instruction, cpu: &mut Cpu) -> Result<(), (Option<u64>, TrapC
             }
         }
         OpecodeKind::OP_DIVUW => {
-            if rs2 == 0 {
-                cpu.regs.write(inst.rd, (2i32.pow(32) - 1) as u64);
-            } else {
-                cpu.regs
-                    .write(inst.rd, (rs1 as u32 / rs2 as u32) as i32 as u64);
-            }
+            // the quotient is sign-extended, so dividing by zero sets every bit
+            let quotient = (rs1 as u32)
+                .checked_div(rs2 as u32)
+                .map_or(u64::MAX, |q| q as i32 as u64);
+            cpu.regs.write(inst.rd, quotient);
         }
         OpecodeKind::OP_REMUW => {
             if rs2 == 0 {
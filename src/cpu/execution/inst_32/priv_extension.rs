@@ -1,10 +1,26 @@
-use crate::cpu::csr::{CSRname, Xstatus};
+use crate::cpu::csr::{CSRname, Xstatus, HSTATUS_VTSR, HSTATUS_VTVM, HSTATUS_VTW};
 use crate::cpu::instruction::{Instruction, OpecodeKind};
 use crate::cpu::{Cpu, PrivilegedLevel, TrapCause};
 use crate::log;
 
 pub fn exec(inst: &Instruction, cpu: &mut Cpu) -> Result<(), (Option<u64>, TrapCause, String)> {
+    let virtual_inst =
+        |cpu: &mut Cpu, msg: &str| Err((cpu.inst_bits()?, TrapCause::VirtualInst, msg.to_string()));
+    let virt = cpu.csrs.virt();
+    let vu_mode = virt && cpu.priv_lv() == PrivilegedLevel::User;
+
     match inst.opc {
+        // sret in VS-mode returns within the guest
+        OpecodeKind::OP_SRET if virt => {
+            if vu_mode || cpu.csrs.hstatus(HSTATUS_VTSR) {
+                return virtual_inst(cpu, "exec sret in VU-mode or with hstatus.VTSR == 1");
+            }
+            let new_priv = cpu.csrs.sret_vsstatus();
+            let new_pc = cpu.csrs.read(CSRname::vsepc.wrap())?;
+            cpu.update_pc(new_pc);
+            cpu.set_priv_lv(new_priv);
+            log::debugln!("priv: {:?}", cpu.priv_lv());
+        }
         OpecodeKind::OP_SRET => {
             let require_priv = if cpu
                 .csrs
@@ -47,6 +63,9 @@ pub fn exec(inst: &Instruction, cpu: &mut Cpu) -> Result<(), (Option<u64>, TrapC
             cpu.csrs
                 .write_xstatus(PrivilegedLevel::Supervisor, Xstatus::SPP, 0b00); // ssatus.SPP = 0
 
+            // hstatus.SPV == 1 returns into the guest
+            cpu.csrs.sret_hstatus();
+
             cpu.set_priv_lv(match new_priv {
                 0b00 => PrivilegedLevel::User,
                 0b01 => PrivilegedLevel::Supervisor,
@@ -75,19 +94,42 @@ pub fn exec(inst: &Instruction, cpu: &mut Cpu) -> Result<(), (Option<u64>, TrapC
                 .write_xstatus(PrivilegedLevel::Machine, Xstatus::MPIE, 0b1); // msatus.MPIE = 1
             cpu.csrs
                 .write_xstatus(PrivilegedLevel::Machine, Xstatus::MPP, 0b00); // msatus.MPP = 0
+            cpu.csrs.mret_tcontrol();
 
-            cpu.set_priv_lv(match new_priv {
+            let new_priv = match new_priv {
                 0b00 => PrivilegedLevel::User,
                 0b01 => PrivilegedLevel::Supervisor,
                 0b10 => panic!("PrivilegedLevel 0x3 is Reserved."),
                 0b11 => PrivilegedLevel::Machine,
                 _ => panic!("invalid PrivilegedLevel"),
-            });
+            };
+            // mstatus.MPV == 1 returns into the guest
+            cpu.csrs.mret_mstatus_mpv(new_priv);
+            cpu.set_priv_lv(new_priv);
             log::debugln!("priv: {:?}", cpu.priv_lv());
         }
-        OpecodeKind::OP_WFI => { /* nop */ }
+        OpecodeKind::OP_WFI => {
+            if vu_mode || virt && cpu.csrs.hstatus(HSTATUS_VTW) {
+                return virtual_inst(cpu, "exec wfi in VU-mode or with hstatus.VTW == 1");
+            }
+            /* nop */
+        }
+        OpecodeKind::OP_DRET => {
+            if !cpu.debug_mode() {
+                return Err((
+                    cpu.inst_bits()?,
+                    TrapCause::IllegalInst,
+                    "exec dret outside debug mode".to_string(),
+                ));
+            }
+            cpu.leave_debug_mode();
+        }
         OpecodeKind::OP_SFENCE_VMA => {
-            if cpu.priv_lv() == PrivilegedLevel::Supervisor
+            if vu_mode || virt && cpu.csrs.hstatus(HSTATUS_VTVM) {
+                return virtual_inst(cpu, "exec sfence.vma in VU-mode or with hstatus.VTVM == 1");
+            }
+            if !virt
+                && cpu.priv_lv() == PrivilegedLevel::Supervisor
                 && cpu
                     .csrs
                     .read_xstatus(PrivilegedLevel::Machine, Xstatus::TVM)
@@ -95,6 +137,12 @@ pub fn exec(inst: &Instruction, cpu: &mut Cpu) -> Result<(), (Option<u64>, TrapC
             {
                 let illegal_inst = cpu.bus.load32(cpu.pc())?;
                 cpu.trap(illegal_inst, TrapCause::IllegalInst);
+            } else {
+                // rs1 == x0 and rs2 == x0 select all addresses and all address spaces
+                let vaddr = inst.rs1.filter(|&r| r != 0).map(|r| cpu.regs.read(Some(r)));
+                let asid = inst.rs2.filter(|&r| r != 0).map(|r| cpu.regs.read(Some(r)));
+                // in a guest it flushes the VS-stage translations like hfence.vvma
+                cpu.mmu.flush_tlb(vaddr, asid, virt);
             }
         }
         _ => panic!("not an privileged extension"),
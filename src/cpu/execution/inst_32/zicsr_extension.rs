@@ -1,205 +1,250 @@
+use crate::cpu::csr::HSTATUS_VTVM;
 use crate::cpu::instruction::{Instruction, OpecodeKind};
-use crate::cpu::{CSRname, Cpu, PrivilegedLevel, TransAlign, TransFor, TrapCause, Xstatus};
+use crate::cpu::{CSRname, Cpu, PrivilegedLevel, TrapCause, Xstatus};
 
-fn check_accessible(cpu: &mut Cpu, dist: usize) -> Result<(), (Option<u64>, TrapCause, String)> {
-    let inst_addr = cpu.trans_addr(TransFor::Fetch, TransAlign::Size8, cpu.pc())?;
-    let invalid_instruction = Some(cpu.bus.load_u32(inst_addr).expect("get instruction failed"));
+// write is false for CSRRS/CSRRC with x0 and CSRRSI/CSRRCI with 0, which only read
+fn check_accessible(
+    cpu: &mut Cpu,
+    dist: usize,
+    write: bool,
+) -> Result<(), (Option<u64>, TrapCause, String)> {
+    let invalid_instruction = cpu.inst_bits()?;
+    let illegal = |msg: String| Err((invalid_instruction, TrapCause::IllegalInst, msg));
+    // what HS-mode could access raises a virtual instruction exception in a guest
+    let virtual_inst = |msg: String| Err((invalid_instruction, TrapCause::VirtualInst, msg));
+    let virt = cpu.csrs.virt();
 
-    if dist >= 4096 {
-        return Err((
-            None,
-            TrapCause::IllegalInst,
-            format!("csr size is 4096, but you accessed {dist:x}"),
+    let Some(desc) = cpu.csrs.describe(dist) else {
+        return illegal(format!("unknown CSR number: {dist:x}"));
+    };
+    if cpu.priv_lv() < desc.priv_lv && !(virt && desc.priv_lv == PrivilegedLevel::Supervisor) {
+        return illegal(format!(
+            "{} needs {:?} mode but accessed in {:?} mode",
+            desc.name(dist),
+            desc.priv_lv,
+            cpu.priv_lv()
+        ));
+    }
+    if desc.debug_only && !cpu.csrs.debug_mode() {
+        return illegal(format!(
+            "{} is only accessible in debug mode",
+            desc.name(dist)
+        ));
+    }
+    if write && desc.read_only {
+        return illegal(format!("{} is read-only", desc.name(dist)));
+    }
+    // the hypervisor and VS CSRs (address bits 9:8 are 0b10) belong to HS-mode
+    if virt && (cpu.priv_lv() < desc.priv_lv || dist >> 8 & 0x3 == 0b10) {
+        return virtual_inst(format!(
+            "{} accessed in virtual {:?} mode",
+            desc.name(dist),
+            cpu.priv_lv()
+        ));
+    }
+
+    // fflags, frm and fcsr are not accessible while FPU is off
+    if (0x001..=0x003).contains(&dist)
+        && (cpu.csrs.read_xstatus(PrivilegedLevel::Machine, Xstatus::FS) == 0
+            || !cpu.csrs.guest_fpu_enabled())
+    {
+        return illegal(format!(
+            "mstatus.FS or vsstatus.FS == 0 but accessed {}",
+            desc.name(dist)
         ));
     }
 
-    match cpu.priv_lv() {
-        PrivilegedLevel::User => {
-            if (0x100..=0x180).contains(&dist) || (0x300..=0x344).contains(&dist) {
-                return Err((
-                    invalid_instruction,
-                    TrapCause::IllegalInst,
-                    format!("You are in User mode but accessed {dist:x}"),
-                ));
-            }
-
-            if (0xc00..=0xc1f).contains(&dist) {
-                let mctren = cpu.csrs.read(CSRname::mcounteren.wrap())?;
-                if mctren >> (dist - 0xc00) & 0x1 == 0 {
-                    return Err((
-                        invalid_instruction,
-                        TrapCause::IllegalInst,
-                        "mcounteren bit is cleared, but attempt reading".to_string(),
-                    ));
-                }
-                let sctren = cpu.csrs.read(CSRname::scounteren.wrap())?;
-                if sctren >> (dist - 0xc00) & 0x1 == 0 {
-                    return Err((
-                        invalid_instruction,
-                        TrapCause::IllegalInst,
-                        "scounteren bit is cleared, but attempt reading".to_string(),
-                    ));
-                }
-            }
+    // below M-mode the counters are only readable when mcounteren (and
+    // hcounteren for a guest, scounteren for U-mode) hand them over
+    let counter = (0xc00..=0xc1f).contains(&dist) || (0xc80..=0xc9f).contains(&dist);
+    if counter && cpu.priv_lv() != PrivilegedLevel::Machine {
+        let mctren = cpu.csrs.read(CSRname::mcounteren.wrap())?;
+        if mctren >> (dist & 0x1f) & 0x1 == 0 {
+            return illegal(format!(
+                "mcounteren bit is cleared, but read {}",
+                desc.name(dist)
+            ));
         }
-        PrivilegedLevel::Supervisor => {
-            if (0x300..=0x344).contains(&dist) {
-                return Err((
-                    invalid_instruction,
-                    TrapCause::IllegalInst,
-                    format!("You are in Supervisor mode but accessed {dist:x}"),
-                ));
-            }
-
-            if (0xc00..=0xc1f).contains(&dist) {
-                let mctren = cpu.csrs.read(CSRname::mcounteren.wrap())?;
-                if mctren >> (dist - 0xc00) & 0x1 == 0 {
-                    return Err((
-                        invalid_instruction,
-                        TrapCause::IllegalInst,
-                        "mcounteren bit is cleared, but attempt reading".to_string(),
-                    ));
-                }
-            }
-
-            if dist == CSRname::satp as usize
-                && cpu
-                    .csrs
-                    .read_xstatus(PrivilegedLevel::Machine, Xstatus::TVM)
-                    == 1
-            {
-                return Err((
-                    invalid_instruction,
-                    TrapCause::IllegalInst,
-                    "mstatus.TVM == 1 but accessed satp".to_string(),
-                ));
-            }
+    }
+    if counter && virt {
+        let hctren = cpu.csrs.read(CSRname::hcounteren.wrap())?;
+        if hctren >> (dist & 0x1f) & 0x1 == 0 {
+            return virtual_inst(format!(
+                "hcounteren bit is cleared, but read {}",
+                desc.name(dist)
+            ));
+        }
+    }
+    if counter && cpu.priv_lv() == PrivilegedLevel::User {
+        let sctren = cpu.csrs.read(CSRname::scounteren.wrap())?;
+        if sctren >> (dist & 0x1f) & 0x1 == 0 {
+            let msg = format!("scounteren bit is cleared, but read {}", desc.name(dist));
+            return if virt {
+                virtual_inst(msg)
+            } else {
+                illegal(msg)
+            };
         }
-        _ => (),
     }
 
-    // riscv-privileged-20190608.pdf p7
-    let csrs_ranges = vec![
-        0x000..=0x0ff,
-        0x400..=0x4ff,
-        0x800..=0x8ff,
-        0xc00..=0xc7f,
-        0xc80..=0xcbf,
-        0xcc0..=0xcff,
-        0x100..=0x1ff,
-        0x500..=0x57f,
-        0x580..=0x5bf,
-        0x5c0..=0x5ff,
-        0x900..=0x97f,
-        0x980..=0x9bf,
-        0x9c0..=0x9ff,
-        0xd00..=0xd7f,
-        //0xd80..=0xdbf,
-        0xdc0..=0xdff,
-        0x200..=0x2ff,
-        0x600..=0x67f,
-        0x680..=0x6bf,
-        0x6c0..=0x6ff,
-        0xa00..=0xa7f,
-        0xa80..=0xabf,
-        0xac0..=0xaff,
-        0xe00..=0xe7f,
-        0xe80..=0xebf,
-        0xec0..=0xeff,
-        //0x300..=0x3ff,
-        0x300..=0x30b, // disable mstateen0(0x30c)
-        0x30d..=0x3bf, // disable pmpaddr16~
-        0x700..=0x77f,
-        0x780..=0x79f,
-        0x7a0..=0x7af,
-        0x7b0..=0x7bf,
-        0x7c0..=0x7ff,
-        0xb00..=0xb7f,
-        0xb80..=0xbbf,
-        0xbc0..=0xbff,
-        0xf00..=0xf7f,
-        //0xf80..=0xfbf,
-        0xfc0..=0xfff,
-    ];
-
-    if csrs_ranges.iter().any(|x| x.contains(&dist)) {
-        match dist {
-            // == depends on access type ==
-            0xc00 => match cpu.priv_lv() {
-                PrivilegedLevel::Machine => Ok(()),
-                _ => Err((
-                    None,
-                    TrapCause::IllegalInst,
-                    format!("writing to cycle is not allowed: {dist:x}"),
-                )),
-            },
-            // == depends on privilege ==
-            // scounteren(0x106) only allow higher
-            0x106 => {
-                if cpu.priv_lv() >= PrivilegedLevel::Supervisor {
-                    Ok(())
-                } else {
-                    Err((
-                        None,
-                        TrapCause::IllegalInst,
-                        format!("unknown CSR number: {dist:x}"),
-                    ))
-                }
-            }
-            // stimecmp(0x14d) only supervisor
-            0x14d => match cpu.priv_lv() {
-                PrivilegedLevel::Supervisor => Ok(()),
-                _ => Err((
-                    None,
-                    TrapCause::IllegalInst,
-                    format!("unknown CSR number: {dist:x}"),
-                )),
-            },
-            _ => Ok(()),
+    if cpu.priv_lv() == PrivilegedLevel::Supervisor && virt {
+        // henvcfg.STCE is hardwired to 0, there is no vstimecmp
+        if dist == CSRname::stimecmp as usize || dist == CSRname::stimecmph as usize {
+            return virtual_inst("accessed stimecmp in VS-mode".to_string());
+        }
+        if dist == CSRname::satp as usize && cpu.csrs.hstatus(HSTATUS_VTVM) {
+            return virtual_inst("hstatus.VTVM == 1 but accessed satp".to_string());
+        }
+    } else if cpu.priv_lv() == PrivilegedLevel::Supervisor {
+        // Sstc hands stimecmp over along with the time counter
+        let stimecmp = dist == CSRname::stimecmp as usize || dist == CSRname::stimecmph as usize;
+        if stimecmp
+            && (!cpu.csrs.stce() || cpu.csrs.read(CSRname::mcounteren.wrap())? >> 1 & 0x1 == 0)
+        {
+            return illegal(
+                "menvcfg.STCE or mcounteren.TM is cleared, but accessed stimecmp".to_string(),
+            );
+        }
+
+        if dist == CSRname::satp as usize
+            && cpu
+                .csrs
+                .read_xstatus(PrivilegedLevel::Machine, Xstatus::TVM)
+                == 1
+        {
+            return illegal("mstatus.TVM == 1 but accessed satp".to_string());
         }
-    } else {
-        // out of range
-        Err((
-            None,
-            TrapCause::IllegalInst,
-            format!("unknown CSR number: {dist:x}"),
-        ))
     }
+
+    Ok(())
+}
+
+// below M-mode scountovf only shows the counters mcounteren hands over
+fn read_csr(cpu: &Cpu, dist: Option<usize>) -> Result<u64, (Option<u64>, TrapCause, String)> {
+    let value = cpu.csrs.read(dist)?;
+    if dist == CSRname::scountovf.wrap() && cpu.priv_lv() != PrivilegedLevel::Machine {
+        return Ok(value & cpu.csrs.read(CSRname::mcounteren.wrap())?);
+    }
+    Ok(value)
 }
 
 pub fn exec(inst: &Instruction, cpu: &mut Cpu) -> Result<(), (Option<u64>, TrapCause, String)> {
-    check_accessible(cpu, inst.rs2.unwrap())?;
+    // rs1 holds the immediate of the I forms
+    let write =
+        matches!(inst.opc, OpecodeKind::OP_CSRRW | OpecodeKind::OP_CSRRWI) || inst.rs1 != Some(0);
+    check_accessible(cpu, inst.rs2.unwrap(), write)?;
+    // the guest may have written mtime since the last tick
+    if inst.rs2 == CSRname::timer.wrap() || inst.rs2 == CSRname::timeh.wrap() {
+        let mtime = cpu.bus.clint.mtime();
+        cpu.csrs.sync_time(mtime);
+    }
+    // a guest accessing an S-level CSR gets the VS one
+    let rs2 = inst.rs2.map(|csr| cpu.csrs.virtual_csr(csr));
 
     match inst.opc {
         OpecodeKind::OP_CSRRW => {
             let rs1 = cpu.regs.read(inst.rs1);
-            cpu.regs.write(inst.rd, cpu.csrs.read(inst.rs2)?);
-            cpu.csrs.write(inst.rs2, rs1)?;
+            cpu.regs.write(inst.rd, read_csr(cpu, rs2)?);
+            cpu.csrs.write(rs2, rs1)?;
         }
         OpecodeKind::OP_CSRRS => {
             let rs1 = cpu.regs.read(inst.rs1);
-            cpu.regs.write(inst.rd, cpu.csrs.read(inst.rs2)?);
-            cpu.csrs.bitset(inst.rs2, rs1)?;
+            cpu.regs.write(inst.rd, read_csr(cpu, rs2)?);
+            cpu.csrs.bitset(rs2, rs1)?;
         }
         OpecodeKind::OP_CSRRC => {
             let rs1 = cpu.regs.read(inst.rs1);
-            cpu.regs.write(inst.rd, cpu.csrs.read(inst.rs2)?);
-            cpu.csrs.bitclr(inst.rs2, rs1)?;
+            cpu.regs.write(inst.rd, read_csr(cpu, rs2)?);
+            cpu.csrs.bitclr(rs2, rs1)?;
         }
         OpecodeKind::OP_CSRRWI => {
-            cpu.regs.write(inst.rd, cpu.csrs.read(inst.rs2)?);
-            cpu.csrs.write(inst.rs2, inst.rs1.unwrap() as u64)?;
+            cpu.regs.write(inst.rd, read_csr(cpu, rs2)?);
+            cpu.csrs.write(rs2, inst.rs1.unwrap() as u64)?;
         }
         OpecodeKind::OP_CSRRSI => {
-            cpu.regs.write(inst.rd, cpu.csrs.read(inst.rs2)?);
-            cpu.csrs.bitset(inst.rs2, inst.rs1.unwrap() as u64)?;
+            cpu.regs.write(inst.rd, read_csr(cpu, rs2)?);
+            cpu.csrs.bitset(rs2, inst.rs1.unwrap() as u64)?;
         }
         OpecodeKind::OP_CSRRCI => {
-            cpu.regs.write(inst.rd, cpu.csrs.read(inst.rs2)?);
-            cpu.csrs.bitclr(inst.rs2, inst.rs1.unwrap() as u64)?;
+            cpu.regs.write(inst.rd, read_csr(cpu, rs2)?);
+            cpu.csrs.bitclr(rs2, inst.rs1.unwrap() as u64)?;
         }
         _ => panic!("not an Zicsr extension"),
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::exec;
+    use crate::cpu::instruction::{Instruction, OpecodeKind, OpecodeKind::*};
+    use crate::cpu::{csr, freg, mmu, reg, CSRname, Cpu, PrivilegedLevel, TrapCause};
+    use crate::{bus, Arguments, Isa};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn csr_inst(opc: OpecodeKind, rs1: usize, csr: usize) -> Instruction {
+        Instruction {
+            opc,
+            rd: Some(10),
+            rs1: Some(rs1),
+            rs2: Some(csr),
+            rs3: None,
+            imm: None,
+        }
+    }
+
+    #[test]
+    fn csr_access_test() {
+        let isa = Isa::Rv32;
+        let bus = bus::Bus::for_test(&Arguments::for_elf("./HelloWorld"), isa);
+        let pc = Rc::new(RefCell::new(bus.mrom.base_addr));
+        let mut cpu: Cpu = Cpu {
+            pc: pc.clone(),
+            bus,
+            regs: reg::Register::new(Rc::new(isa)),
+            fregs: freg::FRegister::new(),
+            csrs: csr::CSRs::new(Rc::new(isa), pc).init(),
+            mmu: mmu::Mmu::new(Rc::new(isa)),
+            isa: Rc::new(isa),
+            priv_lv: PrivilegedLevel::Machine,
+        };
+        let illegal = |result: Result<(), (Option<u64>, TrapCause, String)>| {
+            matches!(result, Err((_, TrapCause::IllegalInst, _)))
+        };
+
+        // unimplemented CSRs and writes to read-only ones
+        assert!(illegal(exec(&csr_inst(OP_CSRRS, 0, 0x7c0), &mut cpu)));
+        assert!(illegal(exec(&csr_inst(OP_CSRRS, 0, 0x14d), &mut cpu)));
+        let mhartid = CSRname::mhartid as usize;
+        assert!(exec(&csr_inst(OP_CSRRS, 0, mhartid), &mut cpu).is_ok());
+        assert!(exec(&csr_inst(OP_CSRRCI, 0, mhartid), &mut cpu).is_ok());
+        assert!(illegal(exec(&csr_inst(OP_CSRRS, 1, mhartid), &mut cpu)));
+        assert!(illegal(exec(&csr_inst(OP_CSRRWI, 0, mhartid), &mut cpu)));
+
+        // WARL fields keep their legal values
+        cpu.regs.write(Some(1), 0xffff_ffff);
+        exec(&csr_inst(OP_CSRRW, 1, CSRname::mideleg as usize), &mut cpu).unwrap();
+        exec(&csr_inst(OP_CSRRS, 0, CSRname::mideleg as usize), &mut cpu).unwrap();
+        assert_eq!(cpu.regs.read(Some(10)), 0x2222);
+
+        // time and timeh show mtime, also between the ticks
+        cpu.bus.clint.set_mtime(0x1_0000_0005);
+        exec(&csr_inst(OP_CSRRS, 0, CSRname::timer as usize), &mut cpu).unwrap();
+        assert_eq!(cpu.regs.read(Some(10)), 5);
+        exec(&csr_inst(OP_CSRRS, 0, CSRname::timeh as usize), &mut cpu).unwrap();
+        assert_eq!(cpu.regs.read(Some(10)), 1);
+
+        // S-mode can't reach M-mode CSRs, nor satp under mstatus.TVM
+        cpu.priv_lv = PrivilegedLevel::Supervisor;
+        let satp = CSRname::satp as usize;
+        assert!(illegal(exec(
+            &csr_inst(OP_CSRRS, 0, CSRname::mscratch as usize),
+            &mut cpu
+        )));
+        assert!(exec(&csr_inst(OP_CSRRS, 0, satp), &mut cpu).is_ok());
+        cpu.csrs.write(CSRname::mstatus.wrap(), 1 << 20).unwrap();
+        assert!(illegal(exec(&csr_inst(OP_CSRRS, 0, satp), &mut cpu)));
+    }
+}
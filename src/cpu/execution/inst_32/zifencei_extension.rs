@@ -0,0 +1,14 @@
+use crate::cpu::instruction::{Instruction, OpecodeKind};
+use crate::cpu::{Cpu, TrapCause};
+
+pub fn exec(inst: &Instruction, cpu: &mut Cpu) -> Result<(), (Option<u64>, TrapCause, String)> {
+    match inst.opc {
+        OpecodeKind::OP_FENCE_I => {
+            // discard the instructions decoded before the preceding stores
+            cpu.bus.icache.flush();
+        }
+        _ => panic!("not a Zifencei extension"),
+    }
+
+    Ok(())
+}
@@ -1,22 +1,41 @@
 use super::decode::Decode;
+use super::instruction::Instruction;
 use super::{TransAlign, TransFor, TrapCause};
 use crate::cpu::Cpu;
 use crate::{log, Isa};
 
-pub fn fetch(cpu: &mut Cpu) -> Result<Box<dyn Decode>, (Option<u64>, TrapCause, String)> {
+pub fn fetch(cpu: &mut Cpu) -> Result<Instruction, (Option<u64>, TrapCause, String)> {
     let index_pc: u64 = cpu.trans_addr(TransFor::Fetch, TransAlign::Size8, cpu.pc())?;
+    match *cpu.isa {
+        Isa::Rv32 => log::infoln!("pc: 0x{:08x}", cpu.pc()),
+        Isa::Rv64 => {
+            log::infoln!("pc: 0x{:016x}", cpu.pc());
+        }
+    };
+
+    if let Some(inst) = cpu.bus.icache.lookup(index_pc) {
+        return Ok(inst);
+    }
+
+    let inst = fetch_raw(cpu, index_pc)?.decode(*cpu.isa)?;
+    // an instruction may cross the page boundary only at the last halfword
+    if index_pc & 0xfff != 0xffe {
+        cpu.bus.icache.insert(index_pc, inst);
+    }
+
+    Ok(inst)
+}
+
+fn fetch_raw(
+    cpu: &mut Cpu,
+    index_pc: u64,
+) -> Result<Box<dyn Decode>, (Option<u64>, TrapCause, String)> {
     let is_cinst: bool = match cpu.bus.load_u8(index_pc) {
         Ok(inst_byte) => inst_byte & 0x3 != 0x3,
         Err((inst, _, msg)) => return Err((inst, TrapCause::InstAccessFault, msg)),
     };
 
     if is_cinst {
-        match *cpu.isa {
-            Isa::Rv32 => log::infoln!("pc: 0x{:08x}", cpu.pc()),
-            Isa::Rv64 => {
-                log::infoln!("pc: 0x{:016x}", cpu.pc());
-            }
-        };
         match cpu.bus.load_u16(index_pc) {
             Ok(new_inst) => Ok(Box::new(new_inst as u16)),
             Err((inst, _, msg)) => Err((inst, TrapCause::InstAccessFault, msg)),
@@ -31,12 +50,6 @@ pub fn fetch(cpu: &mut Cpu) -> Result<Box<dyn Decode>, (Option<u64>, TrapCause,
             Ok(inst) => inst as u32,
             Err((inst, _, msg)) => return Err((inst, TrapCause::InstAccessFault, msg)),
         };
-        match *cpu.isa {
-            Isa::Rv32 => log::infoln!("pc: 0x{:08x}", cpu.pc()),
-            Isa::Rv64 => {
-                log::infoln!("pc: 0x{:016x}", cpu.pc());
-            }
-        };
         Ok(Box::new(inst_upper << 16 | inst_lower))
     }
 }
@@ -0,0 +1,67 @@
+use crate::log;
+use crate::snapshot::{Reader, Snapshot, Writer};
+use std::io;
+
+// narrower values are NaN-boxed in the 64-bit registers
+const NAN_BOX_32: u64 = 0xffff_ffff_0000_0000;
+const CANONICAL_NAN_32: u64 = 0x7fc0_0000;
+
+pub struct FRegister {
+    fregs: [u64; 32],
+}
+
+impl FRegister {
+    pub fn new() -> Self {
+        FRegister { fregs: [0; 32] }
+    }
+
+    pub fn show(&self) {
+        log::debugln!("========================================= fp registers =========================================");
+        for (num, freg) in self.fregs.iter().enumerate() {
+            log::debug!("{:>4}: 0x{:016x}\t", format!("f{num}"), freg);
+            if (num + 1) % 3 == 0 {
+                log::debugln!("");
+            }
+        }
+        log::debugln!("\n=============================================================================================");
+    }
+
+    // raw 64 bit value
+    pub fn read(&self, src: Option<usize>) -> u64 {
+        self.fregs[src.unwrap()]
+    }
+
+    pub fn write(&mut self, dist: Option<usize>, src: u64) {
+        self.fregs[dist.unwrap()] = src;
+    }
+
+    // single precision value (canonical NaN if it isn't NaN-boxed)
+    pub fn read_s(&self, src: Option<usize>) -> u64 {
+        let value = self.read(src);
+        if value & NAN_BOX_32 == NAN_BOX_32 {
+            value & 0xffff_ffff
+        } else {
+            CANONICAL_NAN_32
+        }
+    }
+
+    pub fn write_s(&mut self, dist: Option<usize>, src: u64) {
+        self.write(dist, src & 0xffff_ffff | NAN_BOX_32);
+    }
+}
+
+impl Default for FRegister {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Snapshot for FRegister {
+    fn save(&self, w: &mut Writer) -> io::Result<()> {
+        w.u64s(&self.fregs)
+    }
+
+    fn restore(&mut self, r: &mut Reader) -> io::Result<()> {
+        r.u64s_into(&mut self.fregs)
+    }
+}
@@ -0,0 +1,263 @@
+use super::{csr, freg, mmu, reg, AddrTransMode, Cpu, PrivilegedLevel};
+use crate::snapshot::{self, Reader, Snapshot, Writer};
+use crate::{Arguments, Isa};
+use std::cell::RefCell;
+use std::io;
+use std::mem;
+use std::rc::Rc;
+
+// architectural state of a hart that is parked while another one runs
+pub struct Hart {
+    pc: Rc<RefCell<u64>>,
+    regs: reg::Register,
+    fregs: freg::FRegister,
+    csrs: csr::CSRs,
+    mmu: mmu::Mmu,
+    priv_lv: PrivilegedLevel,
+}
+
+impl Hart {
+    pub fn new(isa: Isa, args: &Arguments, reset_pc: u64, hartid: usize) -> Self {
+        let pc = Rc::new(RefCell::new(reset_pc));
+        let isa = Rc::new(isa);
+
+        Hart {
+            pc: pc.clone(),
+            regs: reg::Register::new(isa.clone()),
+            fregs: freg::FRegister::new(),
+            csrs: csr::CSRs::new(isa.clone(), pc)
+                .init()
+                .with_trans_mode(AddrTransMode::widest(*isa, args.trans_mode))
+                .with_svadu(args.svadu)
+                .with_sstc(args.sstc)
+                .with_hypervisor(args.hypervisor)
+                .with_hartid(hartid),
+            mmu: mmu::Mmu::new(isa),
+            priv_lv: PrivilegedLevel::Machine,
+        }
+    }
+
+    pub fn sync_time(&mut self, mtime: u64) {
+        self.csrs.sync_time(mtime);
+    }
+
+    pub fn debug_mode(&self) -> bool {
+        self.csrs.debug_mode()
+    }
+
+    pub fn show_tlb_stats(&self) {
+        self.mmu.show_tlb_stats(self.csrs.hartid());
+    }
+}
+
+impl Cpu {
+    pub fn from_hart(bus: crate::bus::Bus, hart: Hart, isa: Isa) -> Self {
+        Cpu {
+            pc: hart.pc,
+            bus,
+            regs: hart.regs,
+            fregs: hart.fregs,
+            csrs: hart.csrs,
+            mmu: hart.mmu,
+            isa: Rc::new(isa),
+            priv_lv: hart.priv_lv,
+        }
+    }
+
+    pub fn hartid(&self) -> usize {
+        self.csrs.hartid()
+    }
+
+    // exchange the running hart with a parked one
+    pub fn swap_hart(&mut self, hart: &mut Hart) {
+        mem::swap(&mut self.pc, &mut hart.pc);
+        mem::swap(&mut self.regs, &mut hart.regs);
+        mem::swap(&mut self.fregs, &mut hart.fregs);
+        mem::swap(&mut self.csrs, &mut hart.csrs);
+        mem::swap(&mut self.mmu, &mut hart.mmu);
+        mem::swap(&mut self.priv_lv, &mut hart.priv_lv);
+    }
+}
+
+impl Snapshot for Hart {
+    fn save(&self, w: &mut Writer) -> io::Result<()> {
+        w.tag(b"HART")?;
+        w.u64(*self.pc.borrow())?;
+        self.regs.save(w)?;
+        self.fregs.save(w)?;
+        self.csrs.save(w)?;
+        w.u8(self.priv_lv as u8)
+    }
+
+    fn restore(&mut self, r: &mut Reader) -> io::Result<()> {
+        r.tag(b"HART")?;
+        *self.pc.borrow_mut() = r.u64()?;
+        self.regs.restore(r)?;
+        self.fregs.restore(r)?;
+        self.csrs.restore(r)?;
+        self.priv_lv = restore_priv_lv(r)?;
+
+        // cached translations are rebuilt on demand
+        self.mmu.reset();
+
+        Ok(())
+    }
+}
+
+pub(super) fn restore_priv_lv(r: &mut Reader) -> io::Result<PrivilegedLevel> {
+    match r.u8()? {
+        0b00 => Ok(PrivilegedLevel::User),
+        0b01 => Ok(PrivilegedLevel::Supervisor),
+        0b11 => Ok(PrivilegedLevel::Machine),
+        lv => Err(snapshot::invalid_data(format!(
+            "invalid privileged level {lv}"
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cmdline::Arguments;
+    use crate::cpu::csr::CSRname;
+    use crate::machine::MachineConfig;
+    use crate::{Emulator, INTERLEAVE};
+
+    fn args(harts: usize, sstc: bool) -> Arguments {
+        Arguments {
+            machine: MachineConfig {
+                harts,
+                ..Default::default()
+            },
+            sstc,
+            ..Arguments::for_elf("./HelloWorld")
+        }
+    }
+
+    #[test]
+    fn round_robin_test() {
+        const MSIP_HART1: u64 = 0x0200_0004;
+        const MIP_MSIP: u64 = 1 << 3;
+        let mut emulator = Emulator::for_test(args(2, false));
+        let mscratch = CSRname::mscratch as usize;
+        emulator.cpu.write_csr(mscratch, 0x1234).unwrap();
+        assert_eq!(emulator.cpu.hartid(), 0);
+
+        for _ in 0..INTERLEAVE {
+            emulator.step();
+        }
+        assert_eq!(emulator.cpu.hartid(), 1);
+        assert_eq!(emulator.cpu.read_csr(mscratch), Some(0));
+
+        // an IPI through the CLINT only reaches the target hart
+        emulator.cpu.bus.store32(MSIP_HART1, 1).unwrap();
+        emulator.cpu.check_interrupt().unwrap();
+        assert_ne!(
+            emulator.cpu.read_csr(CSRname::mip as usize).unwrap() & MIP_MSIP,
+            0
+        );
+
+        emulator.switch_hart(0);
+        emulator.cpu.check_interrupt().unwrap();
+        assert_eq!(
+            emulator.cpu.read_csr(CSRname::mip as usize).unwrap() & MIP_MSIP,
+            0
+        );
+        assert_eq!(emulator.cpu.read_csr(mscratch), Some(0x1234));
+    }
+
+    #[test]
+    fn timer_test() {
+        const MTIMECMP_HART0: u64 = 0x0200_4000;
+        const MIP_STIP: u64 = 1 << 5;
+        const MIP_MTIP: u64 = 1 << 7;
+        let mip = |emulator: &Emulator| emulator.cpu.read_csr(CSRname::mip as usize).unwrap();
+        let mut emulator = Emulator::for_test(args(1, false));
+
+        // MTIP follows mtimecmp and leaves STIP alone
+        emulator.cpu.check_interrupt().unwrap();
+        assert_eq!(mip(&emulator) & (MIP_MTIP | MIP_STIP), MIP_MTIP);
+        emulator.cpu.bus.store64(MTIMECMP_HART0, 150).unwrap();
+        emulator.cpu.check_interrupt().unwrap();
+        assert_eq!(mip(&emulator) & MIP_MTIP, 0);
+        emulator.cpu.timer_increment(200);
+        emulator.cpu.check_interrupt().unwrap();
+        assert_eq!(mip(&emulator) & MIP_MTIP, MIP_MTIP);
+
+        // STIP is M-mode's to set, but stimecmp does not exist without Sstc
+        emulator
+            .cpu
+            .write_csr(CSRname::mip as usize, MIP_STIP)
+            .unwrap();
+        emulator.cpu.check_interrupt().unwrap();
+        assert_eq!(mip(&emulator) & (MIP_MTIP | MIP_STIP), MIP_MTIP | MIP_STIP);
+        emulator
+            .cpu
+            .write_csr(CSRname::menvcfgh as usize, 1 << 31)
+            .unwrap();
+        assert_eq!(emulator.cpu.read_csr(CSRname::menvcfgh as usize), Some(0));
+
+        // with Sstc, stimecmp drives STIP once menvcfg.STCE is set (in menvcfgh on rv32)
+        let mut emulator = Emulator::for_test(args(1, true));
+        let stimecmp = CSRname::stimecmp as usize;
+        emulator.cpu.write_csr(stimecmp, 300).unwrap();
+        emulator
+            .cpu
+            .write_csr(CSRname::menvcfgh as usize, 1 << 31)
+            .unwrap();
+        assert_eq!(mip(&emulator) & MIP_STIP, 0);
+        emulator
+            .cpu
+            .write_csr(CSRname::mip as usize, MIP_STIP)
+            .unwrap();
+        assert_eq!(mip(&emulator) & MIP_STIP, 0);
+        emulator.cpu.timer_increment(300);
+        assert_eq!(mip(&emulator) & MIP_STIP, MIP_STIP);
+        emulator.cpu.write_csr(stimecmp, u64::MAX).unwrap();
+        assert_eq!(mip(&emulator) & MIP_STIP, 0);
+    }
+
+    #[test]
+    fn trigger_test() {
+        const MTE: u64 = 1 << 3;
+        const MPTE: u64 = 1 << 7;
+        // rv32: type is in tdata1[31:28]
+        const MCONTROL6_EXECUTE_M: u64 = 6 << 28 | 1 << 6 | 1 << 2;
+        const MCONTROL6_HIT0: u64 = 1 << 22;
+        const ICOUNT_M: u64 = 3 << 28 | 1 << 9;
+        let mut emulator = Emulator::for_test(args(1, false));
+        let csr = |emulator: &Emulator, csr: CSRname| emulator.cpu.read_csr(csr as usize).unwrap();
+        let cpu = &mut emulator.cpu;
+        cpu.write_csr(CSRname::mtvec as usize, 0x1000).unwrap();
+        cpu.write_csr(CSRname::tcontrol as usize, MTE).unwrap();
+        cpu.write_csr(CSRname::tdata1 as usize, MCONTROL6_EXECUTE_M)
+            .unwrap();
+        cpu.write_csr(CSRname::tdata2 as usize, 0x1004).unwrap();
+
+        // an execute trigger fires before the instruction and clears mte
+        emulator.step();
+        emulator.step();
+        assert_eq!(csr(&emulator, CSRname::mcause), 3);
+        assert_eq!(csr(&emulator, CSRname::mepc), 0x1004);
+        assert_eq!(emulator.cpu.pc(), 0x1000);
+        assert_eq!(csr(&emulator, CSRname::tcontrol), MPTE);
+        assert_ne!(csr(&emulator, CSRname::tdata1) & MCONTROL6_HIT0, 0);
+        // with mte clear it stays quiet in M-mode
+        emulator.step();
+        emulator.step();
+        assert_eq!(emulator.cpu.pc(), 0x1008);
+
+        // icount fires once the counted instructions retired
+        let cpu = &mut emulator.cpu;
+        cpu.write_csr(CSRname::tdata1 as usize, 0).unwrap();
+        cpu.write_csr(CSRname::tcontrol as usize, MTE).unwrap();
+        cpu.write_csr(CSRname::tselect as usize, 1).unwrap();
+        cpu.write_csr(CSRname::tdata1 as usize, ICOUNT_M | 2 << 10)
+            .unwrap();
+        emulator.step();
+        assert_eq!(emulator.cpu.pc(), 0x100c);
+        emulator.step();
+        assert_eq!(csr(&emulator, CSRname::mepc), 0x1010);
+        assert_eq!(emulator.cpu.pc(), 0x1000);
+        assert_eq!(csr(&emulator, CSRname::tdata1) >> 10 & 0x3fff, 0);
+    }
+}
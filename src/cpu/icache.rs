@@ -0,0 +1,98 @@
+use super::instruction::Instruction;
+use std::collections::HashMap;
+
+const PAGE_SHIFT: u64 = 12;
+const SLOTS_PER_PAGE: usize = 2048; // instructions are 2-byte aligned
+
+// decoded instructions indexed by their physical address
+pub struct InstCache {
+    pages: HashMap<u64, Vec<Option<Instruction>>>,
+    pub hit: u64,
+    pub miss: u64,
+}
+
+impl InstCache {
+    pub fn new() -> Self {
+        InstCache {
+            pages: HashMap::new(),
+            hit: 0,
+            miss: 0,
+        }
+    }
+
+    fn slot(paddr: u64) -> usize {
+        (paddr >> 1) as usize % SLOTS_PER_PAGE
+    }
+
+    pub fn lookup(&mut self, paddr: u64) -> Option<Instruction> {
+        let inst = self
+            .pages
+            .get(&(paddr >> PAGE_SHIFT))
+            .and_then(|page| page[Self::slot(paddr)]);
+        match inst {
+            Some(_) => self.hit += 1,
+            None => self.miss += 1,
+        }
+        inst
+    }
+
+    pub fn insert(&mut self, paddr: u64, inst: Instruction) {
+        self.pages
+            .entry(paddr >> PAGE_SHIFT)
+            .or_insert_with(|| vec![None; SLOTS_PER_PAGE])[Self::slot(paddr)] = Some(inst);
+    }
+
+    // a store drops every instruction decoded from the pages it touches
+    pub fn invalidate(&mut self, paddr: u64, size: u64) {
+        if self.pages.is_empty() {
+            return;
+        }
+        for page in (paddr >> PAGE_SHIFT)..=((paddr + size - 1) >> PAGE_SHIFT) {
+            self.pages.remove(&page);
+        }
+    }
+
+    pub fn flush(&mut self) {
+        self.pages.clear();
+    }
+}
+
+impl Default for InstCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::instruction::OpecodeKind;
+
+    #[test]
+    fn invalidate_test() {
+        let inst = Instruction {
+            opc: OpecodeKind::OP_ADDI,
+            rd: Some(1),
+            rs1: Some(0),
+            rs2: None,
+            rs3: None,
+            imm: Some(1),
+        };
+        let mut icache = InstCache::new();
+        icache.insert(0x8000_0ffc, inst);
+        icache.insert(0x8000_1000, inst);
+        icache.insert(0x8000_2000, inst);
+        assert!(icache.lookup(0x8000_0ffc).is_some());
+        assert!(icache.lookup(0x8000_0ffe).is_none());
+
+        // a misaligned store across the page boundary hits both pages
+        icache.invalidate(0x8000_0ffe, 4);
+        assert!(icache.lookup(0x8000_0ffc).is_none());
+        assert!(icache.lookup(0x8000_1000).is_none());
+        assert!(icache.lookup(0x8000_2000).is_some());
+        assert_eq!((icache.hit, icache.miss), (2, 3));
+
+        icache.flush();
+        assert!(icache.lookup(0x8000_2000).is_none());
+    }
+}
@@ -1,11 +1,14 @@
 // riscv-spec-20191213-1.pdf page=130
 
-#[derive(Debug)]
+use super::csr::Event;
+
+#[derive(Copy, Clone, Debug)]
 pub struct Instruction {
     pub opc: OpecodeKind,
     pub rd: Option<usize>,
     pub rs1: Option<usize>,
     pub rs2: Option<usize>,
+    pub rs3: Option<usize>,
     pub imm: Option<i32>,
 }
 
@@ -13,13 +16,17 @@ pub enum Extensions {
     BaseI,
     M,
     A,
+    F,
+    D,
     C,
     Zicsr,
+    Zifencei,
     Priv,
+    H,
 }
 
 #[allow(non_camel_case_types)]
-#[derive(Debug)]
+#[derive(Copy, Clone, Debug)]
 pub enum OpecodeKind {
     //== Base Integer Instruction ==
     OP_LUI,
@@ -84,12 +91,34 @@ pub enum OpecodeKind {
     OP_CSRRSI,
     OP_CSRRCI,
 
+    //== Zifencei Extension ==
+    OP_FENCE_I,
+
     //== privileged Instruction ==
     OP_SRET,
     OP_MRET,
     OP_WFI,
+    OP_DRET,
     OP_SFENCE_VMA,
 
+    //== H Extension ==
+    OP_HLV_B,
+    OP_HLV_BU,
+    OP_HLV_H,
+    OP_HLV_HU,
+    OP_HLVX_HU,
+    OP_HLV_W,
+    OP_HLVX_WU,
+    OP_HSV_B,
+    OP_HSV_H,
+    OP_HSV_W,
+    OP_HFENCE_VVMA,
+    OP_HFENCE_GVMA,
+    //-- rv64 --
+    OP_HLV_WU,
+    OP_HLV_D,
+    OP_HSV_D,
+
     //== M Extension ==
     OP_MUL,
     OP_MULH,
@@ -131,6 +160,74 @@ pub enum OpecodeKind {
     OP_AMOMINU_D,
     OP_AMOMAXU_D,
 
+    //== F Extension ==
+    OP_FLW,
+    OP_FSW,
+    OP_FMADD_S,
+    OP_FMSUB_S,
+    OP_FNMSUB_S,
+    OP_FNMADD_S,
+    OP_FADD_S,
+    OP_FSUB_S,
+    OP_FMUL_S,
+    OP_FDIV_S,
+    OP_FSQRT_S,
+    OP_FSGNJ_S,
+    OP_FSGNJN_S,
+    OP_FSGNJX_S,
+    OP_FMIN_S,
+    OP_FMAX_S,
+    OP_FCVT_W_S,
+    OP_FCVT_WU_S,
+    OP_FMV_X_W,
+    OP_FEQ_S,
+    OP_FLT_S,
+    OP_FLE_S,
+    OP_FCLASS_S,
+    OP_FCVT_S_W,
+    OP_FCVT_S_WU,
+    OP_FMV_W_X,
+    //-- rv64 --
+    OP_FCVT_L_S,
+    OP_FCVT_LU_S,
+    OP_FCVT_S_L,
+    OP_FCVT_S_LU,
+
+    //== D Extension ==
+    OP_FLD,
+    OP_FSD,
+    OP_FMADD_D,
+    OP_FMSUB_D,
+    OP_FNMSUB_D,
+    OP_FNMADD_D,
+    OP_FADD_D,
+    OP_FSUB_D,
+    OP_FMUL_D,
+    OP_FDIV_D,
+    OP_FSQRT_D,
+    OP_FSGNJ_D,
+    OP_FSGNJN_D,
+    OP_FSGNJX_D,
+    OP_FMIN_D,
+    OP_FMAX_D,
+    OP_FCVT_S_D,
+    OP_FCVT_D_S,
+    OP_FEQ_D,
+    OP_FLT_D,
+    OP_FLE_D,
+    OP_FCLASS_D,
+    OP_FCVT_W_D,
+    OP_FCVT_WU_D,
+    OP_FCVT_D_W,
+    OP_FCVT_D_WU,
+    //-- rv64 --
+    OP_FCVT_L_D,
+    OP_FCVT_LU_D,
+    OP_FMV_X_D,
+    OP_FCVT_D_L,
+    OP_FCVT_D_LU,
+    OP_FMV_D_X,
+
     //== C Extension ==
     OP_C_ADDI4SPN,
     OP_C_LW,
@@ -167,6 +264,15 @@ pub enum OpecodeKind {
     OP_C_ADDW,
     OP_C_LDSP,
     OP_C_SDSP,
+    OP_C_FLD,
+    OP_C_FSD,
+    OP_C_FLDSP,
+    OP_C_FSDSP,
+    //-- rv32 --
+    OP_C_FLW,
+    OP_C_FSW,
+    OP_C_FLWSP,
+    OP_C_FSWSP,
 }
 
 impl Instruction {
@@ -257,10 +363,27 @@ impl Instruction {
             OpecodeKind::OP_CSRRWI => Extensions::Zicsr,
             OpecodeKind::OP_CSRRSI => Extensions::Zicsr,
             OpecodeKind::OP_CSRRCI => Extensions::Zicsr,
+            OpecodeKind::OP_FENCE_I => Extensions::Zifencei,
             OpecodeKind::OP_SRET => Extensions::Priv,
             OpecodeKind::OP_MRET => Extensions::Priv,
             OpecodeKind::OP_WFI => Extensions::Priv,
+            OpecodeKind::OP_DRET => Extensions::Priv,
             OpecodeKind::OP_SFENCE_VMA => Extensions::Priv,
+            OpecodeKind::OP_HLV_B => Extensions::H,
+            OpecodeKind::OP_HLV_BU => Extensions::H,
+            OpecodeKind::OP_HLV_H => Extensions::H,
+            OpecodeKind::OP_HLV_HU => Extensions::H,
+            OpecodeKind::OP_HLVX_HU => Extensions::H,
+            OpecodeKind::OP_HLV_W => Extensions::H,
+            OpecodeKind::OP_HLVX_WU => Extensions::H,
+            OpecodeKind::OP_HSV_B => Extensions::H,
+            OpecodeKind::OP_HSV_H => Extensions::H,
+            OpecodeKind::OP_HSV_W => Extensions::H,
+            OpecodeKind::OP_HFENCE_VVMA => Extensions::H,
+            OpecodeKind::OP_HFENCE_GVMA => Extensions::H,
+            OpecodeKind::OP_HLV_WU => Extensions::H,
+            OpecodeKind::OP_HLV_D => Extensions::H,
+            OpecodeKind::OP_HSV_D => Extensions::H,
             OpecodeKind::OP_MUL => Extensions::M,
             OpecodeKind::OP_MULH => Extensions::M,
             OpecodeKind::OP_MULHSU => Extensions::M,
@@ -296,6 +419,68 @@ impl Instruction {
             OpecodeKind::OP_AMOMAX_D => Extensions::A,
             OpecodeKind::OP_AMOMINU_D => Extensions::A,
             OpecodeKind::OP_AMOMAXU_D => Extensions::A,
+            OpecodeKind::OP_FLW => Extensions::F,
+            OpecodeKind::OP_FSW => Extensions::F,
+            OpecodeKind::OP_FMADD_S => Extensions::F,
+            OpecodeKind::OP_FMSUB_S => Extensions::F,
+            OpecodeKind::OP_FNMSUB_S => Extensions::F,
+            OpecodeKind::OP_FNMADD_S => Extensions::F,
+            OpecodeKind::OP_FADD_S => Extensions::F,
+            OpecodeKind::OP_FSUB_S => Extensions::F,
+            OpecodeKind::OP_FMUL_S => Extensions::F,
+            OpecodeKind::OP_FDIV_S => Extensions::F,
+            OpecodeKind::OP_FSQRT_S => Extensions::F,
+            OpecodeKind::OP_FSGNJ_S => Extensions::F,
+            OpecodeKind::OP_FSGNJN_S => Extensions::F,
+            OpecodeKind::OP_FSGNJX_S => Extensions::F,
+            OpecodeKind::OP_FMIN_S => Extensions::F,
+            OpecodeKind::OP_FMAX_S => Extensions::F,
+            OpecodeKind::OP_FCVT_W_S => Extensions::F,
+            OpecodeKind::OP_FCVT_WU_S => Extensions::F,
+            OpecodeKind::OP_FMV_X_W => Extensions::F,
+            OpecodeKind::OP_FEQ_S => Extensions::F,
+            OpecodeKind::OP_FLT_S => Extensions::F,
+            OpecodeKind::OP_FLE_S => Extensions::F,
+            OpecodeKind::OP_FCLASS_S => Extensions::F,
+            OpecodeKind::OP_FCVT_S_W => Extensions::F,
+            OpecodeKind::OP_FCVT_S_WU => Extensions::F,
+            OpecodeKind::OP_FMV_W_X => Extensions::F,
+            OpecodeKind::OP_FCVT_L_S => Extensions::F,
+            OpecodeKind::OP_FCVT_LU_S => Extensions::F,
+            OpecodeKind::OP_FCVT_S_L => Extensions::F,
+            OpecodeKind::OP_FCVT_S_LU => Extensions::F,
+            OpecodeKind::OP_FLD => Extensions::D,
+            OpecodeKind::OP_FSD => Extensions::D,
+            OpecodeKind::OP_FMADD_D => Extensions::D,
+            OpecodeKind::OP_FMSUB_D => Extensions::D,
+            OpecodeKind::OP_FNMSUB_D => Extensions::D,
+            OpecodeKind::OP_FNMADD_D => Extensions::D,
+            OpecodeKind::OP_FADD_D => Extensions::D,
+            OpecodeKind::OP_FSUB_D => Extensions::D,
+            OpecodeKind::OP_FMUL_D => Extensions::D,
+            OpecodeKind::OP_FDIV_D => Extensions::D,
+            OpecodeKind::OP_FSQRT_D => Extensions::D,
+            OpecodeKind::OP_FSGNJ_D => Extensions::D,
+            OpecodeKind::OP_FSGNJN_D => Extensions::D,
+            OpecodeKind::OP_FSGNJX_D => Extensions::D,
+            OpecodeKind::OP_FMIN_D => Extensions::D,
+            OpecodeKind::OP_FMAX_D => Extensions::D,
+            OpecodeKind::OP_FCVT_S_D => Extensions::D,
+            OpecodeKind::OP_FCVT_D_S => Extensions::D,
+            OpecodeKind::OP_FEQ_D => Extensions::D,
+            OpecodeKind::OP_FLT_D => Extensions::D,
+            OpecodeKind::OP_FLE_D => Extensions::D,
+            OpecodeKind::OP_FCLASS_D => Extensions::D,
+            OpecodeKind::OP_FCVT_W_D => Extensions::D,
+            OpecodeKind::OP_FCVT_WU_D => Extensions::D,
+            OpecodeKind::OP_FCVT_D_W => Extensions::D,
+            OpecodeKind::OP_FCVT_D_WU => Extensions::D,
+            OpecodeKind::OP_FCVT_L_D => Extensions::D,
+            OpecodeKind::OP_FCVT_LU_D => Extensions::D,
+            OpecodeKind::OP_FMV_X_D => Extensions::D,
+            OpecodeKind::OP_FCVT_D_L => Extensions::D,
+            OpecodeKind::OP_FCVT_D_LU => Extensions::D,
+            OpecodeKind::OP_FMV_D_X => Extensions::D,
             OpecodeKind::OP_C_ADDI4SPN => Extensions::C,
             OpecodeKind::OP_C_LW => Extensions::C,
             OpecodeKind::OP_C_SW => Extensions::C,
@@ -330,6 +515,14 @@ impl Instruction {
             OpecodeKind::OP_C_ADDW => Extensions::C,
             OpecodeKind::OP_C_LDSP => Extensions::C,
             OpecodeKind::OP_C_SDSP => Extensions::C,
+            OpecodeKind::OP_C_FLD => Extensions::C,
+            OpecodeKind::OP_C_FSD => Extensions::C,
+            OpecodeKind::OP_C_FLDSP => Extensions::C,
+            OpecodeKind::OP_C_FSDSP => Extensions::C,
+            OpecodeKind::OP_C_FLW => Extensions::C,
+            OpecodeKind::OP_C_FSW => Extensions::C,
+            OpecodeKind::OP_C_FLWSP => Extensions::C,
+            OpecodeKind::OP_C_FSWSP => Extensions::C,
         }
     }
 
@@ -393,10 +586,27 @@ impl Instruction {
             OpecodeKind::OP_CSRRWI => "csrrwi",
             OpecodeKind::OP_CSRRSI => "csrrsi",
             OpecodeKind::OP_CSRRCI => "csrrci",
+            OpecodeKind::OP_FENCE_I => "fence.i",
             OpecodeKind::OP_SRET => "sret",
             OpecodeKind::OP_MRET => "mret",
             OpecodeKind::OP_WFI => "wfi",
+            OpecodeKind::OP_DRET => "dret",
             OpecodeKind::OP_SFENCE_VMA => "sfence.vma",
+            OpecodeKind::OP_HLV_B => "hlv.b",
+            OpecodeKind::OP_HLV_BU => "hlv.bu",
+            OpecodeKind::OP_HLV_H => "hlv.h",
+            OpecodeKind::OP_HLV_HU => "hlv.hu",
+            OpecodeKind::OP_HLVX_HU => "hlvx.hu",
+            OpecodeKind::OP_HLV_W => "hlv.w",
+            OpecodeKind::OP_HLVX_WU => "hlvx.wu",
+            OpecodeKind::OP_HSV_B => "hsv.b",
+            OpecodeKind::OP_HSV_H => "hsv.h",
+            OpecodeKind::OP_HSV_W => "hsv.w",
+            OpecodeKind::OP_HFENCE_VVMA => "hfence.vvma",
+            OpecodeKind::OP_HFENCE_GVMA => "hfence.gvma",
+            OpecodeKind::OP_HLV_WU => "hlv.wu",
+            OpecodeKind::OP_HLV_D => "hlv.d",
+            OpecodeKind::OP_HSV_D => "hsv.d",
             OpecodeKind::OP_MUL => "mul",
             OpecodeKind::OP_MULH => "mulh",
             OpecodeKind::OP_MULHSU => "mulhsu,",
@@ -432,6 +642,68 @@ impl Instruction {
             OpecodeKind::OP_AMOMAX_D => "amomax.d",
             OpecodeKind::OP_AMOMINU_D => "amominu.d",
             OpecodeKind::OP_AMOMAXU_D => "amomaxu.d",
+            OpecodeKind::OP_FLW => "flw",
+            OpecodeKind::OP_FSW => "fsw",
+            OpecodeKind::OP_FMADD_S => "fmadd.s",
+            OpecodeKind::OP_FMSUB_S => "fmsub.s",
+            OpecodeKind::OP_FNMSUB_S => "fnmsub.s",
+            OpecodeKind::OP_FNMADD_S => "fnmadd.s",
+            OpecodeKind::OP_FADD_S => "fadd.s",
+            OpecodeKind::OP_FSUB_S => "fsub.s",
+            OpecodeKind::OP_FMUL_S => "fmul.s",
+            OpecodeKind::OP_FDIV_S => "fdiv.s",
+            OpecodeKind::OP_FSQRT_S => "fsqrt.s",
+            OpecodeKind::OP_FSGNJ_S => "fsgnj.s",
+            OpecodeKind::OP_FSGNJN_S => "fsgnjn.s",
+            OpecodeKind::OP_FSGNJX_S => "fsgnjx.s",
+            OpecodeKind::OP_FMIN_S => "fmin.s",
+            OpecodeKind::OP_FMAX_S => "fmax.s",
+            OpecodeKind::OP_FCVT_W_S => "fcvt.w.s",
+            OpecodeKind::OP_FCVT_WU_S => "fcvt.wu.s",
+            OpecodeKind::OP_FMV_X_W => "fmv.x.w",
+            OpecodeKind::OP_FEQ_S => "feq.s",
+            OpecodeKind::OP_FLT_S => "flt.s",
+            OpecodeKind::OP_FLE_S => "fle.s",
+            OpecodeKind::OP_FCLASS_S => "fclass.s",
+            OpecodeKind::OP_FCVT_S_W => "fcvt.s.w",
+            OpecodeKind::OP_FCVT_S_WU => "fcvt.s.wu",
+            OpecodeKind::OP_FMV_W_X => "fmv.w.x",
+            OpecodeKind::OP_FCVT_L_S => "fcvt.l.s",
+            OpecodeKind::OP_FCVT_LU_S => "fcvt.lu.s",
+            OpecodeKind::OP_FCVT_S_L => "fcvt.s.l",
+            OpecodeKind::OP_FCVT_S_LU => "fcvt.s.lu",
+            OpecodeKind::OP_FLD => "fld",
+            OpecodeKind::OP_FSD => "fsd",
+            OpecodeKind::OP_FMADD_D => "fmadd.d",
+            OpecodeKind::OP_FMSUB_D => "fmsub.d",
+            OpecodeKind::OP_FNMSUB_D => "fnmsub.d",
+            OpecodeKind::OP_FNMADD_D => "fnmadd.d",
+            OpecodeKind::OP_FADD_D => "fadd.d",
+            OpecodeKind::OP_FSUB_D => "fsub.d",
+            OpecodeKind::OP_FMUL_D => "fmul.d",
+            OpecodeKind::OP_FDIV_D => "fdiv.d",
+            OpecodeKind::OP_FSQRT_D => "fsqrt.d",
+            OpecodeKind::OP_FSGNJ_D => "fsgnj.d",
+            OpecodeKind::OP_FSGNJN_D => "fsgnjn.d",
+            OpecodeKind::OP_FSGNJX_D => "fsgnjx.d",
+            OpecodeKind::OP_FMIN_D => "fmin.d",
+            OpecodeKind::OP_FMAX_D => "fmax.d",
+            OpecodeKind::OP_FCVT_S_D => "fcvt.s.d",
+            OpecodeKind::OP_FCVT_D_S => "fcvt.d.s",
+            OpecodeKind::OP_FEQ_D => "feq.d",
+            OpecodeKind::OP_FLT_D => "flt.d",
+            OpecodeKind::OP_FLE_D => "fle.d",
+            OpecodeKind::OP_FCLASS_D => "fclass.d",
+            OpecodeKind::OP_FCVT_W_D => "fcvt.w.d",
+            OpecodeKind::OP_FCVT_WU_D => "fcvt.wu.d",
+            OpecodeKind::OP_FCVT_D_W => "fcvt.d.w",
+            OpecodeKind::OP_FCVT_D_WU => "fcvt.d.wu",
+            OpecodeKind::OP_FCVT_L_D => "fcvt.l.d",
+            OpecodeKind::OP_FCVT_LU_D => "fcvt.lu.d",
+            OpecodeKind::OP_FMV_X_D => "fmv.x.d",
+            OpecodeKind::OP_FCVT_D_L => "fcvt.d.l",
+            OpecodeKind::OP_FCVT_D_LU => "fcvt.d.lu",
+            OpecodeKind::OP_FMV_D_X => "fmv.d.x",
             OpecodeKind::OP_C_ADDI4SPN => "C.addi4spn",
             OpecodeKind::OP_C_LW => "C.lw",
             OpecodeKind::OP_C_SW => "C.sw",
@@ -466,6 +738,36 @@ impl Instruction {
             OpecodeKind::OP_C_ADDW => "C.addw",
             OpecodeKind::OP_C_LDSP => "C.ldsp",
             OpecodeKind::OP_C_SDSP => "C.sdsp",
+            OpecodeKind::OP_C_FLD => "C.fld",
+            OpecodeKind::OP_C_FSD => "C.fsd",
+            OpecodeKind::OP_C_FLDSP => "C.fldsp",
+            OpecodeKind::OP_C_FSDSP => "C.fsdsp",
+            OpecodeKind::OP_C_FLW => "C.flw",
+            OpecodeKind::OP_C_FSW => "C.fsw",
+            OpecodeKind::OP_C_FLWSP => "C.flwsp",
+            OpecodeKind::OP_C_FSWSP => "C.fswsp",
+        }
+    }
+
+    // the hpm event a retired instruction counts towards besides Instructions
+    pub fn opc_to_event(&self) -> Option<Event> {
+        use OpecodeKind::*;
+        match self.opc {
+            OP_LB | OP_LH | OP_LW | OP_LBU | OP_LHU | OP_LWU | OP_LD | OP_FLW | OP_FLD
+            | OP_C_LW | OP_C_LWSP | OP_C_LD | OP_C_LDSP | OP_C_FLD | OP_C_FLDSP | OP_C_FLW
+            | OP_C_FLWSP | OP_HLV_B | OP_HLV_BU | OP_HLV_H | OP_HLV_HU | OP_HLVX_HU | OP_HLV_W
+            | OP_HLV_WU | OP_HLVX_WU | OP_HLV_D => Some(Event::Loads),
+            OP_SB | OP_SH | OP_SW | OP_SD | OP_FSW | OP_FSD | OP_C_SW | OP_C_SWSP | OP_C_SD
+            | OP_C_SDSP | OP_C_FSD | OP_C_FSDSP | OP_C_FSW | OP_C_FSWSP | OP_HSV_B | OP_HSV_H
+            | OP_HSV_W | OP_HSV_D => Some(Event::Stores),
+            OP_LR_W | OP_SC_W | OP_AMOSWAP_W | OP_AMOADD_W | OP_AMOXOR_W | OP_AMOAND_W
+            | OP_AMOOR_W | OP_AMOMIN_W | OP_AMOMAX_W | OP_AMOMINU_W | OP_AMOMAXU_W | OP_LR_D
+            | OP_SC_D | OP_AMOSWAP_D | OP_AMOADD_D | OP_AMOXOR_D | OP_AMOAND_D | OP_AMOOR_D
+            | OP_AMOMIN_D | OP_AMOMAX_D | OP_AMOMINU_D | OP_AMOMAXU_D => Some(Event::Atomics),
+            OP_BEQ | OP_BNE | OP_BLT | OP_BGE | OP_BLTU | OP_BGEU | OP_C_BEQZ | OP_C_BNEZ => {
+                Some(Event::Branches)
+            }
+            _ => None,
         }
     }
 }
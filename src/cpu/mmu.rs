@@ -1,51 +1,238 @@
 mod pmp;
+mod tlb;
 
-use crate::bus::dram::Dram;
-use crate::bus::Device;
+use crate::bus::Dma;
 use crate::cpu::csr::CSRs;
 use crate::cpu::{CSRname, PrivilegedLevel, TransFor, TrapCause, Xstatus};
 use crate::{log, Isa};
 use std::rc::Rc;
+use tlb::{Tlb, TlbEntry};
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd)]
 pub enum AddrTransMode {
     Bare,
     Sv32,
     Sv39,
+    Sv48,
+    Sv57,
 }
 
-pub struct Mmu {
+impl AddrTransMode {
+    pub fn from_satp(isa: Isa, satp: u64) -> Option<Self> {
+        match isa {
+            Isa::Rv32 => match satp >> 31 & 0x1 {
+                0 => Some(AddrTransMode::Bare),
+                _ => Some(AddrTransMode::Sv32),
+            },
+            Isa::Rv64 => match satp >> 60 & 0xf {
+                0 => Some(AddrTransMode::Bare),
+                8 => Some(AddrTransMode::Sv39),
+                9 => Some(AddrTransMode::Sv48),
+                10 => Some(AddrTransMode::Sv57),
+                _ => None,
+            },
+        }
+    }
+
+    // the widest mode the hart implements (narrower ones are also supported)
+    pub fn widest(isa: Isa, requested: Option<Self>) -> Self {
+        match isa {
+            Isa::Rv32 => AddrTransMode::Sv32,
+            Isa::Rv64 => requested.unwrap_or(AddrTransMode::Sv57),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AddrTransMode::Bare => "bare",
+            AddrTransMode::Sv32 => "sv32",
+            AddrTransMode::Sv39 => "sv39",
+            AddrTransMode::Sv48 => "sv48",
+            AddrTransMode::Sv57 => "sv57",
+        }
+    }
+
+    // (levels, VPN[i] width, PTE size, VA width)
+    fn geometry(&self) -> (u32, u32, u64, u32) {
+        match self {
+            AddrTransMode::Sv32 => (2, 10, 4, 32),
+            AddrTransMode::Sv39 => (3, 9, 8, 39),
+            AddrTransMode::Sv48 => (4, 9, 8, 48),
+            AddrTransMode::Sv57 => (5, 9, 8, 57),
+            AddrTransMode::Bare => unreachable!("bare mode has no page table"),
+        }
+    }
+}
+
+// the stages of a translation: Single is the one of HS-mode (or S-mode),
+// a guest goes through VS-stage and then G-stage
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Stage {
+    Single,
+    VS,
+    G,
+}
+
+// the root page table of a stage, cached from satp, vsatp or hgatp
+#[derive(Copy, Clone)]
+struct Root {
+    atp: u64,
     ppn: u64,
-    trans_mode: AddrTransMode,
+    // ASID, or VMID for hgatp
+    id: u64,
+    mode: AddrTransMode,
+}
+
+impl Root {
+    fn new() -> Self {
+        Root {
+            atp: 0,
+            ppn: 0,
+            id: 0,
+            mode: AddrTransMode::Bare,
+        }
+    }
+
+    fn update(&mut self, isa: Isa, atp: u64) {
+        if atp == self.atp {
+            return;
+        }
+
+        let (ppn_mask, id) = match isa {
+            Isa::Rv32 => (0x3FFFFF, atp >> 22 & 0x1ff),
+            Isa::Rv64 => (0xFFFFFFFFFFF, atp >> 44 & 0xffff),
+        };
+        // the MODE fields are WARL, so they always hold a supported mode;
+        // Sv39x4 and the others share the encodings of Sv39 and so on
+        *self = Root {
+            atp,
+            ppn: atp & ppn_mask,
+            id,
+            mode: AddrTransMode::from_satp(isa, atp)
+                .expect("satp holds an unsupported translation mode"),
+        };
+    }
+}
+
+// what the trap handler learns about the last translation: whether the
+// address was a guest virtual one, and the guest physical address and
+// pseudoinstruction of a guest-page fault
+#[derive(Copy, Clone, Debug, Default)]
+pub struct GuestFault {
+    pub gva: bool,
+    pub gpa: u64,
+    pub tinst: u64,
+}
+
+// the transformed instructions of the implicit VS-stage PTE accesses
+const TINST_PTE_READ: u64 = 0x3000;
+const TINST_PTE_WRITE: u64 = 0x3020;
+
+pub struct Mmu {
+    satp: Root,
+    vsatp: Root,
+    hgatp: Root,
+    guest_fault: GuestFault,
+    tlb: Tlb,
+    // a debugger's translation, which leaves no trace
+    probing: bool,
     isa: Rc<Isa>,
 }
 
 impl Mmu {
     pub fn new(isa: Rc<Isa>) -> Self {
         Mmu {
-            ppn: 0,
-            trans_mode: AddrTransMode::Bare,
+            satp: Root::new(),
+            vsatp: Root::new(),
+            hgatp: Root::new(),
+            guest_fault: GuestFault::default(),
+            tlb: Tlb::new(),
+            probing: false,
             isa,
         }
     }
 
-    fn update_ppn_and_mode(&mut self, csrs: &CSRs) {
-        let satp_ppn_mask = match *self.isa {
-            Isa::Rv32 => 0x3FFFFF,
-            Isa::Rv64 => 0xFFFFFFFFFFF,
-        };
-        self.ppn = csrs.read(CSRname::satp.wrap()).unwrap() & satp_ppn_mask;
+    // forget the cached satp and translations
+    pub fn reset(&mut self) {
+        *self = Mmu::new(self.isa.clone());
+    }
 
+    fn update_roots(&mut self, csrs: &CSRs) {
         let satp = csrs.read(CSRname::satp.wrap()).unwrap();
-        self.trans_mode = match *self.isa {
-            Isa::Rv32 => match satp >> 31 & 0x1 {
-                1 => AddrTransMode::Sv32,
-                _ => AddrTransMode::Bare,
-            },
-            Isa::Rv64 => match satp >> 60 & 0xf {
-                8 => AddrTransMode::Sv39,
-                _ => AddrTransMode::Bare,
-            },
+        let vsatp = csrs.read(CSRname::vsatp.wrap()).unwrap();
+        let hgatp = csrs.read(CSRname::hgatp.wrap()).unwrap();
+
+        // the TLB entries are tagged with the ASID and VMID, so switching
+        // address spaces keeps them; the guest fences what it rewrites
+        self.satp.update(*self.isa, satp);
+        self.vsatp.update(*self.isa, vsatp);
+        self.hgatp.update(*self.isa, hgatp);
+    }
+
+    fn root(&self, stage: Stage) -> Root {
+        match stage {
+            Stage::Single => self.satp,
+            Stage::VS => self.vsatp,
+            Stage::G => self.hgatp,
+        }
+    }
+
+    pub fn guest_fault(&self) -> GuestFault {
+        self.guest_fault
+    }
+
+    // SFENCE.VMA, or HFENCE.VVMA (and SFENCE.VMA in a guest) when virt
+    pub fn flush_tlb(&mut self, vaddr: Option<u64>, asid: Option<u64>, virt: bool) {
+        let (stage, root) = if virt {
+            (Stage::VS, self.vsatp)
+        } else {
+            (Stage::Single, self.satp)
+        };
+        let vpn_mask: u64 = match root.mode {
+            AddrTransMode::Bare => u64::MAX,
+            mode => {
+                let (levels, vpn_bits, _, _) = mode.geometry();
+                (1 << (levels * vpn_bits)) - 1
+            }
+        };
+        self.tlb.flush(
+            vaddr.map(|addr| addr >> 12 & vpn_mask),
+            asid,
+            stage,
+            self.hgatp.id,
+        );
+    }
+
+    // HFENCE.GVMA, which doesn't look at the guest physical address
+    pub fn flush_guest_tlb(&mut self, vmid: Option<u64>) {
+        self.tlb.flush_guest(vmid);
+    }
+
+    // for the TLB miss events
+    pub fn tlb_misses(&self) -> u64 {
+        self.tlb.stats.miss
+    }
+
+    pub fn show_tlb_stats(&self, hartid: usize) {
+        let rate = |hit: u64, miss: u64| {
+            if hit + miss == 0 {
+                0.0
+            } else {
+                hit as f64 * 100.0 / (hit + miss) as f64
+            }
         };
+        eprintln!(
+            "hart{hartid} TLB: {} hits, {} misses ({:.2}% hit)",
+            self.tlb.stats.hit,
+            self.tlb.stats.miss,
+            rate(self.tlb.stats.hit, self.tlb.stats.miss)
+        );
+        eprintln!(
+            "hart{hartid} page walk cache: {} hits, {} misses ({:.2}% hit)",
+            self.tlb.stats.walk_hit,
+            self.tlb.stats.walk_miss,
+            rate(self.tlb.stats.walk_hit, self.tlb.stats.walk_miss)
+        );
     }
 
     fn is_leaf_pte(&self, pte: u64) -> bool {
@@ -72,15 +259,43 @@ impl Mmu {
     fn trap_cause(&self, purpose: TransFor) -> TrapCause {
         match purpose {
             TransFor::Fetch => TrapCause::InstPageFault,
-            TransFor::Load => TrapCause::LoadPageFault,
+            TransFor::Load | TransFor::LoadExec => TrapCause::LoadPageFault,
             TransFor::StoreAMO => TrapCause::StoreAMOPageFault,
             TransFor::Deleg => TrapCause::InstPageFault,
         }
     }
 
+    // a PTE that can't be read or written faults as the access itself
+    fn access_fault(&self, purpose: TransFor) -> TrapCause {
+        match purpose {
+            TransFor::Fetch | TransFor::Deleg => TrapCause::InstAccessFault,
+            TransFor::Load | TransFor::LoadExec => TrapCause::LoadAccessFault,
+            TransFor::StoreAMO => TrapCause::StoreAMOAccessFault,
+        }
+    }
+
+    // a page fault in G-stage is a guest-page fault
+    fn guest_trap_cause(&self, cause: TrapCause) -> TrapCause {
+        match cause {
+            TrapCause::InstPageFault => TrapCause::InstGuestPageFault,
+            TrapCause::LoadPageFault => TrapCause::LoadGuestPageFault,
+            TrapCause::StoreAMOPageFault => TrapCause::StoreAMOGuestPageFault,
+            cause => cause,
+        }
+    }
+
+    // hardware A/D updating is enabled by menvcfg.ADUE, or henvcfg.ADUE for VS-stage
+    fn adue(&self, stage: Stage, csrs: &CSRs) -> bool {
+        match stage {
+            Stage::VS => csrs.vs_adue(),
+            _ => csrs.adue(),
+        }
+    }
+
     fn check_leaf_pte(
         &self,
         purpose: TransFor,
+        stage: Stage,
         priv_lv: PrivilegedLevel,
         csrs: &CSRs,
         pte: u64,
@@ -91,6 +306,19 @@ impl Mmu {
         let pte_u = pte >> 4 & 0x1;
         let pte_a = pte >> 6 & 0x1;
         let pte_d = pte >> 7 & 0x1;
+        let adue = self.adue(stage, csrs);
+
+        // vsstatus.SUM and MXR take over for VS-stage, though mstatus.MXR
+        // still makes executable pages readable there
+        let mstatus_mxr = csrs.read_xstatus(PrivilegedLevel::Machine, Xstatus::MXR) != 0;
+        let (sum, mxr) = match stage {
+            Stage::Single => (
+                csrs.read_xstatus(PrivilegedLevel::Machine, Xstatus::SUM) != 0,
+                mstatus_mxr,
+            ),
+            Stage::VS => (csrs.vsstatus_sum(), csrs.vsstatus_mxr() || mstatus_mxr),
+            Stage::G => (false, mstatus_mxr),
+        };
 
         self.check_pte_validity(purpose, pte)?;
 
@@ -100,12 +328,23 @@ impl Mmu {
             return Err(self.trap_cause(purpose));
         }
 
-        // check the A bit
-        if pte_a == 0 {
+        // check the A bit (Svadu sets it instead of faulting)
+        if pte_a == 0 && !adue {
             log::debugln!("invalid pte_a: {:x}", pte);
             return Err(self.trap_cause(purpose));
         }
 
+        // check sum bit
+        if purpose != TransFor::Fetch
+            && purpose != TransFor::Deleg
+            && !sum
+            && pte_u == 1
+            && priv_lv == PrivilegedLevel::Supervisor
+        {
+            log::debugln!("[SUM] invalid pte_u: {:x}", pte);
+            return Err(self.trap_cause(purpose));
+        }
+
         // check the PTE field according to translate purpose
         match purpose {
             TransFor::Fetch | TransFor::Deleg => {
@@ -115,29 +354,21 @@ impl Mmu {
                 }
             }
             TransFor::Load => {
-                // check sum bit
-                let sum = csrs.read_xstatus(PrivilegedLevel::Machine, Xstatus::SUM);
-                if sum == 0 && pte_u == 1 && priv_lv == PrivilegedLevel::Supervisor {
-                    log::debugln!("[SUM] invalid pte_u: {:x}", pte);
-                    return Err(self.trap_cause(purpose));
-                }
-
                 // check the X and R bit
-                let mxr = csrs.read_xstatus(PrivilegedLevel::Machine, Xstatus::MXR);
-                if pte_r == 0 && (mxr == 0 || pte_x == 0) {
+                if pte_r == 0 && (!mxr || pte_x == 0) {
                     log::debugln!("[MXR == {}] invalid pte_r or pte_x: {:x}", mxr, pte);
                     return Err(TrapCause::LoadPageFault);
                 }
             }
-            TransFor::StoreAMO => {
-                // check sum bit
-                let sum = csrs.read_xstatus(PrivilegedLevel::Machine, Xstatus::SUM);
-                if sum == 0 && pte_u == 1 && priv_lv == PrivilegedLevel::Supervisor {
-                    log::debugln!("[SUM] invalid pte_u: {:x}", pte);
-                    return Err(self.trap_cause(purpose));
+            // hlvx reads what it could execute, R doesn't matter
+            TransFor::LoadExec => {
+                if pte_x == 0 {
+                    log::debugln!("[hlvx] invalid pte_x: {:x}", pte);
+                    return Err(TrapCause::LoadPageFault);
                 }
-
-                if pte_w == 0 || pte_d == 0 {
+            }
+            TransFor::StoreAMO => {
+                if pte_w == 0 || (pte_d == 0 && !adue) {
                     log::debugln!("invalid pte_w: {:x}", pte);
                     return Err(TrapCause::StoreAMOPageFault);
                 }
@@ -148,112 +379,288 @@ impl Mmu {
         Ok(pte)
     }
 
-    #[allow(non_snake_case)]
-    pub fn trans_addr(
+    fn needs_ad_update(&self, purpose: TransFor, stage: Stage, csrs: &CSRs, pte: u64) -> bool {
+        let pte_a = pte >> 6 & 0x1;
+        let pte_d = pte >> 7 & 0x1;
+
+        self.adue(stage, csrs) && (pte_a == 0 || (pte_d == 0 && purpose == TransFor::StoreAMO))
+    }
+
+    // Svadu: the walker sets A (and D on stores) in the PTE on its own.
+    // The hart is the only bus master during a walk, so this read-modify-write is atomic.
+    // It's written like a store, which drops cached instructions and reservations there.
+    fn update_ad_bits(
         &mut self,
         purpose: TransFor,
+        stage: Stage,
+        pte_addr: u64,
+        pte: u64,
+        csrs: &CSRs,
+        mem: &mut Dma,
+    ) -> Result<u64, TrapCause> {
+        let pte = match purpose {
+            TransFor::StoreAMO => pte | 1 << 7 | 1 << 6,
+            _ => pte | 1 << 6,
+        };
+        log::debugln!("update A/D bits: 0x{:x}", pte);
+
+        // a VS-stage PTE is written through G-stage, which must allow it
+        let pte_addr = match stage {
+            Stage::VS => self.g_translate(
+                TransFor::StoreAMO,
+                purpose,
+                pte_addr,
+                TINST_PTE_WRITE,
+                csrs,
+                mem,
+            )?,
+            _ => pte_addr,
+        };
+
+        match *self.isa {
+            Isa::Rv32 => mem.write_u32(pte_addr, pte as u32),
+            Isa::Rv64 => mem.write_u64(pte_addr, pte),
+        }
+        .map_err(|_| self.access_fault(purpose))?;
+
+        Ok(pte)
+    }
+
+    // returns the address of the leaf PTE (a guest physical one for
+    // VS-stage), the PTE itself, its PPN and level
+    #[allow(clippy::too_many_arguments)]
+    fn walk(
+        &mut self,
+        purpose: TransFor,
+        stage: Stage,
+        levels: u32,
+        vpn_bits: u32,
+        pte_size: u64,
+        vpn: &dyn Fn(u32) -> u64,
+        csrs: &CSRs,
+        mem: &mut Dma,
+    ) -> Result<(u64, u64, u64, u32), TrapCause> {
+        const PAGESIZE: u64 = 4096; // 2^12
+
+        let mut ppn = self.root(stage).ppn;
+        let mut level = levels - 1;
+        loop {
+            let pte_addr = ppn * PAGESIZE + vpn(level) * pte_size;
+            log::debugln!("pte_addr({}): 0x{:x}", level, pte_addr);
+            // the walk cache is indexed by the supervisor physical address
+            let pte_paddr = match stage {
+                Stage::VS => {
+                    self.g_translate(TransFor::Load, purpose, pte_addr, TINST_PTE_READ, csrs, mem)?
+                }
+                _ => pte_addr,
+            };
+            let cached = self.tlb.lookup_pte(pte_paddr);
+            let pte = match cached {
+                Some(pte) => pte,
+                None => {
+                    // the root PPN and the PTEs are up to the guest
+                    let pte = match *self.isa {
+                        Isa::Rv32 => mem.read_u32(pte_paddr).map(|pte| pte as u64),
+                        Isa::Rv64 => mem.read_u64(pte_paddr),
+                    }
+                    .map_err(|_| self.access_fault(purpose))?;
+                    self.check_pte_validity(purpose, pte)?
+                }
+            };
+            log::debugln!("pte({}): 0x{:x}", level, pte);
+
+            ppn = match *self.isa {
+                Isa::Rv32 => pte >> 10 & 0x3f_ffff,
+                Isa::Rv64 => pte >> 10 & 0xfff_ffff_ffff,
+            };
+
+            if self.is_leaf_pte(pte) {
+                // a superpage must be aligned to its size
+                if ppn & ((1 << (level * vpn_bits)) - 1) != 0 {
+                    log::debugln!("misaligned superpage: {:x}", pte);
+                    return Err(self.trap_cause(purpose));
+                }
+                return Ok((pte_addr, pte, ppn, level));
+            }
+
+            if level == 0 {
+                return Err(self.trap_cause(purpose));
+            }
+            // the walk cache only holds non-leaf PTEs, so only they count
+            match cached {
+                Some(_) => self.tlb.stats.walk_hit += 1,
+                None => {
+                    self.tlb.stats.walk_miss += 1;
+                    if !self.probing {
+                        self.tlb.insert_pte(pte_paddr, pte);
+                    }
+                }
+            }
+            level -= 1;
+            log::debugln!("PPN{}: 0x{:x}", level, ppn);
+        }
+    }
+
+    // one stage of the translation, without the PMP check
+    fn translate(
+        &mut self,
+        purpose: TransFor,
+        stage: Stage,
         addr: u64,
         csrs: &CSRs,
-        dram: &mut Dram,
+        mem: &mut Dma,
         priv_lv: PrivilegedLevel,
     ) -> Result<u64, TrapCause> {
-        const PAGESIZE: u64 = 4096; // 2^12
+        let root = self.root(stage);
+        if root.mode == AddrTransMode::Bare {
+            return Ok(addr);
+        }
+
+        let (levels, vpn_bits, pte_size, va_bits) = root.mode.geometry();
+        // Sv39x4 and the others widen the root table by two bits
+        let wide_bits = if stage == Stage::G { 2 } else { 0 };
+        let page_off = addr & 0xFFF;
+        let vpn = |level: u32| {
+            let bits = if level == levels - 1 {
+                vpn_bits + wide_bits
+            } else {
+                vpn_bits
+            };
+            addr >> (12 + level * vpn_bits) & ((1 << bits) - 1)
+        };
+
+        if stage == Stage::G {
+            // guest physical addresses are zero-extended
+            if addr >> (va_bits + wide_bits) != 0 {
+                log::debugln!("invalid guest physical address: {:x}", addr);
+                return Err(self.trap_cause(purpose));
+            }
+        } else if root.mode != AddrTransMode::Sv32 {
+            // upper bits of the address must be equal to the bit VA-1
+            let upper = (addr as i64) >> (va_bits - 1);
+            if upper != 0 && upper != -1 {
+                log::debugln!("invalid virtual address: {:x}", addr);
+                return Err(self.trap_cause(purpose));
+            }
+        }
+
+        // G-stage permissions are checked as if for U-mode
+        let priv_lv = match stage {
+            Stage::G => PrivilegedLevel::User,
+            _ => priv_lv,
+        };
+        let (asid, vmid) = match stage {
+            Stage::Single => (root.id, 0),
+            Stage::VS => (root.id, self.hgatp.id),
+            Stage::G => (0, root.id),
+        };
+
+        // the VPN key keeps the translated bits only
+        let vpn_key = addr >> 12 & ((1 << (levels * vpn_bits + wide_bits)) - 1);
+        let entry = match self.tlb.lookup(vpn_key, asid, vmid, stage, priv_lv) {
+            Some(entry)
+                if self
+                    .check_leaf_pte(purpose, stage, priv_lv, csrs, entry.pte)
+                    .is_ok()
+                    && !self.needs_ad_update(purpose, stage, csrs, entry.pte) =>
+            {
+                entry
+            }
+            _ => {
+                // walk again to raise the fault from the current page table
+                if !self.probing {
+                    self.tlb.remove(vpn_key);
+                }
+                let (pte_addr, pte, ppn, level) =
+                    self.walk(purpose, stage, levels, vpn_bits, pte_size, &vpn, csrs, mem)?;
+                self.check_leaf_pte(purpose, stage, priv_lv, csrs, pte)?;
+                let pte = if self.needs_ad_update(purpose, stage, csrs, pte) && !self.probing {
+                    self.update_ad_bits(purpose, stage, pte_addr, pte, csrs, mem)?
+                } else {
+                    pte
+                };
+
+                let entry = TlbEntry {
+                    vpn: vpn_key,
+                    asid,
+                    vmid,
+                    stage,
+                    priv_lv,
+                    pte,
+                    ppn,
+                    level,
+                    vpn_bits,
+                };
+                if !self.probing {
+                    self.tlb.insert(entry);
+                }
+                entry
+            }
+        };
 
-        // update trans_mode and ppn
-        self.update_ppn_and_mode(csrs);
+        let superpage_mask: u64 = (1 << (entry.level * vpn_bits)) - 1;
+        let paddr = ((entry.ppn & !superpage_mask) | (vpn_key & superpage_mask)) << 12 | page_off;
+
+        log::debugln!(
+            "raw address:{:x}\n\t=> transrated address:{:x}",
+            addr,
+            paddr,
+        );
+
+        Ok(paddr)
+    }
+
+    // G-stage checks the access it's given, and reports a fault as a
+    // guest-page fault of the original access
+    fn g_translate(
+        &mut self,
+        access: TransFor,
+        purpose: TransFor,
+        gpa: u64,
+        tinst: u64,
+        csrs: &CSRs,
+        mem: &mut Dma,
+    ) -> Result<u64, TrapCause> {
+        self.translate(access, Stage::G, gpa, csrs, mem, PrivilegedLevel::User)
+            .map_err(|cause| {
+                self.guest_fault.gpa = gpa;
+                self.guest_fault.tinst = tinst;
+                self.guest_trap_cause(match cause {
+                    TrapCause::InstPageFault
+                    | TrapCause::LoadPageFault
+                    | TrapCause::StoreAMOPageFault => self.trap_cause(purpose),
+                    cause => cause,
+                })
+            })
+    }
+
+    // virt: the access is made by a guest (or for one, by hlv and hsv)
+    pub fn trans_addr(
+        &mut self,
+        purpose: TransFor,
+        addr: u64,
+        csrs: &CSRs,
+        mem: &mut Dma,
+        priv_lv: PrivilegedLevel,
+        virt: bool,
+    ) -> Result<u64, TrapCause> {
+        // update the roots of the page tables
+        self.update_roots(csrs);
+        self.guest_fault = GuestFault {
+            gva: virt,
+            ..GuestFault::default()
+        };
 
         match priv_lv {
-            PrivilegedLevel::Supervisor | PrivilegedLevel::User => match self.trans_mode {
+            PrivilegedLevel::Supervisor | PrivilegedLevel::User if virt => {
+                let gpa = self.translate(purpose, Stage::VS, addr, csrs, mem, priv_lv)?;
+                let paddr = self.g_translate(purpose, purpose, gpa, 0, csrs, mem)?;
+                self.pmp(purpose, paddr, priv_lv, csrs)
+            }
+            PrivilegedLevel::Supervisor | PrivilegedLevel::User => match self.satp.mode {
                 AddrTransMode::Bare => Ok(addr),
-                AddrTransMode::Sv32 | AddrTransMode::Sv39 => {
-                    let page_off = addr & 0xFFF;
-                    let mut ppn = self.ppn;
-                    let vpn = match *self.isa {
-                        Isa::Rv32 => vec![addr >> 12 & 0x3FF, addr >> 22 & 0x3FF],
-                        Isa::Rv64 => {
-                            vec![addr >> 12 & 0x1FF, addr >> 21 & 0x1FF, addr >> 30 & 0x1FF]
-                        }
-                    };
-                    let pte_size: u64 = match *self.isa {
-                        Isa::Rv32 => 4,
-                        Isa::Rv64 => 8,
-                    };
-                    let mut level: i32 = match *self.isa {
-                        Isa::Rv32 => 1, // 2 - 1
-                        Isa::Rv64 => 2, // 3 - 1
-                    };
-
-                    let pte = loop {
-                        let pte_addr = ppn * PAGESIZE + vpn[level as usize] * pte_size;
-                        log::debugln!("pte_addr({}): 0x{:x}", level, pte_addr);
-                        let pte =
-                            match *self.isa {
-                                Isa::Rv32 => self
-                                    .check_pte_validity(purpose, dram.load32(pte_addr).unwrap())?,
-                                Isa::Rv64 => self
-                                    .check_pte_validity(purpose, dram.load64(pte_addr).unwrap())?,
-                            };
-                        log::debugln!("pte({}): 0x{:x}", level, pte);
-
-                        if self.is_leaf_pte(pte) {
-                            break pte;
-                        }
-
-                        level -= 1;
-                        if level < 0 {
-                            return Err(self.trap_cause(purpose));
-                        }
-
-                        ppn = match *self.isa {
-                            Isa::Rv32 => pte >> 10 & 0x3fff_ffff,
-                            Isa::Rv64 => pte >> 10 & 0xfff_ffff_ffff,
-                        };
-                        log::debugln!("PPN{}: 0x{:x}", level, ppn);
-                    };
-
-                    self.check_leaf_pte(purpose, priv_lv, csrs, pte)?;
-                    let ppn = match *self.isa {
-                        Isa::Rv32 => vec![pte >> 10 & 0x3FF, pte >> 20 & 0xFFF],
-                        Isa::Rv64 => {
-                            vec![pte >> 10 & 0x1FF, pte >> 19 & 0x1FF, pte >> 28 & 0x3FF_FFFF]
-                        }
-                    };
-
-                    let paddr = match *self.isa {
-                        Isa::Rv32 => match level {
-                            0 => ppn[1] << 22 | ppn[0] << 12 | page_off,
-                            1 => {
-                                if ppn[0] != 0 {
-                                    return Err(self.trap_cause(purpose));
-                                }
-                                ppn[1] << 22 | vpn[0] << 12 | page_off
-                            }
-                            _ => return Err(self.trap_cause(purpose)),
-                        },
-                        Isa::Rv64 => match level {
-                            0 => ppn[2] << 30 | ppn[1] << 21 | ppn[0] << 12 | page_off,
-                            1 => {
-                                if ppn[0] != 0 {
-                                    return Err(self.trap_cause(purpose));
-                                }
-                                ppn[2] << 30 | ppn[1] << 21 | vpn[0] << 12 | page_off
-                            }
-                            2 => {
-                                if ppn[0] != 0 || ppn[1] != 0 {
-                                    return Err(self.trap_cause(purpose));
-                                }
-                                ppn[2] << 30 | vpn[1] << 21 | vpn[0] << 12 | page_off
-                            }
-                            _ => return Err(self.trap_cause(purpose)),
-                        },
-                    };
-
-                    log::debugln!(
-                        "raw address:{:x}\n\t=> transrated address:{:x}",
-                        addr,
-                        paddr,
-                    );
-
+                _ => {
+                    let paddr = self.translate(purpose, Stage::Single, addr, csrs, mem, priv_lv)?;
                     self.pmp(purpose, paddr, priv_lv, csrs)
                 }
             },
@@ -261,4 +668,253 @@ impl Mmu {
             _ => self.pmp(purpose, addr, priv_lv, csrs),
         }
     }
+
+    // the translation of a load, for the debugger: nothing is cached or
+    // counted, and the A/D bits are left as they are
+    pub fn probe_addr(
+        &mut self,
+        addr: u64,
+        csrs: &CSRs,
+        mem: &mut Dma,
+        priv_lv: PrivilegedLevel,
+        virt: bool,
+    ) -> Option<u64> {
+        let (stats, guest_fault) = (self.tlb.stats, self.guest_fault);
+        self.probing = true;
+        let paddr = self.trans_addr(TransFor::Load, addr, csrs, mem, priv_lv, virt);
+        self.probing = false;
+        self.tlb.stats = stats;
+        self.guest_fault = guest_fault;
+        paddr.ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::Arguments;
+    use std::cell::RefCell;
+
+    #[test]
+    fn sv48_test() {
+        let isa = Rc::new(Isa::Rv64);
+        let mut bus = Bus::for_test(&Arguments::for_elf("./HelloWorld"), Isa::Rv64);
+        let mut csrs = CSRs::new(isa.clone(), Rc::new(RefCell::new(0))).init();
+        let mut mmu = Mmu::new(isa.clone());
+
+        // root(0x80001000) -> 0x80002000 -> 0x80003000 -> 0x80004000
+        let pte = |ppn: u64, flags: u64| ppn << 10 | flags;
+        bus.store64(0x80001000 + 8, pte(0x80002, 0x1)).unwrap();
+        bus.store64(0x80002000 + 2 * 8, pte(0x80003, 0x1)).unwrap();
+        bus.store64(0x80003000 + 3 * 8, pte(0x80004, 0x1)).unwrap();
+        bus.store64(0x80004000 + 4 * 8, pte(0x80010, 0xc7)).unwrap();
+        bus.store64(0x80003000 + 5 * 8, pte(0x80200, 0xc7)).unwrap();
+        bus.store64(0x80003000 + 6 * 8, pte(0x80201, 0xc7)).unwrap();
+        bus.store64(0x80003000 + 7 * 8, pte(0x1_0000_0000, 0x1))
+            .unwrap();
+
+        // satp.MODE is WARL
+        let satp = 9 << 60 | 0x80001;
+        let mut sv39_csrs = CSRs::new(isa, Rc::new(RefCell::new(0)))
+            .init()
+            .with_trans_mode(AddrTransMode::Sv39);
+        sv39_csrs.write(CSRname::satp.wrap(), satp).unwrap();
+        assert_eq!(sv39_csrs.read(CSRname::satp.wrap()).unwrap(), 0);
+        csrs.write(CSRname::satp.wrap(), satp).unwrap();
+        assert_eq!(csrs.read(CSRname::satp.wrap()).unwrap(), satp);
+
+        let mut trans = |addr: u64| {
+            mmu.trans_addr(
+                TransFor::Load,
+                addr,
+                &csrs,
+                &mut bus.memory(),
+                PrivilegedLevel::Supervisor,
+                false,
+            )
+        };
+        let vaddr = |vpn: [u64; 4], off: u64| {
+            vpn[3] << 39 | vpn[2] << 30 | vpn[1] << 21 | vpn[0] << 12 | off
+        };
+
+        assert_eq!(trans(vaddr([4, 3, 2, 1], 0x56)).unwrap(), 0x80010056);
+        // 2MiB megapage
+        assert_eq!(trans(vaddr([0x12, 5, 2, 1], 0x345)).unwrap(), 0x80212345);
+        // misaligned megapage
+        assert!(matches!(
+            trans(vaddr([0, 6, 2, 1], 0)),
+            Err(TrapCause::LoadPageFault)
+        ));
+        // a table outside of DRAM is an access fault
+        assert!(matches!(
+            trans(vaddr([0, 7, 2, 1], 0)),
+            Err(TrapCause::LoadAccessFault)
+        ));
+        // bits 63:48 must be the copy of the bit 47
+        assert!(matches!(
+            trans(0x0000_8000_0000_0000),
+            Err(TrapCause::LoadPageFault)
+        ));
+        // leaf PTEs are never in the walk cache, so they don't count
+        assert_eq!((mmu.tlb.stats.walk_hit, mmu.tlb.stats.walk_miss), (6, 4));
+
+        // switching to another address space and back keeps the translations
+        let mut trans = |csrs: &CSRs, addr: u64| {
+            mmu.trans_addr(
+                TransFor::Load,
+                addr,
+                csrs,
+                &mut bus.memory(),
+                PrivilegedLevel::Supervisor,
+                false,
+            )
+        };
+        csrs.write(CSRname::satp.wrap(), satp | 1 << 44).unwrap();
+        assert!(trans(&csrs, vaddr([0x12, 5, 2, 1], 0)).is_ok());
+        csrs.write(CSRname::satp.wrap(), satp).unwrap();
+        assert!(trans(&csrs, vaddr([4, 3, 2, 1], 0)).is_ok());
+        assert_eq!(mmu.tlb.stats.hit, 1);
+    }
+
+    #[test]
+    fn svadu_test() {
+        let isa = Rc::new(Isa::Rv64);
+        let mut bus = Bus::for_test(&Arguments::for_elf("./HelloWorld"), Isa::Rv64);
+
+        // Sv39: root(0x80001000) -> 0x80002000 -> 0x80003000, leaf with A = D = 0
+        let leaf_addr = 0x80003000 + 3 * 8;
+        bus.store64(0x80001000 + 8, 0x80002 << 10 | 0x1).unwrap();
+        bus.store64(0x80002000 + 2 * 8, 0x80003 << 10 | 0x1)
+            .unwrap();
+        bus.store64(leaf_addr, 0x80010 << 10 | 0x7).unwrap();
+        let vaddr = 1 << 30 | 2 << 21 | 3 << 12 | 0x10;
+        let satp = 8 << 60 | 0x80001;
+
+        // without Svadu, clear A/D bits raise page faults and ADUE is read-only zero
+        let mut csrs = CSRs::new(isa.clone(), Rc::new(RefCell::new(0))).init();
+        let mut mmu = Mmu::new(isa.clone());
+        csrs.write(CSRname::satp.wrap(), satp).unwrap();
+        csrs.write(CSRname::menvcfg.wrap(), 1 << 61).unwrap();
+        assert_eq!(csrs.read(CSRname::menvcfg.wrap()).unwrap(), 0);
+        let s_mode = PrivilegedLevel::Supervisor;
+        assert!(matches!(
+            mmu.trans_addr(
+                TransFor::Load,
+                vaddr,
+                &csrs,
+                &mut bus.memory(),
+                s_mode,
+                false
+            ),
+            Err(TrapCause::LoadPageFault)
+        ));
+        assert_eq!(bus.load64(leaf_addr).unwrap() & 0xc0, 0);
+
+        // with Svadu, the walker sets A on loads and D on stores
+        let mut csrs = CSRs::new(isa.clone(), Rc::new(RefCell::new(0)))
+            .init()
+            .with_svadu(true);
+        let mut mmu = Mmu::new(isa);
+        csrs.write(CSRname::satp.wrap(), satp).unwrap();
+        assert_eq!(csrs.read(CSRname::menvcfg.wrap()).unwrap(), 1 << 61);
+        // the debugger's view leaves the PTE, the TLB and the counters alone
+        assert_eq!(
+            mmu.probe_addr(vaddr, &csrs, &mut bus.memory(), s_mode, false),
+            Some(0x80010010)
+        );
+        assert_eq!(bus.load64(leaf_addr).unwrap() & 0xc0, 0);
+        assert_eq!((mmu.tlb.stats.miss, mmu.tlb.stats.walk_miss), (0, 0));
+        assert_eq!(mmu.tlb.lookup_pte(0x80001000 + 8), None);
+        bus.reservations.reserve(0, leaf_addr);
+        assert_eq!(
+            mmu.trans_addr(
+                TransFor::Load,
+                vaddr,
+                &csrs,
+                &mut bus.memory(),
+                s_mode,
+                false
+            )
+            .unwrap(),
+            0x80010010
+        );
+        assert_eq!(bus.load64(leaf_addr).unwrap() & 0xc0, 0x40);
+        // the update is a store, which breaks a reservation on the PTE
+        assert_eq!(bus.reservations.get(0), None);
+        // the cached translation has D = 0, so a store must walk again
+        assert_eq!(
+            mmu.trans_addr(
+                TransFor::StoreAMO,
+                vaddr,
+                &csrs,
+                &mut bus.memory(),
+                s_mode,
+                false
+            )
+            .unwrap(),
+            0x80010010
+        );
+        assert_eq!(bus.load64(leaf_addr).unwrap() & 0xc0, 0xc0);
+
+        // the OS can turn hardware updating off through menvcfg.ADUE
+        bus.store64(leaf_addr, 0x80010 << 10 | 0x7).unwrap();
+        csrs.bitclr(CSRname::menvcfg.wrap(), 1 << 61).unwrap();
+        mmu.flush_tlb(None, None, false);
+        assert!(matches!(
+            mmu.trans_addr(
+                TransFor::StoreAMO,
+                vaddr,
+                &csrs,
+                &mut bus.memory(),
+                s_mode,
+                false
+            ),
+            Err(TrapCause::StoreAMOPageFault)
+        ));
+    }
+
+    #[test]
+    fn two_stage_test() {
+        let isa = Rc::new(Isa::Rv64);
+        let mut bus = Bus::for_test(&Arguments::for_elf("./HelloWorld"), Isa::Rv64);
+        let mut csrs = CSRs::new(isa.clone(), Rc::new(RefCell::new(0)))
+            .init()
+            .with_hypervisor(true);
+        let mut mmu = Mmu::new(isa);
+        let pte = |ppn: u64, flags: u64| ppn << 10 | flags;
+
+        // Sv39x4: the 16KiB root at 0x80004000 maps the guest physical 1GiB
+        // from 0x40000000 onto 0x80000000
+        bus.store64(0x80004000 + 8, pte(0x80000, 0xdf)).unwrap();
+        csrs.write(CSRname::hgatp.wrap(), 8 << 60 | 0x80004)
+            .unwrap();
+
+        // Sv39 in the guest: root(0x40008000) -> 0x40009000 -> 0x4000a000,
+        // 0x1000 -> 0x4000b000 and 0x2000 -> 0x90000000, which G-stage lacks
+        bus.store64(0x80008000, pte(0x40009, 0x1)).unwrap();
+        bus.store64(0x80009000, pte(0x4000a, 0x1)).unwrap();
+        bus.store64(0x8000a000 + 8, pte(0x4000b, 0xc7)).unwrap();
+        bus.store64(0x8000a000 + 2 * 8, pte(0x90000, 0xc7)).unwrap();
+        csrs.write(CSRname::vsatp.wrap(), 8 << 60 | 0x40008)
+            .unwrap();
+
+        let s_mode = PrivilegedLevel::Supervisor;
+        let mut trans = |csrs: &CSRs, addr: u64| {
+            mmu.trans_addr(TransFor::Load, addr, csrs, &mut bus.memory(), s_mode, true)
+                .map_err(|cause| (cause, mmu.guest_fault()))
+        };
+        assert_eq!(trans(&csrs, 0x1010).unwrap(), 0x8000b010);
+        let (cause, fault) = trans(&csrs, 0x2010).unwrap_err();
+        assert!(matches!(cause, TrapCause::LoadGuestPageFault));
+        assert_eq!((fault.gva, fault.gpa, fault.tinst), (true, 0x90000010, 0));
+
+        // a VS-stage PTE outside the guest memory faults as an implicit read;
+        // another ASID, as the old translations are still cached
+        csrs.write(CSRname::vsatp.wrap(), 8 << 60 | 1 << 44 | 0x80000)
+            .unwrap();
+        let (cause, fault) = trans(&csrs, 0x1010).unwrap_err();
+        assert!(matches!(cause, TrapCause::LoadGuestPageFault));
+        assert_eq!((fault.gpa, fault.tinst), (0x80000000, TINST_PTE_READ));
+    }
 }
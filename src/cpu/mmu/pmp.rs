@@ -20,7 +20,8 @@ impl Mmu {
                     return Err(TrapCause::InstPageFault);
                 }
             }
-            TransFor::Load => {
+            // hlvx still needs read permission in the physical memory
+            TransFor::Load | TransFor::LoadExec => {
                 if pmp_r != 1 {
                     log::debugln!("invalid pmp_r: {:x}", pmpcfg);
                     return Err(TrapCause::LoadPageFault);
@@ -102,7 +103,7 @@ impl Mmu {
         } else {
             Err(match purpose {
                 TransFor::Fetch | TransFor::Deleg => TrapCause::InstPageFault,
-                TransFor::Load => TrapCause::LoadPageFault,
+                TransFor::Load | TransFor::LoadExec => TrapCause::LoadPageFault,
                 TransFor::StoreAMO => TrapCause::StoreAMOPageFault,
             })
         }
@@ -0,0 +1,217 @@
+use super::Stage;
+use crate::cpu::PrivilegedLevel;
+use std::collections::HashMap;
+
+const TLB_SIZE: usize = 1024;
+
+#[derive(Copy, Clone)]
+pub struct TlbEntry {
+    pub vpn: u64,
+    pub asid: u64,
+    pub vmid: u64,
+    pub stage: Stage,
+    pub priv_lv: PrivilegedLevel,
+    pub pte: u64,
+    pub ppn: u64,
+    pub level: u32,
+    pub vpn_bits: u32,
+}
+
+impl TlbEntry {
+    // a superpage entry covers every vpn that shares its upper vpn fields
+    fn covers(&self, vpn: u64) -> bool {
+        let shift = self.level * self.vpn_bits;
+        self.vpn >> shift == vpn >> shift
+    }
+
+    // the G bit means nothing in G-stage PTEs
+    fn is_global(&self) -> bool {
+        self.stage != Stage::G && self.pte >> 5 & 0x1 == 1
+    }
+
+    // translations of a guest belong to its VMID
+    fn in_stage(&self, stage: Stage, vmid: u64) -> bool {
+        self.stage == stage && (stage == Stage::Single || self.vmid == vmid)
+    }
+}
+
+#[derive(Copy, Clone, Default)]
+pub struct TlbStats {
+    pub hit: u64,
+    pub miss: u64,
+    pub walk_hit: u64,
+    pub walk_miss: u64,
+}
+
+pub struct Tlb {
+    entries: Vec<Option<TlbEntry>>,
+    // non-leaf PTEs indexed by their physical address
+    walk_cache: HashMap<u64, u64>,
+    pub stats: TlbStats,
+}
+
+impl Tlb {
+    pub fn new() -> Self {
+        Tlb {
+            entries: vec![None; TLB_SIZE],
+            walk_cache: HashMap::new(),
+            stats: TlbStats::default(),
+        }
+    }
+
+    pub fn lookup(
+        &mut self,
+        vpn: u64,
+        asid: u64,
+        vmid: u64,
+        stage: Stage,
+        priv_lv: PrivilegedLevel,
+    ) -> Option<TlbEntry> {
+        match self.entries[vpn as usize % TLB_SIZE] {
+            Some(entry)
+                if entry.vpn == vpn
+                    && entry.in_stage(stage, vmid)
+                    && entry.priv_lv == priv_lv
+                    && (entry.asid == asid || entry.is_global()) =>
+            {
+                self.stats.hit += 1;
+                Some(entry)
+            }
+            _ => {
+                self.stats.miss += 1;
+                None
+            }
+        }
+    }
+
+    pub fn insert(&mut self, entry: TlbEntry) {
+        self.entries[entry.vpn as usize % TLB_SIZE] = Some(entry);
+    }
+
+    pub fn remove(&mut self, vpn: u64) {
+        self.entries[vpn as usize % TLB_SIZE] = None;
+    }
+
+    pub fn lookup_pte(&self, pte_addr: u64) -> Option<u64> {
+        self.walk_cache.get(&pte_addr).copied()
+    }
+
+    pub fn insert_pte(&mut self, pte_addr: u64, pte: u64) {
+        self.walk_cache.insert(pte_addr, pte);
+    }
+
+    // SFENCE.VMA and HFENCE.VVMA: None means "all addresses" or "all
+    // address spaces" of the stage (and the VMID for VS-stage)
+    pub fn flush(&mut self, vpn: Option<u64>, asid: Option<u64>, stage: Stage, vmid: u64) {
+        for slot in self.entries.iter_mut() {
+            if let Some(entry) = slot {
+                let vpn_matched = vpn.is_none_or(|vpn| entry.covers(vpn));
+                let asid_matched = asid.is_none_or(|asid| entry.asid == asid && !entry.is_global());
+                if entry.in_stage(stage, vmid) && vpn_matched && asid_matched {
+                    *slot = None;
+                }
+            }
+        }
+
+        // only leaf entries are invalidated when the address is specified
+        if vpn.is_none() {
+            self.walk_cache.clear();
+        }
+    }
+
+    // HFENCE.GVMA: every translation of the guest, as the VS-stage walks went
+    // through the G-stage
+    pub fn flush_guest(&mut self, vmid: Option<u64>) {
+        for slot in self.entries.iter_mut() {
+            if let Some(entry) = slot {
+                if entry.stage != Stage::Single && vmid.is_none_or(|vmid| entry.vmid == vmid) {
+                    *slot = None;
+                }
+            }
+        }
+        self.walk_cache.clear();
+    }
+}
+
+impl Default for Tlb {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(vpn: u64, asid: u64, pte: u64, level: u32) -> TlbEntry {
+        TlbEntry {
+            vpn,
+            asid,
+            vmid: 0,
+            stage: Stage::Single,
+            priv_lv: PrivilegedLevel::Supervisor,
+            pte,
+            ppn: vpn,
+            level,
+            vpn_bits: 9,
+        }
+    }
+
+    #[test]
+    fn sfence_test() {
+        let mut tlb = Tlb::new();
+        let lookup = |tlb: &mut Tlb, vpn, asid| {
+            tlb.lookup(vpn, asid, 0, Stage::Single, PrivilegedLevel::Supervisor)
+        };
+        let flush = |tlb: &mut Tlb, vpn, asid| tlb.flush(vpn, asid, Stage::Single, 0);
+
+        tlb.insert(entry(0x10, 1, 0xcf, 0));
+        tlb.insert(entry(0x11, 2, 0xcf, 0));
+        tlb.insert(entry(0x12, 1, 0xef, 0)); // global
+        tlb.insert(entry(0x200, 1, 0xcf, 1)); // 2MiB megapage
+        assert!(lookup(&mut tlb, 0x10, 1).is_some());
+        assert!(lookup(&mut tlb, 0x10, 2).is_none());
+        assert!(lookup(&mut tlb, 0x12, 2).is_some());
+        assert_eq!((tlb.stats.hit, tlb.stats.miss), (2, 1));
+
+        // flush a single address space: global mappings survive
+        flush(&mut tlb, None, Some(1));
+        assert!(lookup(&mut tlb, 0x10, 1).is_none());
+        assert!(lookup(&mut tlb, 0x11, 2).is_some());
+        assert!(lookup(&mut tlb, 0x12, 1).is_some());
+        assert!(lookup(&mut tlb, 0x200, 1).is_none());
+
+        // flush an address inside a megapage
+        tlb.insert(entry(0x200, 1, 0xcf, 1));
+        flush(&mut tlb, Some(0x3ff), None);
+        assert!(lookup(&mut tlb, 0x200, 1).is_none());
+        assert!(lookup(&mut tlb, 0x11, 2).is_some());
+
+        tlb.insert_pte(0x8000_1000, 0x21);
+        flush(&mut tlb, Some(0x11), Some(2));
+        assert!(lookup(&mut tlb, 0x11, 2).is_none());
+        assert_eq!(tlb.lookup_pte(0x8000_1000), Some(0x21));
+        flush(&mut tlb, None, None);
+        assert_eq!(tlb.lookup_pte(0x8000_1000), None);
+        assert!(lookup(&mut tlb, 0x12, 1).is_none());
+
+        // a guest's translations are only found under its VMID, and HFENCE.GVMA
+        // leaves the host's alone
+        let vs =
+            |tlb: &mut Tlb, vmid| tlb.lookup(0x20, 1, vmid, Stage::VS, PrivilegedLevel::Supervisor);
+        tlb.insert(TlbEntry {
+            vmid: 3,
+            stage: Stage::VS,
+            ..entry(0x20, 1, 0xcf, 0)
+        });
+        assert!(lookup(&mut tlb, 0x20, 1).is_none());
+        assert!(vs(&mut tlb, 4).is_none());
+        assert!(vs(&mut tlb, 3).is_some());
+        flush(&mut tlb, None, None);
+        assert!(vs(&mut tlb, 3).is_some());
+        tlb.insert(entry(0x11, 2, 0xcf, 0));
+        tlb.flush_guest(Some(3));
+        assert!(vs(&mut tlb, 3).is_none());
+        assert!(lookup(&mut tlb, 0x11, 2).is_some());
+    }
+}
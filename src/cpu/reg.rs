@@ -1,6 +1,9 @@
 use crate::cpu::instruction::reg2str;
 use crate::cpu::CrossIsaUtil;
+use crate::snapshot::{Reader, Snapshot, Writer};
 use crate::{log, Isa};
+use std::fmt;
+use std::io;
 use std::rc::Rc;
 
 pub struct Register {
@@ -94,8 +97,32 @@ impl Register {
     }
 }
 
+// four registers per line with their ABI names
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (num, reg) in self.regs.iter().enumerate() {
+            match *self.isa {
+                Isa::Rv32 => write!(f, "{:>4}: 0x{:08x}", reg2str(num), reg)?,
+                Isa::Rv64 => write!(f, "{:>4}: 0x{:016x}", reg2str(num), reg)?,
+            }
+            f.write_str(if num % 4 == 3 { "\n" } else { "  " })?;
+        }
+        Ok(())
+    }
+}
+
 impl Default for Register {
     fn default() -> Self {
         Self::new(Isa::Rv64.into())
     }
 }
+
+impl Snapshot for Register {
+    fn save(&self, w: &mut Writer) -> io::Result<()> {
+        w.u64s(&self.regs)
+    }
+
+    fn restore(&mut self, r: &mut Reader) -> io::Result<()> {
+        r.u64s_into(&mut self.regs)
+    }
+}
@@ -0,0 +1,811 @@
+// IEEE 754 binary32/binary64 arithmetic with RISC-V rounding modes and exception flags
+
+pub const FLAG_NV: u64 = 0b10000; // invalid operation
+pub const FLAG_DZ: u64 = 0b01000; // divide by zero
+pub const FLAG_OF: u64 = 0b00100; // overflow
+pub const FLAG_UF: u64 = 0b00010; // underflow
+pub const FLAG_NX: u64 = 0b00001; // inexact
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RoundingMode {
+    Rne = 0b000,
+    Rtz = 0b001,
+    Rdn = 0b010,
+    Rup = 0b011,
+    Rmm = 0b100,
+}
+
+impl RoundingMode {
+    pub fn from_bits(rm: u64) -> Option<Self> {
+        match rm {
+            0b000 => Some(RoundingMode::Rne),
+            0b001 => Some(RoundingMode::Rtz),
+            0b010 => Some(RoundingMode::Rdn),
+            0b011 => Some(RoundingMode::Rup),
+            0b100 => Some(RoundingMode::Rmm),
+            _ => None,
+        }
+    }
+
+    fn round_up(self, sign: bool, lsb: bool, rem: u128, half: u128) -> bool {
+        match self {
+            RoundingMode::Rne => rem > half || (rem == half && lsb),
+            RoundingMode::Rtz => false,
+            RoundingMode::Rdn => sign && rem != 0,
+            RoundingMode::Rup => !sign && rem != 0,
+            RoundingMode::Rmm => rem >= half,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FloatFmt {
+    Single,
+    Double,
+}
+
+impl FloatFmt {
+    fn exp_bits(self) -> u32 {
+        match self {
+            FloatFmt::Single => 8,
+            FloatFmt::Double => 11,
+        }
+    }
+
+    fn frac_bits(self) -> u32 {
+        match self {
+            FloatFmt::Single => 23,
+            FloatFmt::Double => 52,
+        }
+    }
+
+    fn bias(self) -> i32 {
+        (1 << (self.exp_bits() - 1)) - 1
+    }
+
+    fn max_exp(self) -> i32 {
+        (1 << self.exp_bits()) - 1
+    }
+
+    fn sign_bit(self) -> u64 {
+        1 << (self.exp_bits() + self.frac_bits())
+    }
+
+    pub fn canonical_nan(self) -> u64 {
+        match self {
+            FloatFmt::Single => 0x7fc0_0000,
+            FloatFmt::Double => 0x7ff8_0000_0000_0000,
+        }
+    }
+
+    fn infinity(self, sign: bool) -> u64 {
+        (self.max_exp() as u64) << self.frac_bits() | if sign { self.sign_bit() } else { 0 }
+    }
+
+    fn zero(self, sign: bool) -> u64 {
+        if sign {
+            self.sign_bit()
+        } else {
+            0
+        }
+    }
+
+    fn max_finite(self, sign: bool) -> u64 {
+        self.infinity(sign) - 1
+    }
+}
+
+// value == sig * 2^exp
+#[derive(Copy, Clone, Debug)]
+enum Unpacked {
+    NaN { signaling: bool },
+    Inf { sign: bool },
+    Zero { sign: bool },
+    Finite { sign: bool, exp: i32, sig: u128 },
+}
+
+fn unpack(bits: u64, fmt: FloatFmt) -> Unpacked {
+    let frac_bits = fmt.frac_bits();
+    let sign = bits & fmt.sign_bit() != 0;
+    let biased = (bits >> frac_bits) as i32 & fmt.max_exp();
+    let frac = bits & ((1 << frac_bits) - 1);
+
+    if biased == fmt.max_exp() {
+        if frac == 0 {
+            Unpacked::Inf { sign }
+        } else {
+            Unpacked::NaN {
+                signaling: frac >> (frac_bits - 1) == 0,
+            }
+        }
+    } else if biased == 0 {
+        if frac == 0 {
+            Unpacked::Zero { sign }
+        } else {
+            Unpacked::Finite {
+                sign,
+                exp: 1 - fmt.bias() - frac_bits as i32,
+                sig: frac as u128,
+            }
+        }
+    } else {
+        Unpacked::Finite {
+            sign,
+            exp: biased - fmt.bias() - frac_bits as i32,
+            sig: (frac | 1 << frac_bits) as u128,
+        }
+    }
+}
+
+fn bit_length(x: u128) -> u32 {
+    128 - x.leading_zeros()
+}
+
+// shift right while keeping any shifted out bits as the sticky bit
+fn shift_right_sticky(x: u128, shift: u32) -> u128 {
+    if shift == 0 {
+        x
+    } else if shift >= 128 {
+        (x != 0) as u128
+    } else {
+        x >> shift | (x & ((1 << shift) - 1) != 0) as u128
+    }
+}
+
+// round sig * 2^exp to the format (sig may carry a sticky bit in its LSB)
+fn round_pack(
+    sign: bool,
+    exp: i32,
+    sig: u128,
+    fmt: FloatFmt,
+    rm: RoundingMode,
+    flags: &mut u64,
+) -> u64 {
+    if sig == 0 {
+        return fmt.zero(sign);
+    }
+
+    // normalize so that the MSB is at bit 126
+    const TOP: u32 = 126;
+    let len = bit_length(sig);
+    let (sig, exp) = if len <= TOP + 1 {
+        (sig << (TOP + 1 - len), exp - (TOP + 1 - len) as i32)
+    } else {
+        (
+            shift_right_sticky(sig, len - TOP - 1),
+            exp + (len - TOP - 1) as i32,
+        )
+    };
+
+    let frac_bits = fmt.frac_bits();
+    let biased = exp + TOP as i32 + fmt.bias();
+    let normal_shift = TOP - frac_bits;
+    let round_at = |shift: u32| -> (u128, bool) {
+        // everything is below the half of LSB
+        let (sig, shift) = if shift > 127 { (1, 127) } else { (sig, shift) };
+        let kept = sig >> shift;
+        let rem = sig & ((1 << shift) - 1);
+        let half = 1 << (shift - 1);
+        let inc = rm.round_up(sign, kept & 1 == 1, rem, half);
+        (kept + inc as u128, rem != 0)
+    };
+
+    let encoded = if biased >= 1 {
+        let (kept, inexact) = round_at(normal_shift);
+        if inexact {
+            *flags |= FLAG_NX;
+        }
+        ((biased as u128 - 1) << frac_bits) + kept
+    } else {
+        let (kept, inexact) = round_at(normal_shift + (1 - biased) as u32);
+        if inexact {
+            *flags |= FLAG_NX;
+            // tininess is detected after rounding
+            let (unbounded, _) = round_at(normal_shift);
+            if biased < 0 || unbounded >> (frac_bits + 1) == 0 {
+                *flags |= FLAG_UF;
+            }
+        }
+        kept
+    };
+
+    if encoded >> frac_bits >= fmt.max_exp() as u128 {
+        *flags |= FLAG_OF | FLAG_NX;
+        let to_inf = match rm {
+            RoundingMode::Rne | RoundingMode::Rmm => true,
+            RoundingMode::Rtz => false,
+            RoundingMode::Rdn => sign,
+            RoundingMode::Rup => !sign,
+        };
+        return if to_inf {
+            fmt.infinity(sign)
+        } else {
+            fmt.max_finite(sign)
+        };
+    }
+
+    encoded as u64 | if sign { fmt.sign_bit() } else { 0 }
+}
+
+fn propagate_nan(a: Unpacked, b: Unpacked, fmt: FloatFmt, flags: &mut u64) -> u64 {
+    if matches!(a, Unpacked::NaN { signaling: true })
+        || matches!(b, Unpacked::NaN { signaling: true })
+    {
+        *flags |= FLAG_NV;
+    }
+    fmt.canonical_nan()
+}
+
+// exact sum of two finite values rounded to the format
+fn add_finite(
+    (sa, ea, ma): (bool, i32, u128),
+    (sb, eb, mb): (bool, i32, u128),
+    fmt: FloatFmt,
+    rm: RoundingMode,
+    flags: &mut u64,
+) -> u64 {
+    let ((sa, ea, ma), (sb, eb, mb)) = if ea >= eb {
+        ((sa, ea, ma), (sb, eb, mb))
+    } else {
+        ((sb, eb, mb), (sa, ea, ma))
+    };
+
+    let diff = (ea - eb) as u32;
+    let room = 125 - bit_length(ma);
+    let (exp, ma, mb) = if diff <= room {
+        (eb, ma << diff, mb)
+    } else {
+        (
+            ea - room as i32,
+            ma << room,
+            shift_right_sticky(mb, diff - room),
+        )
+    };
+
+    if sa == sb {
+        round_pack(sa, exp, ma + mb, fmt, rm, flags)
+    } else if ma > mb {
+        round_pack(sa, exp, ma - mb, fmt, rm, flags)
+    } else if ma < mb {
+        round_pack(sb, exp, mb - ma, fmt, rm, flags)
+    } else {
+        fmt.zero(rm == RoundingMode::Rdn)
+    }
+}
+
+pub fn add(a: u64, b: u64, fmt: FloatFmt, rm: RoundingMode, flags: &mut u64) -> u64 {
+    match (unpack(a, fmt), unpack(b, fmt)) {
+        (x @ Unpacked::NaN { .. }, y) | (x, y @ Unpacked::NaN { .. }) => {
+            propagate_nan(x, y, fmt, flags)
+        }
+        (Unpacked::Inf { sign: sa }, Unpacked::Inf { sign: sb }) => {
+            if sa == sb {
+                fmt.infinity(sa)
+            } else {
+                *flags |= FLAG_NV;
+                fmt.canonical_nan()
+            }
+        }
+        (Unpacked::Inf { sign }, _) | (_, Unpacked::Inf { sign }) => fmt.infinity(sign),
+        (Unpacked::Zero { sign: sa }, Unpacked::Zero { sign: sb }) => {
+            if sa == sb {
+                fmt.zero(sa)
+            } else {
+                fmt.zero(rm == RoundingMode::Rdn)
+            }
+        }
+        (Unpacked::Zero { .. }, Unpacked::Finite { .. }) => b,
+        (Unpacked::Finite { .. }, Unpacked::Zero { .. }) => a,
+        (
+            Unpacked::Finite {
+                sign: sa,
+                exp: ea,
+                sig: ma,
+            },
+            Unpacked::Finite {
+                sign: sb,
+                exp: eb,
+                sig: mb,
+            },
+        ) => add_finite((sa, ea, ma), (sb, eb, mb), fmt, rm, flags),
+    }
+}
+
+pub fn sub(a: u64, b: u64, fmt: FloatFmt, rm: RoundingMode, flags: &mut u64) -> u64 {
+    match unpack(b, fmt) {
+        Unpacked::NaN { .. } => add(a, b, fmt, rm, flags),
+        _ => add(a, b ^ fmt.sign_bit(), fmt, rm, flags),
+    }
+}
+
+pub fn mul(a: u64, b: u64, fmt: FloatFmt, rm: RoundingMode, flags: &mut u64) -> u64 {
+    match (unpack(a, fmt), unpack(b, fmt)) {
+        (x @ Unpacked::NaN { .. }, y) | (x, y @ Unpacked::NaN { .. }) => {
+            propagate_nan(x, y, fmt, flags)
+        }
+        (Unpacked::Inf { .. }, Unpacked::Zero { .. })
+        | (Unpacked::Zero { .. }, Unpacked::Inf { .. }) => {
+            *flags |= FLAG_NV;
+            fmt.canonical_nan()
+        }
+        (Unpacked::Inf { sign: sa }, Unpacked::Inf { sign: sb })
+        | (Unpacked::Inf { sign: sa }, Unpacked::Finite { sign: sb, .. })
+        | (Unpacked::Finite { sign: sa, .. }, Unpacked::Inf { sign: sb }) => fmt.infinity(sa ^ sb),
+        (Unpacked::Zero { sign: sa }, Unpacked::Zero { sign: sb })
+        | (Unpacked::Zero { sign: sa }, Unpacked::Finite { sign: sb, .. })
+        | (Unpacked::Finite { sign: sa, .. }, Unpacked::Zero { sign: sb }) => fmt.zero(sa ^ sb),
+        (
+            Unpacked::Finite {
+                sign: sa,
+                exp: ea,
+                sig: ma,
+            },
+            Unpacked::Finite {
+                sign: sb,
+                exp: eb,
+                sig: mb,
+            },
+        ) => round_pack(sa ^ sb, ea + eb, ma * mb, fmt, rm, flags),
+    }
+}
+
+// a * b + c with a single rounding
+pub fn fma(a: u64, b: u64, c: u64, fmt: FloatFmt, rm: RoundingMode, flags: &mut u64) -> u64 {
+    let (ua, ub, uc) = (unpack(a, fmt), unpack(b, fmt), unpack(c, fmt));
+
+    // inf * 0 is invalid even if c is a quiet NaN
+    let inf_times_zero = matches!(
+        (ua, ub),
+        (Unpacked::Inf { .. }, Unpacked::Zero { .. })
+            | (Unpacked::Zero { .. }, Unpacked::Inf { .. })
+    );
+    if inf_times_zero {
+        *flags |= FLAG_NV;
+        return fmt.canonical_nan();
+    }
+    if matches!(ua, Unpacked::NaN { .. })
+        || matches!(ub, Unpacked::NaN { .. })
+        || matches!(uc, Unpacked::NaN { .. })
+    {
+        if [ua, ub, uc]
+            .iter()
+            .any(|x| matches!(x, Unpacked::NaN { signaling: true }))
+        {
+            *flags |= FLAG_NV;
+        }
+        return fmt.canonical_nan();
+    }
+
+    let product_sign = (a ^ b) & fmt.sign_bit() != 0;
+    match (ua, ub, uc) {
+        (Unpacked::Inf { .. }, _, _) | (_, Unpacked::Inf { .. }, _) => match uc {
+            Unpacked::Inf { sign } if sign != product_sign => {
+                *flags |= FLAG_NV;
+                fmt.canonical_nan()
+            }
+            _ => fmt.infinity(product_sign),
+        },
+        (_, _, Unpacked::Inf { sign }) => fmt.infinity(sign),
+        (Unpacked::Zero { .. }, _, _) | (_, Unpacked::Zero { .. }, _) => match uc {
+            Unpacked::Zero { sign } => {
+                if sign == product_sign {
+                    fmt.zero(sign)
+                } else {
+                    fmt.zero(rm == RoundingMode::Rdn)
+                }
+            }
+            _ => c,
+        },
+        (
+            Unpacked::Finite {
+                exp: ea, sig: ma, ..
+            },
+            Unpacked::Finite {
+                exp: eb, sig: mb, ..
+            },
+            Unpacked::Zero { .. },
+        ) => round_pack(product_sign, ea + eb, ma * mb, fmt, rm, flags),
+        (
+            Unpacked::Finite {
+                exp: ea, sig: ma, ..
+            },
+            Unpacked::Finite {
+                exp: eb, sig: mb, ..
+            },
+            Unpacked::Finite {
+                sign: sc,
+                exp: ec,
+                sig: mc,
+            },
+        ) => add_finite(
+            (product_sign, ea + eb, ma * mb),
+            (sc, ec, mc),
+            fmt,
+            rm,
+            flags,
+        ),
+        _ => unreachable!(),
+    }
+}
+
+pub fn div(a: u64, b: u64, fmt: FloatFmt, rm: RoundingMode, flags: &mut u64) -> u64 {
+    match (unpack(a, fmt), unpack(b, fmt)) {
+        (x @ Unpacked::NaN { .. }, y) | (x, y @ Unpacked::NaN { .. }) => {
+            propagate_nan(x, y, fmt, flags)
+        }
+        (Unpacked::Inf { .. }, Unpacked::Inf { .. })
+        | (Unpacked::Zero { .. }, Unpacked::Zero { .. }) => {
+            *flags |= FLAG_NV;
+            fmt.canonical_nan()
+        }
+        (Unpacked::Inf { sign: sa }, Unpacked::Zero { sign: sb })
+        | (Unpacked::Inf { sign: sa }, Unpacked::Finite { sign: sb, .. }) => fmt.infinity(sa ^ sb),
+        (Unpacked::Finite { sign: sa, .. }, Unpacked::Zero { sign: sb }) => {
+            *flags |= FLAG_DZ;
+            fmt.infinity(sa ^ sb)
+        }
+        (Unpacked::Zero { sign: sa }, Unpacked::Inf { sign: sb })
+        | (Unpacked::Zero { sign: sa }, Unpacked::Finite { sign: sb, .. })
+        | (Unpacked::Finite { sign: sa, .. }, Unpacked::Inf { sign: sb }) => fmt.zero(sa ^ sb),
+        (
+            Unpacked::Finite {
+                sign: sa,
+                exp: ea,
+                sig: ma,
+            },
+            Unpacked::Finite {
+                sign: sb,
+                exp: eb,
+                sig: mb,
+            },
+        ) => {
+            let shift = 126 - bit_length(ma);
+            let dividend = ma << shift;
+            let quot = dividend / mb;
+            let sticky = !dividend.is_multiple_of(mb) as u128;
+            round_pack(
+                sa ^ sb,
+                ea - eb - shift as i32 - 1,
+                quot << 1 | sticky,
+                fmt,
+                rm,
+                flags,
+            )
+        }
+    }
+}
+
+fn isqrt(x: u128) -> u128 {
+    let mut rem = x;
+    let mut root: u128 = 0;
+    let mut bit: u128 = 1 << 126;
+    while bit > x {
+        bit >>= 2;
+    }
+    while bit != 0 {
+        if rem >= root + bit {
+            rem -= root + bit;
+            root = (root >> 1) + bit;
+        } else {
+            root >>= 1;
+        }
+        bit >>= 2;
+    }
+    root
+}
+
+pub fn sqrt(a: u64, fmt: FloatFmt, rm: RoundingMode, flags: &mut u64) -> u64 {
+    match unpack(a, fmt) {
+        x @ Unpacked::NaN { .. } => propagate_nan(x, x, fmt, flags),
+        Unpacked::Zero { .. } => a,
+        Unpacked::Inf { sign: false } => a,
+        Unpacked::Inf { sign: true } | Unpacked::Finite { sign: true, .. } => {
+            *flags |= FLAG_NV;
+            fmt.canonical_nan()
+        }
+        Unpacked::Finite {
+            sign: false,
+            exp,
+            sig,
+        } => {
+            // make the exponent even and the significand as wide as possible
+            let shift = (126 - bit_length(sig)) & !1;
+            let (sig, exp) = (sig << shift, exp - shift as i32);
+            let (sig, exp) = if exp % 2 != 0 {
+                (sig << 1, exp - 1)
+            } else {
+                (sig, exp)
+            };
+            let root = isqrt(sig);
+            let sticky = (root * root != sig) as u128;
+            round_pack(false, exp / 2 - 1, root << 1 | sticky, fmt, rm, flags)
+        }
+    }
+}
+
+// -1, 0, 1 as less, equal, greater (None if unordered)
+fn compare(a: u64, b: u64, fmt: FloatFmt) -> Option<i8> {
+    let key = |x: u64| -> i128 {
+        match unpack(x, fmt) {
+            Unpacked::Zero { .. } => 0,
+            _ => {
+                let magnitude = (x & !fmt.sign_bit()) as i128;
+                if x & fmt.sign_bit() != 0 {
+                    -magnitude
+                } else {
+                    magnitude
+                }
+            }
+        }
+    };
+    match (unpack(a, fmt), unpack(b, fmt)) {
+        (Unpacked::NaN { .. }, _) | (_, Unpacked::NaN { .. }) => None,
+        _ => Some(key(a).cmp(&key(b)) as i8),
+    }
+}
+
+fn is_nan(x: u64, fmt: FloatFmt) -> bool {
+    matches!(unpack(x, fmt), Unpacked::NaN { .. })
+}
+
+fn is_snan(x: u64, fmt: FloatFmt) -> bool {
+    matches!(unpack(x, fmt), Unpacked::NaN { signaling: true })
+}
+
+pub fn eq(a: u64, b: u64, fmt: FloatFmt, flags: &mut u64) -> bool {
+    if is_snan(a, fmt) || is_snan(b, fmt) {
+        *flags |= FLAG_NV;
+    }
+    compare(a, b, fmt) == Some(0)
+}
+
+pub fn lt(a: u64, b: u64, fmt: FloatFmt, flags: &mut u64) -> bool {
+    if is_nan(a, fmt) || is_nan(b, fmt) {
+        *flags |= FLAG_NV;
+    }
+    compare(a, b, fmt) == Some(-1)
+}
+
+pub fn le(a: u64, b: u64, fmt: FloatFmt, flags: &mut u64) -> bool {
+    if is_nan(a, fmt) || is_nan(b, fmt) {
+        *flags |= FLAG_NV;
+    }
+    matches!(compare(a, b, fmt), Some(-1) | Some(0))
+}
+
+fn min_max(a: u64, b: u64, fmt: FloatFmt, flags: &mut u64, is_max: bool) -> u64 {
+    if is_snan(a, fmt) || is_snan(b, fmt) {
+        *flags |= FLAG_NV;
+    }
+    match (is_nan(a, fmt), is_nan(b, fmt)) {
+        (true, true) => fmt.canonical_nan(),
+        (true, false) => b,
+        (false, true) => a,
+        (false, false) => {
+            // -0.0 is less than +0.0
+            let a_less = match compare(a, b, fmt) {
+                Some(0) => a & fmt.sign_bit() != 0,
+                ord => ord == Some(-1),
+            };
+            if a_less ^ is_max {
+                a
+            } else {
+                b
+            }
+        }
+    }
+}
+
+pub fn min(a: u64, b: u64, fmt: FloatFmt, flags: &mut u64) -> u64 {
+    min_max(a, b, fmt, flags, false)
+}
+
+pub fn max(a: u64, b: u64, fmt: FloatFmt, flags: &mut u64) -> u64 {
+    min_max(a, b, fmt, flags, true)
+}
+
+pub fn classify(a: u64, fmt: FloatFmt) -> u64 {
+    let biased = (a >> fmt.frac_bits()) as i32 & fmt.max_exp();
+    match unpack(a, fmt) {
+        Unpacked::Inf { sign: true } => 1 << 0,
+        Unpacked::Finite { sign: true, .. } if biased != 0 => 1 << 1,
+        Unpacked::Finite { sign: true, .. } => 1 << 2,
+        Unpacked::Zero { sign: true } => 1 << 3,
+        Unpacked::Zero { sign: false } => 1 << 4,
+        Unpacked::Finite { sign: false, .. } if biased == 0 => 1 << 5,
+        Unpacked::Finite { sign: false, .. } => 1 << 6,
+        Unpacked::Inf { sign: false } => 1 << 7,
+        Unpacked::NaN { signaling: true } => 1 << 8,
+        Unpacked::NaN { signaling: false } => 1 << 9,
+    }
+}
+
+// convert to integer of `width` bits, saturating on overflow
+pub fn to_int(
+    a: u64,
+    fmt: FloatFmt,
+    width: u32,
+    signed: bool,
+    rm: RoundingMode,
+    flags: &mut u64,
+) -> u64 {
+    let (max, min): (i128, i128) = if signed {
+        ((1 << (width - 1)) - 1, -(1 << (width - 1)))
+    } else {
+        ((1 << width) - 1, 0)
+    };
+    let invalid = |flags: &mut u64, result: i128| -> u64 {
+        *flags |= FLAG_NV;
+        result as u64
+    };
+
+    match unpack(a, fmt) {
+        Unpacked::NaN { .. } => invalid(flags, max),
+        Unpacked::Inf { sign } => invalid(flags, if sign { min } else { max }),
+        Unpacked::Zero { .. } => 0,
+        Unpacked::Finite { sign, exp, sig } => {
+            let mut inexact = false;
+            let magnitude: u128 = if exp >= 0 {
+                if exp > 64 {
+                    return invalid(flags, if sign { min } else { max });
+                }
+                sig << exp
+            } else {
+                let shift = (-exp) as u32;
+                let (kept, rem, half) = if shift >= 128 {
+                    (0, sig, u128::MAX)
+                } else {
+                    (sig >> shift, sig & ((1 << shift) - 1), 1 << (shift - 1))
+                };
+                inexact = rem != 0;
+                kept + rm.round_up(sign, kept & 1 == 1, rem, half) as u128
+            };
+
+            let value = if sign {
+                -(magnitude as i128)
+            } else {
+                magnitude as i128
+            };
+            if value > max || value < min {
+                // overflow is reported as invalid rather than inexact
+                invalid(flags, if sign { min } else { max })
+            } else {
+                if inexact {
+                    *flags |= FLAG_NX;
+                }
+                value as u64
+            }
+        }
+    }
+}
+
+pub fn from_int(value: u64, signed: bool, fmt: FloatFmt, rm: RoundingMode, flags: &mut u64) -> u64 {
+    let sign = signed && (value as i64) < 0;
+    let magnitude = if sign {
+        (value as i64).unsigned_abs()
+    } else {
+        value
+    };
+    round_pack(sign, 0, magnitude as u128, fmt, rm, flags)
+}
+
+pub fn convert(a: u64, from: FloatFmt, to: FloatFmt, rm: RoundingMode, flags: &mut u64) -> u64 {
+    match unpack(a, from) {
+        x @ Unpacked::NaN { .. } => propagate_nan(x, x, to, flags),
+        Unpacked::Inf { sign } => to.infinity(sign),
+        Unpacked::Zero { sign } => to.zero(sign),
+        Unpacked::Finite { sign, exp, sig } => round_pack(sign, exp, sig, to, rm, flags),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arithmetic_test() {
+        let rne = RoundingMode::Rne;
+        let f64_test = |x: f64, y: f64, z: f64| {
+            let (a, b, c) = (x.to_bits(), y.to_bits(), z.to_bits());
+            let mut flags = 0;
+            assert_eq!(
+                add(a, b, FloatFmt::Double, rne, &mut flags),
+                (x + y).to_bits()
+            );
+            assert_eq!(
+                sub(a, b, FloatFmt::Double, rne, &mut flags),
+                (x - y).to_bits()
+            );
+            assert_eq!(
+                mul(a, b, FloatFmt::Double, rne, &mut flags),
+                (x * y).to_bits()
+            );
+            assert_eq!(
+                div(a, b, FloatFmt::Double, rne, &mut flags),
+                (x / y).to_bits()
+            );
+            assert_eq!(
+                fma(a, b, c, FloatFmt::Double, rne, &mut flags),
+                x.mul_add(y, z).to_bits()
+            );
+            assert_eq!(
+                sqrt(x.abs().to_bits(), FloatFmt::Double, rne, &mut flags),
+                x.abs().sqrt().to_bits()
+            );
+        };
+        f64_test(1.0, 3.0, 0.5);
+        f64_test(1e308, 1e-308, -1.0);
+        f64_test(0.1, 0.2, 0.3);
+        f64_test(-2.5e-310, 7.0, 1e-320);
+        f64_test(123456.789, -0.000321, 99.0);
+        f64_test(1.0, 1e-30, -1.0);
+
+        let f32_test = |x: f32, y: f32| {
+            let (a, b) = (x.to_bits() as u64, y.to_bits() as u64);
+            let mut flags = 0;
+            assert_eq!(
+                add(a, b, FloatFmt::Single, rne, &mut flags),
+                (x + y).to_bits() as u64
+            );
+            assert_eq!(
+                mul(a, b, FloatFmt::Single, rne, &mut flags),
+                (x * y).to_bits() as u64
+            );
+            assert_eq!(
+                div(a, b, FloatFmt::Single, rne, &mut flags),
+                (x / y).to_bits() as u64
+            );
+        };
+        f32_test(1.0, 3.0);
+        f32_test(3.4e38, 10.0);
+        f32_test(1.5e-45, 0.5);
+    }
+
+    #[test]
+    fn flags_test() {
+        let mut flags = 0;
+        div(
+            1.0f64.to_bits(),
+            0.0f64.to_bits(),
+            FloatFmt::Double,
+            RoundingMode::Rne,
+            &mut flags,
+        );
+        assert_eq!(flags, FLAG_DZ);
+
+        let mut flags = 0;
+        let result = mul(
+            f32::MAX.to_bits() as u64,
+            2.0f32.to_bits() as u64,
+            FloatFmt::Single,
+            RoundingMode::Rtz,
+            &mut flags,
+        );
+        assert_eq!(result, f32::MAX.to_bits() as u64);
+        assert_eq!(flags, FLAG_OF | FLAG_NX);
+
+        let mut flags = 0;
+        let result = to_int(
+            (-1.5f64).to_bits(),
+            FloatFmt::Double,
+            32,
+            false,
+            RoundingMode::Rne,
+            &mut flags,
+        );
+        assert_eq!(result, 0);
+        assert_eq!(flags, FLAG_NV);
+
+        let mut flags = 0;
+        let result = to_int(
+            2.5f64.to_bits(),
+            FloatFmt::Double,
+            64,
+            true,
+            RoundingMode::Rmm,
+            &mut flags,
+        );
+        assert_eq!(result, 3);
+        assert_eq!(flags, FLAG_NX);
+    }
+}
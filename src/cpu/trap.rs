@@ -1,43 +1,36 @@
-use super::csr::Xstatus;
+use super::csr::{DebugCause, TriggerAction, Xstatus};
+use super::mmu::GuestFault;
 use super::{CSRname, Cpu, PrivilegedLevel};
+use crate::bus;
 use crate::{log, Isa, TrapCause};
 
 impl Cpu {
     pub fn check_interrupt(&mut self) -> Result<(), (Option<u64>, TrapCause, String)> {
         const SSIP: u64 = 1;
+        const VSSIP: u64 = 2;
         const MSIP: u64 = 3;
         const STIP: u64 = 5;
+        const VSTIP: u64 = 6;
         const MTIP: u64 = 7;
         const SEIP: u64 = 9;
+        const VSEIP: u64 = 10;
         const MEIP: u64 = 11;
+        const LCOFIP: u64 = 13;
 
-        const MTIME: u64 = 0x0200_BFF8;
-        const MTIMECMP: u64 = 0x0200_4000;
-        let mtime: u64 = self.bus.load64(MTIME).unwrap();
-        let mtimecmp: u64 = self.bus.load64(MTIMECMP).unwrap();
-        if mtime >= mtimecmp {
-            self.csrs
-                .bitset(CSRname::mip.wrap(), 1 << MTIP | 1 << STIP)
-                .unwrap() // ignore result
-        } else {
-            self.csrs
-                .bitclr(CSRname::mip.wrap(), 1 << MTIP | 1 << STIP)
-                .unwrap()
-        };
-
-        self.csrs
-            .write(
-                CSRname::mip.wrap(),
-                self.csrs.read(CSRname::mip.wrap()).unwrap() & !(self.bus.plic.mip_mask)
-                    | self.bus.plic.mip_value,
-            )
-            .ok();
+        // STIP is left to M-mode software, or to stimecmp with Sstc
+        let hartid = self.hartid();
+        let clint_mip = (self.bus.clint.msip(hartid) as u64) << MSIP
+            | (self.bus.clint.mtip(hartid) as u64) << MTIP;
+        self.csrs.drive_mip(1 << MSIP | 1 << MTIP, clint_mip);
+        self.csrs.drive_mip(bus::MIP_EIP, self.bus.plic.mip[hartid]);
 
         let mip = self.csrs.read(CSRname::mip.wrap()).unwrap();
         let mie = self.csrs.read(CSRname::mie.wrap()).unwrap();
 
         let pending_interrupts = mip & mie;
         let mideleg = self.csrs.read(CSRname::mideleg.wrap()).unwrap();
+        let hideleg = self.csrs.read(CSRname::hideleg.wrap()).unwrap();
+        let virt = self.csrs.virt();
         let mstatus_mie = self
             .csrs
             .read_xstatus(PrivilegedLevel::Machine, Xstatus::MIE);
@@ -50,21 +43,34 @@ impl Cpu {
             let mstatus_sie = self
                 .csrs
                 .read_xstatus(PrivilegedLevel::Machine, Xstatus::SIE);
+            // a guest can always be interrupted by HS-mode
             let s_enabled = match self.priv_lv() {
                 PrivilegedLevel::Machine => 0,
-                PrivilegedLevel::Supervisor => (mstatus_sie != 0) as i64,
+                PrivilegedLevel::Supervisor if !virt => (mstatus_sie != 0) as i64,
                 _ => 1,
             };
 
-            pending_interrupts & mideleg & (-s_enabled as u64)
+            pending_interrupts & mideleg & !hideleg & (-s_enabled as u64)
+        } else {
+            enabled_interrupt_mask
+        };
+        // the ones hideleg hands over to VS-mode are only taken in the guest
+        let enabled_interrupt_mask = if enabled_interrupt_mask == 0 {
+            let vs_enabled = match self.priv_lv() {
+                PrivilegedLevel::Supervisor if virt => self.csrs.vsstatus_sie() as i64,
+                PrivilegedLevel::User if virt => 1,
+                _ => 0,
+            };
+
+            pending_interrupts & mideleg & hideleg & (-vs_enabled as u64)
         } else {
             enabled_interrupt_mask
         };
         let is_interrupt_enabled = |bit: u64| (enabled_interrupt_mask & (1 << bit)) != 0;
 
         if is_interrupt_enabled(MEIP) {
-            self.csrs.bitclr(CSRname::mip.wrap(), 1 << MEIP).unwrap();
-            self.bus.plic.mip_value = 0;
+            self.csrs.drive_mip(1 << MEIP, 0);
+            self.bus.plic.mip[hartid] &= !(1 << MEIP);
             return Err((
                 Some(0),
                 TrapCause::MachineExternalInterrupt,
@@ -72,7 +78,7 @@ impl Cpu {
             ));
         }
         if is_interrupt_enabled(MSIP) {
-            self.csrs.bitclr(CSRname::mip.wrap(), 1 << MSIP).unwrap();
+            self.csrs.drive_mip(1 << MSIP, 0);
             return Err((
                 Some(0),
                 TrapCause::MachineSoftwareInterrupt,
@@ -80,8 +86,6 @@ impl Cpu {
             ));
         }
         if is_interrupt_enabled(MTIP) {
-            // TODO: bit clear when mtimecmp written
-            self.csrs.bitclr(CSRname::mip.wrap(), 1 << MTIP).unwrap();
             return Err((
                 Some(0),
                 TrapCause::MachineTimerInterrupt,
@@ -90,7 +94,7 @@ impl Cpu {
         }
         if is_interrupt_enabled(SEIP) {
             self.csrs.bitclr(CSRname::mip.wrap(), 1 << SEIP).unwrap();
-            self.bus.plic.mip_value = 0;
+            self.bus.plic.mip[hartid] &= !(1 << SEIP);
             return Err((
                 Some(0),
                 TrapCause::SupervisorExternalInterrupt,
@@ -106,119 +110,178 @@ impl Cpu {
             ));
         }
         if is_interrupt_enabled(STIP) {
-            // TODO: bit clear when mtimecmp written
-            self.csrs.bitclr(CSRname::mip.wrap(), 1 << STIP).unwrap();
             return Err((
                 Some(0),
                 TrapCause::SupervisorTimerInterrupt,
                 "supervisor timer interrupt".to_string(),
             ));
         }
+        // the VS-level ones are cleared through hvip (or vsip) by software
+        if is_interrupt_enabled(VSEIP) {
+            return Err((
+                Some(0),
+                TrapCause::VirtualSupervisorExternalInterrupt,
+                "virtual supervisor external interrupt".to_string(),
+            ));
+        }
+        if is_interrupt_enabled(VSSIP) {
+            return Err((
+                Some(0),
+                TrapCause::VirtualSupervisorSoftwareInterrupt,
+                "virtual supervisor software interrupt".to_string(),
+            ));
+        }
+        if is_interrupt_enabled(VSTIP) {
+            return Err((
+                Some(0),
+                TrapCause::VirtualSupervisorTimerInterrupt,
+                "virtual supervisor timer interrupt".to_string(),
+            ));
+        }
+        if is_interrupt_enabled(LCOFIP) {
+            return Err((
+                Some(0),
+                TrapCause::CounterOverflowInterrupt,
+                "counter overflow interrupt".to_string(),
+            ));
+        }
 
         Ok(())
     }
 
-    fn get_deleg(&self, cause_of_trap: TrapCause) -> u64 {
-        match cause_of_trap {
+    // medeleg or mideleg, and what HS-mode delegates further to VS-mode
+    fn get_deleg(&self, interrupt: bool) -> (u64, u64) {
+        if interrupt {
+            (
+                self.csrs.read(CSRname::mideleg.wrap()).unwrap(),
+                self.csrs.read(CSRname::hideleg.wrap()).unwrap(),
+            )
+        } else {
+            (
+                self.csrs.read(CSRname::medeleg.wrap()).unwrap(),
+                self.csrs.read(CSRname::hedeleg.wrap()).unwrap(),
+            )
+        }
+    }
+
+    // an itrigger or etrigger that matches the trap raises a breakpoint (or
+    // halts the hart) at the first instruction of the handler
+    pub fn trap(&mut self, tval_addr: u64, cause_of_trap: TrapCause) {
+        // exceptions in debug mode change nothing but stop the program buffer
+        if self.debug_mode() {
+            self.csrs.raise_debug_exception();
+            return;
+        }
+
+        let prev_priv = self.priv_lv();
+        let trigger = self.check_trap_triggers(cause_of_trap, prev_priv);
+        self.take_trap(tval_addr, cause_of_trap);
+
+        match trigger {
+            Some(TriggerAction::Breakpoint) => {
+                log::infoln!(
+                    "[exception: {:?}] trap trigger fired",
+                    TrapCause::Breakpoint
+                );
+                self.take_trap(0, TrapCause::Breakpoint);
+            }
+            Some(TriggerAction::DebugMode) => self.enter_debug_mode(DebugCause::Trigger),
+            None => (),
+        }
+    }
+
+    fn take_trap(&mut self, tval_addr: u64, cause_of_trap: TrapCause) {
+        let prev_priv = self.priv_lv();
+        let prev_virt = self.csrs.virt();
+        self.bus.reservations.yield_reservation(self.hartid());
+
+        let interrupt = cause_of_trap as u64 & 1 << 31 != 0;
+        let code = cause_of_trap as u64 & 0x7fff_ffff;
+        let xcause = match (*self.isa, interrupt) {
+            (Isa::Rv32, true) => 1 << 31 | code,
+            (Isa::Rv64, true) => 1 << 63 | code,
+            (_, false) => code,
+        };
+
+        // whether tval holds a guest virtual address, and what the guest-page
+        // fault was about
+        let guest = match cause_of_trap {
             TrapCause::InstAddrMisaligned
             | TrapCause::InstAccessFault
-            | TrapCause::IllegalInst
-            | TrapCause::Breakpoint
-            | TrapCause::UmodeEcall
-            | TrapCause::SmodeEcall
-            | TrapCause::MmodeEcall
             | TrapCause::LoadAddrMisaligned
             | TrapCause::LoadAccessFault
             | TrapCause::StoreAMOAddrMisaligned
             | TrapCause::StoreAMOAccessFault
             | TrapCause::InstPageFault
             | TrapCause::LoadPageFault
-            | TrapCause::StoreAMOPageFault => self.csrs.read(CSRname::medeleg.wrap()).unwrap(),
-            TrapCause::SupervisorSoftwareInterrupt
-            | TrapCause::MachineSoftwareInterrupt
-            | TrapCause::SupervisorTimerInterrupt
-            | TrapCause::MachineTimerInterrupt
-            | TrapCause::SupervisorExternalInterrupt
-            | TrapCause::MachineExternalInterrupt => {
-                self.csrs.read(CSRname::mideleg.wrap()).unwrap()
-            }
-        }
-    }
-
-    pub fn trap(&mut self, tval_addr: u64, cause_of_trap: TrapCause) {
-        let prev_priv = self.priv_lv();
+            | TrapCause::StoreAMOPageFault
+            | TrapCause::InstGuestPageFault
+            | TrapCause::LoadGuestPageFault
+            | TrapCause::StoreAMOGuestPageFault => self.mmu.guest_fault(),
+            TrapCause::Breakpoint => GuestFault {
+                gva: prev_virt,
+                ..GuestFault::default()
+            },
+            _ => GuestFault::default(),
+        };
 
-        // check Machine Trap Delegation Registers
-        let deleg = self.get_deleg(cause_of_trap);
-        let new_pc = if self.priv_lv() != PrivilegedLevel::Machine
-            && (deleg & 1 << cause_of_trap as u32) != 0
-        {
+        // check Machine Trap Delegation Registers, then the hypervisor ones
+        let (deleg, hdeleg) = self.get_deleg(interrupt);
+        let new_pc = if prev_priv != PrivilegedLevel::Machine && deleg >> code & 1 != 0 {
             self.set_priv_lv(PrivilegedLevel::Supervisor);
-            let scause = self.csrs.read(CSRname::scause.wrap()).unwrap();
 
-            log::infoln!("delegated");
-            self.csrs
-                .write(
-                    CSRname::scause.wrap(),
-                    match *self.isa {
-                        Isa::Rv32 => cause_of_trap as u64,
-                        Isa::Rv64 => match cause_of_trap {
-                            TrapCause::MachineSoftwareInterrupt
-                            | TrapCause::MachineTimerInterrupt
-                            | TrapCause::MachineExternalInterrupt
-                            | TrapCause::SupervisorSoftwareInterrupt
-                            | TrapCause::SupervisorTimerInterrupt
-                            | TrapCause::SupervisorExternalInterrupt => {
-                                (1 << 63) | (cause_of_trap as u64 & 0x7fff_ffff)
-                            }
-                            _ => cause_of_trap as u64,
-                        },
-                    },
-                )
-                .unwrap();
-            self.csrs.write(CSRname::sepc.wrap(), self.pc()).unwrap();
-            self.csrs.write(CSRname::stval.wrap(), tval_addr).unwrap();
-            self.csrs.write_xstatus(
-                PrivilegedLevel::Supervisor,
-                // sstatus.SPIE = sstatus.SIE
-                Xstatus::SPIE,
-                self.csrs
-                    .read_xstatus(PrivilegedLevel::Supervisor, Xstatus::SIE),
-            );
-            self.csrs
-                .write_xstatus(PrivilegedLevel::Supervisor, Xstatus::SIE, 0b0); // Ssatus.SIE = 0
-            self.csrs
-                .write_xstatus(PrivilegedLevel::Supervisor, Xstatus::SPP, prev_priv as u64); // set prev_priv to SPP
+            if prev_virt && hdeleg >> code & 1 != 0 {
+                log::infoln!("delegated to VS-mode");
+                // VS-mode sees its interrupts at the codes of the S-mode ones
+                let (vscause, code) = if interrupt {
+                    (xcause - 1, code - 1)
+                } else {
+                    (xcause, code)
+                };
+                self.csrs.write(CSRname::vscause.wrap(), vscause).unwrap();
+                self.csrs.write(CSRname::vsepc.wrap(), self.pc()).unwrap();
+                self.csrs.write(CSRname::vstval.wrap(), tval_addr).unwrap();
+                self.csrs.trap_vsstatus(prev_priv);
 
-            let stvec = self.csrs.read(CSRname::stvec.wrap()).unwrap();
-            if stvec & 0b1 == 1 {
-                (stvec - 1) + 4 * scause.trailing_zeros() as u64
+                let vstvec = self.csrs.read(CSRname::vstvec.wrap()).unwrap();
+                trap_vector(vstvec, interrupt, code)
             } else {
-                stvec
+                log::infoln!("delegated");
+                self.csrs.write(CSRname::scause.wrap(), xcause).unwrap();
+                self.csrs.write(CSRname::sepc.wrap(), self.pc()).unwrap();
+                self.csrs.write(CSRname::stval.wrap(), tval_addr).unwrap();
+                self.csrs.write_xstatus(
+                    PrivilegedLevel::Supervisor,
+                    // sstatus.SPIE = sstatus.SIE
+                    Xstatus::SPIE,
+                    self.csrs
+                        .read_xstatus(PrivilegedLevel::Supervisor, Xstatus::SIE),
+                );
+                self.csrs
+                    .write_xstatus(PrivilegedLevel::Supervisor, Xstatus::SIE, 0b0); // Ssatus.SIE = 0
+                self.csrs.write_xstatus(
+                    PrivilegedLevel::Supervisor,
+                    Xstatus::SPP,
+                    prev_priv as u64,
+                ); // set prev_priv to SPP
+                if self.csrs.hypervisor() {
+                    self.csrs.trap_hstatus(prev_priv, prev_virt, guest.gva);
+                    self.csrs
+                        .write(CSRname::htval.wrap(), guest.gpa >> 2)
+                        .unwrap();
+                    self.csrs
+                        .write(CSRname::htinst.wrap(), guest.tinst)
+                        .unwrap();
+                }
+
+                let stvec = self.csrs.read(CSRname::stvec.wrap()).unwrap();
+                trap_vector(stvec, interrupt, code)
             }
         } else {
             self.set_priv_lv(PrivilegedLevel::Machine);
-            let mcause = self.csrs.read(CSRname::mcause.wrap()).unwrap();
+            self.csrs.trap_tcontrol();
 
-            self.csrs
-                .write(
-                    CSRname::mcause.wrap(),
-                    match *self.isa {
-                        Isa::Rv32 => cause_of_trap as u64,
-                        Isa::Rv64 => match cause_of_trap {
-                            TrapCause::MachineSoftwareInterrupt
-                            | TrapCause::MachineTimerInterrupt
-                            | TrapCause::MachineExternalInterrupt
-                            | TrapCause::SupervisorSoftwareInterrupt
-                            | TrapCause::SupervisorTimerInterrupt
-                            | TrapCause::SupervisorExternalInterrupt => {
-                                (1 << 63) | (cause_of_trap as u64 & 0x7fff_ffff)
-                            }
-                            _ => cause_of_trap as u64,
-                        },
-                    },
-                )
-                .unwrap();
+            self.csrs.write(CSRname::mcause.wrap(), xcause).unwrap();
             self.csrs.write(CSRname::mepc.wrap(), self.pc()).unwrap();
             self.csrs.write(CSRname::mtval.wrap(), tval_addr).unwrap();
             self.csrs.write_xstatus(
@@ -232,16 +295,30 @@ impl Cpu {
                 .write_xstatus(PrivilegedLevel::Machine, Xstatus::MIE, 0b0); // msatus.MIE = 0
             self.csrs
                 .write_xstatus(PrivilegedLevel::Machine, Xstatus::MPP, prev_priv as u64); // set prev_priv to MPP
+            if self.csrs.hypervisor() {
+                self.csrs.trap_mstatus_mpv(prev_virt, guest.gva);
+                self.csrs
+                    .write(CSRname::mtval2.wrap(), guest.gpa >> 2)
+                    .unwrap();
+                self.csrs
+                    .write(CSRname::mtinst.wrap(), guest.tinst)
+                    .unwrap();
+            }
 
             let mtvec = self.csrs.read(CSRname::mtvec.wrap()).unwrap();
-            if mtvec & 0b1 == 1 {
-                (mtvec - 1) + 4 * mcause.trailing_zeros() as u64
-            } else {
-                mtvec
-            }
+            trap_vector(mtvec, interrupt, code)
         };
 
         self.update_pc(new_pc);
         log::infoln!("new pc: 0x{:x}", self.pc());
     }
 }
+
+// in vectored mode, interrupts jump to base + 4 * cause
+fn trap_vector(tvec: u64, interrupt: bool, code: u64) -> u64 {
+    match tvec & 0b1 {
+        1 if interrupt => (tvec - 1) + 4 * code,
+        1 => tvec - 1,
+        _ => tvec,
+    }
+}
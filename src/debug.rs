@@ -0,0 +1,459 @@
+mod jtag;
+
+use crate::cpu::csr::DebugCause;
+use crate::cpu::{Cpu, Hart};
+use crate::{log, Emulator, Isa};
+pub use jtag::JtagServer;
+use std::mem;
+use std::thread;
+use std::time::Duration;
+
+// DMI register addresses
+const DATA0: u32 = 0x04;
+const DMCONTROL: u32 = 0x10;
+const DMSTATUS: u32 = 0x11;
+const HARTINFO: u32 = 0x12;
+const ABSTRACTCS: u32 = 0x16;
+const COMMAND: u32 = 0x17;
+const ABSTRACTAUTO: u32 = 0x18;
+const PROGBUF0: u32 = 0x20;
+const HALTSUM0: u32 = 0x40;
+
+// data0..1 hold a 64-bit register, the program buffer is followed by an implicit ebreak
+const DATA_COUNT: usize = 2;
+const PROGBUF_SIZE: usize = 8;
+const DATA_END: u32 = DATA0 + DATA_COUNT as u32;
+const PROGBUF_END: u32 = PROGBUF0 + PROGBUF_SIZE as u32;
+
+// dmcontrol
+const HALTREQ: u32 = 1 << 31;
+const RESUMEREQ: u32 = 1 << 30;
+const ACKHAVERESET: u32 = 1 << 28;
+const HARTSELLO: u32 = 0x3ff << 16;
+const SETRESETHALTREQ: u32 = 1 << 3;
+const CLRRESETHALTREQ: u32 = 1 << 2;
+const NDMRESET: u32 = 1 << 1;
+const DMACTIVE: u32 = 1 << 0;
+
+// dmstatus of debug spec 1.0; only one hart is selected, so all* and any* agree
+const DMSTATUS_VERSION: u32 = 3;
+const HASRESETHALTREQ: u32 = 1 << 5;
+const AUTHENTICATED: u32 = 1 << 7;
+const HALTED: u32 = 0x3 << 8;
+const RUNNING: u32 = 0x3 << 10;
+const NONEXISTENT: u32 = 0x3 << 14;
+const RESUMEACK: u32 = 0x3 << 16;
+const HAVERESET: u32 = 0x3 << 18;
+const IMPEBREAK: u32 = 1 << 22;
+
+// dscratch0 and dscratch1, no memory-mapped data registers
+const HARTINFO_VALUE: u32 = 2 << 20;
+
+// abstractcs.cmderr; commands finish at once, so they are never busy
+const CMDERR_NONE: u32 = 0;
+const CMDERR_NOT_SUPPORTED: u32 = 2;
+const CMDERR_EXCEPTION: u32 = 3;
+const CMDERR_HALT_RESUME: u32 = 4;
+
+// the Access Register command
+const CMDTYPE_ACCESS_REGISTER: u32 = 0;
+const AARPOSTINCREMENT: u32 = 1 << 19;
+const POSTEXEC: u32 = 1 << 18;
+const TRANSFER: u32 = 1 << 17;
+const WRITE: u32 = 1 << 16;
+const FIRST_GPR: usize = 0x1000;
+const FIRST_FPR: usize = 0x1020;
+
+// autoexecprogbuf and autoexecdata
+const AUTOEXEC_MASK: u32 = ((1 << PROGBUF_SIZE) - 1) << 16 | ((1 << DATA_COUNT) - 1);
+
+// check for the debugger every JTAG_POLL_INTERVAL instructions while running
+pub const JTAG_POLL_INTERVAL: u64 = 0x1000;
+
+// the Debug Module Interface that the transport reaches the debug module through
+pub trait Dmi {
+    fn dmi_read(&mut self, addr: u32) -> u32;
+    fn dmi_write(&mut self, addr: u32, data: u32);
+}
+
+pub struct DebugModule {
+    // hartsel, ndmreset and dmactive as written
+    dmcontrol: u32,
+    data: [u32; DATA_COUNT],
+    progbuf: [u32; PROGBUF_SIZE],
+    command: u32,
+    cmderr: u32,
+    abstractauto: u32,
+    // for every hart
+    resumeack: Vec<bool>,
+    havereset: Vec<bool>,
+    resethaltreq: Vec<bool>,
+}
+
+impl DebugModule {
+    pub fn new(harts: usize) -> Self {
+        DebugModule {
+            dmcontrol: 0,
+            data: [0; DATA_COUNT],
+            progbuf: [0; PROGBUF_SIZE],
+            command: 0,
+            cmderr: CMDERR_NONE,
+            abstractauto: 0,
+            resumeack: vec![false; harts],
+            havereset: vec![true; harts],
+            resethaltreq: vec![false; harts],
+        }
+    }
+
+    fn hartsel(&self) -> usize {
+        ((self.dmcontrol & HARTSELLO) >> 16) as usize
+    }
+}
+
+impl Dmi for Emulator {
+    fn dmi_read(&mut self, addr: u32) -> u32 {
+        let dm = &self.debug_module;
+        let value = match addr {
+            DATA0..DATA_END => dm.data[(addr - DATA0) as usize],
+            DMCONTROL => dm.dmcontrol,
+            DMSTATUS => self.dmstatus(),
+            HARTINFO => HARTINFO_VALUE,
+            ABSTRACTCS => (PROGBUF_SIZE as u32) << 24 | dm.cmderr << 8 | DATA_COUNT as u32,
+            ABSTRACTAUTO => dm.abstractauto,
+            PROGBUF0..PROGBUF_END => dm.progbuf[(addr - PROGBUF0) as usize],
+            HALTSUM0 => self.haltsum0(),
+            // command is write-only, and there is no system bus access or authentication
+            _ => 0,
+        };
+        self.autoexec(addr);
+        value
+    }
+
+    fn dmi_write(&mut self, addr: u32, data: u32) {
+        let dm = &mut self.debug_module;
+        match addr {
+            DATA0..DATA_END => dm.data[(addr - DATA0) as usize] = data,
+            DMCONTROL => return self.write_dmcontrol(data),
+            ABSTRACTCS => dm.cmderr &= !(data >> 8 & 0x7),
+            COMMAND => {
+                // nothing runs until the last error is cleared
+                if dm.cmderr == CMDERR_NONE {
+                    dm.command = data;
+                    self.execute_command();
+                }
+                return;
+            }
+            ABSTRACTAUTO => dm.abstractauto = data & AUTOEXEC_MASK,
+            PROGBUF0..PROGBUF_END => dm.progbuf[(addr - PROGBUF0) as usize] = data,
+            _ => (),
+        }
+        self.autoexec(addr);
+    }
+}
+
+impl Emulator {
+    fn hart_halted(&self, hartid: usize) -> bool {
+        if hartid == self.current_hart {
+            self.cpu.debug_mode()
+        } else {
+            self.harts[hartid].debug_mode()
+        }
+    }
+
+    fn all_halted(&self) -> bool {
+        (0..self.harts.len()).all(|hartid| self.hart_halted(hartid))
+    }
+
+    // the selected hart becomes the running one; None if it doesn't exist
+    fn select_hart(&mut self) -> Option<usize> {
+        let hartid = self.debug_module.hartsel();
+        if hartid >= self.harts.len() {
+            return None;
+        }
+        self.switch_hart(hartid);
+        Some(hartid)
+    }
+
+    fn dmstatus(&self) -> u32 {
+        let dm = &self.debug_module;
+        let hartid = dm.hartsel();
+        let status = IMPEBREAK | AUTHENTICATED | HASRESETHALTREQ | DMSTATUS_VERSION;
+        if hartid >= self.harts.len() {
+            return status | NONEXISTENT;
+        }
+
+        let flag = |set: bool, bits: u32| if set { bits } else { 0 };
+        status
+            | flag(self.hart_halted(hartid), HALTED)
+            | flag(!self.hart_halted(hartid), RUNNING)
+            | flag(dm.resumeack[hartid], RESUMEACK)
+            | flag(dm.havereset[hartid], HAVERESET)
+    }
+
+    // the halted harts among the 32 around hartsel
+    fn haltsum0(&self) -> u32 {
+        let first = self.debug_module.hartsel() & !0x1f;
+        (0..32)
+            .filter(|i| first + i < self.harts.len() && self.hart_halted(first + i))
+            .fold(0, |haltsum, i| haltsum | 1 << i)
+    }
+
+    fn write_dmcontrol(&mut self, data: u32) {
+        let harts = self.harts.len();
+        let dm = &mut self.debug_module;
+        if data & DMACTIVE == 0 {
+            // the module resets, but what happened to the harts stays
+            let havereset = mem::take(&mut dm.havereset);
+            *dm = DebugModule {
+                havereset,
+                ..DebugModule::new(harts)
+            };
+            return;
+        }
+
+        let ndmreset = data & NDMRESET != 0 && dm.dmcontrol & NDMRESET == 0;
+        dm.dmcontrol = data & (HARTSELLO | NDMRESET | DMACTIVE);
+        let hartid = dm.hartsel();
+        if hartid < harts {
+            if data & ACKHAVERESET != 0 {
+                dm.havereset[hartid] = false;
+            }
+            if data & SETRESETHALTREQ != 0 {
+                dm.resethaltreq[hartid] = true;
+            } else if data & CLRRESETHALTREQ != 0 {
+                dm.resethaltreq[hartid] = false;
+            }
+        }
+        if ndmreset {
+            self.reset_harts();
+        }
+        let Some(hartid) = self.select_hart() else {
+            return;
+        };
+
+        // resumereq is ignored along with haltreq
+        if data & HALTREQ != 0 {
+            if !self.cpu.debug_mode() {
+                self.cpu.enter_debug_mode(DebugCause::HaltRequest);
+            }
+        } else if data & RESUMEREQ != 0 {
+            let halted = self.cpu.debug_mode();
+            if halted {
+                self.cpu.leave_debug_mode();
+            }
+            self.debug_module.resumeack[hartid] = halted;
+        }
+    }
+
+    // ndmreset restarts every hart from the reset vector; memory and devices
+    // keep their state
+    fn reset_harts(&mut self) {
+        let isa = self.cpu.isa();
+        let reset_pc = Cpu::reset_pc(&self.cpu.bus, &self.args);
+        let mtime = self.cpu.bus.clint.mtime();
+        for hartid in 0..self.harts.len() {
+            let mut hart = Hart::new(isa, &self.args, reset_pc, hartid);
+            hart.sync_time(mtime);
+            if hartid == self.current_hart {
+                self.cpu.swap_hart(&mut hart);
+            } else {
+                self.harts[hartid] = hart;
+            }
+            self.cpu.bus.reservations.yield_reservation(hartid);
+            self.debug_module.havereset[hartid] = true;
+        }
+
+        for hartid in 0..self.harts.len() {
+            if self.debug_module.resethaltreq[hartid] {
+                self.switch_hart(hartid);
+                self.cpu.enter_debug_mode(DebugCause::ResetHaltRequest);
+            }
+        }
+    }
+
+    // accessing a data or progbuf word with its abstractauto bit set runs the command again
+    fn autoexec(&mut self, addr: u32) {
+        let bit = match addr {
+            DATA0..DATA_END => addr - DATA0,
+            PROGBUF0..PROGBUF_END => addr - PROGBUF0 + 16,
+            _ => return,
+        };
+        let dm = &self.debug_module;
+        if dm.abstractauto >> bit & 1 != 0 && dm.cmderr == CMDERR_NONE {
+            self.execute_command();
+        }
+    }
+
+    fn execute_command(&mut self) {
+        if let Err(cmderr) = self.access_register(self.debug_module.command) {
+            self.debug_module.cmderr = cmderr;
+        }
+    }
+
+    // Access Register is the only abstract command
+    fn access_register(&mut self, command: u32) -> Result<(), u32> {
+        if command >> 24 != CMDTYPE_ACCESS_REGISTER {
+            return Err(CMDERR_NOT_SUPPORTED);
+        }
+        if self.select_hart().is_none() || !self.cpu.debug_mode() {
+            return Err(CMDERR_HALT_RESUME);
+        }
+
+        let regno = (command & 0xffff) as usize;
+        if command & TRANSFER != 0 {
+            // aarsize 2 and 3 are 32 and 64 bits
+            let size = command >> 20 & 0x7;
+            let max_size = match (regno, self.cpu.isa()) {
+                (0x1020..=0x103f, _) | (_, Isa::Rv64) => 3,
+                (_, Isa::Rv32) => 2,
+            };
+            if !(2..=max_size).contains(&size) {
+                return Err(CMDERR_NOT_SUPPORTED);
+            }
+
+            let data = self.debug_module.data;
+            if command & WRITE != 0 {
+                let value = match size {
+                    2 => data[0] as u64,
+                    _ => (data[1] as u64) << 32 | data[0] as u64,
+                };
+                self.write_register(regno, value).ok_or(CMDERR_EXCEPTION)?;
+            } else {
+                let value = self.read_register(regno).ok_or(CMDERR_EXCEPTION)?;
+                self.debug_module.data[0] = value as u32;
+                if size == 3 {
+                    self.debug_module.data[1] = (value >> 32) as u32;
+                }
+            }
+        }
+
+        if command & AARPOSTINCREMENT != 0 {
+            self.debug_module.command = command & !0xffff | (regno as u32 + 1) & 0xffff;
+        }
+        if command & POSTEXEC != 0 {
+            let progbuf = self.debug_module.progbuf;
+            if let Err((_, cause, msg)) = self.cpu.exec_progbuf(&progbuf) {
+                log::infoln!("[progbuf: {:?}] {}", cause, msg);
+                return Err(CMDERR_EXCEPTION);
+            }
+        }
+        Ok(())
+    }
+
+    // CSRs, then the GPRs and the FPRs
+    fn read_register(&self, regno: usize) -> Option<u64> {
+        match regno {
+            0x0000..=0x0fff => {
+                self.cpu.csr_read_only(regno)?;
+                self.cpu.read_csr(regno)
+            }
+            0x1000..=0x101f => Some(self.cpu.regs.read(Some(regno - FIRST_GPR))),
+            0x1020..=0x103f => Some(self.cpu.fregs.read(Some(regno - FIRST_FPR))),
+            _ => None,
+        }
+    }
+
+    fn write_register(&mut self, regno: usize, value: u64) -> Option<()> {
+        match regno {
+            0x0000..=0x0fff => {
+                if self.cpu.csr_read_only(regno)? {
+                    return None;
+                }
+                self.cpu.write_csr(regno, value)
+            }
+            0x1000..=0x101f => {
+                self.cpu.regs.write(Some(regno - FIRST_GPR), value);
+                Some(())
+            }
+            0x1020..=0x103f => {
+                self.cpu.fregs.write(Some(regno - FIRST_FPR), value);
+                Some(())
+            }
+            _ => None,
+        }
+    }
+
+    // while every hart is halted there is nothing to do but wait for the debugger
+    pub(crate) fn poll_jtag(&mut self) {
+        let Some(mut jtag) = self.jtag.take() else {
+            return;
+        };
+        let active = jtag.serve(self);
+        self.jtag = Some(jtag);
+        if !active && self.all_halted() {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmdline::Arguments;
+
+    #[test]
+    fn debug_module_test() {
+        const DPC: u32 = 0x7b1;
+        const S0: u32 = 0x1008;
+        const AARSIZE_32: u32 = 2 << 20;
+        const AARSIZE_64: u32 = 3 << 20;
+        const DCSR: u32 = 0x7b0;
+        const DCSR_STEP_BIT: u64 = 1 << 2;
+        let mut emulator = Emulator::for_test(Arguments::for_elf("./HelloWorld"));
+        let cmderr = |emulator: &mut Emulator| emulator.dmi_read(ABSTRACTCS) >> 8 & 0x7;
+
+        emulator.dmi_write(DMCONTROL, DMACTIVE);
+        assert_eq!(emulator.dmi_read(DMSTATUS) & HALTED, 0);
+        emulator.dmi_write(DMCONTROL, HALTREQ | DMACTIVE);
+        assert_eq!(emulator.dmi_read(DMSTATUS) & HALTED, HALTED);
+        assert_eq!(emulator.dmi_read(HALTSUM0), 1);
+
+        // the pc to resume at
+        emulator.dmi_write(COMMAND, AARSIZE_32 | TRANSFER | DPC);
+        assert_eq!(cmderr(&mut emulator), CMDERR_NONE);
+        assert_eq!(emulator.dmi_read(DATA0), 0x1000);
+
+        // write s0, then run `addi s0, s0, 1` from the program buffer
+        emulator.dmi_write(DATA0, 41);
+        emulator.dmi_write(PROGBUF0, 0x0014_0413);
+        emulator.dmi_write(PROGBUF0 + 1, 0x0010_0073);
+        emulator.dmi_write(COMMAND, AARSIZE_32 | POSTEXEC | TRANSFER | WRITE | S0);
+        emulator.dmi_write(COMMAND, AARSIZE_32 | TRANSFER | S0);
+        assert_eq!(cmderr(&mut emulator), CMDERR_NONE);
+        assert_eq!(emulator.dmi_read(DATA0), 42);
+
+        // HelloWorld is rv32
+        emulator.dmi_write(COMMAND, AARSIZE_64 | TRANSFER | S0);
+        assert_eq!(cmderr(&mut emulator), CMDERR_NOT_SUPPORTED);
+        emulator.dmi_write(ABSTRACTCS, 0x7 << 8);
+        assert_eq!(cmderr(&mut emulator), CMDERR_NONE);
+
+        // `csrr zero, 0x7ff` doesn't name a CSR
+        emulator.dmi_write(PROGBUF0, 0x7ff0_2073);
+        emulator.dmi_write(COMMAND, POSTEXEC);
+        assert_eq!(cmderr(&mut emulator), CMDERR_EXCEPTION);
+        emulator.dmi_write(ABSTRACTCS, 0x7 << 8);
+
+        // nor does a loop of `nop; j -4` finish
+        emulator.dmi_write(PROGBUF0, 0x0000_0013);
+        emulator.dmi_write(PROGBUF0 + 1, 0xffdf_f06f);
+        emulator.dmi_write(COMMAND, POSTEXEC);
+        assert_eq!(cmderr(&mut emulator), CMDERR_EXCEPTION);
+        assert_eq!(emulator.dmi_read(DMSTATUS) & HALTED, HALTED);
+        emulator.dmi_write(ABSTRACTCS, 0x7 << 8);
+
+        // a single step halts again after one instruction
+        emulator
+            .cpu
+            .write_csr(DCSR as usize, DCSR_STEP_BIT)
+            .unwrap();
+        emulator.dmi_write(DMCONTROL, RESUMEREQ | DMACTIVE);
+        assert_eq!(emulator.dmi_read(DMSTATUS) & RESUMEACK, RESUMEACK);
+        emulator.step();
+        assert_eq!(emulator.dmi_read(DMSTATUS) & HALTED, HALTED);
+        let dcsr = emulator.cpu.read_csr(DCSR as usize).unwrap();
+        assert_eq!(dcsr >> 6 & 0x7, DebugCause::Step as u64);
+        emulator.dmi_write(COMMAND, AARSIZE_32 | TRANSFER | DPC);
+        assert_eq!(emulator.dmi_read(DATA0), 0x1004);
+    }
+}
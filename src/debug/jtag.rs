@@ -0,0 +1,276 @@
+use super::Dmi;
+use crate::socket::{Listener, Server};
+use std::io;
+
+// the instructions of the 5-bit IR
+const IR_LEN: u32 = 5;
+const IDCODE: u32 = 0x01;
+const DTMCS: u32 = 0x10;
+const DMI: u32 = 0x11;
+
+// version 1, with the part number and manufacturer left at 0
+const IDCODE_VALUE: u32 = 0x1000_0001;
+// dtmcs: version 1 (debug spec 0.13 and 1.0) with 7 address bits
+const ABITS: u32 = 7;
+const DTMCS_VALUE: u32 = ABITS << 4 | 1;
+// dmi: address, data and op
+const DMI_LEN: u32 = ABITS + 34;
+const DMI_OP_READ: u64 = 1;
+const DMI_OP_WRITE: u64 = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum TapState {
+    TestLogicReset,
+    RunTestIdle,
+    SelectDrScan,
+    CaptureDr,
+    ShiftDr,
+    Exit1Dr,
+    PauseDr,
+    Exit2Dr,
+    UpdateDr,
+    SelectIrScan,
+    CaptureIr,
+    ShiftIr,
+    Exit1Ir,
+    PauseIr,
+    Exit2Ir,
+    UpdateIr,
+}
+
+impl TapState {
+    // the state after a rising edge of TCK
+    fn next(self, tms: bool) -> Self {
+        use TapState::*;
+        match (self, tms) {
+            (TestLogicReset, false) | (RunTestIdle, false) => RunTestIdle,
+            (TestLogicReset, true) | (SelectIrScan, true) => TestLogicReset,
+            (RunTestIdle, true) | (UpdateDr, true) | (UpdateIr, true) => SelectDrScan,
+            (SelectDrScan, false) => CaptureDr,
+            (SelectDrScan, true) => SelectIrScan,
+            (CaptureDr, false) | (ShiftDr, false) | (Exit2Dr, false) => ShiftDr,
+            (CaptureDr, true) | (ShiftDr, true) => Exit1Dr,
+            (Exit1Dr, false) | (PauseDr, false) => PauseDr,
+            (Exit1Dr, true) | (Exit2Dr, true) => UpdateDr,
+            (PauseDr, true) => Exit2Dr,
+            (UpdateDr, false) | (UpdateIr, false) => RunTestIdle,
+            (SelectIrScan, false) => CaptureIr,
+            (CaptureIr, false) | (ShiftIr, false) | (Exit2Ir, false) => ShiftIr,
+            (CaptureIr, true) | (ShiftIr, true) => Exit1Ir,
+            (Exit1Ir, false) | (PauseIr, false) => PauseIr,
+            (Exit1Ir, true) | (Exit2Ir, true) => UpdateIr,
+            (PauseIr, true) => Exit2Ir,
+        }
+    }
+}
+
+// a TAP with the RISC-V debug transport module behind it
+struct Tap {
+    state: TapState,
+    ir: u32,
+    // the register being shifted and its length
+    shift: u64,
+    len: u32,
+    tck: bool,
+    tdo: bool,
+    // the last DMI access, which the next scan captures; it always succeeds
+    dmi_addr: u32,
+    dmi_data: u32,
+}
+
+impl Tap {
+    fn new() -> Self {
+        Tap {
+            state: TapState::TestLogicReset,
+            ir: IDCODE,
+            shift: 0,
+            len: 1,
+            tck: false,
+            tdo: false,
+            dmi_addr: 0,
+            dmi_data: 0,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.state = TapState::TestLogicReset;
+        self.ir = IDCODE;
+    }
+
+    // TMS and TDI are sampled on the rising edge of TCK
+    fn set_pins(&mut self, tck: bool, tms: bool, tdi: bool, dmi: &mut dyn Dmi) {
+        let rising = tck && !self.tck;
+        self.tck = tck;
+        if !rising {
+            return;
+        }
+
+        if matches!(self.state, TapState::ShiftDr | TapState::ShiftIr) {
+            self.shift = self.shift >> 1 | (tdi as u64) << (self.len - 1);
+        }
+        self.state = self.state.next(tms);
+        match self.state {
+            TapState::TestLogicReset => self.ir = IDCODE,
+            TapState::CaptureDr => self.capture_dr(),
+            TapState::UpdateDr => self.update_dr(dmi),
+            // the IR captures 0b00001 as JTAG requires
+            TapState::CaptureIr => (self.shift, self.len) = (1, IR_LEN),
+            TapState::UpdateIr => self.ir = self.shift as u32 & ((1 << IR_LEN) - 1),
+            _ => (),
+        }
+        self.tdo = self.shift & 1 != 0;
+    }
+
+    fn capture_dr(&mut self) {
+        (self.shift, self.len) = match self.ir {
+            IDCODE => (IDCODE_VALUE as u64, 32),
+            DTMCS => (DTMCS_VALUE as u64, 32),
+            // op 0: the previous access succeeded
+            DMI => (
+                (self.dmi_addr as u64) << 34 | (self.dmi_data as u64) << 2,
+                DMI_LEN,
+            ),
+            // BYPASS and the instructions that don't exist
+            _ => (0, 1),
+        };
+    }
+
+    // dtmcs has nothing to reset, as DMI accesses never fail or stay busy
+    fn update_dr(&mut self, dmi: &mut dyn Dmi) {
+        if self.ir != DMI {
+            return;
+        }
+        let addr = (self.shift >> 34) as u32 & ((1 << ABITS) - 1);
+        let data = (self.shift >> 2) as u32;
+        match self.shift & 0x3 {
+            DMI_OP_READ => {
+                self.dmi_addr = addr;
+                self.dmi_data = dmi.dmi_read(addr);
+            }
+            DMI_OP_WRITE => {
+                self.dmi_addr = addr;
+                self.dmi_data = data;
+                dmi.dmi_write(addr, data);
+            }
+            _ => (),
+        }
+    }
+}
+
+// serves OpenOCD's remote_bitbang driver, one client at a time
+pub struct JtagServer {
+    server: Server,
+    tap: Tap,
+}
+
+impl JtagServer {
+    // "1234" or "host:1234" is treated as TCP, others as an unix socket path
+    pub fn listen(target: &str) -> io::Result<Self> {
+        let listener = Listener::bind(target)?;
+        eprintln!("jtag: listening on {listener}");
+        Ok(JtagServer {
+            server: Server::new(listener)?,
+            tap: Tap::new(),
+        })
+    }
+
+    // never blocks; false when the client had nothing to say
+    pub fn serve(&mut self, dmi: &mut dyn Dmi) -> bool {
+        if self.server.accept() {
+            self.tap.reset();
+        }
+
+        let mut buf = [0; 4096];
+        let len = self.server.read(&mut buf);
+        if len == 0 {
+            return false;
+        }
+
+        let mut reply = Vec::new();
+        for &command in &buf[..len] {
+            match command {
+                b'0'..=b'7' => {
+                    let pins = command - b'0';
+                    self.tap
+                        .set_pins(pins & 0x4 != 0, pins & 0x2 != 0, pins & 0x1 != 0, dmi);
+                }
+                b'R' => reply.push(if self.tap.tdo { b'1' } else { b'0' }),
+                // 'r' to 'u' set trst and srst; only trst is wired
+                b'r'..=b'u' if (command - b'r') & 0x2 != 0 => self.tap.reset(),
+                b'Q' => {
+                    self.server.disconnect();
+                    return true;
+                }
+                // blink and the rest
+                _ => (),
+            }
+        }
+
+        if !reply.is_empty() {
+            self.server.write(&reply);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Registers([u32; 1 << ABITS]);
+
+    impl Dmi for Registers {
+        fn dmi_read(&mut self, addr: u32) -> u32 {
+            self.0[addr as usize]
+        }
+
+        fn dmi_write(&mut self, addr: u32, data: u32) {
+            self.0[addr as usize] = data;
+        }
+    }
+
+    fn clock(tap: &mut Tap, dmi: &mut Registers, tms: bool, tdi: bool) -> bool {
+        tap.set_pins(false, tms, tdi, dmi);
+        let tdo = tap.tdo;
+        tap.set_pins(true, tms, tdi, dmi);
+        tdo
+    }
+
+    // from Run-Test/Idle through a DR or IR scan and back
+    fn scan(tap: &mut Tap, dmi: &mut Registers, ir: bool, value: u64, len: u32) -> u64 {
+        clock(tap, dmi, true, false);
+        if ir {
+            clock(tap, dmi, true, false);
+        }
+        clock(tap, dmi, false, false);
+        clock(tap, dmi, false, false);
+        let mut out = 0;
+        for bit in 0..len {
+            let tdo = clock(tap, dmi, bit == len - 1, value >> bit & 1 != 0);
+            out |= (tdo as u64) << bit;
+        }
+        clock(tap, dmi, true, false);
+        clock(tap, dmi, false, false);
+        out
+    }
+
+    #[test]
+    fn tap_test() {
+        let tap = &mut Tap::new();
+        let dmi = &mut Registers([0; 1 << ABITS]);
+
+        // IDCODE is selected after reset
+        clock(tap, dmi, false, false);
+        assert_eq!(scan(tap, dmi, false, 0, 32), IDCODE_VALUE as u64);
+        scan(tap, dmi, true, DTMCS as u64, IR_LEN);
+        assert_eq!(scan(tap, dmi, false, 0, 32), DTMCS_VALUE as u64);
+
+        // a DMI access shows up in the next scan
+        scan(tap, dmi, true, DMI as u64, IR_LEN);
+        let write = 0x10 << 34 | 0x1234 << 2 | DMI_OP_WRITE;
+        scan(tap, dmi, false, write, DMI_LEN);
+        scan(tap, dmi, false, 0x10 << 34 | DMI_OP_READ, DMI_LEN);
+        assert_eq!(scan(tap, dmi, false, 0, DMI_LEN), 0x10 << 34 | 0x1234 << 2);
+        assert_eq!(dmi.0[0x10], 0x1234);
+    }
+}
@@ -102,7 +102,7 @@ impl ElfLoader {
             addr_table.push(seg.offset_and_addr());
         }
 
-        addr_table.sort_by(|x, y| (x.1).cmp(&y.1));
+        addr_table.sort_by_key(|x| x.1);
         for w in addr_table.windows(2) {
             let (a, z) = (w[0], w[1]);
             if a.1 <= addr && addr < z.1 {
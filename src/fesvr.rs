@@ -1,5 +1,9 @@
 mod syscall;
+use crate::log;
+use crate::snapshot::{self, Reader, Snapshot, Writer};
 use crate::Emulator;
+use std::ffi::CString;
+use std::io;
 
 pub struct FrontendServer {
     fd_table: Vec<Option<u64>>,
@@ -36,6 +40,70 @@ impl FrontendServer {
     }
 }
 
+// host files are reopened by path, with the same flags and offset
+impl Snapshot for FrontendServer {
+    fn save(&self, w: &mut Writer) -> io::Result<()> {
+        w.tag(b"FESV")?;
+        w.u64(self.fd_table.len() as u64)?;
+        for host_fd in self.fd_table.iter() {
+            match *host_fd {
+                None => w.u8(0)?,
+                Some(fd) if fd <= 2 => {
+                    w.u8(1)?;
+                    w.u64(fd)?;
+                }
+                Some(fd) => {
+                    let path = std::fs::read_link(format!("/proc/self/fd/{fd}"))?;
+                    let flags = unsafe { libc::fcntl(fd as i32, libc::F_GETFL) };
+                    let offset = unsafe { libc::lseek(fd as i32, 0, libc::SEEK_CUR) };
+                    if flags < 0 || offset < 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                    w.u8(2)?;
+                    w.bytes(path.to_string_lossy().as_bytes())?;
+                    w.u64(flags as u64)?;
+                    w.u64(offset as u64)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn restore(&mut self, r: &mut Reader) -> io::Result<()> {
+        r.tag(b"FESV")?;
+        for fd in self.fd_table.iter().flatten().filter(|fd| **fd > 2) {
+            unsafe { libc::close(*fd as i32) };
+        }
+
+        let fd_num = r.u64()? as usize;
+        self.fd_table = Vec::with_capacity(fd_num);
+        for _ in 0..fd_num {
+            let host_fd = match r.u8()? {
+                0 => None,
+                1 => Some(r.u64()?),
+                _ => {
+                    let path = CString::new(r.bytes()?)
+                        .map_err(|e| snapshot::invalid_data(e.to_string()))?;
+                    let flags = r.u64()? as i32 & !(libc::O_CREAT | libc::O_TRUNC | libc::O_EXCL);
+                    let offset = r.u64()? as i64;
+                    let fd = unsafe { libc::open(path.as_ptr(), flags) };
+                    if fd < 0 || unsafe { libc::lseek(fd, offset, libc::SEEK_SET) } < 0 {
+                        return Err(io::Error::new(
+                            io::ErrorKind::NotFound,
+                            format!("reopening {path:?} failed: {}", io::Error::last_os_error()),
+                        ));
+                    }
+                    Some(fd as u64)
+                }
+            };
+            self.fd_table.push(host_fd);
+        }
+
+        Ok(())
+    }
+}
+
 impl Emulator {
     pub fn check_tohost(&mut self) -> bool {
         let tohost_addr = self.tohost_addr.unwrap();
@@ -100,7 +168,10 @@ impl Emulator {
             80 => self
                 .frontend_server
                 .fstat(&mut self.cpu, sysargs[1], sysargs[2]),
-            93 => self.frontend_server.exit(sysargs[1]),
+            93 => {
+                log::infoln!("sys_exit(93)");
+                self.exit(sysargs[1] as i32)
+            }
             291 => panic!("sys_statx is not implemented"),
             1039 => panic!("sys_lstat is not implemented"),
             2011 => {
@@ -118,7 +189,7 @@ impl Emulator {
         self.cpu.bus.store64(tohost_addr, 0).unwrap();
 
         if tohost & 1 == 1 {
-            std::process::exit(tohost as i32);
+            self.exit(tohost as i32);
         } else {
             let syscall_addr: u64 = tohost << 16 >> 16;
             let mut syscall_args: [u64; 8] = [
@@ -240,12 +240,6 @@ impl FrontendServer {
         ret
     }
 
-    pub fn exit(&self, code: u64) -> i64 {
-        log::infoln!("sys_exit(93)");
-
-        std::process::exit(code as i32)
-    }
-
     pub fn getmainvars(&self, cpu: &mut Cpu, args: &Arguments, dst_addr: u64, limit: u64) -> i64 {
         log::infoln!("sys_getmainvars(2011)");
 
@@ -0,0 +1,450 @@
+mod target;
+
+use crate::cpu::PrivilegedLevel;
+use crate::socket::{Client, Listener};
+use crate::{Emulator, Isa};
+use std::collections::HashSet;
+use std::io::ErrorKind;
+use target::{FIRST_CSR_REGNUM, FIRST_FPR_REGNUM, PRIV_REGNUM};
+
+// check Ctrl-C from gdb every POLL_INTERVAL instructions while running
+const POLL_INTERVAL: u64 = 0x1000;
+
+enum StopReason {
+    Step,
+    Breakpoint,
+    Interrupt,
+}
+
+pub struct GdbServer {
+    conn: Box<dyn Client>,
+    breakpoints: HashSet<u64>,
+    no_ack: bool,
+    // gdb asked to kill the target rather than detach
+    killed: bool,
+}
+
+impl GdbServer {
+    // "1234" or "host:1234" is treated as TCP, others as an unix socket path
+    pub fn listen(target: &str) -> std::io::Result<Self> {
+        let listener = Listener::bind(target)?;
+        eprintln!("waiting for gdb connection on {listener}");
+        Ok(GdbServer {
+            conn: listener.accept()?,
+            breakpoints: HashSet::new(),
+            no_ack: false,
+            killed: false,
+        })
+    }
+
+    fn read_byte(&mut self) -> Option<u8> {
+        let mut buf = [0; 1];
+        match self.conn.read(&mut buf) {
+            Ok(1) => Some(buf[0]),
+            _ => None,
+        }
+    }
+
+    // None if the connection is closed
+    fn recv_packet(&mut self) -> Option<String> {
+        loop {
+            // skip acks and out of packet interrupts
+            while self.read_byte()? != b'$' {}
+
+            let mut data = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    b'#' => break,
+                    b'}' => data.push(self.read_byte()? ^ 0x20),
+                    byte => data.push(byte),
+                }
+            }
+            let checksum = [self.read_byte()?, self.read_byte()?];
+            let checksum = u8::from_str_radix(std::str::from_utf8(&checksum).ok()?, 16).ok();
+
+            if self.no_ack {
+                return Some(String::from_utf8_lossy(&data).to_string());
+            }
+            if checksum == Some(checksum_of(&data)) {
+                self.conn.write_all(b"+").ok()?;
+                return Some(String::from_utf8_lossy(&data).to_string());
+            }
+            self.conn.write_all(b"-").ok()?;
+        }
+    }
+
+    fn send_packet(&mut self, data: &str) {
+        let mut escaped = Vec::new();
+        for byte in data.bytes() {
+            match byte {
+                b'#' | b'$' | b'}' | b'*' => escaped.extend([b'}', byte ^ 0x20]),
+                _ => escaped.push(byte),
+            }
+        }
+        let mut packet = vec![b'$'];
+        packet.extend(&escaped);
+        packet.extend(format!("#{:02x}", checksum_of(&escaped)).bytes());
+
+        loop {
+            if self.conn.write_all(&packet).is_err() || self.no_ack {
+                return;
+            }
+            match self.read_byte() {
+                Some(b'-') => continue,
+                _ => return,
+            }
+        }
+    }
+
+    // check Ctrl-C (0x03) sent while target is running
+    fn interrupted(&mut self) -> bool {
+        let mut buf = [0; 1];
+        self.conn.set_nonblocking(true).ok();
+        let result = self.conn.read(&mut buf);
+        self.conn.set_nonblocking(false).ok();
+        match result {
+            Ok(1) => buf[0] == 0x03,
+            Ok(_) => true, // connection closed
+            Err(e) => e.kind() != ErrorKind::WouldBlock,
+        }
+    }
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum: u8, byte| sum.wrapping_add(*byte))
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+// register values are sent as target-endian (little) bytes
+fn encode_reg(value: u64, isa: Isa) -> String {
+    match isa {
+        Isa::Rv32 => encode_hex(&(value as u32).to_le_bytes()),
+        Isa::Rv64 => encode_hex(&value.to_le_bytes()),
+    }
+}
+
+fn decode_reg(hex: &str) -> Option<u64> {
+    let bytes = decode_hex(hex)?;
+    if bytes.is_empty() || bytes.len() > 8 {
+        return None;
+    }
+    Some(
+        bytes
+            .iter()
+            .rev()
+            .fold(0, |acc, byte| acc << 8 | *byte as u64),
+    )
+}
+
+fn parse_addr_len(args: &str) -> Option<(u64, usize)> {
+    let (addr, len) = args.split_once(',')?;
+    Some((
+        u64::from_str_radix(addr, 16).ok()?,
+        usize::from_str_radix(len, 16).ok()?,
+    ))
+}
+
+impl Emulator {
+    pub fn gdb_emulation(&mut self, target: &str) {
+        let mut server = match GdbServer::listen(target) {
+            Ok(server) => server,
+            Err(e) => panic!("gdb connection failed: {e:?}"),
+        };
+
+        // exit the loop when gdb has gone
+        while let Some(packet) = server.recv_packet() {
+            let reply = match self.handle_packet(&mut server, &packet) {
+                Some(reply) => reply,
+                None => break,
+            };
+            server.send_packet(&reply);
+            if packet == "QStartNoAckMode" {
+                server.no_ack = true;
+            }
+        }
+
+        if server.killed {
+            self.exit(0);
+        }
+
+        // keep running without debugger
+        loop {
+            self.step();
+        }
+    }
+
+    // None if gdb has detached or killed the target
+    fn handle_packet(&mut self, server: &mut GdbServer, packet: &str) -> Option<String> {
+        let (cmd, args) = packet.split_at(packet.chars().next().map_or(0, |c| c.len_utf8()));
+        let reply = match cmd {
+            "?" => self.stop_reply(5, ""),
+            "g" => {
+                let isa = self.cpu.isa();
+                let mut regs: String = (0..32)
+                    .map(|r| encode_reg(self.cpu.regs.read(Some(r)), isa))
+                    .collect();
+                regs += &encode_reg(self.cpu.pc(), isa);
+                regs
+            }
+            "G" => {
+                let width = match self.cpu.isa() {
+                    Isa::Rv32 => 8,
+                    Isa::Rv64 => 16,
+                };
+                for (regnum, i) in (0..args.len()).step_by(width).enumerate().take(33) {
+                    match args.get(i..i + width).and_then(decode_reg) {
+                        Some(value) => self.write_gdb_reg(regnum, value),
+                        None => return Some("E01".to_string()),
+                    };
+                }
+                "OK".to_string()
+            }
+            "p" => match usize::from_str_radix(args, 16)
+                .ok()
+                .and_then(|regnum| Some((regnum, self.read_gdb_reg(regnum)?)))
+            {
+                // FP registers are 64 bit wide even on rv32
+                Some((regnum, value)) if (FIRST_FPR_REGNUM..FIRST_CSR_REGNUM).contains(&regnum) => {
+                    encode_hex(&value.to_le_bytes())
+                }
+                Some((_, value)) => encode_reg(value, self.cpu.isa()),
+                None => "E01".to_string(),
+            },
+            "P" => {
+                let written = args.split_once('=').and_then(|(regnum, value)| {
+                    let regnum = usize::from_str_radix(regnum, 16).ok()?;
+                    self.write_gdb_reg(regnum, decode_reg(value)?)
+                });
+                match written {
+                    Some(()) => "OK".to_string(),
+                    None => "E01".to_string(),
+                }
+            }
+            "m" => match parse_addr_len(args).and_then(|(addr, len)| self.read_memory(addr, len)) {
+                Some(bytes) => encode_hex(&bytes),
+                None => "E14".to_string(),
+            },
+            "M" => {
+                let written = args.split_once(':').and_then(|(addr_len, data)| {
+                    let (addr, len) = parse_addr_len(addr_len)?;
+                    let bytes = decode_hex(data)?;
+                    if bytes.len() != len {
+                        return None;
+                    }
+                    self.write_memory(addr, &bytes)
+                });
+                match written {
+                    Some(()) => "OK".to_string(),
+                    None => "E14".to_string(),
+                }
+            }
+            "c" | "s" => {
+                if let Ok(addr) = u64::from_str_radix(args, 16) {
+                    self.cpu.set_pc(addr);
+                }
+                match self.resume(server, cmd == "s") {
+                    StopReason::Step => self.stop_reply(5, ""),
+                    StopReason::Breakpoint => self.stop_reply(5, "swbreak:;"),
+                    StopReason::Interrupt => self.stop_reply(2, ""),
+                }
+            }
+            "Z" | "z" => {
+                // software and hardware breakpoints are both handled by the stub
+                let mut fields = args.split(',');
+                let kind = fields.next();
+                let addr = fields.next().and_then(|a| u64::from_str_radix(a, 16).ok());
+                match (kind, addr) {
+                    (Some("0") | Some("1"), Some(addr)) => {
+                        if cmd == "Z" {
+                            server.breakpoints.insert(addr);
+                        } else {
+                            server.breakpoints.remove(&addr);
+                        }
+                        "OK".to_string()
+                    }
+                    _ => String::new(),
+                }
+            }
+            // registers and memory are accessed on the hart selected with Hg
+            "H" => match args.split_at(args.len().min(1)) {
+                ("g", thread) => match self.parse_thread(thread) {
+                    Some(Some(hartid)) => {
+                        self.switch_hart(hartid);
+                        "OK".to_string()
+                    }
+                    Some(None) => "OK".to_string(),
+                    None => "E01".to_string(),
+                },
+                // every hart runs on continue and step
+                _ => "OK".to_string(),
+            },
+            "T" => match self.parse_thread(args) {
+                Some(Some(_)) => "OK".to_string(),
+                _ => "E01".to_string(),
+            },
+            "D" => {
+                server.send_packet("OK");
+                return None;
+            }
+            "k" => {
+                server.killed = true;
+                return None;
+            }
+            "q" | "Q" => self.handle_query(packet),
+            _ => String::new(),
+        };
+
+        Some(reply)
+    }
+
+    fn handle_query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            "PacketSize=4000;qXfer:features:read+;swbreak+;hwbreak+;QStartNoAckMode+".to_string()
+        } else if packet == "QStartNoAckMode" {
+            "OK".to_string()
+        } else if packet == "qAttached" {
+            "1".to_string()
+        } else if packet == "qC" {
+            format!("QC{:x}", self.current_hart + 1)
+        } else if packet == "qfThreadInfo" {
+            let threads: Vec<String> = (1..=self.harts.len()).map(|id| format!("{id:x}")).collect();
+            format!("m{}", threads.join(","))
+        } else if packet == "qsThreadInfo" {
+            "l".to_string()
+        } else if let Some(args) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            match parse_addr_len(args) {
+                Some((offset, len)) => {
+                    let xml = target::target_xml(self.cpu.isa(), &self.cpu.csr_list());
+                    let offset = (offset as usize).min(xml.len());
+                    let end = (offset + len).min(xml.len());
+                    let prefix = if end == xml.len() { "l" } else { "m" };
+                    format!("{prefix}{}", &xml[offset..end])
+                }
+                None => "E01".to_string(),
+            }
+        } else {
+            String::new()
+        }
+    }
+
+    // the harts are threads 1, 2, ... so that gdb follows a switch at the end of a quantum
+    fn stop_reply(&self, signal: u8, info: &str) -> String {
+        format!("T{signal:02x}thread:{:x};{info}", self.current_hart + 1)
+    }
+
+    // Some(None) for any thread, "0" or "-1"
+    fn parse_thread(&self, thread: &str) -> Option<Option<usize>> {
+        match thread {
+            "0" | "-1" => Some(None),
+            _ => match usize::from_str_radix(thread, 16) {
+                Ok(id) if (1..=self.harts.len()).contains(&id) => Some(Some(id - 1)),
+                _ => None,
+            },
+        }
+    }
+
+    fn resume(&mut self, server: &mut GdbServer, single_step: bool) -> StopReason {
+        let mut count: u64 = 0;
+        loop {
+            self.step();
+            if single_step {
+                return StopReason::Step;
+            }
+            if server.breakpoints.contains(&self.cpu.pc()) {
+                return StopReason::Breakpoint;
+            }
+
+            count += 1;
+            if count.is_multiple_of(POLL_INTERVAL) && server.interrupted() {
+                return StopReason::Interrupt;
+            }
+        }
+    }
+
+    fn read_gdb_reg(&self, regnum: usize) -> Option<u64> {
+        match regnum {
+            0..=31 => Some(self.cpu.regs.read(Some(regnum))),
+            32 => Some(self.cpu.pc()),
+            r if (FIRST_FPR_REGNUM..FIRST_CSR_REGNUM).contains(&r) => {
+                Some(self.cpu.fregs.read(Some(r - FIRST_FPR_REGNUM)))
+            }
+            PRIV_REGNUM => Some(self.cpu.priv_level() as u64),
+            r if (FIRST_CSR_REGNUM..PRIV_REGNUM).contains(&r) => {
+                self.cpu.csr_read_only(r - FIRST_CSR_REGNUM)?;
+                self.cpu.read_csr(r - FIRST_CSR_REGNUM)
+            }
+            _ => None,
+        }
+    }
+
+    fn write_gdb_reg(&mut self, regnum: usize, value: u64) -> Option<()> {
+        match regnum {
+            0..=31 => self.cpu.regs.write(Some(regnum), value),
+            32 => self.cpu.set_pc(value),
+            r if (FIRST_FPR_REGNUM..FIRST_CSR_REGNUM).contains(&r) => {
+                self.cpu.fregs.write(Some(r - FIRST_FPR_REGNUM), value)
+            }
+            PRIV_REGNUM => self.cpu.set_priv_level(match value {
+                0b00 => PrivilegedLevel::User,
+                0b01 => PrivilegedLevel::Supervisor,
+                0b11 => PrivilegedLevel::Machine,
+                _ => return None,
+            }),
+            r if (FIRST_CSR_REGNUM..PRIV_REGNUM).contains(&r) => {
+                if self.cpu.csr_read_only(r - FIRST_CSR_REGNUM)? {
+                    return None;
+                }
+                self.cpu.write_csr(r - FIRST_CSR_REGNUM, value)?
+            }
+            _ => return None,
+        }
+        Some(())
+    }
+
+    fn read_memory(&mut self, addr: u64, len: usize) -> Option<Vec<u8>> {
+        (0..len as u64)
+            .map(|i| {
+                let paddr = self.cpu.debug_trans_addr(addr.wrapping_add(i))?;
+                self.cpu.bus.load_u8(paddr).ok().map(|byte| byte as u8)
+            })
+            .collect()
+    }
+
+    fn write_memory(&mut self, addr: u64, bytes: &[u8]) -> Option<()> {
+        for (i, byte) in bytes.iter().enumerate() {
+            let paddr = self.cpu.debug_trans_addr(addr.wrapping_add(i as u64))?;
+            self.cpu.bus.store8(paddr, *byte as u64).ok()?;
+        }
+        Some(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packet_util_test() {
+        assert_eq!(checksum_of(b"qSupported"), 0x37);
+        assert_eq!(checksum_of(b"OK"), 0x9a);
+        assert_eq!(decode_hex("00ff10"), Some(vec![0x00, 0xff, 0x10]));
+        assert_eq!(decode_hex("0"), None);
+        assert_eq!(encode_reg(0x8000_0000, Isa::Rv32), "00000080");
+        assert_eq!(encode_reg(0x1234, Isa::Rv64), "3412000000000000");
+        assert_eq!(decode_reg("3412000000000000"), Some(0x1234));
+        assert_eq!(parse_addr_len("80000000,4"), Some((0x8000_0000, 4)));
+    }
+}
@@ -0,0 +1,72 @@
+use crate::Isa;
+
+// gdb numbers FP registers from 33, CSRs from 65 (= 65 + csr number)
+// and the privilege level after them.
+pub const FIRST_FPR_REGNUM: usize = 33;
+pub const FIRST_CSR_REGNUM: usize = 65;
+pub const PRIV_REGNUM: usize = FIRST_CSR_REGNUM + 4096;
+
+// csrs are the (name, number) of the CSRs the hart implements
+pub fn target_xml(isa: Isa, csrs: &[(String, usize)]) -> String {
+    let (arch, xlen) = match isa {
+        Isa::Rv32 => ("riscv:rv32", 32),
+        Isa::Rv64 => ("riscv:rv64", 64),
+    };
+    let gprs = [
+        "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "fp", "s1", "a0", "a1", "a2", "a3", "a4",
+        "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+        "t5", "t6",
+    ];
+
+    let fprs = [
+        "ft0", "ft1", "ft2", "ft3", "ft4", "ft5", "ft6", "ft7", "fs0", "fs1", "fa0", "fa1", "fa2",
+        "fa3", "fa4", "fa5", "fa6", "fa7", "fs2", "fs3", "fs4", "fs5", "fs6", "fs7", "fs8", "fs9",
+        "fs10", "fs11", "ft8", "ft9", "ft10", "ft11",
+    ];
+
+    let mut xml = format!(
+        "<?xml version=\"1.0\"?>\
+         <!DOCTYPE target SYSTEM \"gdb-target.dtd\">\
+         <target version=\"1.0\">\
+         <architecture>{arch}</architecture>\
+         <feature name=\"org.gnu.gdb.riscv.cpu\">"
+    );
+    for (num, name) in gprs.iter().enumerate() {
+        let reg_type = match *name {
+            "sp" | "fp" => "data_ptr",
+            _ => "int",
+        };
+        xml += &format!(
+            "<reg name=\"{name}\" bitsize=\"{xlen}\" type=\"{reg_type}\" regnum=\"{num}\"/>"
+        );
+    }
+    xml += &format!("<reg name=\"pc\" bitsize=\"{xlen}\" type=\"code_ptr\" regnum=\"32\"/>");
+    xml += "</feature><feature name=\"org.gnu.gdb.riscv.fpu\">";
+    for (num, name) in fprs.iter().enumerate() {
+        xml += &format!(
+            "<reg name=\"{name}\" bitsize=\"64\" type=\"ieee_double\" regnum=\"{}\"/>",
+            FIRST_FPR_REGNUM + num
+        );
+    }
+    for (name, num) in [("fflags", 0x001), ("frm", 0x002), ("fcsr", 0x003)] {
+        xml += &format!(
+            "<reg name=\"{name}\" bitsize=\"{xlen}\" type=\"int\" regnum=\"{}\" group=\"float\"/>",
+            FIRST_CSR_REGNUM + num
+        );
+    }
+    xml += "</feature><feature name=\"org.gnu.gdb.riscv.csr\">";
+    // fflags, frm and fcsr are in the fpu feature
+    for (name, num) in csrs.iter().filter(|(_, num)| *num > 0x003) {
+        xml += &format!(
+            "<reg name=\"{name}\" bitsize=\"{xlen}\" type=\"int\" regnum=\"{}\" group=\"csr\"/>",
+            FIRST_CSR_REGNUM + num
+        );
+    }
+    xml += &format!(
+        "</feature><feature name=\"org.gnu.gdb.riscv.virtual\">\
+         <reg name=\"priv\" bitsize=\"{xlen}\" type=\"int\" regnum=\"{PRIV_REGNUM}\"/>\
+         </feature></target>"
+    );
+
+    xml
+}
@@ -1,12 +1,20 @@
 pub mod bus;
 pub mod cmdline;
 pub mod cpu;
+mod debug;
 pub mod elfload;
 mod fesvr;
+mod gdbserver;
 pub mod log;
+pub mod machine;
+mod monitor;
+pub mod snapshot;
+mod socket;
 
+use bus::term::{self, Request};
 use cmdline::Arguments;
-use cpu::{Cpu, TrapCause};
+use cpu::{Cpu, Hart, TrapCause};
+use debug::{DebugModule, JtagServer, JTAG_POLL_INTERVAL};
 use fesvr::FrontendServer;
 
 const INTERLEAVE: u64 = 5000;
@@ -20,52 +28,170 @@ pub enum Isa {
 
 pub struct Emulator {
     pub cpu: Cpu,
+    // the slot of the running hart holds a stale state until it is parked
+    harts: Vec<Hart>,
+    current_hart: usize,
     frontend_server: FrontendServer,
     tohost_addr: Option<u64>,
     fromhost_addr: Option<u64>,
     args: Arguments,
+    interleave_count: u64,
+    // steps of every hart, for --snapshot-at and the JTAG polling
+    inst_count: u64,
+    debug_module: DebugModule,
+    jtag: Option<JtagServer>,
 }
 
 impl Emulator {
+    // the emulator running the ELF of args, for the tests
+    #[cfg(test)]
+    pub fn for_test(args: Arguments) -> Self {
+        log::LOG_LEVEL.get_or_init(|| log::LogLv::NoLog);
+        let loader =
+            elfload::ElfLoader::try_new(&args.filename).expect("loading the test ELF failed");
+        Emulator::new(loader, args)
+    }
+
     pub fn new(loader: elfload::ElfLoader, args: Arguments) -> Self {
         let isa = loader.target_arch();
         let (tohost_addr, fromhost_addr) = loader.get_host_addr(isa);
 
-        Emulator {
-            cpu: Cpu::new(loader, &args, isa),
+        let cpu = Cpu::new(loader, &args, isa);
+        let reset_pc = Cpu::reset_pc(&cpu.bus, &args);
+        let harts = (0..args.machine.harts)
+            .map(|hartid| Hart::new(isa, &args, reset_pc, hartid))
+            .collect();
+        let debug_module = DebugModule::new(args.machine.harts);
+        let jtag = args.jtag_target.as_ref().map(|target| {
+            JtagServer::listen(target)
+                .unwrap_or_else(|e| panic!("listening for jtag on {target} failed: {e}"))
+        });
+
+        let mut emulator = Emulator {
+            cpu,
+            harts,
+            current_hart: 0,
             frontend_server: FrontendServer::new(),
             tohost_addr,
             fromhost_addr,
             args,
+            interleave_count: 0,
+            inst_count: 0,
+            debug_module,
+            jtag,
+        };
+
+        if let Some(path) = emulator.args.restore_path.clone() {
+            if let Err(e) = emulator.read_snapshot(&path) {
+                panic!("restoring the snapshot from {path} failed: {e}");
+            }
         }
+        snapshot::install_signal_handler();
+
+        emulator
     }
 
     pub fn emulation(&mut self) {
+        if let Some(target) = self.args.gdb_target.clone() {
+            self.gdb_emulation(&target);
+            return;
+        }
+
         loop {
-            for _ in 0..INTERLEAVE {
-                *crate::log::INST_COUNT.lock().unwrap() += 1;
-                log::diffln!("0x{:016x}", self.cpu.pc());
-
-                match self.cpu.exec_one_cycle() {
-                    Ok(()) => (),
-                    Err((addr, cause, msg)) => {
-                        log::infoln!("[exception: {:?}] {}", cause, msg);
-                        self.cpu.trap(addr.unwrap_or(self.cpu.pc()), cause);
-                    }
+            self.step();
+        }
+    }
+
+    fn step(&mut self) {
+        self.inst_count += 1;
+        let inst_count = self.inst_count;
+        // the diff log starts after LOG_ENABLE_INST instructions
+        if log::LOG_LEVEL.get() == Some(&log::LogLv::Diff) {
+            *log::INST_COUNT.lock().unwrap() = inst_count;
+        }
+        log::diffln!("0x{:016x}", self.cpu.pc());
+
+        // a halted hart waits for the debugger
+        let halted = self.cpu.debug_mode();
+        if !halted {
+            match self.cpu.exec_one_cycle() {
+                Ok(()) => (),
+                Err((addr, cause, msg)) => {
+                    log::infoln!("[exception: {:?}] {}", cause, msg);
+                    self.cpu.trap(addr.unwrap_or(self.cpu.pc()), cause);
                 }
+            }
+            self.cpu.check_single_step();
+        }
+
+        log::diffln!(":");
+        self.cpu.regs.show();
 
-                log::diffln!(":");
-                self.cpu.regs.show();
+        if self.tohost_addr.is_some() && self.fromhost_addr.is_some() && self.check_tohost() {
+            self.handle_syscall();
+        }
 
-                if self.tohost_addr.is_some() && self.fromhost_addr.is_some() && self.check_tohost()
-                {
-                    self.handle_syscall();
+        self.interleave_count += 1;
+        if self.interleave_count == INTERLEAVE {
+            self.interleave_count = 0;
+            // devices advance once every harts have run their quantum
+            let next_hart = (self.current_hart + 1) % self.harts.len();
+            if next_hart == 0 {
+                self.timer_increment(INTERLEAVE / INSNS_PER_RTC_TICK);
+                self.cpu.bus.tick();
+            }
+            self.switch_hart(next_hart);
+        }
+
+        if self.jtag.is_some() && (halted || inst_count.is_multiple_of(JTAG_POLL_INTERVAL)) {
+            self.poll_jtag();
+        }
+
+        if self.args.snapshot_at == Some(inst_count) || snapshot::take_request() {
+            self.write_snapshot(&self.args.snapshot_path);
+        }
+
+        match term::take_request() {
+            Some(Request::Quit) => {
+                eprintln!("\ncarron: terminated");
+                self.exit(0);
+            }
+            Some(Request::Monitor) => self.monitor(),
+            None => (),
+        }
+    }
+
+    // every way out of the emulation ends here
+    fn exit(&self, code: i32) -> ! {
+        if self.args.tlb_stats {
+            for (hartid, hart) in self.harts.iter().enumerate() {
+                if hartid == self.current_hart {
+                    self.cpu.show_tlb_stats();
+                } else {
+                    hart.show_tlb_stats();
                 }
             }
+        }
+        std::process::exit(code)
+    }
+
+    fn switch_hart(&mut self, hartid: usize) {
+        if hartid == self.current_hart {
+            return;
+        }
+
+        self.cpu.swap_hart(&mut self.harts[self.current_hart]);
+        self.cpu.swap_hart(&mut self.harts[hartid]);
+        self.current_hart = hartid;
+    }
 
-            self.cpu.reservation_set = None;
-            self.cpu.timer_increment(INTERLEAVE / INSNS_PER_RTC_TICK);
-            self.cpu.bus.uart.tick(&mut self.cpu.bus.plic);
+    fn timer_increment(&mut self, inc: u64) {
+        self.cpu.timer_increment(inc);
+        let mtime = self.cpu.bus.clint.mtime();
+        for (hartid, hart) in self.harts.iter_mut().enumerate() {
+            if hartid != self.current_hart {
+                hart.sync_time(mtime);
+            }
         }
     }
 }
@@ -0,0 +1,380 @@
+use std::fs;
+
+// the address space reserved for each device
+pub const MROM_SIZE: u64 = 0x1_0000;
+pub const CLINT_SIZE: u64 = 0xc0000;
+pub const PLIC_SIZE: u64 = 0x100_0000;
+pub const UART_SIZE: u64 = 0x100;
+pub const VIRTIO_SIZE: u64 = 0x1000;
+pub const VIRTIO_SLOTS: u64 = 8;
+// the CLINT banks msip and mtimecmp below mtime at 0xbff8, which leaves room
+// for 4095 harts, and the PLIC has two 4KiB contexts per hart from 0x20_0000
+pub const HARTS_MAX: usize = ((PLIC_SIZE - 0x20_0000) / 0x2000) as usize;
+// the transport tracks notified queues in a u64; every port takes two queues
+// and the control queues another two
+pub const CONSOLE_PORTS_MAX: usize = 31;
+
+// virtio-mmio devices, which take consecutive transport slots and IRQs in this order
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VirtioKind {
+    Block,
+    Console,
+    Rng,
+    Net,
+}
+
+impl VirtioKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            VirtioKind::Block => "virtio-blk",
+            VirtioKind::Console => "virtio-console",
+            VirtioKind::Rng => "virtio-rng",
+            VirtioKind::Net => "virtio-net",
+        }
+    }
+}
+
+// memory map and interrupt routing of the emulated board
+#[derive(Clone, Debug)]
+pub struct MachineConfig {
+    pub harts: usize,
+    // DRAM starts at the ELF entry point unless it is specified
+    pub dram_base: Option<u64>,
+    pub dram_size: u64,
+    // raw image of the initial DRAM contents, mapped copy-on-write
+    pub dram_image: Option<String>,
+    pub mrom_base: u64,
+    pub clint_base: u64,
+    pub plic_base: u64,
+    pub uart_base: u64,
+    pub uart_irq: u32,
+    // stdio, pty, tcp:ADDR:PORT, unix:PATH or file:PATH
+    pub uart_backend: String,
+    // file whose contents are typed into the UART before the backend's input
+    pub uart_input: Option<String>,
+    // copy the UART output to stdout when the backend is not stdio
+    pub uart_tee: bool,
+    pub virtio_base: u64,
+    // IRQ of the first virtio slot
+    pub virtio_irq: u32,
+    // raw disk image behind the virtio block device
+    pub virtio_blk_image: Option<String>,
+    pub virtio_blk_readonly: bool,
+    // host backends of the console ports, the first one being the console
+    pub virtio_console: Vec<String>,
+    // the entropy device is enabled by giving its seed
+    pub virtio_rng_seed: Option<u64>,
+    // backend of the virtio network device: unix:PATH, loopback:PATH or pcap:tx=PATH,rx=PATH
+    pub virtio_net: Option<String>,
+    pub virtio_net_mac: [u8; 6],
+}
+
+impl Default for MachineConfig {
+    fn default() -> Self {
+        MachineConfig {
+            harts: 1,
+            dram_base: None,
+            dram_size: 2 * 1024 * 1024 * 1024,
+            dram_image: None,
+            mrom_base: 0x1000,
+            clint_base: 0x0200_0000,
+            plic_base: 0x0c00_0000,
+            uart_base: 0x1000_0000,
+            uart_irq: 1,
+            uart_backend: "stdio".to_string(),
+            uart_input: None,
+            uart_tee: false,
+            virtio_base: 0x1000_1000,
+            virtio_irq: 2,
+            virtio_blk_image: None,
+            virtio_blk_readonly: false,
+            virtio_console: Vec::new(),
+            virtio_rng_seed: None,
+            virtio_net: None,
+            virtio_net_mac: [0x52, 0x54, 0x00, 0x12, 0x34, 0x56],
+        }
+    }
+}
+
+impl MachineConfig {
+    // "key = value" per line, '#' starts a comment
+    pub fn from_file(path: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+        let mut config = MachineConfig::default();
+        for (lineno, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or(format!("{path}:{}: expected \"key = value\"", lineno + 1))?;
+            config
+                .set(key.trim(), value.trim())
+                .map_err(|e| format!("{path}:{}: {e}", lineno + 1))?;
+        }
+
+        Ok(config)
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "dram_image" => {
+                self.dram_image = Some(value.to_string());
+                return Ok(());
+            }
+            "uart_backend" => {
+                self.uart_backend = parse_uart_backend(value)?;
+                return Ok(());
+            }
+            "uart_input" => {
+                self.uart_input = Some(value.to_string());
+                return Ok(());
+            }
+            "virtio_blk_image" => {
+                self.virtio_blk_image = Some(value.to_string());
+                return Ok(());
+            }
+            "virtio_console" => {
+                self.virtio_console = parse_console_ports(value)?;
+                return Ok(());
+            }
+            "virtio_net" => {
+                self.virtio_net = Some(parse_net_backend(value)?);
+                return Ok(());
+            }
+            "virtio_net_mac" => {
+                self.virtio_net_mac = parse_mac(value)?;
+                return Ok(());
+            }
+            _ => (),
+        }
+
+        let num = parse_num(value)?;
+        match key {
+            "harts" if num > 0 && num <= HARTS_MAX as u64 => self.harts = num as usize,
+            "dram_base" => self.dram_base = Some(num),
+            "dram_size" if num > 0 => self.dram_size = num,
+            "mrom_base" => self.mrom_base = num,
+            "clint_base" => self.clint_base = num,
+            "plic_base" => self.plic_base = num,
+            "uart_base" => self.uart_base = num,
+            "uart_irq" if num > 0 && num < 32 => self.uart_irq = num as u32,
+            "uart_tee" => self.uart_tee = num != 0,
+            "virtio_base" => self.virtio_base = num,
+            "virtio_irq" if num > 0 && num + VIRTIO_SLOTS <= 32 => self.virtio_irq = num as u32,
+            "virtio_blk_readonly" => self.virtio_blk_readonly = num != 0,
+            "virtio_rng_seed" => self.virtio_rng_seed = Some(num),
+            "harts" | "dram_size" | "uart_irq" | "virtio_irq" => {
+                return Err(format!("invalid {key}: {value}"))
+            }
+            _ => return Err(format!("unknown key: {key}")),
+        }
+        Ok(())
+    }
+
+    // (name, base, size) of the devices, which shadow DRAM where they overlap it
+    pub fn device_regions(&self) -> Vec<(&'static str, u64, u64)> {
+        vec![
+            ("mrom", self.mrom_base, MROM_SIZE),
+            ("clint", self.clint_base, CLINT_SIZE),
+            ("plic", self.plic_base, PLIC_SIZE),
+            ("uart", self.uart_base, UART_SIZE),
+            ("virtio", self.virtio_base, VIRTIO_SIZE * VIRTIO_SLOTS),
+        ]
+    }
+
+    // (kind, base, irq) of the enabled virtio devices
+    pub fn virtio_slots(&self) -> Vec<(VirtioKind, u64, u32)> {
+        [
+            (VirtioKind::Block, self.virtio_blk_image.is_some()),
+            (VirtioKind::Console, !self.virtio_console.is_empty()),
+            (VirtioKind::Rng, self.virtio_rng_seed.is_some()),
+            (VirtioKind::Net, self.virtio_net.is_some()),
+        ]
+        .into_iter()
+        .filter(|(_, enabled)| *enabled)
+        .enumerate()
+        .map(|(slot, (kind, _))| {
+            (
+                kind,
+                self.virtio_base + VIRTIO_SIZE * slot as u64,
+                self.virtio_irq + slot as u32,
+            )
+        })
+        .collect()
+    }
+
+    pub fn check(&self) -> Result<(), String> {
+        let mut regions = self.device_regions();
+        // DRAM from the ELF entry point may sit under the devices, but DRAM
+        // placed on purpose must not
+        if let Some(base) = self.dram_base {
+            if base.checked_add(self.dram_size).is_none() {
+                return Err(format!("dram ({base:#x}-) runs past the address space"));
+            }
+            regions.push(("dram", base, self.dram_size));
+        }
+        regions.sort_by_key(|(_, base, _)| *base);
+        for pair in regions.windows(2) {
+            let ((name_a, base_a, size_a), (name_b, base_b, _)) = (pair[0], pair[1]);
+            if base_a.checked_add(size_a).is_none_or(|end| end > base_b) {
+                return Err(format!(
+                    "{name_a} ({base_a:#x}-) overlaps {name_b} ({base_b:#x}-)"
+                ));
+            }
+        }
+
+        let virtio_irqs = self.virtio_irq..self.virtio_irq + VIRTIO_SLOTS as u32;
+        if virtio_irqs.contains(&self.uart_irq) {
+            return Err(format!(
+                "uart irq {} is shared with the virtio irqs {virtio_irqs:?}",
+                self.uart_irq
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn parse_uart_backend(value: &str) -> Result<String, String> {
+    let value = value.trim();
+    match value.split_once(':') {
+        None if value == "stdio" || value == "pty" => Ok(value.to_string()),
+        Some(("tcp", addr)) if addr.contains(':') => Ok(value.to_string()),
+        Some(("unix" | "file", path)) if !path.is_empty() => Ok(value.to_string()),
+        _ => Err(format!("invalid uart backend: {value}")),
+    }
+}
+
+// comma separated list of "stdout", "file:PATH" or "unix:PATH"
+fn parse_console_ports(value: &str) -> Result<Vec<String>, String> {
+    let ports = value
+        .split(',')
+        .map(|port| port.trim())
+        .map(|port| match port.split_once(':') {
+            None if port == "stdout" => Ok(port.to_string()),
+            Some(("file" | "unix", path)) if !path.is_empty() => Ok(port.to_string()),
+            _ => Err(format!("invalid console port: {port}")),
+        })
+        .collect::<Result<Vec<String>, String>>()?;
+    if ports.len() > CONSOLE_PORTS_MAX {
+        return Err(format!(
+            "{} console ports are more than the {CONSOLE_PORTS_MAX} supported",
+            ports.len()
+        ));
+    }
+    Ok(ports)
+}
+
+fn parse_net_backend(value: &str) -> Result<String, String> {
+    let value = value.trim();
+    match value.split_once(':') {
+        Some(("unix" | "loopback", path)) if !path.is_empty() => Ok(value.to_string()),
+        Some(("pcap", files))
+            if files.split(',').all(|file| {
+                matches!(file.trim().split_once('='), Some(("tx" | "rx", path)) if !path.is_empty())
+            }) =>
+        {
+            Ok(value.to_string())
+        }
+        _ => Err(format!("invalid network backend: {value}")),
+    }
+}
+
+// six colon separated hex bytes; the multicast bit must be clear
+fn parse_mac(value: &str) -> Result<[u8; 6], String> {
+    let bytes = value
+        .split(':')
+        .map(|byte| u8::from_str_radix(byte.trim(), 16))
+        .collect::<Result<Vec<u8>, _>>();
+    match bytes.map(<[u8; 6]>::try_from) {
+        Ok(Ok(mac)) if mac[0] & 1 == 0 => Ok(mac),
+        _ => Err(format!("invalid MAC address: {value}")),
+    }
+}
+
+// decimal or 0x-prefixed hex with an optional K/M/G suffix
+pub fn parse_num(value: &str) -> Result<u64, String> {
+    let (digits, unit) = match value.chars().last() {
+        Some('K' | 'k') => (&value[..value.len() - 1], 1 << 10),
+        Some('M' | 'm') => (&value[..value.len() - 1], 1 << 20),
+        Some('G' | 'g') => (&value[..value.len() - 1], 1 << 30),
+        _ => (value, 1),
+    };
+    let num = match digits.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(&hex.replace('_', ""), 16),
+        None => digits.replace('_', "").parse::<u64>(),
+    };
+    num.ok()
+        .and_then(|num| num.checked_mul(unit))
+        .ok_or(format!("invalid number: {value}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn machine_config_test() {
+        assert_eq!(parse_num("0x8000_0000"), Ok(0x8000_0000));
+        assert_eq!(parse_num("64M"), Ok(64 * 1024 * 1024));
+        assert!(parse_num("0xg").is_err());
+
+        let mut config = MachineConfig::default();
+        config.set("dram_size", "256M").unwrap();
+        config.set("uart_base", "0x1001_0000").unwrap();
+        assert!(config.set("uart_irq", "0").is_err());
+        assert!(config.set("harts", &HARTS_MAX.to_string()).is_ok());
+        assert!(config.set("harts", &(HARTS_MAX + 1).to_string()).is_err());
+        assert!(config.set("vga_base", "0x0").is_err());
+        config.set("uart_backend", "tcp:127.0.0.1:4321").unwrap();
+        assert!(config.set("uart_backend", "tcp:4321").is_err());
+        assert!(config.set("uart_backend", "serial").is_err());
+        assert!(config.check().is_ok());
+
+        // the UART must not sit inside the PLIC
+        config.set("uart_base", "0x0c20_0000").unwrap();
+        assert!(config.check().is_err());
+        config.set("uart_base", "0x1000_0000").unwrap();
+
+        // nor inside DRAM
+        config.set("dram_base", "0x8000_0000").unwrap();
+        assert!(config.check().is_ok());
+        config.set("dram_base", "0x0").unwrap();
+        assert!(config.check().is_err());
+        config.set("dram_base", "0xffff_ffff_f000_0000").unwrap();
+        assert!(config.check().is_err());
+        config.set("dram_base", "0x8000_0000").unwrap();
+
+        // nor share an IRQ with the virtio devices
+        config.set("virtio_blk_image", "rootfs.img").unwrap();
+        assert_eq!(
+            config.virtio_slots(),
+            vec![(VirtioKind::Block, 0x1000_1000, 2)]
+        );
+        config.set("uart_irq", "9").unwrap();
+        assert!(config.check().is_err());
+
+        // only the enabled devices take a slot
+        config.set("virtio_rng_seed", "42").unwrap();
+        assert_eq!(config.virtio_slots()[1], (VirtioKind::Rng, 0x1000_2000, 3));
+        config
+            .set("virtio_console", "stdout, unix:/tmp/port1")
+            .unwrap();
+        assert_eq!(config.virtio_console, vec!["stdout", "unix:/tmp/port1"]);
+        assert_eq!(config.virtio_slots()[2].0, VirtioKind::Rng);
+        assert!(config.set("virtio_console", "tcp:1234").is_err());
+        let ports = vec!["stdout"; CONSOLE_PORTS_MAX + 1].join(",");
+        assert!(config.set("virtio_console", &ports).is_err());
+
+        config
+            .set("virtio_net", "pcap:tx=out.pcap, rx=in.pcap")
+            .unwrap();
+        assert_eq!(config.virtio_slots()[3], (VirtioKind::Net, 0x1000_4000, 5));
+        assert!(config.set("virtio_net", "pcap:out.pcap").is_err());
+        config.set("virtio_net_mac", "52:54:00:ab:cd:ef").unwrap();
+        assert_eq!(config.virtio_net_mac, [0x52, 0x54, 0, 0xab, 0xcd, 0xef]);
+        assert!(config.set("virtio_net_mac", "01:00:5e:00:00:01").is_err());
+        assert!(config.set("virtio_net_mac", "52:54:00:12:34").is_err());
+    }
+}
@@ -0,0 +1,70 @@
+use crate::bus::term;
+use crate::machine::parse_num;
+use crate::Emulator;
+use std::io::{self, BufRead};
+
+const HELP: &str = "\
+cont | c            resume the guest
+quit | q            exit the emulator
+info registers      show the registers of the running hart
+x ADDR [COUNT]      dump COUNT words of physical memory (default: 4)
+snapshot [PATH]     write a snapshot (default: --snapshot path)
+";
+
+impl Emulator {
+    // entered by C-a c; the guest is paused until the monitor is left
+    pub(crate) fn monitor(&mut self) {
+        term::enter_monitor();
+        eprintln!("\ncarron monitor - type 'help' for more information");
+
+        let mut line = String::new();
+        loop {
+            eprint!("(carron) ");
+            line.clear();
+            if io::stdin().lock().read_line(&mut line).unwrap_or(0) == 0 {
+                break;
+            }
+            match line.split_whitespace().collect::<Vec<&str>>().as_slice() {
+                [] => (),
+                ["c" | "cont"] => break,
+                ["q" | "quit"] => self.exit(0),
+                ["info", "registers"] => {
+                    eprintln!("  pc: {:#x}  hart: {}", self.cpu.pc(), self.current_hart);
+                    eprint!("{}", self.cpu.regs);
+                }
+                ["x", addr, count @ ..] => self.dump_memory(addr, count.first().copied()),
+                ["snapshot"] => self.write_snapshot(&self.args.snapshot_path),
+                ["snapshot", path] => self.write_snapshot(path),
+                ["help" | "?"] => eprint!("{HELP}"),
+                _ => eprintln!("unknown command: {}", line.trim()),
+            }
+        }
+
+        term::leave_monitor();
+    }
+
+    fn dump_memory(&self, addr: &str, count: Option<&str>) {
+        let (addr, count) = match (parse_num(addr), count.map_or(Ok(4), parse_num)) {
+            (Ok(addr), Ok(count)) => (addr, count),
+            (Err(e), _) | (_, Err(e)) => {
+                eprintln!("{e}");
+                return;
+            }
+        };
+
+        for line in (0..count).step_by(4) {
+            let line_addr = addr + 4 * line;
+            eprint!("{line_addr:#018x}:");
+            for word in line..(line + 4).min(count) {
+                match self.cpu.bus.load_u32(addr + 4 * word) {
+                    Ok(data) => eprint!(" {data:08x}"),
+                    Err((_, _, msg)) => {
+                        eprintln!(" {msg}");
+                        return;
+                    }
+                }
+            }
+            eprintln!();
+        }
+    }
+}
@@ -0,0 +1,331 @@
+use crate::Emulator;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+const MAGIC: &[u8; 8] = b"CARRONSS";
+const VERSION: u32 = 9;
+
+// set by SIGUSR1 and consumed by the emulation loop
+static SNAPSHOT_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn request_snapshot(_signum: libc::c_int) {
+    SNAPSHOT_REQUESTED.store(true, Ordering::Relaxed);
+}
+
+pub fn install_signal_handler() {
+    unsafe {
+        libc::signal(
+            libc::SIGUSR1,
+            request_snapshot as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        );
+    }
+}
+
+pub fn request() {
+    SNAPSHOT_REQUESTED.store(true, Ordering::Relaxed);
+}
+
+pub fn take_request() -> bool {
+    SNAPSHOT_REQUESTED.swap(false, Ordering::Relaxed)
+}
+
+pub fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+pub trait Snapshot {
+    fn save(&self, w: &mut Writer) -> io::Result<()>;
+    fn restore(&mut self, r: &mut Reader) -> io::Result<()>;
+}
+
+// little endian, length-prefixed slices
+pub struct Writer<'a> {
+    inner: &'a mut dyn Write,
+}
+
+impl<'a> Writer<'a> {
+    pub fn new(inner: &'a mut dyn Write) -> Self {
+        Writer { inner }
+    }
+
+    // each section starts with a tag to detect a corrupted stream early
+    pub fn tag(&mut self, tag: &[u8; 4]) -> io::Result<()> {
+        self.inner.write_all(tag)
+    }
+
+    pub fn u8(&mut self, data: u8) -> io::Result<()> {
+        self.inner.write_all(&[data])
+    }
+
+    pub fn u32(&mut self, data: u32) -> io::Result<()> {
+        self.inner.write_all(&data.to_le_bytes())
+    }
+
+    pub fn u64(&mut self, data: u64) -> io::Result<()> {
+        self.inner.write_all(&data.to_le_bytes())
+    }
+
+    pub fn opt_u64(&mut self, data: Option<u64>) -> io::Result<()> {
+        match data {
+            Some(data) => {
+                self.u8(1)?;
+                self.u64(data)
+            }
+            None => self.u8(0),
+        }
+    }
+
+    pub fn bytes(&mut self, data: &[u8]) -> io::Result<()> {
+        self.u64(data.len() as u64)?;
+        self.inner.write_all(data)
+    }
+
+    pub fn u32s(&mut self, data: &[u32]) -> io::Result<()> {
+        self.u64(data.len() as u64)?;
+        data.iter().try_for_each(|x| self.u32(*x))
+    }
+
+    pub fn u64s(&mut self, data: &[u64]) -> io::Result<()> {
+        self.u64(data.len() as u64)?;
+        data.iter().try_for_each(|x| self.u64(*x))
+    }
+}
+
+pub struct Reader<'a> {
+    inner: &'a mut dyn Read,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(inner: &'a mut dyn Read) -> Self {
+        Reader { inner }
+    }
+
+    pub fn tag(&mut self, tag: &[u8; 4]) -> io::Result<()> {
+        let mut buf = [0; 4];
+        self.inner.read_exact(&mut buf)?;
+        if &buf != tag {
+            return Err(invalid_data(format!(
+                "expected section {} but found {}",
+                String::from_utf8_lossy(tag),
+                String::from_utf8_lossy(&buf)
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn u8(&mut self) -> io::Result<u8> {
+        let mut buf = [0; 1];
+        self.inner.read_exact(&mut buf)?;
+        Ok(buf[0])
+    }
+
+    pub fn u32(&mut self) -> io::Result<u32> {
+        let mut buf = [0; 4];
+        self.inner.read_exact(&mut buf)?;
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    pub fn u64(&mut self) -> io::Result<u64> {
+        let mut buf = [0; 8];
+        self.inner.read_exact(&mut buf)?;
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    pub fn opt_u64(&mut self) -> io::Result<Option<u64>> {
+        match self.u8()? {
+            0 => Ok(None),
+            _ => Ok(Some(self.u64()?)),
+        }
+    }
+
+    fn len(&mut self, expected: Option<usize>) -> io::Result<usize> {
+        let len = self.u64()? as usize;
+        match expected {
+            Some(expected) if expected != len => Err(invalid_data(format!(
+                "expected {expected} elements but found {len}"
+            ))),
+            _ => Ok(len),
+        }
+    }
+
+    pub fn bytes(&mut self) -> io::Result<Vec<u8>> {
+        let len = self.len(None)?;
+        let mut buf = vec![0; len];
+        self.inner.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    // the length must match the destination
+    pub fn bytes_into(&mut self, dst: &mut [u8]) -> io::Result<()> {
+        self.len(Some(dst.len()))?;
+        self.inner.read_exact(dst)
+    }
+
+    pub fn u32s_into(&mut self, dst: &mut [u32]) -> io::Result<()> {
+        self.len(Some(dst.len()))?;
+        dst.iter_mut().try_for_each(|x| {
+            *x = self.u32()?;
+            Ok(())
+        })
+    }
+
+    pub fn u64s_into(&mut self, dst: &mut [u64]) -> io::Result<()> {
+        self.len(Some(dst.len()))?;
+        dst.iter_mut().try_for_each(|x| {
+            *x = self.u64()?;
+            Ok(())
+        })
+    }
+}
+
+impl Emulator {
+    pub fn save_snapshot(&self, out: &mut dyn Write) -> io::Result<()> {
+        let mut w = Writer::new(out);
+        w.inner.write_all(MAGIC)?;
+        w.u32(VERSION)?;
+        w.u8(self.cpu.isa() as u8)?;
+
+        // the running hart is saved from the cpu and the others from their parked state
+        w.u64(self.harts.len() as u64)?;
+        w.u64(self.current_hart as u64)?;
+        for (hartid, hart) in self.harts.iter().enumerate() {
+            if hartid == self.current_hart {
+                self.cpu.save(&mut w)?;
+            } else {
+                hart.save(&mut w)?;
+            }
+        }
+        self.cpu.bus.dram.save(&mut w)?;
+        self.cpu.bus.clint.save(&mut w)?;
+        self.cpu.bus.plic.save(&mut w)?;
+        self.cpu.bus.reservations.save(&mut w)?;
+        self.cpu.bus.save_devices(&mut w)?;
+        self.frontend_server.save(&mut w)?;
+
+        w.tag(b"EMU ")?;
+        w.u64(self.interleave_count)?;
+        w.u64(self.inst_count)
+    }
+
+    pub fn restore_snapshot(&mut self, input: &mut dyn Read) -> io::Result<()> {
+        let mut r = Reader::new(input);
+        let mut magic = [0; 8];
+        r.inner.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid_data("not a carron snapshot".to_string()));
+        }
+        let version = r.u32()?;
+        if version != VERSION {
+            return Err(invalid_data(format!(
+                "unsupported snapshot version {version} (expected {VERSION})"
+            )));
+        }
+        if r.u8()? != self.cpu.isa() as u8 {
+            return Err(invalid_data(
+                "the snapshot was taken with a different ISA".to_string(),
+            ));
+        }
+
+        let harts = r.u64()? as usize;
+        if harts != self.harts.len() {
+            return Err(invalid_data(format!(
+                "expected {} harts but found {harts}",
+                self.harts.len()
+            )));
+        }
+        let current_hart = r.u64()? as usize;
+        if current_hart >= harts {
+            return Err(invalid_data(format!("invalid running hart {current_hart}")));
+        }
+        self.switch_hart(current_hart);
+        for hartid in 0..harts {
+            if hartid == self.current_hart {
+                self.cpu.restore(&mut r)?;
+            } else {
+                self.harts[hartid].restore(&mut r)?;
+            }
+        }
+        self.cpu.bus.dram.restore(&mut r)?;
+        self.cpu.bus.clint.restore(&mut r)?;
+        self.cpu.bus.plic.restore(&mut r)?;
+        self.cpu.bus.reservations.restore(&mut r)?;
+        self.cpu.bus.restore_devices(&mut r)?;
+        self.frontend_server.restore(&mut r)?;
+
+        r.tag(b"EMU ")?;
+        self.interleave_count = r.u64()?;
+        self.inst_count = r.u64()?;
+
+        Ok(())
+    }
+
+    pub fn write_snapshot(&self, path: &str) {
+        let result = File::create(path).and_then(|file| {
+            let mut out = BufWriter::new(file);
+            self.save_snapshot(&mut out)?;
+            out.flush()
+        });
+
+        match result {
+            Ok(()) => eprintln!(
+                "snapshot written to {path} at {} instructions",
+                self.inst_count
+            ),
+            Err(e) => eprintln!("writing snapshot to {path} failed: {e}"),
+        }
+    }
+
+    pub fn read_snapshot(&mut self, path: &str) -> io::Result<()> {
+        let mut input = BufReader::new(File::open(path)?);
+        self.restore_snapshot(&mut input)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmdline::Arguments;
+    use crate::cpu::csr::CSRname;
+
+    #[test]
+    fn snapshot_test() {
+        let mut emulator = Emulator::for_test(Arguments::for_elf("./HelloWorld"));
+        emulator.cpu.regs.write(Some(10), 0xdead_beef);
+        emulator.cpu.fregs.write(Some(1), 0x3ff0_0000_0000_0000);
+        emulator
+            .cpu
+            .write_csr(CSRname::mscratch as usize, 0x1234)
+            .unwrap();
+        let base = emulator.cpu.bus.dram.base_addr;
+        emulator.cpu.bus.reservations.reserve(0, base + 0x40);
+        let reserved = emulator.cpu.bus.reservations.get(0);
+        emulator.cpu.bus.store32(base + 0x100_0000, 0xcafe).unwrap();
+        emulator.cpu.bus.clint.clint[0x4000] = 0x55;
+
+        let mut buf = Vec::new();
+        emulator.save_snapshot(&mut buf).unwrap();
+
+        let mut restored = Emulator::for_test(Arguments::for_elf("./HelloWorld"));
+        restored.cpu.bus.store32(base + 0x200_0000, 0x1).unwrap();
+        restored.restore_snapshot(&mut buf.as_slice()).unwrap();
+        assert_eq!(restored.cpu.regs.read(Some(10)), 0xdead_beef);
+        assert_eq!(restored.cpu.fregs.read(Some(1)), 0x3ff0_0000_0000_0000);
+        assert_eq!(
+            restored.cpu.read_csr(CSRname::mscratch as usize),
+            Some(0x1234)
+        );
+        assert_eq!(restored.cpu.bus.reservations.get(0), reserved);
+        assert_eq!(restored.cpu.bus.load32(base + 0x100_0000).unwrap(), 0xcafe);
+        assert_eq!(restored.cpu.bus.load32(base + 0x200_0000).unwrap(), 0);
+        assert_eq!(restored.cpu.bus.clint.clint[0x4000], 0x55);
+        assert_eq!(
+            restored.cpu.bus.load32(base).unwrap(),
+            emulator.cpu.bus.load32(base).unwrap()
+        );
+
+        // unknown versions are rejected
+        buf[8] = 0xff;
+        assert!(restored.restore_snapshot(&mut buf.as_slice()).is_err());
+    }
+}
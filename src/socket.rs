@@ -0,0 +1,209 @@
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+
+// a listening socket for a host client of a device or a debugger
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+pub trait Client: Read + Write {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()>;
+}
+
+impl Client for TcpStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        TcpStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+impl Client for UnixStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        UnixStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+// a socket left behind by a previous run; anything else at the path is kept
+fn remove_stale_socket(path: &str) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() => fs::remove_file(path),
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+impl Listener {
+    pub fn bind_tcp(addr: &str) -> io::Result<Self> {
+        Ok(Listener::Tcp(TcpListener::bind(addr)?))
+    }
+
+    pub fn bind_unix(path: &str) -> io::Result<Self> {
+        remove_stale_socket(path)?;
+        Ok(Listener::Unix(UnixListener::bind(path)?))
+    }
+
+    // "1234" or "host:1234" is treated as TCP, others as an unix socket path
+    pub fn bind(target: &str) -> io::Result<Self> {
+        if target.parse::<u16>().is_ok() {
+            Self::bind_tcp(&format!("127.0.0.1:{target}"))
+        } else if target.contains(':') {
+            Self::bind_tcp(target)
+        } else {
+            Self::bind_unix(target)
+        }
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Listener::Tcp(listener) => listener.set_nonblocking(nonblocking),
+            Listener::Unix(listener) => listener.set_nonblocking(nonblocking),
+        }
+    }
+
+    // blocks unless the listener doesn't
+    pub fn accept(&self) -> io::Result<Box<dyn Client>> {
+        Ok(match self {
+            Listener::Tcp(listener) => {
+                let (stream, _) = listener.accept()?;
+                // the clients are interactive
+                stream.set_nodelay(true)?;
+                Box::new(stream)
+            }
+            Listener::Unix(listener) => Box::new(listener.accept()?.0),
+        })
+    }
+}
+
+impl fmt::Display for Listener {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Listener::Tcp(listener) => match listener.local_addr() {
+                Ok(addr) => write!(f, "tcp:{addr}"),
+                Err(_) => write!(f, "tcp"),
+            },
+            Listener::Unix(listener) => match listener.local_addr() {
+                Ok(addr) => match addr.as_pathname() {
+                    Some(path) => write!(f, "unix:{}", path.display()),
+                    None => write!(f, "unix"),
+                },
+                Err(_) => write!(f, "unix"),
+            },
+        }
+    }
+}
+
+// serves one client at a time without blocking the emulation
+pub struct Server {
+    // absent when the client was connected to rather than accepted
+    listener: Option<Listener>,
+    client: Option<Box<dyn Client>>,
+}
+
+impl Server {
+    pub fn new(listener: Listener) -> io::Result<Self> {
+        listener.set_nonblocking(true)?;
+        Ok(Server {
+            listener: Some(listener),
+            client: None,
+        })
+    }
+
+    pub fn connected_to(client: Box<dyn Client>) -> io::Result<Self> {
+        client.set_nonblocking(true)?;
+        Ok(Server {
+            listener: None,
+            client: Some(client),
+        })
+    }
+
+    #[cfg(test)]
+    pub fn listener(&self) -> Option<&Listener> {
+        self.listener.as_ref()
+    }
+
+    pub fn connected(&self) -> bool {
+        self.client.is_some()
+    }
+
+    pub fn disconnect(&mut self) {
+        self.client = None;
+    }
+
+    // true when a new client has just been taken
+    pub fn accept(&mut self) -> bool {
+        if self.client.is_some() {
+            return false;
+        }
+        let Some(listener) = &self.listener else {
+            return false;
+        };
+        self.client = listener
+            .accept()
+            .and_then(|client| client.set_nonblocking(true).map(|_| client))
+            .ok();
+        self.client.is_some()
+    }
+
+    // never blocks; 0 when there is no input or the client has gone
+    pub fn read(&mut self, buf: &mut [u8]) -> usize {
+        self.accept();
+        match self.client.as_mut().map(|client| client.read(buf)) {
+            Some(Ok(len)) if len > 0 => len,
+            Some(Err(e)) if e.kind() == io::ErrorKind::WouldBlock => 0,
+            None => 0,
+            // closed
+            Some(_) => {
+                self.client = None;
+                0
+            }
+        }
+    }
+
+    // output is dropped while no client is connected, but a slow one is waited for
+    pub fn write(&mut self, data: &[u8]) {
+        let Some(client) = &mut self.client else {
+            return;
+        };
+        let result = client
+            .set_nonblocking(false)
+            .and_then(|_| client.write_all(data))
+            .and_then(|_| client.set_nonblocking(true));
+        if result.is_err() {
+            self.client = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stale_socket_test() {
+        let path = std::env::temp_dir().join(format!("carron-socket-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        // a socket left behind is replaced
+        drop(Listener::bind_unix(path).unwrap());
+        let mut server = Server::new(Listener::bind_unix(path).unwrap()).unwrap();
+        let mut client = UnixStream::connect(path).unwrap();
+        client.write_all(b"hi").unwrap();
+        let mut buf = [0; 2];
+        let mut len = 0;
+        while len == 0 {
+            len = server.read(&mut buf);
+        }
+        assert_eq!(&buf[..len], &b"hi"[..len]);
+
+        // but a file at the path is not
+        fs::remove_file(path).unwrap();
+        fs::write(path, b"data").unwrap();
+        assert!(Listener::bind_unix(path).is_err());
+        assert_eq!(fs::read(path).unwrap(), b"data");
+        fs::remove_file(path).unwrap();
+    }
+}